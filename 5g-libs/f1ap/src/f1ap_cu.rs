@@ -39,6 +39,7 @@ impl<T> Application for F1apCu<T> where
         + IndicationHandler<InitialUlRrcMessageTransferProcedure>
         + IndicationHandler<UlRrcMessageTransferProcedure>
         + IndicationHandler<UeContextReleaseRequestProcedure>
+        + IndicationHandler<UeInactivityNotificationProcedure>
 {
 }
 
@@ -53,7 +54,8 @@ where
         + RequestProvider<GnbDuConfigurationUpdateProcedure>
         + IndicationHandler<InitialUlRrcMessageTransferProcedure>
         + IndicationHandler<UlRrcMessageTransferProcedure>
-        + IndicationHandler<UeContextReleaseRequestProcedure>,
+        + IndicationHandler<UeContextReleaseRequestProcedure>
+        + IndicationHandler<UeInactivityNotificationProcedure>,
     // Todo - add all other procedures
 {
     type TopPdu = F1apPdu;
@@ -87,6 +89,10 @@ where
                 UeContextReleaseRequestProcedure::call_provider(&self.0, req, logger).await;
                 None
             }
+            InitiatingMessage::UeInactivityNotification(req) => {
+                UeInactivityNotificationProcedure::call_provider(&self.0, req, logger).await;
+                None
+            }
             m => {
                 error!(logger, "Unhandled message {:?}", m);
                 return None;
@@ -17,22 +17,16 @@ pub fn generate_challenge(
     k: &[u8; 16],
     opc: &[u8; 16],
     serving_network_name: &[u8],
-    sqn: &mut [u8; 6],
+    sqn: &[u8; 6],
 ) -> Challenge {
     // TS33.501, section 6.1.3.2.0
     // Generate an AV with AMF set to 1 as defined in 33.102.
-    // Generating a fresh sequence number SQN and an unpredictable challenge RAND
-
-    // TODO: Resynchronization
-    // TODO: Increment SQN.
+    // The caller is responsible for supplying a fresh sequence number SQN.  We generate an unpredictable challenge RAND.
 
     // RAND
     let mut rand = [0u8; 16];
     rand::rng().fill_bytes(&mut rand);
 
-    // Serving network name length as a two byte KDF input parameter.
-    let serving_network_name_len_for_kdf = (serving_network_name.len() as u16).to_be_bytes();
-
     // MAC, XRES, CK, IK, AK
     let mut m = Milenage::new_with_opc(*k, *opc);
     let mac = m.f1(&rand, sqn, &AMF);
@@ -53,14 +47,63 @@ pub fn generate_challenge(
     autn[6..8].copy_from_slice(&AMF);
     autn[8..16].copy_from_slice(&mac);
 
-    // Derive KAUSF (as per Annex A.2) and calculate XRES* (as per Annex A.4).
+    let (xres_star, kseaf) =
+        derive_res_star_and_kseaf(&ck, &ik, serving_network_name, &autn[0..6], &rand, &xres);
+
+    Challenge {
+        rand,
+        autn,
+        xres_star,
+        kseaf,
+    }
+}
+
+/// The UE's response to a challenge, as calculated by the USIM and ME.
+pub struct ChallengeResponse {
+    pub res_star: [u8; 16],
+    pub kseaf: [u8; 32],
+}
+
+/// Calculate the UE side of the 5G AKA challenge - RES* and KSEAF - from RAND and AUTN.  This is the
+/// counterpart to generate_challenge(), and is used for testing.  Returns None if AUTN fails the MAC check.
+pub fn respond_to_challenge(
+    k: &[u8; 16],
+    opc: &[u8; 16],
+    serving_network_name: &[u8],
+    rand: &[u8; 16],
+    autn: &[u8; 16],
+) -> Option<ChallengeResponse> {
+    let mut m = Milenage::new_with_opc(*k, *opc);
+    let (res, ck, ik, ak) = m.f2345(rand);
+    let sqn: [u8; 6] = std::array::from_fn(|ii| autn[ii] ^ ak[ii]);
+    let amf: [u8; 2] = autn[6..8].try_into().unwrap();
+    if m.f1(rand, &sqn, &amf)[..] != autn[8..16] {
+        return None;
+    }
+    let (res_star, kseaf) =
+        derive_res_star_and_kseaf(&ck, &ik, serving_network_name, &autn[0..6], rand, &res);
+    Some(ChallengeResponse { res_star, kseaf })
+}
+
+fn derive_res_star_and_kseaf(
+    ck: &[u8; 16],
+    ik: &[u8; 16],
+    serving_network_name: &[u8],
+    sqn_xor_ak: &[u8],
+    rand: &[u8; 16],
+    res: &[u8; 8],
+) -> ([u8; 16], [u8; 32]) {
+    // Derive KAUSF (as per Annex A.2) and calculate (X)RES* (as per Annex A.4).
+
+    // Serving network name length as a two byte KDF input parameter.
+    let serving_network_name_len_for_kdf = (serving_network_name.len() as u16).to_be_bytes();
 
     // KAUSF* - TS33.501, Annex A.2, using key definition function from TS33.220, B.2.0.
-    let mut kausf = HmacSha256::new_from_slice(&[ck, ik].concat()).expect("Can't fail");
+    let mut kausf = HmacSha256::new_from_slice(&[*ck, *ik].concat()).expect("Can't fail");
     kausf.update(&[0x6A]); // FC
     kausf.update(serving_network_name); // P0 = serving network name
     kausf.update(&serving_network_name_len_for_kdf); // L0
-    kausf.update(&autn[0..6]); // P1 = SQN ^ AK
+    kausf.update(sqn_xor_ak); // P1 = SQN ^ AK
     kausf.update(&[0x00, 0x06]); // L1
     let kausf: [u8; 32] = kausf.finalize().into_bytes().into();
 
@@ -72,24 +115,37 @@ pub fn generate_challenge(
     let kseaf: [u8; 32] = kseaf.finalize().into_bytes().into();
 
     // XRES* - TS33.501, Annex A.4, using key definition function from TS33.220, B.2.0.
-    let mut xres_star = HmacSha256::new_from_slice(&[ck, ik].concat()).expect("Can't fail");
+    let mut xres_star = HmacSha256::new_from_slice(&[*ck, *ik].concat()).expect("Can't fail");
     xres_star.update(&[0x6B]); // FC
     xres_star.update(serving_network_name); // P0 = serving network name
     xres_star.update(&serving_network_name_len_for_kdf); // L0
-    xres_star.update(&rand); // P1 = RAND
+    xres_star.update(rand); // P1 = RAND
     xres_star.update(&[0x00, 0x10]); // L1
-    xres_star.update(&xres); // P2 = XRES
+    xres_star.update(res); // P2 = XRES
     xres_star.update(&[0x00, 0x08]); // L2
     let xres_star: [u8; 16] = xres_star.finalize().into_bytes()[16..]
         .try_into()
         .expect("Can't fail");
 
-    Challenge {
-        rand,
-        autn,
-        xres_star,
-        kseaf,
+    (xres_star, kseaf)
+}
+
+/// Verify the AUTS parameter returned by a UE that rejected our challenge with a synch failure, and
+/// if it is genuine, return the UE's SQN (SQN_MS).  Returns None if MAC-S does not match.
+pub fn resync_sqn(k: &[u8; 16], opc: &[u8; 16], rand: &[u8; 16], auts: &[u8]) -> Option<[u8; 6]> {
+    // TS33.102, 6.3.3: AUTS = Conc(SQN_MS) || MAC-S, where Conc(SQN_MS) = SQN_MS ^ f5*K(RAND) and
+    // MAC-S = f1*K(SQN_MS || RAND || AMF).  "The AMF used to calculate MAC-S assumes a dummy value of all zeros".
+    if auts.len() != 14 {
+        return None;
     }
+    let mut m = Milenage::new_with_opc(*k, *opc);
+    let ak_star = m.f5star(rand);
+    let sqn_ms: [u8; 6] = std::array::from_fn(|ii| auts[ii] ^ ak_star[ii]);
+    let mac_s = m.f1star(rand, &sqn_ms, &[0x00, 0x00]);
+    if mac_s[..] != auts[6..14] {
+        return None;
+    }
+    Some(sqn_ms)
 }
 
 pub fn derive_kamf(kseaf: &[u8; 32], imsi: &[u8]) -> [u8; 32] {
@@ -119,8 +175,12 @@ pub fn derive_krrcint(kgnb: &[u8; 32]) -> [u8; 16] {
     derive_algorithm_key(kgnb, 0x04, 0x02)
 }
 
-pub fn derive_knasint(kamf: &[u8; 32]) -> [u8; 16] {
-    derive_algorithm_key(kamf, 0x02, 0x02)
+pub fn derive_knasint(kamf: &[u8; 32], algorithm_identity: u8) -> [u8; 16] {
+    derive_algorithm_key(kamf, 0x02, algorithm_identity)
+}
+
+pub fn derive_knasenc(kamf: &[u8; 32], algorithm_identity: u8) -> [u8; 16] {
+    derive_algorithm_key(kamf, 0x01, algorithm_identity)
 }
 
 fn derive_algorithm_key(
@@ -141,3 +201,51 @@ fn derive_algorithm_key(
         .try_into()
         .expect("Can't fail")
 }
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    // TS35.208, 4.3, test set 1.
+    const K: [u8; 16] = hex!("465b5ce8b199b49faa5f0a2ee238a6bc");
+    const OPC: [u8; 16] = hex!("cd63cb71954a9f4e48a5994e37a02baf");
+    const RAND: [u8; 16] = hex!("23553cbe9637a89d218ae64dae47bf35");
+    const SQN: [u8; 6] = hex!("ff9bb4d0b607");
+
+    fn ue_auts(sqn_ms: &[u8; 6]) -> [u8; 14] {
+        let mut m = Milenage::new_with_opc(K, OPC);
+        let ak_star = m.f5star(&RAND);
+        let mac_s = m.f1star(&RAND, sqn_ms, &[0x00, 0x00]);
+        let mut auts = [0u8; 14];
+        for (ii, b) in auts[0..6].iter_mut().enumerate() {
+            *b = sqn_ms[ii] ^ ak_star[ii];
+        }
+        auts[6..14].copy_from_slice(&mac_s);
+        auts
+    }
+
+    #[test]
+    fn test_challenge_response() {
+        let snn = b"5G:mnc093.mcc208.3gppnetwork.org";
+        let challenge = generate_challenge(&K, &OPC, snn, &SQN);
+        let response =
+            respond_to_challenge(&K, &OPC, snn, &challenge.rand, &challenge.autn).unwrap();
+        assert_eq!(response.res_star, challenge.xres_star);
+        assert_eq!(response.kseaf, challenge.kseaf);
+
+        let mut bad_autn = challenge.autn;
+        bad_autn[15] ^= 0x01;
+        assert!(respond_to_challenge(&K, &OPC, snn, &challenge.rand, &bad_autn).is_none());
+    }
+
+    #[test]
+    fn test_resync_sqn() {
+        let auts = ue_auts(&SQN);
+        assert_eq!(resync_sqn(&K, &OPC, &RAND, &auts), Some(SQN));
+
+        let mut bad_auts = auts;
+        bad_auts[13] ^= 0x01;
+        assert_eq!(resync_sqn(&K, &OPC, &RAND, &bad_auts), None);
+    }
+}
@@ -1,5 +1,7 @@
 mod keygen;
+pub mod nea2;
 pub mod nia2;
+pub mod suci;
 pub use keygen::*;
 
 pub const NAS_ABBA: [u8; 2] = [0u8; 2];
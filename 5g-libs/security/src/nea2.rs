@@ -0,0 +1,40 @@
+use aes::Aes128;
+use ctr::cipher::{KeyIvInit, StreamCipher};
+
+type Aes128Ctr = ctr::Ctr128BE<Aes128>;
+
+// TS33.401, B.1.3
+pub fn apply_nea2_keystream(
+    ciphering_key: &[u8; 16],
+    count: [u8; 4],
+    bearer_identity_5bit: u8,
+    direction_1bit: u8,
+    message: &mut [u8],
+) {
+    /*
+    The 128-bit counter block T1 is constructed as
+    T1 = COUNT[0] .. COUNT[31] │ BEARER[0] .. BEARER[4] │ DIRECTION │ 0^26 │ 0^64.
+    Ciphering and deciphering are the same operation - XOR with the AES-CTR keystream.
+    */
+    let mut counter_block = [0u8; 16];
+    counter_block[0..4].copy_from_slice(&count);
+    counter_block[4] = (bearer_identity_5bit << 3) | (direction_1bit << 2);
+    Aes128Ctr::new(ciphering_key.into(), &counter_block.into()).apply_keystream(message);
+}
+
+#[cfg(test)]
+use hex_literal::hex;
+
+#[test]
+fn test_nea2_test_set_1() {
+    // TS33.401, C.1, test set 1 (253 bits, so the last 3 bits of the output are not significant).
+    let count = hex!("398a59b4");
+    let bearer = 0x15;
+    let direction = 0b1;
+    let ck = hex!("d3c5d592327fb11c4035c6680af8c6d1");
+    let mut message = hex!("981ba6824c1bfb1ab485472029b71d808ce33e2cc3c0b5fc1f3de8a6dc66b1f0");
+    let expected = hex!("e9fed8a63d155304d71df20bf3e82214b20ed7dad2f233dc3c22d7bdeeed8e78");
+    apply_nea2_keystream(&ck, count, bearer, direction, &mut message);
+    assert_eq!(message[..31], expected[..31]);
+    assert_eq!(message[31] & 0xf8, expected[31] & 0xf8);
+}
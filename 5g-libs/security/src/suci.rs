@@ -0,0 +1,190 @@
+//! suci - concealment and de-concealment of the SUCI (subscription concealed identifier) using the
+//! ECIES protection schemes of TS33.501, Annex C.
+
+use aes::Aes128;
+use ctr::cipher::{KeyIvInit, StreamCipher};
+use hmac::{Hmac, Mac};
+use sha2::{Digest, Sha256};
+
+type HmacSha256 = Hmac<Sha256>;
+type Aes128Ctr = ctr::Ctr128BE<Aes128>;
+
+// TS33.501, C.3.4.1 and C.3.4.2 - both profiles use the same symmetric parameters.
+const ENC_KEY_LEN: usize = 16;
+const ICB_LEN: usize = 16;
+const MAC_KEY_LEN: usize = 32;
+const MAC_LEN: usize = 8;
+const PROFILE_A_EPHEMERAL_KEY_LEN: usize = 32;
+const PROFILE_B_EPHEMERAL_KEY_LEN: usize = 33; // compressed point
+
+/// Decrypt the scheme output of a Profile A (X25519) SUCI, returning the scheme input (the
+/// BCD-encoded MSIN).  Returns None if the scheme output is malformed or fails its MAC check.
+pub fn deconceal_profile_a(hn_private_key: &[u8; 32], scheme_output: &[u8]) -> Option<Vec<u8>> {
+    if scheme_output.len() < PROFILE_A_EPHEMERAL_KEY_LEN + MAC_LEN {
+        return None;
+    }
+    let eph_public_key: [u8; 32] = scheme_output[..PROFILE_A_EPHEMERAL_KEY_LEN]
+        .try_into()
+        .unwrap();
+    let hn_private_key = x25519_dalek::StaticSecret::from(*hn_private_key);
+    let shared_secret =
+        hn_private_key.diffie_hellman(&x25519_dalek::PublicKey::from(eph_public_key));
+    decrypt(
+        shared_secret.as_bytes(),
+        &scheme_output[..PROFILE_A_EPHEMERAL_KEY_LEN],
+        &scheme_output[PROFILE_A_EPHEMERAL_KEY_LEN..],
+    )
+}
+
+/// Decrypt the scheme output of a Profile B (secp256r1) SUCI, returning the scheme input (the
+/// BCD-encoded MSIN).  Returns None if the scheme output is malformed or fails its MAC check.
+pub fn deconceal_profile_b(hn_private_key: &[u8; 32], scheme_output: &[u8]) -> Option<Vec<u8>> {
+    if scheme_output.len() < PROFILE_B_EPHEMERAL_KEY_LEN + MAC_LEN {
+        return None;
+    }
+    let eph_public_key = &scheme_output[..PROFILE_B_EPHEMERAL_KEY_LEN];
+    let hn_private_key = p256::SecretKey::from_slice(hn_private_key).ok()?;
+    let eph_public_key = p256::PublicKey::from_sec1_bytes(eph_public_key).ok()?;
+    let shared_secret = p256::ecdh::diffie_hellman(
+        hn_private_key.to_nonzero_scalar(),
+        eph_public_key.as_affine(),
+    );
+    decrypt(
+        shared_secret.raw_secret_bytes(),
+        &scheme_output[..PROFILE_B_EPHEMERAL_KEY_LEN],
+        &scheme_output[PROFILE_B_EPHEMERAL_KEY_LEN..],
+    )
+}
+
+/// Conceal a scheme input (the BCD-encoded MSIN) using Profile A (X25519), returning the scheme
+/// output.  This is the UE side of the scheme, and takes the UE's ephemeral private key.
+pub fn conceal_profile_a(
+    hn_public_key: &[u8; 32],
+    eph_private_key: &[u8; 32],
+    scheme_input: &[u8],
+) -> Vec<u8> {
+    let eph_private_key = x25519_dalek::StaticSecret::from(*eph_private_key);
+    let eph_public_key = x25519_dalek::PublicKey::from(&eph_private_key);
+    let shared_secret =
+        eph_private_key.diffie_hellman(&x25519_dalek::PublicKey::from(*hn_public_key));
+    let mut scheme_output = eph_public_key.as_bytes().to_vec();
+    scheme_output.extend(encrypt(
+        shared_secret.as_bytes(),
+        eph_public_key.as_bytes(),
+        scheme_input,
+    ));
+    scheme_output
+}
+
+fn encrypt(shared_secret: &[u8], eph_public_key: &[u8], plaintext: &[u8]) -> Vec<u8> {
+    let k = ansi_x963_kdf(shared_secret, eph_public_key);
+    let enc_key = &k[..ENC_KEY_LEN];
+    let icb = &k[ENC_KEY_LEN..ENC_KEY_LEN + ICB_LEN];
+    let mac_key = &k[ENC_KEY_LEN + ICB_LEN..];
+
+    let mut ciphertext = plaintext.to_vec();
+    Aes128Ctr::new(enc_key.into(), icb.into()).apply_keystream(&mut ciphertext);
+
+    let mut mac = HmacSha256::new_from_slice(mac_key).expect("Can't fail");
+    mac.update(&ciphertext);
+    ciphertext.extend_from_slice(&mac.finalize().into_bytes()[..MAC_LEN]);
+    ciphertext
+}
+
+fn decrypt(
+    shared_secret: &[u8],
+    eph_public_key: &[u8],
+    ciphertext_and_mac: &[u8],
+) -> Option<Vec<u8>> {
+    let (ciphertext, mac_tag) = ciphertext_and_mac.split_at(ciphertext_and_mac.len() - MAC_LEN);
+
+    // TS33.501, C.3.3: the ephemeral public key is the SharedInfo1 parameter of the KDF.
+    let k = ansi_x963_kdf(shared_secret, eph_public_key);
+    let enc_key = &k[..ENC_KEY_LEN];
+    let icb = &k[ENC_KEY_LEN..ENC_KEY_LEN + ICB_LEN];
+    let mac_key = &k[ENC_KEY_LEN + ICB_LEN..];
+
+    let mut mac = HmacSha256::new_from_slice(mac_key).expect("Can't fail");
+    mac.update(ciphertext);
+    mac.verify_truncated_left(mac_tag).ok()?;
+
+    let mut plaintext = ciphertext.to_vec();
+    Aes128Ctr::new(enc_key.into(), icb.into()).apply_keystream(&mut plaintext);
+    Some(plaintext)
+}
+
+// ANSI X9.63 KDF with SHA-256, as specified in SECG SEC 1, 3.6.1.
+fn ansi_x963_kdf(
+    shared_secret: &[u8],
+    shared_info: &[u8],
+) -> [u8; ENC_KEY_LEN + ICB_LEN + MAC_KEY_LEN] {
+    let mut k = [0u8; ENC_KEY_LEN + ICB_LEN + MAC_KEY_LEN];
+    for (counter, block) in k.chunks_mut(32).enumerate() {
+        let mut hash = Sha256::new();
+        hash.update(shared_secret);
+        hash.update(((counter + 1) as u32).to_be_bytes());
+        hash.update(shared_info);
+        block.copy_from_slice(&hash.finalize());
+    }
+    k
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use hex_literal::hex;
+
+    #[test]
+    fn test_profile_a() {
+        // TS33.501, C.4.3
+        let hn_private_key =
+            hex!("c53c22208b61860b06c62e5406a7b330c2b577aa5558981510d128247d38bd1d");
+        let scheme_output = hex!(
+            "b2e92f836055a255837debf850b528997ce0201cb82adfe4be1f587d07d8457d"
+            "cb02352410"
+            "cddd9e730ef3fa87"
+        );
+        assert_eq!(
+            deconceal_profile_a(&hn_private_key, &scheme_output),
+            Some(hex!("00012080f6").to_vec())
+        );
+
+        let mut tampered = scheme_output;
+        tampered[32] ^= 0x01;
+        assert_eq!(deconceal_profile_a(&hn_private_key, &tampered), None);
+    }
+
+    #[test]
+    fn test_conceal_profile_a() {
+        // TS33.501, C.4.3
+        let hn_public_key =
+            hex!("5a8d38864820197c3394b92613b20b91633cbd897119273bf8e4a6f4eec0a650");
+        let eph_private_key =
+            hex!("c80949f13ebe61af4ebdbd293ea4f942696b9e815d7e8f0096bbf6ed7de62256");
+        assert_eq!(
+            conceal_profile_a(&hn_public_key, &eph_private_key, &hex!("00012080f6")),
+            hex!(
+                "b2e92f836055a255837debf850b528997ce0201cb82adfe4be1f587d07d8457d"
+                "cb02352410"
+                "cddd9e730ef3fa87"
+            )
+            .to_vec()
+        );
+    }
+
+    #[test]
+    fn test_profile_b() {
+        // TS33.501, C.4.4
+        let hn_private_key =
+            hex!("f1ab1074477ebcc7f554ea1c5fc368b1616730155e0041ac447d6301975fecda");
+        let scheme_output = hex!(
+            "039aab8376597021e855679a9778ea0b67396e68c66df32c0f41e9acca2da9b9d1"
+            "46a33fc271"
+            "6ac7dae96aa30a4d"
+        );
+        assert_eq!(
+            deconceal_profile_b(&hn_private_key, &scheme_output),
+            Some(hex!("00012080f6").to_vec())
+        );
+    }
+}
@@ -1,3 +1,3 @@
 // The canonical form of Snssai.
-#[derive(Copy, Clone, Debug)]
+#[derive(Copy, Clone, Debug, PartialEq, Eq)]
 pub struct Snssai(pub u8, pub Option<[u8; 3]>);
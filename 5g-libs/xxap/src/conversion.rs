@@ -1,5 +1,5 @@
-use crate::{GtpTeid, TransportLayerAddress};
-use anyhow::bail;
+use crate::{GtpTeid, Snssai, TransportLayerAddress};
+use anyhow::{anyhow, bail};
 use async_net::IpAddr;
 use bitvec::prelude::*;
 
@@ -66,3 +66,32 @@ impl std::fmt::Display for GtpTeid {
         write!(f, "{:x?}", u32::from_be_bytes(self.0))
     }
 }
+
+// An Snssai is written as "<SST>" or "<SST>-<SD>", where the SD is 6 hex digits - for example "1" or "1-000001".
+impl std::str::FromStr for Snssai {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self, anyhow::Error> {
+        let (sst, sd) = match s.split_once('-') {
+            Some((sst, sd)) => {
+                let mut bytes = [0u8; 3];
+                hex::decode_to_slice(sd, &mut bytes)
+                    .map_err(|_| anyhow!("SD of S-NSSAI {s} is not 6 hex digits"))?;
+                (sst, Some(bytes))
+            }
+            None => (s, None),
+        };
+        let sst = sst
+            .parse::<u8>()
+            .map_err(|_| anyhow!("SST of S-NSSAI {s} is not a number from 0-255"))?;
+        Ok(Snssai(sst, sd))
+    }
+}
+
+impl std::fmt::Display for Snssai {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        match self.1 {
+            Some(sd) => write!(f, "{}-{}", self.0, hex::encode(sd)),
+            None => write!(f, "{}", self.0),
+        }
+    }
+}
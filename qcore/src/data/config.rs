@@ -1,4 +1,10 @@
-use std::net::{IpAddr, Ipv4Addr};
+use super::data_networks::DataNetworkConfig;
+use super::equipment_identities::EquipmentIdentities;
+use super::home_network_keys::HomeNetworkKeys;
+use super::nas_algorithms::{NasCipheringAlgorithm, NasIntegrityAlgorithm};
+use std::net::IpAddr;
+use std::time::Duration;
+use xxap::Snssai;
 
 #[derive(Debug, Clone)]
 pub struct Config {
@@ -14,8 +20,8 @@ pub struct Config {
     // Serving network name
     pub serving_network_name: String,
 
-    // The SST of the one and only slice (SNSSAI).  SD is not implemented.
-    pub sst: u8,
+    // The network slices (S-NSSAIs) that we serve.
+    pub snssais: Vec<Snssai>,
 
     // Test flags
     pub skip_ue_authentication_check: bool,
@@ -23,9 +29,51 @@ pub struct Config {
     // AMF IDs (AMF region / AMF set / AMF pointer)
     pub amf_ids: [u8; 3],
 
-    // Name of the N6 tun device
-    pub n6_tun_name: String,
+    // Data networks, each with its own N6 tun device and UE subnet.  The first is the default DNN.
+    pub data_networks: Vec<DataNetworkConfig>,
 
-    // /24 UE subnet.
-    pub ue_subnet: Ipv4Addr,
+    // Whether a SIM without a static address gets the same address back on a data network as last time, if it is
+    // free.
+    pub sticky_ue_ips: bool,
+
+    // Whether to put an SDAP header on downlink packets, so that the UE learns their QFI.  The UE always puts an
+    // SDAP header on uplink packets.
+    pub sdap_header_dl: bool,
+
+    // NAS integrity and ciphering algorithms, in order of preference.
+    pub nas_integrity_algorithms: Vec<NasIntegrityAlgorithm>,
+    pub nas_ciphering_algorithms: Vec<NasCipheringAlgorithm>,
+
+    // How long to wait for the UE to reply to a NAS request before retransmitting it.  TS24.501 sets T3522, T3550,
+    // T3560 and T3570 to 6 seconds.
+    pub nas_retransmission_timer: Duration,
+
+    // Periodic registration update timer that we give to UEs.
+    pub t3512: Duration,
+
+    // How long a UE can go without contacting us once its UE context has been released, before we implicitly
+    // deregister it.  This should be longer than T3512.
+    pub mobile_reachable_timer: Duration,
+
+    // Network name that we give to UEs in UE Configuration Update Commands.
+    pub full_network_name: Option<String>,
+    pub short_network_name: Option<String>,
+
+    // Local time zone, as an offset from UTC in minutes.  If set, we give UEs the network name and time (NITZ) in a
+    // UE Configuration Update Command after they register.
+    pub time_zone_offset_mins: Option<i16>,
+
+    // How long to wait for a paged UE to send a Service Request before paging it again (T3513).
+    pub paging_retransmission_timer: Duration,
+
+    // How many downlink packets we buffer for each PDU session of an idle UE while we page it, and for how long.
+    pub downlink_buffer_max_packets: usize,
+    pub downlink_buffer_max_age: Duration,
+
+    // Home network private keys for SUCI de-concealment, by home network public key identifier.
+    pub home_network_keys: HomeNetworkKeys,
+
+    // Devices that may or may not register, checked against the IMEISV that the UE gives us in the Security Mode
+    // Complete.
+    pub equipment_identities: EquipmentIdentities,
 }
@@ -0,0 +1,151 @@
+use super::qos::QosFlowTemplate;
+use anyhow::{Context, Result, ensure};
+use serde::Deserialize;
+use slog::{Logger, error, info};
+use std::fs;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+/// A data network that UEs can connect to, and the N6 interface that QCore uses to reach it.
+#[derive(Deserialize, Debug, Clone)]
+pub struct DataNetworkConfig {
+    /// Data network name, as signaled by the UE - for example "internet".
+    pub dnn: String,
+
+    /// Name of the Linux tun device for this data network's N6 traffic.
+    pub n6_tun_name: String,
+
+    /// Name of the Linux tap device onto which this data network's Ethernet PDU sessions are bridged.  If not
+    /// supplied, the data network doesn't support Ethernet PDU sessions.
+    pub n6_tap_name: Option<String>,
+
+    /// /24 UE subnet.  UEs are allocated host numbers 1-254, from a pool of host numbers that belongs to this data
+    /// network.
+    pub ue_subnet: Ipv4Addr,
+
+    /// /56 IPv6 prefix, from which each IPv6 or IPv4v6 PDU session is given a /64 prefix.  If not supplied, the
+    /// data network only supports IPv4.
+    pub ue_ipv6_prefix: Option<Ipv6Addr>,
+
+    /// IPv4 and IPv6 DNS servers to give to UEs that ask for them.
+    #[serde(default)]
+    pub dns_servers: Vec<IpAddr>,
+
+    /// IPv4 link MTU, or Ethernet frame payload MTU for Ethernet PDU sessions, to give to UEs that ask for it.
+    pub mtu: Option<u16>,
+
+    /// IPv4 and IPv6 P-CSCF addresses to give to UEs that ask for them, for IMS.
+    #[serde(default)]
+    pub p_cscf_servers: Vec<IpAddr>,
+
+    /// QoS flows that each IP PDU session gets in addition to its default QoS flow, in order of precedence.
+    #[serde(default)]
+    pub qos_flows: Vec<QosFlowTemplate>,
+}
+
+/// Encode a DNN as length-prefixed labels, like an APN - see TS24.501, 9.11.2.1B and TS23.003, 9.1.
+pub fn encode_dnn(dnn: &str) -> Vec<u8> {
+    let mut value = vec![];
+    for label in dnn.split('.') {
+        value.push(label.len() as u8);
+        value.extend_from_slice(label.as_bytes());
+    }
+    value
+}
+
+#[derive(Deserialize)]
+struct DataNetworksFile {
+    data_network: Vec<DataNetworkConfig>,
+}
+
+/// Load the data network table.  The first data network is the default, used when the UE does not
+/// ask for a particular DNN.
+pub fn load_data_networks_file(filename: &str, logger: &Logger) -> Result<Vec<DataNetworkConfig>> {
+    let contents = fs::read_to_string(filename).inspect_err(|e| {
+        error!(
+            logger,
+            "Failed to load data network file {filename} with error code {e}"
+        )
+    })?;
+    let file: DataNetworksFile = toml::from_str(&contents)?;
+    check_data_networks(&file.data_network)?;
+    for dn in file.data_network.iter() {
+        info!(
+            logger,
+            "Loaded data network {} - tun device {}, UE subnet {}/24",
+            dn.dnn,
+            dn.n6_tun_name,
+            dn.ue_subnet
+        );
+    }
+    Ok(file.data_network)
+}
+
+pub fn check_data_networks(data_networks: &[DataNetworkConfig]) -> Result<()> {
+    ensure!(!data_networks.is_empty(), "No data networks configured");
+    for (ii, dn) in data_networks.iter().enumerate() {
+        ensure!(
+            dn.ue_subnet.octets()[3] == 0,
+            "Final byte of UE subnet of data network {} must be 0",
+            dn.dnn
+        );
+        if let Some(tap) = &dn.n6_tap_name {
+            ensure!(
+                data_networks.iter().all(|x| &x.n6_tun_name != tap),
+                "Tap device {tap} of data network {} is also a tun device",
+                dn.dnn
+            );
+        }
+        // Each template takes up a QFI, and may need a DRB of its own out of the UE's 32.
+        ensure!(
+            dn.qos_flows.len() <= 16,
+            "Data network {} has more than 16 QoS flow templates",
+            dn.dnn
+        );
+        for qos_flow in &dn.qos_flows {
+            qos_flow
+                .check()
+                .with_context(|| format!("Bad QoS flow template on data network {}", dn.dnn))?;
+        }
+        if let Some(prefix) = dn.ue_ipv6_prefix {
+            ensure!(
+                prefix.octets()[7..].iter().all(|x| *x == 0),
+                "UE IPv6 prefix of data network {} must be a /56",
+                dn.dnn
+            );
+        }
+        for other in &data_networks[..ii] {
+            ensure!(
+                !other.dnn.eq_ignore_ascii_case(&dn.dnn),
+                "Data network {} is configured twice",
+                dn.dnn
+            );
+            ensure!(
+                other.n6_tun_name != dn.n6_tun_name,
+                "Data networks {} and {} share tun device {}",
+                other.dnn,
+                dn.dnn,
+                dn.n6_tun_name
+            );
+            ensure!(
+                dn.n6_tap_name.is_none() || other.n6_tap_name != dn.n6_tap_name,
+                "Data networks {} and {} share tap device",
+                other.dnn,
+                dn.dnn
+            );
+            ensure!(
+                other.ue_subnet != dn.ue_subnet,
+                "Data networks {} and {} share UE subnet {}",
+                other.dnn,
+                dn.dnn,
+                dn.ue_subnet
+            );
+            ensure!(
+                dn.ue_ipv6_prefix.is_none() || other.ue_ipv6_prefix != dn.ue_ipv6_prefix,
+                "Data networks {} and {} share UE IPv6 prefix",
+                other.dnn,
+                dn.dnn
+            );
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,68 @@
+use anyhow::{Result, ensure};
+use serde::Deserialize;
+use slog::{Logger, error, info};
+use std::fs;
+
+/// Devices that may or may not register, by IMEI or TAC (the first 8 digits of the IMEI).  This is a
+/// local stand-in for an Equipment Identity Register.
+#[derive(Deserialize, Debug, Clone, Default)]
+#[serde(deny_unknown_fields)]
+pub struct EquipmentIdentities {
+    /// If supplied, only devices whose IMEI or TAC is listed may register.
+    pub allow: Option<Vec<String>>,
+
+    /// Devices whose IMEI or TAC is listed may not register.
+    #[serde(default)]
+    pub deny: Vec<String>,
+}
+
+impl EquipmentIdentities {
+    /// Whether a device with this IMEI or IMEISV may register.  A device that didn't give us its identity
+    /// is only let in if there is no allow list.
+    pub fn allows(&self, pei: Option<&str>) -> bool {
+        let listed = |list: &[String], pei: &str| list.iter().any(|x| pei.starts_with(x.as_str()));
+        match pei {
+            Some(pei) => {
+                !listed(&self.deny, pei)
+                    && self.allow.as_ref().is_none_or(|allow| listed(allow, pei))
+            }
+            None => self.allow.is_none(),
+        }
+    }
+}
+
+/// Load the allowed and denied equipment identities.
+pub fn load_equipment_identities_file(
+    filename: &str,
+    logger: &Logger,
+) -> Result<EquipmentIdentities> {
+    let contents = fs::read_to_string(filename).inspect_err(|e| {
+        error!(
+            logger,
+            "Failed to load equipment identity file {filename} with error code {e}"
+        )
+    })?;
+    let equipment_identities: EquipmentIdentities = toml::from_str(&contents)?;
+    check_equipment_identities(&equipment_identities)?;
+    info!(
+        logger,
+        "Loaded {} denied equipment identities from {filename}{}",
+        equipment_identities.deny.len(),
+        match &equipment_identities.allow {
+            Some(allow) => format!(", and an allow list of {}", allow.len()),
+            None => String::new(),
+        }
+    );
+    Ok(equipment_identities)
+}
+
+pub fn check_equipment_identities(equipment_identities: &EquipmentIdentities) -> Result<()> {
+    let allow = equipment_identities.allow.iter().flatten();
+    for x in allow.chain(equipment_identities.deny.iter()) {
+        ensure!(
+            (x.len() == 8 || x.len() == 14) && x.chars().all(|c| c.is_ascii_digit()),
+            "Equipment identity {x} is neither an 8 digit TAC nor a 14 digit IMEI"
+        );
+    }
+    Ok(())
+}
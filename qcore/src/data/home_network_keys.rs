@@ -0,0 +1,52 @@
+use anyhow::{Result, bail};
+use serde::Deserialize;
+use slog::{Logger, error, info};
+use std::collections::HashMap;
+use std::fs;
+
+/// ECIES protection scheme profile of a home network key - TS33.501, Annex C.3.4.
+#[derive(Deserialize, Debug, Clone, Copy, PartialEq)]
+pub enum EciesProfile {
+    A,
+    B,
+}
+
+#[derive(Deserialize, Clone)]
+pub struct HomeNetworkKey {
+    pub profile: EciesProfile,
+    #[serde(with = "hex")]
+    pub private_key: [u8; 32],
+}
+
+// Keep the private key out of logs.
+impl std::fmt::Debug for HomeNetworkKey {
+    fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
+        write!(f, "HomeNetworkKey(profile {:?})", self.profile)
+    }
+}
+
+/// Home network private keys, indexed by home network public key identifier.
+pub type HomeNetworkKeys = HashMap<u8, HomeNetworkKey>;
+
+/// Load the home network private keys used for SUCI de-concealment.
+pub fn load_home_network_keys_file(filename: &str, logger: &Logger) -> Result<HomeNetworkKeys> {
+    let contents = fs::read_to_string(filename).inspect_err(|e| {
+        error!(
+            logger,
+            "Failed to load home network key file {filename} with error code {e}"
+        )
+    })?;
+    let table: HashMap<String, HomeNetworkKey> = toml::from_str(&contents)?;
+    let mut keys = HashMap::new();
+    for (key, value) in table.into_iter() {
+        let Some(Ok(id)) = key.strip_prefix("key-").map(|id| id.parse::<u8>()) else {
+            bail!("Key {key} in {filename} is not of the form 'key-<0-255>'")
+        };
+        info!(
+            logger,
+            "Loaded profile {:?} home network key with id {id} from {filename}", value.profile
+        );
+        keys.insert(id, value);
+    }
+    Ok(keys)
+}
@@ -1,12 +1,26 @@
 mod config;
+mod nas_algorithms;
 mod nas_context;
 mod pdu_session;
+mod qos;
+mod registered_ue;
 mod security_context;
+mod ue_configuration_update;
 mod ue_context;
+mod ue_message;
 mod userplane_session;
+pub mod data_networks;
+pub mod equipment_identities;
+pub mod home_network_keys;
 pub mod sims;
+pub mod sqn_store;
 
 pub use config::*;
+pub use nas_algorithms::*;
 pub use pdu_session::*;
+pub use qos::*;
+pub use registered_ue::*;
+pub use ue_configuration_update::*;
 pub use ue_context::*;
+pub use ue_message::*;
 pub use userplane_session::*;
@@ -0,0 +1,38 @@
+use anyhow::{Result, bail};
+use std::str::FromStr;
+
+/// NAS integrity algorithm - the values are the algorithm identities of TS33.501, 5.11.1.2.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NasIntegrityAlgorithm {
+    Nia2 = 2,
+}
+
+/// NAS ciphering algorithm - the values are the algorithm identities of TS33.501, 5.11.1.1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum NasCipheringAlgorithm {
+    Nea0 = 0,
+    Nea2 = 2,
+}
+
+impl FromStr for NasIntegrityAlgorithm {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "nia2" => Ok(NasIntegrityAlgorithm::Nia2),
+            _ => bail!("Unsupported NAS integrity algorithm {s} - supported algorithms are: nia2"),
+        }
+    }
+}
+
+impl FromStr for NasCipheringAlgorithm {
+    type Err = anyhow::Error;
+    fn from_str(s: &str) -> Result<Self> {
+        match s.to_lowercase().as_str() {
+            "nea0" => Ok(NasCipheringAlgorithm::Nea0),
+            "nea2" => Ok(NasCipheringAlgorithm::Nea2),
+            _ => bail!(
+                "Unsupported NAS ciphering algorithm {s} - supported algorithms are: nea0, nea2"
+            ),
+        }
+    }
+}
@@ -1,29 +1,99 @@
+use super::nas_algorithms::{NasCipheringAlgorithm, NasIntegrityAlgorithm};
 use super::security_context::SecurityContext;
-use anyhow::{Result, anyhow};
-use oxirush_nas::{Nas5gsMessage, decode_nas_5gs_message, encode_nas_5gs_message};
+use anyhow::{Result, anyhow, bail};
+use oxirush_nas::{Nas5gmmMessage, Nas5gsMessage, decode_nas_5gs_message, encode_nas_5gs_message};
+use std::borrow::Cow;
 
 #[derive(Debug, Default)]
 pub struct NasContext {
     security_context: Option<SecurityContext>,
+
+    // Set once the UE has sent a message that passes the integrity check.  From then on, we don't
+    // process any message that hasn't been integrity checked (TS24.501, 4.4.4.3).
+    secure_exchange_established: bool,
 }
 
 impl NasContext {
     pub fn decode(&mut self, data: &[u8]) -> Result<Nas5gsMessage> {
-        let nas = decode_nas_5gs_message(data)
-            .map_err(|e| anyhow!("NAS decode error - {e} - message bytes: {:?}", data))?;
-        match nas {
-            Nas5gsMessage::SecurityProtected(_security_header, body) => {
-                // TODO: Check the security header
-                Ok(*body)
+        // The MAC covers the message as sent, so check integrity before any deciphering, patching or decoding.
+        let protected = is_security_protected(data);
+        let mut data = Cow::Borrowed(data);
+        if protected && self.security_context.is_some() {
+            let count = self.check_integrity(&data)?;
+            if let Some(security_context) = &self.security_context {
+                security_context.decipher_uplink(data.to_mut(), count);
             }
-            nas => {
-                // TODO: Check if this is meant to be secured and reject if not
-                Ok(nas)
+        }
+        let data = patch_nas_for_oai_deregistration_security_header(data);
+        let nas = decode_nas_5gs_message(&data)
+            .map_err(|e| anyhow!("NAS decode error - {e} - message bytes: {:?}", data))?;
+        let nas = match nas {
+            Nas5gsMessage::SecurityProtected(_security_header, body) => *body,
+            nas => nas,
+        };
+
+        if self.security_context.is_none() || (!protected && !self.secure_exchange_established) {
+            // Either the UE is using a security context that we don't have, or we haven't established
+            // secure exchange of NAS messages yet.
+            if !allowed_without_integrity_protection(&nas) {
+                bail!(
+                    "Discard NAS message that is not integrity protected {:?}",
+                    nas
+                )
             }
+        } else if !protected {
+            bail!("Discard unprotected NAS message {:?}", nas)
         }
+        Ok(nas)
     }
-    pub fn enable_security(&mut self, knasint: [u8; 16]) {
-        self.security_context = Some(SecurityContext::new(knasint));
+
+    pub fn enable_security(
+        &mut self,
+        kamf: [u8; 32],
+        integrity_algorithm: NasIntegrityAlgorithm,
+        ciphering_algorithm: NasCipheringAlgorithm,
+    ) {
+        self.security_context = Some(SecurityContext::new(
+            kamf,
+            integrity_algorithm,
+            ciphering_algorithm,
+        ));
+        self.secure_exchange_established = false;
+    }
+
+    pub fn kamf(&self) -> Option<&[u8; 32]> {
+        self.security_context.as_ref().map(|x| x.kamf())
+    }
+
+    /// Check the integrity of a security protected uplink message against this context,
+    /// returning the message's uplink NAS COUNT.
+    pub fn check_integrity(&mut self, data: &[u8]) -> Result<u32> {
+        let Some(security_context) = &mut self.security_context else {
+            bail!("No NAS security context")
+        };
+        let count = security_context.check_uplink_integrity(data)?;
+        self.secure_exchange_established = true;
+        Ok(count)
+    }
+
+    /// Decipher and decode the NAS message container of an initial NAS message that has passed the integrity
+    /// check of this context, given the initial NAS message's uplink NAS COUNT.
+    pub fn decode_initial_nas_container(
+        &self,
+        container: &[u8],
+        count: u32,
+    ) -> Result<Nas5gsMessage> {
+        let Some(security_context) = &self.security_context else {
+            bail!("No NAS security context")
+        };
+        let mut data = container.to_vec();
+        security_context.decipher_uplink_container(&mut data, count);
+        decode_nas_5gs_message(&data).map_err(|e| {
+            anyhow!(
+                "NAS message container decode error - {e} - bytes: {:?}",
+                data
+            )
+        })
     }
 
     pub fn encode(&mut self, nas: Nas5gsMessage) -> Result<Vec<u8>> {
@@ -35,3 +105,53 @@ impl NasContext {
         Ok(nas)
     }
 }
+
+fn is_security_protected(data: &[u8]) -> bool {
+    // TS24.501, 9.3.1: the security header type is the low nibble of the second byte of a 5GMM message.
+    const FIVEGMM_EPD: u8 = 0x7e;
+    data.len() > 1 && data[0] == FIVEGMM_EPD && data[1] & 0x0f != 0
+}
+
+// TS24.501, 4.4.4.3: the messages that the AMF processes without integrity protection.
+fn allowed_without_integrity_protection(nas: &Nas5gsMessage) -> bool {
+    matches!(
+        nas,
+        Nas5gsMessage::Gmm(
+            _,
+            Nas5gmmMessage::RegistrationRequest(_)
+                | Nas5gmmMessage::IdentityResponse(_)
+                | Nas5gmmMessage::AuthenticationResponse(_)
+                | Nas5gmmMessage::AuthenticationFailure(_)
+                | Nas5gmmMessage::SecurityModeReject(_)
+                | Nas5gmmMessage::DeregistrationRequestFromUe(_)
+                | Nas5gmmMessage::DeregistrationAcceptToUe(_)
+                | Nas5gmmMessage::ServiceRequest(_)
+                | Nas5gmmMessage::ControlPlaneServiceRequest(_)
+        )
+    )
+}
+
+// OAI UE sends a security protected deregistration request where the inner
+// message has security header type 0x0100 - INTEGRITY_PROTECTED_AND_CIPHERED_WITH_NEW_SECU_CTX -
+// but no security header.
+// Wireshark parses this OK, but our Oxirush NAS decoder doesn't.
+// Current hypothesis is that OAI is getting it wrong, and Wireshark is tolerating it because
+// it calculates inner messsage offsets assuming that it cannot have a security header.
+//
+// For now, we have this hack to patch the message to pacify the NAS decoder.
+fn patch_nas_for_oai_deregistration_security_header(mut nas_bytes: Cow<'_, [u8]>) -> Cow<'_, [u8]> {
+    const INNER_SECURITY_HEADER_TYPE_OFFSET: usize = 8;
+    if nas_bytes.len() < (INNER_SECURITY_HEADER_TYPE_OFFSET + 1) {
+        return nas_bytes;
+    }
+
+    // Security protected MM message.
+    // The inner message header starts at byte 7, and its security header type is at byte 8.
+    if nas_bytes[0] == 0x7e
+        && nas_bytes[1] == 0x02
+        && nas_bytes[INNER_SECURITY_HEADER_TYPE_OFFSET] != 0x00
+    {
+        nas_bytes.to_mut()[INNER_SECURITY_HEADER_TYPE_OFFSET] = 0x00;
+    }
+    nas_bytes
+}
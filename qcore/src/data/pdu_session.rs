@@ -1,10 +1,242 @@
-use crate::UserplaneSession;
-use xxap::Snssai;
+use crate::userplane::DrbTunnel;
+use crate::{
+    DEFAULT_QOS_RULE_ID, PduSessionModification, QosFlow, QosRule, SessionAmbr, UserplaneSession,
+};
+use anyhow::{Result, bail, ensure};
+use asn1_per::NonEmpty;
+use xxap::{GtpTunnel, Snssai};
 
 #[derive(Debug)]
 pub struct PduSession {
     pub id: u8,
     pub snssai: Snssai,
-    pub dnn: Vec<u8>,
+    pub dnn: String,
     pub userplane_info: UserplaneSession,
+    pub session_ambr: SessionAmbr,
+    pub qos_rules: Vec<QosRule>,
+    pub qos_flows: Vec<QosFlow>,
+
+    // The session's DRBs, starting with its default DRB, which carries the default QoS flow.
+    pub drbs: Vec<Drb>,
+}
+
+/// A DRB and the QoS flows mapped to it.  At PDU session establishment, QoS flows with the same 5QI share a DRB.
+/// QoS flows added later by a PDU session modification go on the default DRB, and a DRB whose QoS flows are all
+/// deleted is released.
+#[derive(Debug, Clone)]
+pub struct Drb {
+    pub id: u8,
+    pub qfis: Vec<u8>,
+}
+
+impl Drb {
+    /// Map QoS flows to DRBs, one for each 5QI, using the given DRB IDs.  The first QoS flow is the default QoS
+    /// flow.  Fails if there are not enough DRB IDs.
+    pub fn map_qos_flows(qos_flows: &[QosFlow], drb_ids: &[u8]) -> Result<Vec<Drb>> {
+        let mut drbs: Vec<(u8, Drb)> = vec![];
+        for flow in qos_flows {
+            if let Some((_, drb)) = drbs.iter_mut().find(|(x, _)| *x == flow.five_qi) {
+                drb.qfis.push(flow.qfi);
+                continue;
+            }
+            let Some(id) = drb_ids.get(drbs.len()) else {
+                bail!("Not enough DRBs for QoS flows {qos_flows:?}");
+            };
+            drbs.push((
+                flow.five_qi,
+                Drb {
+                    id: *id,
+                    qfis: vec![flow.qfi],
+                },
+            ));
+        }
+        Ok(drbs.into_iter().map(|(_, drb)| drb).collect())
+    }
+
+    /// Which of the given QoS flows are mapped to this DRB.
+    pub fn qos_flows(&self, qos_flows: &[QosFlow]) -> Vec<QosFlow> {
+        qos_flows
+            .iter()
+            .filter(|flow| self.qfis.contains(&flow.qfi))
+            .copied()
+            .collect()
+    }
+}
+
+/// A change to the QoS flows mapped to one of a session's DRBs.
+#[derive(Debug, Clone)]
+pub struct DrbModification {
+    pub drb: Drb,
+    pub qfis_to_add: Vec<u8>,
+    pub qfis_to_release: Vec<u8>,
+}
+
+/// The QoS of a PDU session as it will be after a modification.  It is only applied to the session once the DU and
+/// the UE have both accepted it.
+#[derive(Debug, Clone)]
+pub struct SessionQos {
+    pub session_ambr: SessionAmbr,
+    pub qos_rules: Vec<QosRule>,
+    pub qos_flows: Vec<QosFlow>,
+    pub drbs: Vec<Drb>,
+}
+
+impl SessionQos {
+    /// The session's default DRB.
+    pub fn default_drb(&self) -> &Drb {
+        &self.drbs[0]
+    }
+
+    // Deleted QoS flows come off their DRB, and new ones go on the default DRB.  A DRB left with no QoS flows is
+    // dropped.  This is never the default DRB, because the default QoS rule, and so the default QoS flow, can't be
+    // deleted.
+    fn remap_qos_flows(&mut self) {
+        for drb in self.drbs.iter_mut() {
+            drb.qfis
+                .retain(|qfi| self.qos_flows.iter().any(|x| x.qfi == *qfi));
+        }
+        for flow in &self.qos_flows {
+            if !self.drbs.iter().any(|drb| drb.qfis.contains(&flow.qfi)) {
+                self.drbs[0].qfis.push(flow.qfi);
+            }
+        }
+        self.drbs.retain(|drb| !drb.qfis.is_empty());
+    }
+}
+
+impl PduSession {
+    /// Work out the QoS that this session would have after a modification.
+    pub fn modified_qos(&self, modification: &PduSessionModification) -> Result<SessionQos> {
+        let mut qos_rules = self.qos_rules.clone();
+        for id in &modification.delete_qos_rules {
+            ensure!(
+                *id != DEFAULT_QOS_RULE_ID,
+                "Default QoS rule can't be deleted"
+            );
+            let count = qos_rules.len();
+            qos_rules.retain(|x| x.id != *id);
+            ensure!(qos_rules.len() < count, "No QoS rule with id {id}");
+        }
+        for rule in &modification.add_qos_rules {
+            ensure!(rule.id != 0, "QoS rule id 0 is reserved");
+            ensure!(
+                !qos_rules.iter().any(|x| x.id == rule.id),
+                "QoS rule {} already exists",
+                rule.id
+            );
+            ensure!(rule.qfi > 0 && rule.qfi < 64, "Invalid QFI {}", rule.qfi);
+            ensure!(
+                !rule.packet_filters.is_empty(),
+                "QoS rule {} has no packet filters",
+                rule.id
+            );
+            qos_rules.push(rule.clone());
+        }
+
+        // Keep the QoS flows that are still used by a QoS rule, and add any new ones.
+        let mut qos_flows: Vec<QosFlow> = self
+            .qos_flows
+            .iter()
+            .filter(|flow| qos_rules.iter().any(|x| x.qfi == flow.qfi))
+            .copied()
+            .collect();
+        for rule in &qos_rules {
+            if !qos_flows.iter().any(|x| x.qfi == rule.qfi) {
+                qos_flows.push(
+                    modification
+                        .add_qos_flows
+                        .iter()
+                        .find(|x| x.qfi == rule.qfi)
+                        .copied()
+                        .unwrap_or(QosFlow::new(rule.qfi)),
+                );
+            }
+        }
+
+        let mut qos = SessionQos {
+            session_ambr: modification.session_ambr.unwrap_or(self.session_ambr),
+            qos_rules,
+            qos_flows,
+            drbs: self.drbs.clone(),
+        };
+        qos.remap_qos_flows();
+        Ok(qos)
+    }
+
+    /// Work out which of the session's DRBs need to change to give it the given QoS.  Returns the DRBs to modify,
+    /// starting with the default DRB, which is always included so that the DU and UE hear about any change, and the
+    /// IDs of the DRBs that have lost all their QoS flows and need to be released.
+    pub fn drb_modifications(&self, qos: &SessionQos) -> (NonEmpty<DrbModification>, Vec<u8>) {
+        let ambr_changed = qos.session_ambr != self.session_ambr;
+        let modification = |drb: &Drb| {
+            let old_qfis = self
+                .drbs
+                .iter()
+                .find(|x| x.id == drb.id)
+                .map(|x| x.qfis.as_slice())
+                .unwrap_or_default();
+            DrbModification {
+                drb: drb.clone(),
+                qfis_to_add: drb
+                    .qfis
+                    .iter()
+                    .filter(|qfi| !old_qfis.contains(qfi))
+                    .copied()
+                    .collect(),
+                qfis_to_release: old_qfis
+                    .iter()
+                    .filter(|qfi| !drb.qfis.contains(qfi))
+                    .copied()
+                    .collect(),
+            }
+        };
+
+        // The session AMBR is part of the QoS of every DRB.
+        let mut modified = NonEmpty::new(modification(qos.default_drb()));
+        for drb in &qos.drbs[1..] {
+            let x = modification(drb);
+            if ambr_changed || !x.qfis_to_add.is_empty() || !x.qfis_to_release.is_empty() {
+                modified.push(x);
+            }
+        }
+        let released = self
+            .drbs
+            .iter()
+            .filter(|drb| !qos.drbs.iter().any(|x| x.id == drb.id))
+            .map(|drb| drb.id)
+            .collect();
+        (modified, released)
+    }
+
+    /// Apply a modification that the DU and UE have accepted, including to the userplane.
+    pub fn set_qos(&mut self, qos: SessionQos) {
+        self.userplane_info.set_qos_rules(&qos.qos_rules);
+        if qos.session_ambr != self.session_ambr {
+            self.userplane_info.set_session_ambr(&qos.session_ambr);
+        }
+        self.session_ambr = qos.session_ambr;
+        self.qos_rules = qos.qos_rules;
+        self.qos_flows = qos.qos_flows;
+        self.drbs = qos.drbs;
+    }
+
+    /// Match up the session's DRBs with the downlink tunnels that the DU set up for them, given as (DRB ID, tunnel)
+    /// pairs.
+    pub fn drb_tunnels(&self, remote_tunnels: &[(u8, GtpTunnel)]) -> Result<Vec<DrbTunnel>> {
+        self.drbs
+            .iter()
+            .map(|drb| {
+                let Some((_, remote_tunnel_info)) =
+                    remote_tunnels.iter().find(|(id, _)| *id == drb.id)
+                else {
+                    bail!("DU did not set up DRB {}", drb.id);
+                };
+                Ok(DrbTunnel {
+                    drb_id: drb.id,
+                    qfis: drb.qfis.clone(),
+                    remote_tunnel_info: remote_tunnel_info.clone(),
+                })
+            })
+            .collect()
+    }
 }
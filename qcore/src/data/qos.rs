@@ -0,0 +1,289 @@
+//! qos - QoS rules, QoS flows and session AMBR of a PDU session, the UE AMBR, and the QoS flow templates of a
+//! data network
+use anyhow::{Result, ensure};
+use serde::Deserialize;
+use std::net::Ipv4Addr;
+
+/// QoS rule identifier of the default QoS rule that QCore creates at PDU session establishment.
+pub const DEFAULT_QOS_RULE_ID: u8 = 1;
+
+/// QFI of the QoS flow used by the default QoS rule.
+pub const DEFAULT_QFI: u8 = 1;
+
+// 5QI of the default QoS flow, and of QoS flows created by a PDU session modification - TS23.501, table 5.7.4-1.
+const DEFAULT_5QI: u8 = 82;
+const NEW_FLOW_5QI: u8 = 9;
+
+// Allocation and retention priority level of QoS flows that don't come from a template - TS23.501, 5.7.2.2.
+const DEFAULT_ARP_PRIORITY_LEVEL: u8 = 14;
+
+// TS24.501, table 9.11.4.13.1 - packet filter directions and packet filter component types.
+pub const PACKET_FILTER_DOWNLINK: u8 = 0b01;
+pub const PACKET_FILTER_UPLINK: u8 = 0b10;
+pub const PACKET_FILTER_BIDIRECTIONAL: u8 = 0b11;
+pub const MATCH_ALL: u8 = 0b00000001;
+pub const IPV4_REMOTE_ADDRESS: u8 = 0b00010000;
+pub const IPV4_LOCAL_ADDRESS: u8 = 0b00010001;
+pub const IPV6_REMOTE_ADDRESS_PREFIX_LENGTH: u8 = 0b00100001;
+pub const IPV6_LOCAL_ADDRESS_PREFIX_LENGTH: u8 = 0b00100011;
+pub const PROTOCOL_IDENTIFIER_NEXT_HEADER: u8 = 0b00110000;
+pub const SINGLE_LOCAL_PORT: u8 = 0b01000000;
+pub const LOCAL_PORT_RANGE: u8 = 0b01000001;
+pub const SINGLE_REMOTE_PORT: u8 = 0b01010000;
+pub const REMOTE_PORT_RANGE: u8 = 0b01010001;
+
+/// Session aggregate maximum bit rate, enforced across all of a PDU session's traffic.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct SessionAmbr {
+    pub downlink_kbps: u32,
+    pub uplink_kbps: u32,
+}
+
+impl Default for SessionAmbr {
+    // For SIMs with no session AMBR in their subscription data.  High enough not to get in the way.
+    fn default() -> Self {
+        SessionAmbr {
+            downlink_kbps: 1_000_000,
+            uplink_kbps: 1_000_000,
+        }
+    }
+}
+
+/// UE aggregate maximum bit rate, enforced across all of a UE's PDU sessions.
+#[derive(Debug, Clone, Copy, PartialEq, Eq, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct UeAmbr {
+    pub downlink_kbps: u32,
+    pub uplink_kbps: u32,
+}
+
+/// A QoS rule, which tells the UE which QoS flow to use for an uplink packet - see TS24.501, 9.11.4.13.
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct QosRule {
+    pub id: u8,
+    pub precedence: u8,
+    pub qfi: u8,
+    pub packet_filters: Vec<PacketFilter>,
+}
+
+impl QosRule {
+    /// The default QoS rule, which matches all packets.
+    pub fn default_rule() -> Self {
+        QosRule {
+            id: DEFAULT_QOS_RULE_ID,
+            precedence: 0xff,
+            qfi: DEFAULT_QFI,
+            packet_filters: vec![PacketFilter {
+                id: 0b1111,
+                direction: PACKET_FILTER_BIDIRECTIONAL,
+                contents: vec![MATCH_ALL],
+            }],
+        }
+    }
+}
+
+#[derive(Debug, Clone, PartialEq, Eq)]
+pub struct PacketFilter {
+    pub id: u8,
+    /// 0b01 = downlink only, 0b10 = uplink only, 0b11 = bidirectional.
+    pub direction: u8,
+    /// Packet filter components, encoded as in TS24.501, table 9.11.4.13.1.
+    pub contents: Vec<u8>,
+}
+
+/// A QoS flow of a PDU session, and the QoS that it gets from the DU.
+#[derive(Debug, Clone, Copy, PartialEq, Eq)]
+pub struct QosFlow {
+    pub qfi: u8,
+    pub five_qi: u8,
+    pub arp_priority_level: u8,
+}
+
+impl QosFlow {
+    pub fn default_flow() -> Self {
+        QosFlow {
+            qfi: DEFAULT_QFI,
+            five_qi: DEFAULT_5QI,
+            arp_priority_level: DEFAULT_ARP_PRIORITY_LEVEL,
+        }
+    }
+
+    pub fn new(qfi: u8) -> Self {
+        QosFlow {
+            qfi,
+            five_qi: NEW_FLOW_5QI,
+            arp_priority_level: DEFAULT_ARP_PRIORITY_LEVEL,
+        }
+    }
+}
+
+/// A QoS flow that every IP PDU session on a data network gets alongside its default QoS flow, along with the
+/// packet filters of the QoS rule that classifies traffic onto it.  For example, voice or telemetry traffic can be
+/// given its own 5QI, and so its own DRB.
+#[derive(Debug, Clone, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct QosFlowTemplate {
+    /// 5QI of the QoS flow - TS23.501, table 5.7.4-1.
+    pub five_qi: u8,
+
+    /// Allocation and retention priority level, from 1 (highest) to 15.  Defaults to 14.
+    pub arp_priority_level: Option<u8>,
+
+    /// Packet filters, any of which puts a packet onto the QoS flow.
+    pub packet_filters: Vec<PacketFilterTemplate>,
+}
+
+/// A packet filter of a QoS flow template.  A packet matches if it matches all of the supplied fields.  Local
+/// means the UE's end of the traffic, and remote means the data network's.
+#[derive(Debug, Clone, Default, Deserialize)]
+#[serde(deny_unknown_fields)]
+pub struct PacketFilterTemplate {
+    #[serde(default)]
+    pub direction: PacketFilterDirection,
+    pub remote_ipv4_addr: Option<Ipv4Addr>,
+    /// Defaults to 255.255.255.255 when remote_ipv4_addr is supplied.
+    pub remote_ipv4_mask: Option<Ipv4Addr>,
+    /// IP protocol number, for example 17 for UDP.
+    pub protocol: Option<u8>,
+    pub local_port: Option<u16>,
+    pub local_port_range: Option<[u16; 2]>,
+    pub remote_port: Option<u16>,
+    pub remote_port_range: Option<[u16; 2]>,
+}
+
+#[derive(Debug, Clone, Copy, Default, PartialEq, Eq, Deserialize)]
+#[serde(rename_all = "lowercase")]
+pub enum PacketFilterDirection {
+    Uplink,
+    Downlink,
+    #[default]
+    Bidirectional,
+}
+
+impl QosFlowTemplate {
+    pub fn check(&self) -> Result<()> {
+        ensure!(self.five_qi > 0, "5QI 0 is reserved");
+        ensure!(
+            self.arp_priority_level
+                .is_none_or(|x| (1..=15).contains(&x)),
+            "ARP priority level must be from 1 to 15"
+        );
+        ensure!(
+            !self.packet_filters.is_empty() && self.packet_filters.len() <= 15,
+            "QoS flow template must have from 1 to 15 packet filters"
+        );
+        for filter in &self.packet_filters {
+            filter.check()?;
+        }
+        Ok(())
+    }
+
+    pub fn qos_flow(&self, qfi: u8) -> QosFlow {
+        QosFlow {
+            qfi,
+            five_qi: self.five_qi,
+            arp_priority_level: self
+                .arp_priority_level
+                .unwrap_or(DEFAULT_ARP_PRIORITY_LEVEL),
+        }
+    }
+
+    pub fn qos_rule(&self, id: u8, precedence: u8, qfi: u8) -> QosRule {
+        QosRule {
+            id,
+            precedence,
+            qfi,
+            packet_filters: self
+                .packet_filters
+                .iter()
+                .zip(1..)
+                .map(|(filter, filter_id)| filter.packet_filter(filter_id))
+                .collect(),
+        }
+    }
+}
+
+impl PacketFilterTemplate {
+    pub(crate) fn check(&self) -> Result<()> {
+        ensure!(
+            self.local_port.is_none() || self.local_port_range.is_none(),
+            "Packet filter has both a local port and a local port range"
+        );
+        ensure!(
+            self.remote_port.is_none() || self.remote_port_range.is_none(),
+            "Packet filter has both a remote port and a remote port range"
+        );
+        ensure!(
+            self.remote_ipv4_addr.is_some() || self.remote_ipv4_mask.is_none(),
+            "Packet filter has a remote IPv4 mask but no remote IPv4 address"
+        );
+        for [low, high] in [self.local_port_range, self.remote_port_range]
+            .into_iter()
+            .flatten()
+        {
+            ensure!(low <= high, "Bad port range {low}-{high}");
+        }
+        Ok(())
+    }
+
+    // Encode the packet filter components - TS24.501, table 9.11.4.13.1.
+    pub(crate) fn packet_filter(&self, id: u8) -> PacketFilter {
+        let mut contents = vec![];
+        if let Some(addr) = self.remote_ipv4_addr {
+            contents.push(IPV4_REMOTE_ADDRESS);
+            contents.extend_from_slice(&addr.octets());
+            let mask = self.remote_ipv4_mask.unwrap_or(Ipv4Addr::BROADCAST);
+            contents.extend_from_slice(&mask.octets());
+        }
+        if let Some(protocol) = self.protocol {
+            contents.extend_from_slice(&[PROTOCOL_IDENTIFIER_NEXT_HEADER, protocol]);
+        }
+        if let Some(port) = self.local_port {
+            contents.push(SINGLE_LOCAL_PORT);
+            contents.extend_from_slice(&port.to_be_bytes());
+        }
+        if let Some([low, high]) = self.local_port_range {
+            contents.push(LOCAL_PORT_RANGE);
+            contents.extend_from_slice(&low.to_be_bytes());
+            contents.extend_from_slice(&high.to_be_bytes());
+        }
+        if let Some(port) = self.remote_port {
+            contents.push(SINGLE_REMOTE_PORT);
+            contents.extend_from_slice(&port.to_be_bytes());
+        }
+        if let Some([low, high]) = self.remote_port_range {
+            contents.push(REMOTE_PORT_RANGE);
+            contents.extend_from_slice(&low.to_be_bytes());
+            contents.extend_from_slice(&high.to_be_bytes());
+        }
+        if contents.is_empty() {
+            contents.push(MATCH_ALL);
+        }
+        PacketFilter {
+            id,
+            direction: match self.direction {
+                PacketFilterDirection::Uplink => PACKET_FILTER_UPLINK,
+                PacketFilterDirection::Downlink => PACKET_FILTER_DOWNLINK,
+                PacketFilterDirection::Bidirectional => PACKET_FILTER_BIDIRECTIONAL,
+            },
+            contents,
+        }
+    }
+}
+
+/// A change to the QoS of a PDU session.
+#[derive(Debug, Clone, Default)]
+pub struct PduSessionModification {
+    /// The new session AMBR, if it is changing.
+    pub session_ambr: Option<SessionAmbr>,
+
+    /// QoS rules to create.  A QoS rule with a QFI that is not already in use creates a new QoS flow.
+    pub add_qos_rules: Vec<QosRule>,
+
+    /// The 5QI and ARP priority level of the new QoS flows.  A new QoS flow that isn't listed here gets 5QI 9.
+    pub add_qos_flows: Vec<QosFlow>,
+
+    /// Identifiers of QoS rules to delete.  QoS flows that are left without a QoS rule are deleted too.
+    pub delete_qos_rules: Vec<u8>,
+}
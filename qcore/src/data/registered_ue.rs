@@ -0,0 +1,49 @@
+use super::nas_context::NasContext;
+use crate::PduSession;
+use f1ap::NrCgi;
+use std::time::Instant;
+use xxap::Snssai;
+
+/// The state that QCore keeps for a registered UE in CM-IDLE, once its UE context has been
+/// released, so that the UE can later send a Service Request or re-register using its 5G-GUTI.
+#[derive(Debug)]
+pub struct RegisteredUe {
+    pub imsi: String,
+    pub nas: NasContext,
+    pub allowed_nssai: Vec<Snssai>,
+    pub pei: Option<String>,
+
+    // PDU sessions whose userplane state we keep, but which have no DRB, while the UE is idle.
+    pub pdu_sessions: Vec<PduSession>,
+
+    // The cell that the UE was last connected to, whose DU we page the UE through.
+    pub nr_cgi: NrCgi,
+
+    // When the UE context was released, which is when the mobile reachable timer starts.
+    pub released_at: Instant,
+
+    // Whether we are paging the UE because downlink data has arrived for it.
+    pub paging: bool,
+}
+
+impl RegisteredUe {
+    pub fn new(
+        imsi: String,
+        nas: NasContext,
+        allowed_nssai: Vec<Snssai>,
+        pei: Option<String>,
+        pdu_sessions: Vec<PduSession>,
+        nr_cgi: NrCgi,
+    ) -> Self {
+        RegisteredUe {
+            imsi,
+            nas,
+            allowed_nssai,
+            pei,
+            pdu_sessions,
+            nr_cgi,
+            released_at: Instant::now(),
+            paging: false,
+        }
+    }
+}
@@ -1,21 +1,59 @@
-use anyhow::Result;
+use super::nas_algorithms::{NasCipheringAlgorithm, NasIntegrityAlgorithm};
+use anyhow::{Result, ensure};
 use oxirush_nas::{Nas5gsMessage, Nas5gsSecurityHeaderType, encode_nas_5gs_message};
-use security::nia2::calculate_nia2_mac;
+use security::{nea2::apply_nea2_keystream, nia2::calculate_nia2_mac};
+
+// TS33.501, 6.4.3.1
+// The BEARER input shall be equal to the NAS connection identifier.
+const BEARER: u8 = 1;
+
+// The DIRECTION bit shall be set to 0 for uplink and 1 for downlink.
+const DIRECTION_UL: u8 = 0b0;
+const DIRECTION_DL: u8 = 0b1;
+
+// TS24.501, 9.1.1: a security protected message has a 7 byte header - EPD, security header type,
+// 4 byte MAC and sequence number.
+const SECURITY_HEADER_LEN: usize = 7;
+const MAC_OFFSET: usize = 2;
+const SEQUENCE_NUMBER_OFFSET: usize = 6;
 
 // TODO - should this really be cloneable
 #[derive(Clone, Debug)]
 pub struct SecurityContext {
+    kamf: [u8; 32],
     ik: [u8; 16],
+    ciphering_algorithm: NasCipheringAlgorithm,
+    ck: [u8; 16],
     dl_count: u32,
+    ul_count: u32,
 }
 
 impl SecurityContext {
-    pub fn new(ik: [u8; 16]) -> Self {
-        SecurityContext { ik, dl_count: 0 }
+    pub fn new(
+        kamf: [u8; 32],
+        integrity_algorithm: NasIntegrityAlgorithm,
+        ciphering_algorithm: NasCipheringAlgorithm,
+    ) -> Self {
+        let ik = security::derive_knasint(&kamf, integrity_algorithm as u8);
+        let ck = security::derive_knasenc(&kamf, ciphering_algorithm as u8);
+        SecurityContext {
+            kamf,
+            ik,
+            ciphering_algorithm,
+            ck,
+            dl_count: 0,
+            ul_count: 0,
+        }
+    }
+
+    pub fn kamf(&self) -> &[u8; 32] {
+        &self.kamf
     }
 
     pub fn encode_with_integrity(&mut self, nas: Nas5gsMessage) -> Result<Vec<u8>> {
-        let security_header_type = if self.dl_count == 0 {
+        // The first message (the Security Mode Command) is not ciphered.
+        let ciphered = self.dl_count != 0;
+        let security_header_type = if !ciphered {
             Nas5gsSecurityHeaderType::IntegrityProtectedWithNewContext
         } else {
             Nas5gsSecurityHeaderType::IntegrityProtectedAndCiphered
@@ -25,29 +63,88 @@ impl SecurityContext {
             Nas5gsMessage::protect(nas, security_header_type, 0, (self.dl_count & 0xff) as u8);
         let mut nas_bytes = encode_nas_5gs_message(&nas)?;
 
+        if ciphered {
+            self.apply_keystream(
+                &mut nas_bytes[SECURITY_HEADER_LEN..],
+                self.dl_count,
+                DIRECTION_DL,
+            );
+        }
+
         // Run the MAC calculation over the inner message, which starts at byte 6.
+        let mac = calculate_nia2_mac(
+            &self.ik,
+            self.dl_count.to_be_bytes(),
+            BEARER,
+            DIRECTION_DL,
+            &nas_bytes[SEQUENCE_NUMBER_OFFSET..],
+        );
+        nas_bytes[MAC_OFFSET..MAC_OFFSET + 4].copy_from_slice(&mac);
 
-        // TS33.501, 6.4.3.1
-        // The BEARER input shall be equal to the NAS connection identifier.
-        let bearer = 1;
+        self.dl_count = (self.dl_count + 1) & 0xffffff;
+        Ok(nas_bytes)
+    }
 
-        // The DIRECTION bit shall be set to 0 for uplink and 1 for downlink.
-        let direction = 0b1;
+    /// Check the MAC of a security protected uplink message, returning its uplink NAS COUNT.
+    pub fn check_uplink_integrity(&mut self, nas_bytes: &[u8]) -> Result<u32> {
+        ensure!(
+            nas_bytes.len() > SECURITY_HEADER_LEN,
+            "Security protected NAS message too short"
+        );
+        ensure!(
+            nas_bytes[1] & 0x0f != 0,
+            "NAS message is not security protected"
+        );
+
+        // TS24.501, 4.4.3.1: the receiver estimates the NAS overflow counter from the 8-bit sequence number.
+        let sequence_number = nas_bytes[SEQUENCE_NUMBER_OFFSET] as u32;
+        let mut count = (self.ul_count & 0xffff00) | sequence_number;
+        if count < self.ul_count {
+            count = (count + 0x100) & 0xffffff;
+        }
 
         let mac = calculate_nia2_mac(
             &self.ik,
-            self.dl_count.to_be_bytes(),
-            bearer,
-            direction,
-            &nas_bytes[6..],
+            count.to_be_bytes(),
+            BEARER,
+            DIRECTION_UL,
+            &nas_bytes[SEQUENCE_NUMBER_OFFSET..],
+        );
+        ensure!(
+            mac == nas_bytes[MAC_OFFSET..MAC_OFFSET + 4],
+            "NAS MAC check failed for uplink NAS COUNT {count}"
         );
 
-        nas_bytes[2] = mac[0];
-        nas_bytes[3] = mac[1];
-        nas_bytes[4] = mac[2];
-        nas_bytes[5] = mac[3];
+        self.ul_count = (count + 1) & 0xffffff;
+        Ok(count)
+    }
+
+    /// Decipher a security protected uplink message in place, given the uplink NAS COUNT returned by
+    /// check_uplink_integrity().
+    pub fn decipher_uplink(&self, nas_bytes: &mut [u8], count: u32) {
+        // TS24.501, table 9.3.1 - security header types 2 and 4 are ciphered.
+        const INTEGRITY_PROTECTED_AND_CIPHERED: u8 = 0b0010;
+        const INTEGRITY_PROTECTED_AND_CIPHERED_WITH_NEW_CONTEXT: u8 = 0b0100;
+        if let INTEGRITY_PROTECTED_AND_CIPHERED
+        | INTEGRITY_PROTECTED_AND_CIPHERED_WITH_NEW_CONTEXT = nas_bytes[1] & 0x0f
+        {
+            self.apply_keystream(&mut nas_bytes[SECURITY_HEADER_LEN..], count, DIRECTION_UL);
+        }
+    }
 
-        self.dl_count = (self.dl_count + 1) & 0xffffff;
-        Ok(nas_bytes)
+    // TS24.501, 4.4.6: the NAS message container of an initial NAS message is ciphered using the NAS COUNT of the
+    // initial NAS message, even though the initial NAS message itself is sent unciphered.
+    pub fn decipher_uplink_container(&self, container: &mut [u8], count: u32) {
+        self.apply_keystream(container, count, DIRECTION_UL);
+    }
+
+    // Ciphering and deciphering are the same operation.
+    fn apply_keystream(&self, message: &mut [u8], count: u32, direction: u8) {
+        match self.ciphering_algorithm {
+            NasCipheringAlgorithm::Nea0 => {}
+            NasCipheringAlgorithm::Nea2 => {
+                apply_nea2_keystream(&self.ck, count.to_be_bytes(), BEARER, direction, message)
+            }
+        }
     }
 }
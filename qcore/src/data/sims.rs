@@ -1,9 +1,14 @@
-use anyhow::{Result, bail};
+use super::data_networks::DataNetworkConfig;
+use super::qos::{SessionAmbr, UeAmbr};
+use crate::userplane::MAX_SESSIONS_PER_DATA_NETWORK;
+use anyhow::{Result, bail, ensure};
 use derive_deref::Deref;
-use serde::Deserialize;
+use serde::{Deserialize, Deserializer};
 use slog::{Logger, error, info};
 use std::collections::HashMap;
 use std::fs;
+use std::net::Ipv4Addr;
+use xxap::Snssai;
 
 #[derive(Deserialize, Debug)]
 pub struct SimCreds {
@@ -11,6 +16,37 @@ pub struct SimCreds {
     pub ki: [u8; 16],
     #[serde(with = "hex")]
     pub opc: [u8; 16],
+    /// Network slices that the SIM is subscribed to.  If none are listed, it is subscribed to all of
+    /// QCore's slices.
+    #[serde(default, deserialize_with = "deserialize_snssais")]
+    pub snssais: Vec<Snssai>,
+    /// Fixed IPv4 addresses of the SIM, by DNN.  On other data networks, it gets a dynamic address.
+    #[serde(default)]
+    pub static_ipv4_addrs: HashMap<String, Ipv4Addr>,
+    /// Session AMBR of each of the SIM's PDU sessions.  If not supplied, a default of 1 Gbps applies.
+    pub session_ambr: Option<SessionAmbr>,
+    /// UE AMBR, shared by all of the SIM's PDU sessions.  If not supplied, there is no limit beyond the session
+    /// AMBRs.
+    pub ue_ambr: Option<UeAmbr>,
+}
+
+impl SimCreds {
+    /// The SIM's fixed IPv4 address on the given data network, if it has one.
+    pub fn static_ipv4_addr(&self, dnn: &str) -> Option<Ipv4Addr> {
+        self.static_ipv4_addrs
+            .iter()
+            .find(|(x, _)| x.eq_ignore_ascii_case(dnn))
+            .map(|(_, addr)| *addr)
+    }
+}
+
+fn deserialize_snssais<'de, D: Deserializer<'de>>(
+    deserializer: D,
+) -> Result<Vec<Snssai>, D::Error> {
+    Vec::<String>::deserialize(deserializer)?
+        .iter()
+        .map(|x| x.parse().map_err(serde::de::Error::custom))
+        .collect()
 }
 
 #[derive(Deref)]
@@ -39,3 +75,32 @@ pub fn load_sims_file(filename: &str, logger: &Logger) -> Result<&'static SimTab
     let b = Box::new(SimTable(new_table));
     Ok(Box::leak(b))
 }
+
+/// Check that each static IPv4 address is in the UE subnet of a configured data network, and that no two of them
+/// on the same data network are the same, since an address can only be used by one PDU session at a time.
+pub fn check_static_ipv4_addrs(sims: &SimTable, data_networks: &[DataNetworkConfig]) -> Result<()> {
+    let mut addrs: HashMap<Ipv4Addr, &str> = HashMap::new();
+    for (imsi, sim) in sims.iter() {
+        for (dnn, addr) in &sim.static_ipv4_addrs {
+            let Some(data_network) = data_networks
+                .iter()
+                .find(|x| x.dnn.eq_ignore_ascii_case(dnn))
+            else {
+                bail!("Static IPv4 address {addr} of imsi-{imsi} is for unknown DNN {dnn}");
+            };
+            let host_number = addr.octets()[3];
+            ensure!(
+                addr.octets()[0..3] == data_network.ue_subnet.octets()[0..3]
+                    && (1..MAX_SESSIONS_PER_DATA_NETWORK).contains(&(host_number as usize)),
+                "Static IPv4 address {addr} of imsi-{imsi} is not a UE address in subnet {}/24 of data network {dnn}",
+                data_network.ue_subnet
+            );
+            if let Some(other_imsi) = addrs.insert(*addr, imsi.as_str()) {
+                bail!(
+                    "Static IPv4 address {addr} is given to both imsi-{other_imsi} and imsi-{imsi}"
+                );
+            }
+        }
+    }
+    Ok(())
+}
@@ -0,0 +1,146 @@
+use anyhow::Result;
+use slog::{Logger, info};
+use std::collections::{BTreeMap, HashMap};
+use std::fs;
+use std::sync::Mutex;
+
+// TS33.102, C.1.1: SQN = SEQ || IND, where IND is the 5 least significant bits.
+const IND_LEN: u32 = 5;
+const IND_MASK: u64 = (1 << IND_LEN) - 1;
+const SQN_MASK: u64 = (1 << 48) - 1;
+
+/// Per-SIM authentication sequence numbers (SQN_HE), optionally persisted to a file so that
+/// they survive a restart of QCore.
+pub struct SqnStore {
+    filename: Option<String>,
+    sqns: Mutex<Sqns>,
+    // The generation of the SQNs last written to file.  It is locked for the whole of each write.
+    persisted_generation: async_std::sync::Mutex<u64>,
+}
+
+#[derive(Default)]
+struct Sqns {
+    table: HashMap<String, u64>,
+    // Incremented on each change, so that the file is never overwritten by an older snapshot.
+    generation: u64,
+}
+
+// A copy of the SQNs to write to file, and its generation.
+type Snapshot = (u64, BTreeMap<String, u64>);
+
+impl SqnStore {
+    /// Create a store that is not backed by a file.
+    pub fn in_memory() -> Self {
+        SqnStore {
+            filename: None,
+            sqns: Mutex::new(Sqns::default()),
+            persisted_generation: async_std::sync::Mutex::new(0),
+        }
+    }
+
+    /// Load the SQN store from file, or start afresh if the file does not exist yet.
+    pub fn load(filename: &str, logger: &Logger) -> Result<Self> {
+        let mut sqns = HashMap::new();
+        match fs::read_to_string(filename) {
+            Ok(contents) => {
+                let table: HashMap<String, u64> = toml::from_str(&contents)?;
+                for (key, sqn) in table.into_iter() {
+                    let imsi = key.strip_prefix("imsi-").unwrap_or(&key);
+                    sqns.insert(imsi.to_string(), sqn & SQN_MASK);
+                }
+                info!(logger, "Loaded {} SQNs from {filename}", sqns.len());
+            }
+            Err(e) if e.kind() == std::io::ErrorKind::NotFound => {
+                info!(logger, "SQN file {filename} not found - it will be created");
+            }
+            Err(e) => return Err(e.into()),
+        }
+        Ok(SqnStore {
+            filename: Some(filename.to_string()),
+            sqns: Mutex::new(Sqns {
+                table: sqns,
+                generation: 0,
+            }),
+            persisted_generation: async_std::sync::Mutex::new(0),
+        })
+    }
+
+    /// Allocate a fresh SQN for a new authentication vector for this SIM.  The SQN is on file before this returns,
+    /// so that a restart can't lead to it being used again.
+    pub async fn next_sqn(&self, imsi: &str) -> Result<[u8; 6]> {
+        let (new_sqn, snapshot) = {
+            let mut sqns = self.sqns.lock().unwrap();
+            let sqn = sqns.table.entry(imsi.to_string()).or_insert(0);
+
+            // TS33.102, C.3.2.  Each fresh authentication vector gets the next SEQ, and we step IND through the
+            // array positions so that the USIM's freshness check (C.2.2) accepts it.
+            let seq = (*sqn >> IND_LEN) + 1;
+            let ind = (*sqn + 1) & IND_MASK;
+            *sqn = ((seq << IND_LEN) | ind) & SQN_MASK;
+            let new_sqn = *sqn;
+            (new_sqn, self.snapshot(&mut sqns))
+        };
+        self.persist(snapshot).await?;
+        Ok(sqn_to_bytes(new_sqn))
+    }
+
+    /// Resynchronize our SQN for this SIM with the SQN_MS that the UE reported in AUTS.
+    pub async fn resync(&self, imsi: &str, sqn_ms: &[u8; 6], logger: &Logger) -> Result<()> {
+        let snapshot = {
+            let mut sqns = self.sqns.lock().unwrap();
+
+            // TS33.102, C.3.4: "SEQHE is set to SEQMS".  The next vector generated will use SEQMS + 1.
+            let sqn_ms = sqn_from_bytes(sqn_ms);
+            info!(logger, "Resynchronize SQN of imsi-{imsi} to {sqn_ms:#x}");
+            sqns.table.insert(imsi.to_string(), sqn_ms);
+            self.snapshot(&mut sqns)
+        };
+        self.persist(snapshot).await
+    }
+
+    // Take a copy of the SQNs to write to file, if there is a file.
+    fn snapshot(&self, sqns: &mut Sqns) -> Option<Snapshot> {
+        self.filename.as_ref()?;
+        sqns.generation += 1;
+        let table = sqns
+            .table
+            .iter()
+            .map(|(imsi, sqn)| (format!("imsi-{imsi}"), *sqn))
+            .collect();
+        Some((sqns.generation, table))
+    }
+
+    // Write the SQNs to file on a blocking thread, since this is called from async tasks, and wait for the write
+    // to finish.
+    async fn persist(&self, snapshot: Option<Snapshot>) -> Result<()> {
+        let (Some(filename), Some((generation, table))) = (self.filename.clone(), snapshot) else {
+            return Ok(());
+        };
+        let mut persisted_generation = self.persisted_generation.lock().await;
+        if generation <= *persisted_generation {
+            // A later snapshot has already been written.
+            return Ok(());
+        }
+
+        // Write to a temporary file and rename, so that a crash never leaves a truncated file.
+        async_std::task::spawn_blocking(move || {
+            let tmp_filename = format!("{filename}.tmp");
+            fs::write(&tmp_filename, toml::to_string(&table)?)?;
+            fs::rename(&tmp_filename, &filename)?;
+            anyhow::Ok(())
+        })
+        .await?;
+        *persisted_generation = generation;
+        Ok(())
+    }
+}
+
+fn sqn_to_bytes(sqn: u64) -> [u8; 6] {
+    sqn.to_be_bytes()[2..8].try_into().unwrap()
+}
+
+fn sqn_from_bytes(sqn: &[u8; 6]) -> u64 {
+    let mut bytes = [0u8; 8];
+    bytes[2..8].copy_from_slice(sqn);
+    u64::from_be_bytes(bytes)
+}
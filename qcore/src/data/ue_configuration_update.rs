@@ -0,0 +1,26 @@
+//! ue_configuration_update - changes that the network pushes to a registered UE
+
+use xxap::Snssai;
+
+/// Changes to push to a registered UE with a UE Configuration Update Command - see TS24.501, 5.4.4.
+#[derive(Debug, Clone, Default)]
+pub struct UeConfigurationUpdate {
+    /// Give the UE a new 5G-GUTI.
+    pub reallocate_guti: bool,
+
+    /// Send the network name and, if QCore has a local time zone, the universal time and local time zone (NITZ).
+    pub network_identity_and_time: bool,
+
+    /// Send the current registration area (TAI list).
+    pub tai_list: bool,
+
+    /// A new Allowed NSSAI for the UE.
+    pub allowed_nssai: Option<Vec<Snssai>>,
+}
+
+impl UeConfigurationUpdate {
+    /// TS24.501, 5.4.4.2: the UE has to acknowledge a new 5G-GUTI, TAI list or Allowed NSSAI.
+    pub fn acknowledgement_requested(&self) -> bool {
+        self.reallocate_guti || self.tai_list || self.allowed_nssai.is_some()
+    }
+}
@@ -1,17 +1,32 @@
 use super::nas_context::NasContext;
-use crate::PduSession;
+use crate::{PduSession, UeMessage};
 use f1ap::{GnbDuUeF1apId, NrCgi};
 use pdcp::PdcpTx;
+use std::collections::VecDeque;
+use xxap::Snssai;
 
 #[derive(Debug)]
 pub struct UeContext {
     pub key: u32,
     pub gnb_du_ue_f1ap_id: GnbDuUeF1apId,
     pub tmsi: [u8; 4],
+    pub imsi: Option<String>,
+    pub registered: bool,
+    pub allowed_nssai: Vec<Snssai>,
+
+    // The UE's PEI (IMEISV or IMEI), if it has told us.
+    pub pei: Option<String>,
     pub pdu_sessions: Vec<PduSession>,
+
+    // Whether the DU has a UE context for the UE, set up along with its first DRBs.  It outlives the UE's PDU
+    // sessions, so later DRBs are added to it by UE Context Modification.
+    pub f1_ue_context_established: bool,
     pub pdcp_tx: PdcpTx,
     pub nr_cgi: NrCgi,
     pub nas: NasContext,
+
+    // Messages that arrived while a procedure was waiting for something else, to handle once it finishes.
+    pub deferred_messages: VecDeque<UeMessage>,
 }
 
 impl UeContext {
@@ -20,10 +35,16 @@ impl UeContext {
             key: ue_id,
             gnb_du_ue_f1ap_id,
             tmsi: rand::random(), // TODO: 0xffffffff is not a valid TMSI (TS23.003, 2.4)
+            imsi: None,
+            registered: false,
+            allowed_nssai: vec![],
+            pei: None,
             pdu_sessions: vec![],
+            f1_ue_context_established: false,
             pdcp_tx: PdcpTx::default(),
             nr_cgi,
             nas: NasContext::default(),
+            deferred_messages: VecDeque::new(),
         }
     }
 }
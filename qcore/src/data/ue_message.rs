@@ -0,0 +1,27 @@
+use crate::{PduSessionModification, UeConfigurationUpdate};
+use f1ap::F1apPdu;
+use oxirush_nas::Nas5gsMessage;
+
+/// A message for a UE message handler.
+#[derive(Debug)]
+pub enum UeMessage {
+    F1ap(Box<F1apPdu>),
+
+    /// An uplink NAS message, already deciphered and integrity checked, that arrived while a procedure was
+    /// waiting for a different one.
+    Nas(Box<Nas5gsMessage>),
+
+    /// Operator request to deregister the UE, for example because its SIM has been revoked.
+    Deregister {
+        reregistration_required: bool,
+    },
+
+    /// Operator request to change the QoS of one of the UE's PDU sessions.
+    ModifyPduSession {
+        pdu_session_id: u8,
+        modification: PduSessionModification,
+    },
+
+    /// Operator request to update the UE's configuration.
+    UpdateConfiguration(UeConfigurationUpdate),
+}
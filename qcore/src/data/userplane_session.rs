@@ -1,15 +1,76 @@
-use std::net::IpAddr;
+use crate::userplane::{QosClassifier, RateLimiter};
+use crate::{QosRule, SessionAmbr};
+use std::net::{Ipv4Addr, Ipv6Addr};
 use xxap::GtpTeid;
 
 #[derive(Debug)]
 pub struct UserplaneSession {
     pub qfi: u8,
     pub uplink_gtp_teid: GtpTeid,
-    pub ue_ip_addr: IpAddr,
+
+    // Whether this is an Ethernet PDU session, bridged onto the data network's tap device.
+    pub ethernet: bool,
+
+    // The UE's IPv4 address, for an IPv4 or IPv4v6 session.
+    pub ue_ipv4_addr: Option<Ipv4Addr>,
+
+    // The /64 prefix that we advertise to the UE, for an IPv6 or IPv4v6 session.
+    pub ue_ipv6_prefix: Option<Ipv6Addr>,
+
+    // The interface identifier that the UE uses to build its IPv6 link-local address - see TS23.501, 5.8.2.2.2.
+    pub ue_ipv6_interface_identifier: [u8; 8],
+
+    // Enforcement of the session AMBR and UE AMBR on the session's uplink and downlink traffic.
+    pub uplink_rate_limiter: RateLimiter,
+    pub downlink_rate_limiter: RateLimiter,
+
+    // Classification of the session's downlink packets onto its QoS flows.
+    pub qos_classifier: QosClassifier,
+}
+
+impl UserplaneSession {
+    /// The session's slot in the userplane forwarding tables, which is the last two bytes of its uplink TEID.
+    pub fn index(&self) -> usize {
+        u16::from_be_bytes([self.uplink_gtp_teid.0[2], self.uplink_gtp_teid.0[3]]) as usize
+    }
+
+    /// The index of the session's data network, which is the first of those two bytes.
+    pub fn data_network_idx(&self) -> usize {
+        self.uplink_gtp_teid.0[2] as usize
+    }
+
+    /// The session's index on its data network, which is the second of them.  This is the host number of its
+    /// IPv4 address and the last byte of its IPv6 prefix.
+    pub fn host_number(&self) -> usize {
+        self.uplink_gtp_teid.0[3] as usize
+    }
+
+    /// Start enforcing a new session AMBR.
+    pub fn set_session_ambr(&self, session_ambr: &SessionAmbr) {
+        self.uplink_rate_limiter
+            .set_session_rate(session_ambr.uplink_kbps);
+        self.downlink_rate_limiter
+            .set_session_rate(session_ambr.downlink_kbps);
+    }
+
+    /// Start classifying downlink packets using a new set of QoS rules.
+    pub fn set_qos_rules(&self, qos_rules: &[QosRule]) {
+        self.qos_classifier.set_qos_rules(qos_rules);
+    }
 }
 
 impl std::fmt::Display for UserplaneSession {
     fn fmt(&self, f: &mut std::fmt::Formatter<'_>) -> std::fmt::Result {
-        write!(f, "({},{})", self.uplink_gtp_teid, self.ue_ip_addr)
+        write!(f, "({}", self.uplink_gtp_teid)?;
+        if let Some(ipv4) = self.ue_ipv4_addr {
+            write!(f, ",{ipv4}")?;
+        }
+        if let Some(prefix) = self.ue_ipv6_prefix {
+            write!(f, ",{prefix}/64")?;
+        }
+        if self.ethernet {
+            write!(f, ",ethernet")?;
+        }
+        write!(f, ")")
     }
 }
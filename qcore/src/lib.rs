@@ -9,6 +9,18 @@ use procedures::{HandlerApi, Procedure};
 use protocols::*;
 
 pub use data::Config;
+pub use data::UeConfigurationUpdate;
+pub use data::{NasCipheringAlgorithm, NasIntegrityAlgorithm};
+pub use data::{PacketFilter, PduSessionModification, QosFlow, QosRule, SessionAmbr, UeAmbr};
+pub use data::{PacketFilterDirection, PacketFilterTemplate, QosFlowTemplate};
 pub use qcore::QCore;
 pub use sims::{SimCreds, SimTable};
-pub use data::sims;
\ No newline at end of file
+pub use data::sims;
+pub use sqn_store::SqnStore;
+pub use data::sqn_store;
+pub use home_network_keys::{EciesProfile, HomeNetworkKey, HomeNetworkKeys};
+pub use data::home_network_keys;
+pub use data_networks::DataNetworkConfig;
+pub use data::data_networks;
+pub use equipment_identities::EquipmentIdentities;
+pub use data::equipment_identities;
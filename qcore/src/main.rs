@@ -7,11 +7,17 @@ use async_std::channel::Sender;
 use async_std::prelude::*;
 use clap::Parser;
 use local_ip_address;
-use qcore::{Config, QCore};
+use qcore::{
+    Config, DataNetworkConfig, EquipmentIdentities, NasCipheringAlgorithm, NasIntegrityAlgorithm,
+    QCore,
+};
 use signal_hook::consts::signal::*;
 use signal_hook_async_std::Signals;
 use slog::{Drain, Logger, o};
-use std::net::{IpAddr, Ipv4Addr};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::time::Duration;
+use xxap::Snssai;
 
 #[derive(Parser, Debug)]
 #[command(author, version, about, long_about = None)]
@@ -32,18 +38,118 @@ struct Args {
     #[arg(long)]
     mnc: String,
 
+    /// Comma separated list of network slices (S-NSSAIs) to serve, each of the form <SST> or <SST>-<SD>,
+    /// where SD is 6 hex digits - for example "1,1-000001".
+    #[arg(long, value_delimiter = ',', default_value = "1")]
+    snssais: Vec<Snssai>,
+
+    /// Data network file to load, listing each DNN with its own tun device, UE subnet, DNS servers
+    /// and MTU.  If not supplied, QCore serves a single data network as configured by
+    /// --dnn, --n6-tun-name and --ue-subnet.
+    #[arg(long)]
+    data_network_file: Option<String>,
+
+    /// Name of the single data network, when --data-network-file is not supplied.
+    #[arg(long, default_value = "internet")]
+    dnn: String,
+
     /// Name of the Linux tun device to open for routing userplane packet to/from UEs on the N6 reference point.
     #[arg(long, default_value = "ue")]
     n6_tun_name: String,
 
+    /// Name of the Linux tap device onto which Ethernet PDU sessions are bridged, when --data-network-file is not
+    /// supplied.  If not supplied, UEs can't have Ethernet PDU sessions.
+    #[arg(long)]
+    n6_tap_name: Option<String>,
+
     /// UE subnet.  This is the network address of a /24 IPv4 subnet in dotted demical notation.  
     /// The final byte must be 0.  UEs are allocated host numbers 1-254.
     #[arg(long, default_value_t = Ipv4Addr::new(10,255,0,0))]
     ue_subnet: Ipv4Addr,
 
+    /// UE IPv6 prefix.  This is a /56 prefix from which each IPv6 or IPv4v6 PDU session is given a /64.  If not
+    /// supplied, UEs only get IPv4 PDU sessions.
+    #[arg(long)]
+    ue_ipv6_prefix: Option<Ipv6Addr>,
+
+    /// Give each SIM the same UE IP address (and IPv6 prefix) on a data network as last time, if it is free.  SIMs
+    /// with a static IPv4 address in the SIM credentials file always get that.
+    #[arg(long)]
+    sticky_ue_ips: bool,
+
+    /// Put an SDAP header on downlink packets, telling the UE the QoS flow of each one.  Off by default, because
+    /// SRS UE doesn't support it.
+    #[arg(long)]
+    sdap_header_dl: bool,
+
+    /// Comma separated list of IPv4 and IPv6 DNS servers to give to UEs, when --data-network-file is
+    /// not supplied.
+    #[arg(long, value_delimiter = ',')]
+    dns_servers: Vec<IpAddr>,
+
+    /// Comma separated list of IPv4 and IPv6 P-CSCF addresses to give to UEs, for IMS, when --data-network-file is
+    /// not supplied.
+    #[arg(long, value_delimiter = ',')]
+    p_cscf_servers: Vec<IpAddr>,
+
+    /// IPv4 link MTU (or Ethernet frame payload MTU) to give to UEs, when --data-network-file is not supplied.
+    #[arg(long)]
+    mtu: Option<u16>,
+
+    /// Periodic registration update timer (T3512) to give to UEs, in seconds.  QCore implicitly deregisters a UE
+    /// that it hasn't heard from for four minutes longer than this.
+    #[arg(long, default_value_t = 3240)]
+    t3512_secs: u64,
+
+    /// Number of downlink packets that QCore buffers for each PDU session of an idle UE while it pages the UE.
+    #[arg(long, default_value_t = 64)]
+    downlink_buffer_packets: usize,
+
+    /// Number of seconds for which QCore keeps a downlink packet buffered for an idle UE.
+    #[arg(long, default_value_t = 10)]
+    downlink_buffer_secs: u64,
+
     /// SIM credentials file to load.
     #[arg(long, default_value = "./sims.toml")]
     sim_cred_file: String,
+
+    /// File in which QCore persists the authentication sequence number (SQN) of each SIM.  
+    /// It is created if it does not exist.
+    #[arg(long, default_value = "./sqns.toml")]
+    sqn_file: String,
+
+    /// Home network private key file to load, for de-concealment of SUCIs protected with
+    /// ECIES Profile A or B.  If not supplied, only the null protection scheme is supported.
+    #[arg(long)]
+    home_network_key_file: Option<String>,
+
+    /// Equipment identity file to load, listing the IMEIs or TACs of devices that are allowed or denied
+    /// registration.  If not supplied, any device may register.
+    #[arg(long)]
+    equipment_identity_file: Option<String>,
+
+    /// Comma separated list of NAS integrity algorithms, in order of preference.  
+    /// QCore selects the first one that the UE supports.  Supported algorithms: nia2.
+    #[arg(long, value_delimiter = ',', default_value = "nia2")]
+    nas_integrity_algorithms: Vec<NasIntegrityAlgorithm>,
+
+    /// Comma separated list of NAS ciphering algorithms, in order of preference.  
+    /// QCore selects the first one that the UE supports.  Supported algorithms: nea2, nea0.
+    #[arg(long, value_delimiter = ',', default_value = "nea2,nea0")]
+    nas_ciphering_algorithms: Vec<NasCipheringAlgorithm>,
+
+    /// Full network name to show on UEs.
+    #[arg(long)]
+    full_network_name: Option<String>,
+
+    /// Short network name to show on UEs.
+    #[arg(long)]
+    short_network_name: Option<String>,
+
+    /// Local time zone, as an offset from UTC in minutes - for example 60 or -300.  If supplied, QCore sends UEs the
+    /// network name and time after they register, for the benefit of UEs that have no other source of time.
+    #[arg(long, allow_hyphen_values = true)]
+    time_zone_offset_mins: Option<i16>,
 }
 
 #[async_std::main]
@@ -53,11 +159,43 @@ async fn main() -> Result<()> {
 
     let args = Args::parse();
     let (plmn, serving_network_name) = convert_mcc_mnc(&args.mcc, &args.mnc).unwrap();
-    check_ue_subnet(&args.ue_subnet)?;
     check_local_ip(&args.local_ip)?;
+    ensure!(
+        args.time_zone_offset_mins.is_none_or(|x| x % 15 == 0),
+        "Time zone offset must be a whole number of quarter hours"
+    );
     slog::info!(&logger, "Serving network name {}", serving_network_name);
 
     let sims = Box::new(qcore::sims::load_sims_file(&args.sim_cred_file, &logger)?);
+    let sqns = qcore::SqnStore::load(&args.sqn_file, &logger)?;
+    let home_network_keys = match &args.home_network_key_file {
+        Some(filename) => qcore::home_network_keys::load_home_network_keys_file(filename, &logger)?,
+        None => HashMap::new(),
+    };
+    let equipment_identities = match &args.equipment_identity_file {
+        Some(filename) => {
+            qcore::equipment_identities::load_equipment_identities_file(filename, &logger)?
+        }
+        None => EquipmentIdentities::default(),
+    };
+    let data_networks = match &args.data_network_file {
+        Some(filename) => qcore::data_networks::load_data_networks_file(filename, &logger)?,
+        None => {
+            let data_networks = vec![DataNetworkConfig {
+                dnn: args.dnn,
+                n6_tun_name: args.n6_tun_name,
+                n6_tap_name: args.n6_tap_name,
+                ue_subnet: args.ue_subnet,
+                ue_ipv6_prefix: args.ue_ipv6_prefix,
+                dns_servers: args.dns_servers,
+                mtu: args.mtu,
+                p_cscf_servers: args.p_cscf_servers,
+                qos_flows: vec![],
+            }];
+            qcore::data_networks::check_data_networks(&data_networks)?;
+            data_networks
+        }
+    };
 
     let qc = QCore::start(
         Config {
@@ -67,12 +205,28 @@ async fn main() -> Result<()> {
             name: Some("QCore".to_string()),
             serving_network_name,
             skip_ue_authentication_check: false,
-            sst: 1,
-            n6_tun_name: args.n6_tun_name,
-            ue_subnet: args.ue_subnet,
+            snssais: args.snssais,
+            data_networks,
+            sticky_ue_ips: args.sticky_ue_ips,
+            sdap_header_dl: args.sdap_header_dl,
+            nas_integrity_algorithms: args.nas_integrity_algorithms,
+            nas_ciphering_algorithms: args.nas_ciphering_algorithms,
+            nas_retransmission_timer: Duration::from_secs(6),
+            t3512: Duration::from_secs(args.t3512_secs),
+            // TS24.501, 5.3.7: by default, the mobile reachable timer is four minutes greater than T3512.
+            mobile_reachable_timer: Duration::from_secs(args.t3512_secs + 240),
+            full_network_name: args.full_network_name,
+            short_network_name: args.short_network_name,
+            time_zone_offset_mins: args.time_zone_offset_mins,
+            paging_retransmission_timer: Duration::from_secs(5),
+            downlink_buffer_max_packets: args.downlink_buffer_packets,
+            downlink_buffer_max_age: Duration::from_secs(args.downlink_buffer_secs),
+            home_network_keys,
+            equipment_identities,
         },
         logger,
         Box::leak(sims),
+        sqns,
     )
     .await?;
 
@@ -102,14 +256,6 @@ fn exit_on_panic() {
     }));
 }
 
-fn check_ue_subnet(ue_subnet: &Ipv4Addr) -> Result<()> {
-    ensure!(
-        ue_subnet.octets()[3] == 0,
-        "Final byte of UE subnet must be 0"
-    );
-    Ok(())
-}
-
 fn check_local_ip(ip: &IpAddr) -> Result<()> {
     ensure!(
         !ip.is_unspecified(),
@@ -32,6 +32,17 @@ impl<'a, A: HandlerApi> F1SetupProcedure<'a, A> {
             self.logger,
             "F1 setup with DU name:{gnb_du_name}, id:{:x}", r.gnb_du_id.0
         );
+
+        // The DU's tracking areas make up the registration area that we give to UEs in its cells, and its cells are
+        // where we page UEs that were last connected to it.
+        let cells = r
+            .gnb_du_served_cells_list
+            .iter()
+            .flat_map(|x| x.0.iter())
+            .map(|x| x.served_cell_information.clone())
+            .collect();
+        self.set_served_cells(r.gnb_du_id.0, cells);
+
         let response = crate::f1ap::build::f1_setup_response(r, self.config().clone().name)?;
         self.log_message("<< F1SetupResponse");
         Ok((response, None))
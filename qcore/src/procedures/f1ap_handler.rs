@@ -1,7 +1,7 @@
 //! f1ap - F1AP entry points
 use super::gnb_du_configuration_update::GnbDuConfigurationUpdateProcedure;
 use super::{f1_removal::F1RemovalProcedure, f1_setup::F1SetupProcedure};
-use crate::HandlerApi;
+use crate::{HandlerApi, UeMessage};
 use async_trait::async_trait;
 use derive_deref::Deref;
 use f1ap::{
@@ -9,8 +9,8 @@ use f1ap::{
     F1SetupResponse, F1apCu, F1apPdu, GnbDuConfigurationUpdate,
     GnbDuConfigurationUpdateAcknowledge, GnbDuConfigurationUpdateFailure,
     InitialUlRrcMessageTransfer, InitialUlRrcMessageTransferProcedure, InitiatingMessage,
-    UeContextReleaseRequest, UeContextReleaseRequestProcedure, UlRrcMessageTransfer,
-    UlRrcMessageTransferProcedure,
+    UeContextReleaseRequest, UeContextReleaseRequestProcedure, UeInactivityNotification,
+    UeInactivityNotificationProcedure, UlRrcMessageTransfer, UlRrcMessageTransferProcedure,
 };
 use slog::{Logger, info, warn};
 use xxap::{
@@ -71,7 +71,9 @@ impl<A: HandlerApi> IndicationHandler<InitialUlRrcMessageTransferProcedure> for
         if let Err(e) = self
             .dispatch_ue_message(
                 id,
-                F1apPdu::InitiatingMessage(InitiatingMessage::InitialUlRrcMessageTransfer(r)),
+                UeMessage::F1ap(Box::new(F1apPdu::InitiatingMessage(
+                    InitiatingMessage::InitialUlRrcMessageTransfer(r),
+                ))),
             )
             .await
         {
@@ -89,7 +91,9 @@ impl<A: HandlerApi> IndicationHandler<UlRrcMessageTransferProcedure> for F1apHan
         if let Err(e) = self
             .dispatch_ue_message(
                 r.gnb_cu_ue_f1ap_id.0,
-                F1apPdu::InitiatingMessage(InitiatingMessage::UlRrcMessageTransfer(r)),
+                UeMessage::F1ap(Box::new(F1apPdu::InitiatingMessage(
+                    InitiatingMessage::UlRrcMessageTransfer(r),
+                ))),
             )
             .await
         {
@@ -104,7 +108,9 @@ impl<A: HandlerApi> IndicationHandler<UeContextReleaseRequestProcedure> for F1ap
         if let Err(e) = self
             .dispatch_ue_message(
                 r.gnb_cu_ue_f1ap_id.0,
-                F1apPdu::InitiatingMessage(InitiatingMessage::UeContextReleaseRequest(r)),
+                UeMessage::F1ap(Box::new(F1apPdu::InitiatingMessage(
+                    InitiatingMessage::UeContextReleaseRequest(r),
+                ))),
             )
             .await
         {
@@ -116,6 +122,26 @@ impl<A: HandlerApi> IndicationHandler<UeContextReleaseRequestProcedure> for F1ap
     }
 }
 
+#[async_trait]
+impl<A: HandlerApi> IndicationHandler<UeInactivityNotificationProcedure> for F1apHandler<A> {
+    async fn handle(&self, r: UeInactivityNotification, _logger: &Logger) {
+        if let Err(e) = self
+            .dispatch_ue_message(
+                r.gnb_cu_ue_f1ap_id.0,
+                UeMessage::F1ap(Box::new(F1apPdu::InitiatingMessage(
+                    InitiatingMessage::UeInactivityNotification(r),
+                ))),
+            )
+            .await
+        {
+            warn!(
+                _logger,
+                "Failed to dispatch UeInactivityNotification - {}", e
+            );
+        }
+    }
+}
+
 #[async_trait]
 impl<A: HandlerApi> EventHandler for F1apHandler<A> {
     async fn handle_event(&self, event: TnlaEvent, tnla_id: u32, logger: &Logger) {
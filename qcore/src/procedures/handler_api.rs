@@ -1,10 +1,11 @@
-use crate::SimCreds;
-use crate::{Config, UserplaneSession};
+use crate::userplane::DrbTunnel;
+use crate::{Config, SessionAmbr, UeMessage, UserplaneSession};
+use crate::{RegisteredUe, SimCreds, SqnStore};
 use anyhow::Result;
 use async_trait::async_trait;
-use f1ap::F1apPdu;
+use f1ap::{NrCgi, ServedCellInformation};
 use slog::Logger;
-use xxap::{GtpTunnel, Indication, Procedure, RequestError};
+use xxap::{Indication, Procedure, RequestError};
 
 /// Trait representing the collection of services needed by QCore handlers.
 #[async_trait]
@@ -12,9 +13,24 @@ pub trait HandlerApi: Send + Sync + Clone + 'static {
     fn config(&self) -> &Config;
 
     fn lookup_sim(&self, imsi: &str) -> Option<&'static SimCreds>;
+    fn sqn_store(&self) -> &SqnStore;
+
+    fn store_registered_ue(&self, tmsi: [u8; 4], ue: RegisteredUe);
+    fn take_registered_ue(&self, tmsi: &[u8; 4]) -> Option<RegisteredUe>;
+    fn tmsi_in_use(&self, tmsi: &[u8; 4]) -> bool;
+
+    // The cells served by each DU, keyed by gNB-DU ID.  The TACs of the DU that owns a UE's cell make up the
+    // registration area that we give to the UE, and its cells are where we page the UE once it is idle.
+    fn set_served_cells(&self, gnb_du_id: u64, cells: Vec<ServedCellInformation>);
+    fn tracking_areas(&self, nr_cgi: &NrCgi) -> Vec<[u8; 3]>;
+    fn paging_cells(&self, nr_cgi: &NrCgi) -> Vec<NrCgi>;
+
+    fn add_connected_ue(&self, imsi: String, tmsi: [u8; 4], ue_id: u32);
+    fn remove_connected_ue(&self, imsi: &str, tmsi: &[u8; 4], ue_id: u32);
+    fn change_connected_tmsi(&self, old_tmsi: &[u8; 4], new_tmsi: [u8; 4], ue_id: u32);
 
     fn spawn_ue_message_handler(&self) -> u32;
-    async fn dispatch_ue_message(&self, ue_id: u32, message: F1apPdu) -> Result<()>;
+    async fn dispatch_ue_message(&self, ue_id: u32, message: UeMessage) -> Result<()>;
     fn delete_ue_channel(&self, ue_id: u32);
     fn delete_ue_channels(&self);
 
@@ -25,12 +41,27 @@ pub trait HandlerApi: Send + Sync + Clone + 'static {
     ) -> Result<P::Success, RequestError<P::Failure>>;
     async fn f1ap_indication<P: Indication>(&self, r: P::Request, logger: &Logger);
 
-    async fn reserve_userplane_session(&self, logger: &Logger) -> Result<UserplaneSession>;
+    async fn reserve_userplane_session(
+        &self,
+        data_network_idx: usize,
+        pdu_session_type: u8,
+        session_ambr: &SessionAmbr,
+        imsi: Option<&str>,
+        logger: &Logger,
+    ) -> Result<UserplaneSession>;
     async fn commit_userplane_session(
         &self,
         session: &UserplaneSession,
-        remote_tunnel_info: GtpTunnel,
+        drb_tunnels: Vec<DrbTunnel>,
+        imsi: Option<&str>,
         logger: &Logger,
     ) -> Result<()>;
+    async fn remap_userplane_qos_flows(
+        &self,
+        session: &UserplaneSession,
+        drb_qfis: Vec<(u8, Vec<u8>)>,
+        logger: &Logger,
+    );
+    async fn deactivate_userplane_session(&self, session: &UserplaneSession, logger: &Logger);
     async fn delete_userplane_session(&self, session: &UserplaneSession, logger: &Logger);
 }
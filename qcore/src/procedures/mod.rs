@@ -3,10 +3,12 @@ mod f1_setup;
 mod f1ap_handler;
 mod gnb_du_configuration_update;
 mod handler_api;
+mod paging;
 mod procedure;
 mod ue_procedures;
 
 pub use f1ap_handler::F1apHandler;
 pub use handler_api::HandlerApi;
+pub use paging::PagingProcedure;
 pub use procedure::Procedure;
 pub use ue_procedures::UeMessageHandler;
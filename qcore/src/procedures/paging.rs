@@ -0,0 +1,28 @@
+//! paging - the network asks the DU to page a UE in CM-IDLE, because downlink data has arrived for it
+
+use super::{HandlerApi, Procedure};
+use anyhow::Result;
+use derive_deref::{Deref, DerefMut};
+use f1ap::NrCgi;
+use slog::Logger;
+
+#[derive(Deref, DerefMut)]
+pub struct PagingProcedure<'a, A: HandlerApi>(Procedure<'a, A>);
+
+impl<'a, A: HandlerApi> PagingProcedure<'a, A> {
+    pub fn new(api: &'a A, logger: &'a Logger) -> Self {
+        PagingProcedure(Procedure::new(api, logger))
+    }
+
+    // Paging Procedure - see TS23.502, 4.2.3.3 and TS38.473, 8.7.1
+    // 1.    F1ap Paging <<
+    // The UE responds with a Service Request, handled by the initial access procedure.
+    pub async fn run(&self, tmsi: &[u8; 4], nr_cgi: &NrCgi) -> Result<()> {
+        let cells = self.paging_cells(nr_cgi);
+        let paging = crate::f1ap::build::paging(&self.config().amf_ids, tmsi, cells)?;
+        self.log_message("<< Paging");
+        self.f1ap_indication::<f1ap::PagingProcedure>(paging, self.logger)
+            .await;
+        Ok(())
+    }
+}
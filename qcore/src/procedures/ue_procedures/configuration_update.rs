@@ -0,0 +1,69 @@
+//! configuration_update - the network updates the configuration of a registered UE, such as its 5G-GUTI
+
+use super::{NasTimer, UeProcedure};
+use crate::{HandlerApi, UeConfigurationUpdate, expect_nas};
+use anyhow::{Result, anyhow};
+use derive_deref::{Deref, DerefMut};
+use oxirush_nas::{Nas5gmmMessage, Nas5gsMessage};
+use slog::info;
+use std::time::SystemTime;
+
+#[derive(Deref, DerefMut)]
+pub struct ConfigurationUpdateProcedure<'a, A: HandlerApi>(UeProcedure<'a, A>);
+
+impl<'a, A: HandlerApi> ConfigurationUpdateProcedure<'a, A> {
+    pub fn new(inner: UeProcedure<'a, A>) -> Self {
+        ConfigurationUpdateProcedure(inner)
+    }
+
+    // Generic UE Configuration Update Procedure - see TS24.501, 5.4.4
+    // 1.    Nas Configuration Update Command <<
+    // 2.    Nas Configuration Update Complete >> (if acknowledgement was requested)
+    pub async fn run(mut self, update: UeConfigurationUpdate) -> Result<()> {
+        let acknowledgement_requested = update.acknowledgement_requested();
+        let new_tmsi = update.reallocate_guti.then(|| self.unused_tmsi());
+        let tacs = update
+            .tai_list
+            .then(|| self.tracking_areas(&self.ue.nr_cgi));
+        let time = update.network_identity_and_time.then(SystemTime::now);
+        let command = crate::nas::build::configuration_update_command(
+            self.config(),
+            acknowledgement_requested,
+            new_tmsi.as_ref(),
+            tacs.as_deref(),
+            update.allowed_nssai.as_deref(),
+            update.network_identity_and_time,
+            time,
+        );
+        self.log_message("<< NasConfigurationUpdateCommand");
+        if !acknowledgement_requested {
+            return self.nas_indication(command).await;
+        }
+
+        let _rsp = expect_nas!(
+            ConfigurationUpdateComplete,
+            self.nas_request(command, NasTimer::T3555).await?
+        )?;
+        self.log_message(">> NasConfigurationUpdateComplete");
+
+        // TS24.501, 5.4.4.4: the new 5G-GUTI and Allowed NSSAI take effect once the UE has acknowledged them.
+        if let Some(tmsi) = new_tmsi {
+            self.change_connected_tmsi(&self.ue.tmsi, tmsi, self.ue.key);
+            self.ue.tmsi = tmsi;
+            info!(self.logger, "Reallocated 5G-GUTI");
+        }
+        if let Some(allowed_nssai) = update.allowed_nssai {
+            self.ue.allowed_nssai = allowed_nssai;
+        }
+        Ok(())
+    }
+
+    // Pick a 5G-TMSI that no connected or idle UE has.
+    fn unused_tmsi(&self) -> [u8; 4] {
+        let mut tmsi = rand::random::<[u8; 4]>();
+        while self.tmsi_in_use(&tmsi) {
+            tmsi = rand::random::<[u8; 4]>();
+        }
+        tmsi
+    }
+}
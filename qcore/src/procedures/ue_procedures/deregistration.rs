@@ -1,12 +1,16 @@
-use anyhow::{Result, bail};
+use anyhow::{Result, anyhow, bail};
 use derive_deref::{Deref, DerefMut};
 use f1ap::{Cause, CauseRadioNetwork};
 use oxirush_nas::messages::NasDeregistrationRequestFromUe;
+use oxirush_nas::{Nas5gmmMessage, Nas5gsMessage};
 use slog::info;
-use crate::{HandlerApi};
+use crate::{HandlerApi, expect_nas};
 use super::UeContextReleaseProcedure;
 
-use super::UeProcedure;
+use super::{NasTimer, UeProcedure};
+
+// TS24.501, figure 9.11.3.20.1 - switch off bit of the de-registration type.
+const SWITCH_OFF: u8 = 0b1000;
 
 #[derive(Deref, DerefMut)]
 pub struct DeregistrationProcedure<'a, A: HandlerApi>(UeProcedure<'a, A>);
@@ -16,18 +20,47 @@ impl<'a, A: HandlerApi> DeregistrationProcedure<'a, A> {
         DeregistrationProcedure(inner)
     }
 
-    pub async fn run(self, _r: NasDeregistrationRequestFromUe) -> Result<()> {
-        info!(self.logger, "UE deregisters - perform context release");
+    pub async fn ue_initiated(mut self, r: NasDeregistrationRequestFromUe) -> Result<()> {
+        self.ue.registered = false;
+
+        // TS24.501, 5.5.2.2.2: there is no Deregistration Accept if the UE is switching off.
+        if r.de_registration_type.value & SWITCH_OFF != 0 {
+            info!(self.logger, "UE switches off - perform context release");
+        } else {
+            info!(self.logger, "UE deregisters - perform context release");
+            self.log_message("<< NasDeregistrationAcceptFromUe");
+            self.nas_indication(crate::nas::build::deregistration_accept())
+                .await?;
+        }
+
+        self.release_context().await?;
+        bail!("Normal deregistration")
+    }
+
+    pub async fn network_initiated(mut self, reregistration_required: bool) -> Result<()> {
+        info!(
+            self.logger,
+            "Network initiated deregistration, reregistration required = {reregistration_required}"
+        );
+        self.ue.registered = false;
+
+        let r = crate::nas::build::deregistration_request(reregistration_required);
+        self.log_message("<< NasDeregistrationRequestToUe");
+        let _rsp = expect_nas!(
+            DeregistrationAcceptToUe,
+            self.nas_request(r, NasTimer::T3522).await?
+        )?;
+        self.log_message(">> NasDeregistrationAcceptToUe");
 
-        // TODO - send NAS deregistration accept (UE originating de-registration).
-        // Is this piggy-backed in the RRC Container on the F1 Context Release Command?
+        self.release_context().await?;
+        bail!("Network initiated deregistration")
+    }
 
+    // The caller then returns an error to get the UE message handler to self-destruct
+    // and free up the userplane sessions and channel.
+    async fn release_context(self) -> Result<()> {
         UeContextReleaseProcedure::new(self.0)
             .cu_initiated(Cause::RadioNetwork(CauseRadioNetwork::NormalRelease))
-            .await?;
-
-        // Return an error to get the UE message handler to self-destruct
-        // and free up the userplane sessions and channel.
-        bail!("Normal deregistration")
+            .await
     }
 }
@@ -1,17 +1,27 @@
 //! initial_access - procedure in which UE makes first contact with the 5G core
 
-use super::{HandlerApi, UeProcedure};
-use crate::SimCreds;
+use super::{
+    ConfigurationUpdateProcedure, HandlerApi, NasTimer, ServiceRequestProcedure,
+    UeContextReleaseProcedure, UeProcedure,
+};
 use crate::expect_nas;
-use crate::nas::parse::MobileIdentity;
+use crate::nas::{FgmmCause, FgsRegistrationType, PduSessionStatus, parse::MobileIdentity};
+use crate::{
+    NasCipheringAlgorithm, NasIntegrityAlgorithm, PduSession, RegisteredUe, SimCreds,
+    UeConfigurationUpdate,
+};
 use anyhow::{Result, anyhow, bail};
 use asn1_per::SerDes;
 use derive_deref::{Deref, DerefMut};
-use f1ap::{DuToCuRrcContainer, InitialUlRrcMessageTransfer, SrbId};
+use f1ap::{Cause, CauseRadioNetwork, DuToCuRrcContainer, InitialUlRrcMessageTransfer, SrbId};
 use oxirush_nas::messages::{
-    NasAuthenticationResponse, NasRegistrationRequest, NasSecurityModeComplete,
+    NasAuthenticationFailure, NasAuthenticationResponse, NasMessageContainer,
+    NasRegistrationRequest, NasSecurityModeComplete, NasServiceRequest,
+};
+use oxirush_nas::{
+    Nas5gmmMessage, Nas5gsMessage, NasFGsMobileIdentity, NasUeSecurityCapability,
+    decode_nas_5gs_message,
 };
-use oxirush_nas::{Nas5gmmMessage, Nas5gsMessage, NasUeSecurityCapability};
 use rrc::UlDcchMessage;
 use rrc::{
     C1_4, C1_6, CriticalExtensions22, RrcSetupComplete, RrcSetupRequest, UlCcchMessage,
@@ -19,6 +29,52 @@ use rrc::{
 };
 use security::Challenge;
 use slog::{info, warn};
+use std::fmt;
+use xxap::Snssai;
+
+// A registration failure that we tell the UE about, so that it backs off rather than immediately retrying.
+#[derive(Debug)]
+enum Rejection {
+    Registration { fgmm_cause: u8, reason: String },
+    Authentication { reason: String },
+    Service { fgmm_cause: u8, reason: String },
+}
+
+impl Rejection {
+    fn registration(fgmm_cause: u8, reason: String) -> Self {
+        Rejection::Registration { fgmm_cause, reason }
+    }
+
+    fn service(fgmm_cause: u8, reason: String) -> Self {
+        Rejection::Service { fgmm_cause, reason }
+    }
+
+    fn authentication(reason: String) -> Self {
+        Rejection::Authentication { reason }
+    }
+}
+
+impl fmt::Display for Rejection {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        match self {
+            Rejection::Registration { fgmm_cause, reason } => {
+                write!(
+                    f,
+                    "{reason} - reject registration with 5GMM cause #{fgmm_cause}"
+                )
+            }
+            Rejection::Authentication { reason } => write!(f, "{reason} - reject authentication"),
+            Rejection::Service { fgmm_cause, reason } => {
+                write!(
+                    f,
+                    "{reason} - reject service request with 5GMM cause #{fgmm_cause}"
+                )
+            }
+        }
+    }
+}
+
+impl std::error::Error for Rejection {}
 
 #[derive(Deref, DerefMut)]
 pub struct InitialAccessProcedure<'a, A: HandlerApi>(UeProcedure<'a, A>);
@@ -29,21 +85,250 @@ impl<'a, A: HandlerApi> InitialAccessProcedure<'a, A> {
     }
 
     pub async fn run(mut self, r: InitialUlRrcMessageTransfer) -> Result<()> {
-        let registration_request = self.handle_rrc_setup(r).await?;
-        let (imsi, ue_security_capability) =
+        match self.handle_initial_nas(r).await {
+            Ok(Some(service_request)) => {
+                ServiceRequestProcedure::new(self.0)
+                    .run(service_request)
+                    .await
+            }
+            // Once the UE is registered, give it the network name and time if we have a local time zone.
+            Ok(None) if self.config().time_zone_offset_mins.is_some() => {
+                ConfigurationUpdateProcedure::new(self.0)
+                    .run(UeConfigurationUpdate {
+                        network_identity_and_time: true,
+                        ..Default::default()
+                    })
+                    .await
+            }
+            Ok(None) => Ok(()),
+            Err(e) => {
+                if let Some(rejection) = e.downcast_ref::<Rejection>() {
+                    self.reject(rejection).await?;
+                }
+                Err(e)
+            }
+        }
+    }
+
+    // Register the UE, or resume its NAS security context if it is coming back from CM-IDLE with a Service
+    // Request, in which case we return the Service Request for the service request procedure to carry on with.
+    async fn handle_initial_nas(
+        &mut self,
+        r: InitialUlRrcMessageTransfer,
+    ) -> Result<Option<NasServiceRequest>> {
+        let (nas, nas_bytes) = self.handle_rrc_setup(r).await?;
+        match nas {
+            Nas5gsMessage::Gmm(_header, Nas5gmmMessage::ServiceRequest(service_request)) => {
+                let service_request = self.resume_service(service_request, &nas_bytes).await?;
+                Ok(Some(service_request))
+            }
+            nas => {
+                let registration_request = expect_nas!(RegistrationRequest, nas)?;
+                self.register(registration_request, &nas_bytes).await?;
+                Ok(None)
+            }
+        }
+    }
+
+    async fn register(
+        &mut self,
+        registration_request: NasRegistrationRequest,
+        nas_bytes: &[u8],
+    ) -> Result<()> {
+        let registration_type = registration_request.fgs_registration_type.value & 0b111;
+        let initial_session_status = session_status(&registration_request);
+        let nas_message_container = registration_request.nas_message_container.clone();
+        let (mobile_identity, ue_security_capability, requested_nssai) =
             self.check_registration_request(registration_request)?;
-        let kamf = self.authenticate_ue(&imsi).await?;
-        self.activate_nas_security(ue_security_capability, &kamf)
-            .await?;
-        self.activate_rrc_security(&kamf).await?;
+        let (imsi, registered_ue) = self.identify_ue(mobile_identity).await?;
+
+        // Hold on to the PDU sessions of a UE coming back from CM-IDLE in the UE context, so that they are
+        // cleaned up if registration fails.
+        let resumed_ul_nas_count = match registered_ue {
+            Some((mut registered_ue, tmsi)) => {
+                self.ue.pdu_sessions = std::mem::take(&mut registered_ue.pdu_sessions);
+                self.resume_nas_security(registered_ue, tmsi, nas_bytes)
+            }
+            None => None,
+        };
+
+        // We only believe the PDU session status and uplink data status in an integrity protected Registration
+        // Request - either the initial one, if it passes the check of the resumed security context, in which case
+        // they are in its NAS message container, or the copy in the Security Mode Complete.
+        let (ul_nas_count, (ue_pdu_session_status, uplink_data_status)) = match resumed_ul_nas_count
+        {
+            Some(ul_nas_count) => match nas_message_container {
+                Some(container) => {
+                    let nas = self.decode_nas_message_container(&container, ul_nas_count)?;
+                    let registration_request = expect_nas!(RegistrationRequest, nas)?;
+                    (ul_nas_count, session_status(&registration_request))
+                }
+                None => (ul_nas_count, initial_session_status),
+            },
+            None => {
+                self.delete_pdu_sessions(std::mem::take(&mut self.ue.pdu_sessions))
+                    .await;
+                let kamf = self.authenticate_ue(&imsi).await?;
+                let registration_request = self
+                    .activate_nas_security(ue_security_capability, &kamf)
+                    .await?;
+                (0, session_status(&registration_request))
+            }
+        };
+        self.check_equipment_identity()?;
+
+        // TS24.501, 5.5.1.3.4: a UE coming back from CM-IDLE with a mobility or periodic registration update keeps
+        // its PDU sessions, other than any that it says it no longer has.  An initial registration, or one that
+        // isn't protected by the UE's stored NAS security context, starts without any.
+        let keep_pdu_sessions = matches!(
+            registration_type,
+            FgsRegistrationType::MOBILITY_REGISTRATION_UPDATING
+                | FgsRegistrationType::PERIODIC_REGISTRATION_UPDATING
+        );
+        let (kept, released): (Vec<PduSession>, Vec<PduSession>) =
+            std::mem::take(&mut self.ue.pdu_sessions)
+                .into_iter()
+                .partition(|x| {
+                    keep_pdu_sessions
+                        && ue_pdu_session_status
+                            .as_ref()
+                            .is_none_or(|active| active.contains(&x.id))
+                });
+        self.ue.pdu_sessions = kept;
+        self.delete_pdu_sessions(released).await;
+
+        // Tell the UE which of its PDU sessions are still active.  We don't set up DRBs during registration, so
+        // the user plane of any session that the UE has uplink data for isn't reactivated - the UE can do that
+        // with a Service Request.
+        let pdu_session_status = (ue_pdu_session_status.is_some()
+            || uplink_data_status.is_some()
+            || !self.ue.pdu_sessions.is_empty())
+        .then(|| PduSessionStatus {
+            active: self.ue.pdu_sessions.iter().map(|x| x.id).collect(),
+            reactivation_failed: uplink_data_status,
+        });
+
+        let (allowed_nssai, rejected_nssai, subscribed_nssai) =
+            self.select_nssai(&imsi, requested_nssai.as_deref())?;
+
+        self.activate_rrc_security(ul_nas_count).await?;
         info!(self.logger, "Registered imsi-{imsi}");
-        self.complete_nas_registration().await
+        self.ue.imsi = Some(imsi);
+
+        // TS24.501, 5.5.1.2.4: we send the Configured NSSAI if the UE didn't request any S-NSSAIs or asked
+        // for ones that it can't have.
+        let configured_nssai = (requested_nssai.is_none() || !rejected_nssai.is_empty())
+            .then_some(subscribed_nssai.as_slice());
+        self.complete_nas_registration(
+            &allowed_nssai,
+            &rejected_nssai,
+            configured_nssai,
+            pdu_session_status.as_ref(),
+        )
+        .await?;
+        self.ue.allowed_nssai = allowed_nssai;
+        self.ue.registered = true;
+        Ok(())
+    }
+
+    // TS24.501, 5.6.1: a UE in CM-IDLE identifies itself using its 5G-S-TMSI, and protects the Service Request
+    // with its existing NAS security context.  If we still have that, we carry on using it.
+    async fn resume_service(
+        &mut self,
+        r: NasServiceRequest,
+        nas_bytes: &[u8],
+    ) -> Result<NasServiceRequest> {
+        self.log_message(">> NasServiceRequest");
+        let (amf_set_and_pointer, tmsi) =
+            crate::nas::parse::five_g_s_tmsi(&r.fg_s_tmsi).map_err(|e| {
+                Rejection::service(FgmmCause::INVALID_MANDATORY_INFORMATION, e.to_string())
+            })?;
+        let registered_ue = if amf_set_and_pointer == self.config().amf_ids[1..] {
+            self.take_registered_ue(&tmsi)
+        } else {
+            None
+        };
+        let Some(mut registered_ue) = registered_ue else {
+            bail!(Rejection::service(
+                FgmmCause::UE_IDENTITY_CANNOT_BE_DERIVED_BY_THE_NETWORK,
+                "Service Request from unknown 5G-S-TMSI".to_string()
+            ))
+        };
+
+        let imsi = registered_ue.imsi.clone();
+        let allowed_nssai = std::mem::take(&mut registered_ue.allowed_nssai);
+        let pdu_sessions = std::mem::take(&mut registered_ue.pdu_sessions);
+        let Some(ul_nas_count) = self.resume_nas_security(registered_ue, tmsi, nas_bytes) else {
+            self.delete_pdu_sessions(pdu_sessions).await;
+            bail!(Rejection::service(
+                FgmmCause::UE_IDENTITY_CANNOT_BE_DERIVED_BY_THE_NETWORK,
+                format!("Service Request from imsi-{imsi} failed integrity check")
+            ))
+        };
+        info!(self.logger, "Resume service for imsi-{imsi}");
+        self.ue.imsi = Some(imsi);
+        self.ue.allowed_nssai = allowed_nssai;
+        self.ue.pdu_sessions = pdu_sessions;
+        self.ue.registered = true;
+
+        // The PDU session status and uplink data status aren't cleartext IEs.
+        let r = match &r.nas_message_container {
+            Some(container) => {
+                let nas = self.decode_nas_message_container(container, ul_nas_count)?;
+                expect_nas!(ServiceRequest, nas)?
+            }
+            None => r,
+        };
+        self.activate_rrc_security(ul_nas_count).await?;
+        Ok(r)
+    }
+
+    // TS24.501, 4.4.6: a UE with a NAS security context sends only the cleartext IEs of an initial NAS message
+    // in the clear.  It puts the whole message in the NAS message container, ciphered using the NAS COUNT of
+    // the initial message, so we can only read it once the initial message has passed the integrity check.
+    fn decode_nas_message_container(
+        &self,
+        container: &NasMessageContainer,
+        ul_nas_count: u32,
+    ) -> Result<Nas5gsMessage> {
+        self.ue
+            .nas
+            .decode_initial_nas_container(&container.value, ul_nas_count)
+    }
+
+    async fn delete_pdu_sessions(&self, sessions: Vec<PduSession>) {
+        for session in sessions {
+            self.delete_userplane_session(&session.userplane_info, self.logger)
+                .await;
+        }
+    }
+
+    // Send Registration Reject, Authentication Reject or Service Reject, then release the UE's F1 context.
+    async fn reject(mut self, rejection: &Rejection) -> Result<()> {
+        let r = match rejection {
+            Rejection::Registration { fgmm_cause, .. } => {
+                self.log_message("<< NasRegistrationReject");
+                crate::nas::build::registration_reject(*fgmm_cause)
+            }
+            Rejection::Authentication { .. } => {
+                self.log_message("<< NasAuthenticationReject");
+                crate::nas::build::authentication_reject()
+            }
+            Rejection::Service { fgmm_cause, .. } => {
+                self.log_message("<< NasServiceReject");
+                crate::nas::build::service_reject(*fgmm_cause)
+            }
+        };
+        self.nas_indication(r).await?;
+        UeContextReleaseProcedure::new(self.0)
+            .cu_initiated(Cause::RadioNetwork(CauseRadioNetwork::NormalRelease))
+            .await
     }
 
     async fn handle_rrc_setup(
         &mut self,
         r: InitialUlRrcMessageTransfer,
-    ) -> Result<NasRegistrationRequest> {
+    ) -> Result<(Nas5gsMessage, Vec<u8>)> {
         let cell_group_config = self.check_initial_transfer(r)?;
         self.log_message(">> RrcSetupRequest");
         let rrc_setup = crate::rrc::build::setup(0, cell_group_config);
@@ -51,38 +336,149 @@ impl<'a, A: HandlerApi> InitialAccessProcedure<'a, A> {
         let response = self.rrc_request(SrbId(0), rrc_setup).await?;
         let nas_bytes = self.check_rrc_setup_complete(response)?;
         self.log_message(">> RrcSetupComplete");
-        expect_nas!(RegistrationRequest, self.ue.nas.decode(&nas_bytes)?)
+        let nas = self.ue.nas.decode(&nas_bytes)?;
+        Ok((nas, nas_bytes))
+    }
+
+    // Get the SUPI of the UE, together with its stored NAS context if it registered using a 5G-GUTI that we
+    // allocated.
+    async fn identify_ue(
+        &mut self,
+        mobile_identity: MobileIdentity,
+    ) -> Result<(String, Option<(RegisteredUe, [u8; 4])>)> {
+        let (imsi, plmn) = match mobile_identity {
+            MobileIdentity::Suci { imsi, plmn } => (imsi, plmn),
+            MobileIdentity::Guti {
+                plmn,
+                amf_ids,
+                tmsi,
+            } => {
+                if plmn == self.config().plmn && amf_ids == self.config().amf_ids {
+                    if let Some(registered_ue) = self.take_registered_ue(&tmsi) {
+                        return Ok((registered_ue.imsi.clone(), Some((registered_ue, tmsi))));
+                    }
+                }
+                info!(self.logger, "Unknown 5G-GUTI - request SUCI from UE");
+                self.request_suci().await?
+            }
+        };
+
+        if plmn != self.config().plmn {
+            // This will cause authentication to fail, because the UE will form its
+            // serving network name using its MCC/MNC, and we form ours using our MCC/MNC.
+            bail!(Rejection::registration(
+                FgmmCause::PLMN_NOT_ALLOWED,
+                format!(
+                    "UE PLMN {:?} doesn't match ours {:?}",
+                    &plmn,
+                    self.config().plmn
+                )
+            ))
+        }
+        Ok((imsi, None))
+    }
+
+    async fn request_suci(&mut self) -> Result<(String, [u8; 3])> {
+        let r = crate::nas::build::identity_request();
+        self.log_message("<< NasIdentityRequest");
+        let rsp = expect_nas!(
+            IdentityResponse,
+            self.nas_request(r, NasTimer::T3570).await?
+        )?;
+        self.log_message(">> NasIdentityResponse");
+        match self.parse_mobile_identity(&rsp.mobile_identity)? {
+            MobileIdentity::Suci { imsi, plmn } => Ok((imsi, plmn)),
+            MobileIdentity::Guti { .. } => bail!(Rejection::registration(
+                FgmmCause::INVALID_MANDATORY_INFORMATION,
+                "Identity Response contained 5G-GUTI not SUCI".to_string()
+            )),
+        }
+    }
+
+    // TS33.501, 6.4.6: if the initial NAS message passes the integrity check using the UE's existing NAS
+    // security context, we can carry on using it without re-authenticating the UE.
+    fn resume_nas_security(
+        &mut self,
+        mut registered_ue: RegisteredUe,
+        tmsi: [u8; 4],
+        nas_bytes: &[u8],
+    ) -> Option<u32> {
+        match registered_ue.nas.check_integrity(nas_bytes) {
+            Ok(ul_nas_count) => {
+                info!(
+                    self.logger,
+                    "Resume NAS security context of imsi-{}", registered_ue.imsi
+                );
+                self.ue.nas = registered_ue.nas;
+                self.ue.pei = registered_ue.pei;
+                self.ue.tmsi = tmsi;
+                Some(ul_nas_count)
+            }
+            Err(e) => {
+                warn!(
+                    self.logger,
+                    "Can't resume NAS security context of imsi-{} - {e}", registered_ue.imsi
+                );
+                None
+            }
+        }
     }
 
     async fn authenticate_ue(&mut self, imsi: &String) -> Result<[u8; 32]> {
-        let Some(sim) = self.lookup_sim(&imsi) else {
-            bail!("Unknown IMSI {} tried to register", imsi)
+        let Some(sim) = self.lookup_sim(imsi) else {
+            bail!(Rejection::registration(
+                FgmmCause::FGS_SERVICES_NOT_ALLOWED,
+                format!("Unknown IMSI {} tried to register", imsi)
+            ))
         };
-        let challenge = self.generate_challenge(sim);
-        let r = crate::nas::build::authentication_request(&challenge.rand, &challenge.autn);
-        self.log_message("<< NasAuthenticationRequest");
-        let response = expect_nas!(AuthenticationResponse, self.nas_request(r).await?)?;
-        self.log_message(">> NasAuthenticationResponse");
-        self.check_authentication_response(response, &challenge)?;
-        let kamf = security::derive_kamf(&challenge.kseaf, imsi.as_bytes());
-        Ok(kamf)
+        let mut resync_attempted = false;
+        loop {
+            let challenge = self.generate_challenge(imsi, sim).await?;
+            let r = crate::nas::build::authentication_request(&challenge.rand, &challenge.autn);
+            self.log_message("<< NasAuthenticationRequest");
+            match self.nas_request(r, NasTimer::T3560).await? {
+                Nas5gsMessage::Gmm(_header, Nas5gmmMessage::AuthenticationResponse(response)) => {
+                    self.log_message(">> NasAuthenticationResponse");
+                    self.check_authentication_response(response, &challenge)?;
+                    let kamf = security::derive_kamf(&challenge.kseaf, imsi.as_bytes());
+                    return Ok(kamf);
+                }
+                Nas5gsMessage::Gmm(_header, Nas5gmmMessage::AuthenticationFailure(failure)) => {
+                    self.log_message(">> NasAuthenticationFailure");
+                    self.check_authentication_failure(imsi, &failure, resync_attempted)?;
+
+                    // TS33.102, 6.3.5: the HE/AuC sends fresh authentication vectors after resynchronization.
+                    self.resync_sqn(imsi, sim, failure, &challenge).await?;
+                    resync_attempted = true;
+                }
+                m => bail!("Expected Nas AuthenticationResponse but got {:?}", m),
+            }
+        }
     }
 
     async fn activate_nas_security(
         &mut self,
         ue_security_capabilities: NasUeSecurityCapability,
         kamf: &[u8; 32],
-    ) -> Result<()> {
-        self.configure_nas_security(kamf, &ue_security_capabilities);
-        let r = crate::nas::build::security_mode_command(ue_security_capabilities);
+    ) -> Result<NasRegistrationRequest> {
+        let (integrity_algorithm, ciphering_algorithm) =
+            self.configure_nas_security(kamf, &ue_security_capabilities)?;
+        let r = crate::nas::build::security_mode_command(
+            ue_security_capabilities.clone(),
+            integrity_algorithm,
+            ciphering_algorithm,
+        );
         self.log_message("<< NasSecurityModeCommand");
-        let rsp = expect_nas!(SecurityModeComplete, self.nas_request(r).await?)?;
+        let rsp = expect_nas!(
+            SecurityModeComplete,
+            self.nas_request(r, NasTimer::T3560).await?
+        )?;
         self.log_message(">> NasSecurityModeComplete");
-        self.check_nas_security_mode_complete(rsp)
+        self.check_nas_security_mode_complete(rsp, &ue_security_capabilities)
     }
 
-    async fn activate_rrc_security(&mut self, kamf: &[u8; 32]) -> Result<()> {
-        self.configure_rrc_security(kamf);
+    async fn activate_rrc_security(&mut self, ul_nas_count: u32) -> Result<()> {
+        self.configure_rrc_security(ul_nas_count)?;
         let r = crate::rrc::build::security_mode_command(1);
         self.log_message("<< RrcSecurityModeCommand");
         let _rrc_security_mode_complete = self.rrc_request(SrbId(1), r).await;
@@ -90,15 +486,27 @@ impl<'a, A: HandlerApi> InitialAccessProcedure<'a, A> {
         Ok(())
     }
 
-    async fn complete_nas_registration(&mut self) -> Result<()> {
+    async fn complete_nas_registration(
+        &mut self,
+        allowed_nssai: &[Snssai],
+        rejected_nssai: &[Snssai],
+        configured_nssai: Option<&[Snssai]>,
+        pdu_session_status: Option<&PduSessionStatus>,
+    ) -> Result<()> {
         let r = crate::nas::build::registration_accept(
-            self.config().sst,
-            &self.config().plmn,
-            &self.config().amf_ids,
+            allowed_nssai,
+            rejected_nssai,
+            configured_nssai,
+            self.config(),
             &self.ue.tmsi,
+            &self.tracking_areas(&self.ue.nr_cgi),
+            pdu_session_status,
         );
         self.log_message("<< NasRegistrationAccept");
-        let _rsp = expect_nas!(RegistrationComplete, self.nas_request(r).await?)?;
+        let _rsp = expect_nas!(
+            RegistrationComplete,
+            self.nas_request(r, NasTimer::T3550).await?
+        )?;
         self.log_message(">> NasRegistrationComplete");
         Ok(())
     }
@@ -135,40 +543,134 @@ impl<'a, A: HandlerApi> InitialAccessProcedure<'a, A> {
     fn check_registration_request(
         &self,
         registration_request: NasRegistrationRequest,
-    ) -> Result<(String, NasUeSecurityCapability)> {
+    ) -> Result<(MobileIdentity, NasUeSecurityCapability, Option<Vec<Snssai>>)> {
         self.log_message(">> NAS Registration Request");
 
         let Some(ue_security_capability) = registration_request.ue_security_capability else {
-            bail!("UE security capability missing from Registration Request");
+            bail!(Rejection::registration(
+                FgmmCause::INVALID_MANDATORY_INFORMATION,
+                "UE security capability missing from Registration Request".to_string()
+            ));
         };
         let ue_security_capability = ue_security_capability.to_owned();
-        let MobileIdentity { imsi, plmn } =
-            crate::nas::parse::fgs_mobile_identity(&registration_request.fgs_mobile_identity)?;
+        let mobile_identity =
+            self.parse_mobile_identity(&registration_request.fgs_mobile_identity)?;
 
-        if plmn != self.config().plmn {
-            // This will cause authentication to fail, because the UE will form its
-            // serving network name using its MCC/MNC, and we form ours using our MCC/MNC.
-            bail!(
-                "UE PLMN {:?} doesn't match ours {:?}",
-                &plmn,
-                self.config().plmn
-            )
+        let requested_nssai = registration_request
+            .requested_nssai
+            .as_ref()
+            .map(crate::nas::parse::nssai)
+            .transpose()?;
+
+        Ok((mobile_identity, ue_security_capability, requested_nssai))
+    }
+
+    // Work out the Allowed NSSAI from the UE's Requested NSSAI and its subscription - see TS23.501, 5.15.5.2.1.
+    // Returns the Allowed NSSAI, the requested S-NSSAIs that we reject, and the UE's subscribed S-NSSAIs.
+    fn select_nssai(
+        &self,
+        imsi: &str,
+        requested_nssai: Option<&[Snssai]>,
+    ) -> Result<(Vec<Snssai>, Vec<Snssai>, Vec<Snssai>)> {
+        let snssais = &self.config().snssais;
+        let subscribed_nssai: Vec<Snssai> = match self.lookup_sim(imsi) {
+            Some(sim) if !sim.snssais.is_empty() => snssais
+                .iter()
+                .filter(|x| sim.snssais.contains(x))
+                .copied()
+                .collect(),
+            _ => snssais.clone(),
+        };
+
+        // A UE that doesn't request any slices gets all the ones it is subscribed to.
+        let (allowed_nssai, rejected_nssai): (Vec<Snssai>, Vec<Snssai>) = match requested_nssai {
+            Some(requested_nssai) => requested_nssai
+                .iter()
+                .copied()
+                .partition(|x| subscribed_nssai.contains(x)),
+            None => (subscribed_nssai.clone(), vec![]),
+        };
+        if allowed_nssai.is_empty() {
+            bail!(Rejection::registration(
+                FgmmCause::NO_NETWORK_SLICES_AVAILABLE,
+                format!(
+                    "UE requested S-NSSAIs {:?} but is only allowed {:?}",
+                    requested_nssai.unwrap_or_default(),
+                    subscribed_nssai
+                )
+            ))
         }
+        Ok((allowed_nssai, rejected_nssai, subscribed_nssai))
+    }
 
-        Ok((imsi, ue_security_capability))
+    fn parse_mobile_identity(
+        &self,
+        fgs_mobile_identity: &NasFGsMobileIdentity,
+    ) -> Result<MobileIdentity> {
+        crate::nas::parse::fgs_mobile_identity(
+            fgs_mobile_identity,
+            &self.config().home_network_keys,
+        )
+        .map_err(|e| {
+            // For example, a SUCI that we can't de-conceal.
+            Rejection::registration(FgmmCause::ILLEGAL_UE, e.to_string()).into()
+        })
     }
 
-    fn generate_challenge(&self, sim: &SimCreds) -> Challenge {
-        let mut sqn = [0, 0, 0, 0, 0, 0];
-        let challenge = security::generate_challenge(
+    async fn generate_challenge(&self, imsi: &str, sim: &SimCreds) -> Result<Challenge> {
+        let sqn = self.sqn_store().next_sqn(imsi).await?;
+        Ok(security::generate_challenge(
             &sim.ki,
             &sim.opc,
             self.config().serving_network_name.as_bytes(),
-            &mut sqn,
-        );
-        // TODO: handle SQN properly
-        //self.increment_sim_sqn(sim);
-        challenge
+            &sqn,
+        ))
+    }
+
+    // TS24.501, 5.4.1.3.7: handle an Authentication Failure from the UE.  We only carry on if this is the
+    // first synch failure.  Otherwise, the UE doesn't accept our challenge - most likely because it has
+    // a different key - so we give up.
+    fn check_authentication_failure(
+        &self,
+        imsi: &str,
+        failure: &NasAuthenticationFailure,
+        resync_attempted: bool,
+    ) -> Result<()> {
+        let reason = match failure.fgmm_cause.value {
+            FgmmCause::SYNCH_FAILURE if !resync_attempted => return Ok(()),
+            FgmmCause::SYNCH_FAILURE => "Repeated synch failure",
+            FgmmCause::MAC_FAILURE => "MAC failure",
+            FgmmCause::NON_5G_AUTHENTICATION_UNACCEPTABLE => "Non-5G authentication unacceptable",
+            cause => {
+                bail!(Rejection::authentication(format!(
+                    "imsi-{imsi} rejected authentication challenge with 5GMM cause #{cause}"
+                )))
+            }
+        };
+        bail!(Rejection::authentication(format!(
+            "imsi-{imsi} rejected authentication challenge - {reason}"
+        )))
+    }
+
+    async fn resync_sqn(
+        &self,
+        imsi: &str,
+        sim: &SimCreds,
+        failure: NasAuthenticationFailure,
+        challenge: &Challenge,
+    ) -> Result<()> {
+        let Some(auts) = failure.authentication_failure_parameter else {
+            bail!(Rejection::authentication(
+                "Synch failure without authentication failure parameter".to_string()
+            ));
+        };
+        let Some(sqn_ms) = security::resync_sqn(&sim.ki, &sim.opc, &challenge.rand, &auts.value)
+        else {
+            bail!(Rejection::authentication(format!(
+                "AUTS from imsi-{imsi} failed verification"
+            )));
+        };
+        self.sqn_store().resync(imsi, &sqn_ms, self.logger).await
     }
 
     fn check_authentication_response(
@@ -191,7 +693,9 @@ impl<'a, A: HandlerApi> InitialAccessProcedure<'a, A> {
                 "Skipping authentication checks for testability reasons"
             );
         } else if authentication_response_parameter.value != challenge.xres_star {
-            bail!("Ue responded incorrectly to challenge")
+            bail!(Rejection::authentication(
+                "UE responded incorrectly to challenge".to_string()
+            ))
         }
 
         Ok(())
@@ -200,51 +704,152 @@ impl<'a, A: HandlerApi> InitialAccessProcedure<'a, A> {
     fn check_nas_security_mode_complete(
         &mut self,
         security_mode_complete: NasSecurityModeComplete,
-    ) -> Result<()> {
-        match security_mode_complete {
-            NasSecurityModeComplete {
-                imeisv: _imeisv,
-                nas_message_container: Some(container),
-                non_imeisv_pei: _non_imeisv_pei,
-            } => {
-                // TS24.501, 4.4.6 "After activating a 5G NAS security context resulting from a security
-                // mode control procedure... the UE shall include the entire REGISTRATION REQUEST ... in the ...
-                // NAS message container IE in the SECURITY MODE COMPLETE message."
-                let nas = self.ue.nas.decode(&container.value)?;
-                let _registration_request = expect_nas!(RegistrationRequest, nas)?;
-            }
-            m => {
-                warn!(self.logger, "Registration request missing from {:?}", m)
+        ue_security_capabilities: &NasUeSecurityCapability,
+    ) -> Result<NasRegistrationRequest> {
+        // TS24.501, 5.4.2.3: the UE sends its IMEISV if we asked for it, or another PEI if it doesn't have one.
+        let NasSecurityModeComplete {
+            imeisv,
+            nas_message_container,
+            non_imeisv_pei,
+        } = security_mode_complete;
+        if let Some(pei) = imeisv.as_ref().or(non_imeisv_pei.as_ref()) {
+            match crate::nas::parse::pei(pei) {
+                Ok(pei) => self.ue.pei = Some(pei),
+                Err(e) => warn!(self.logger, "Ignoring PEI - {e}"),
             }
         }
 
-        // TODO - do something with retransmitted registration request
+        // TS24.501, 4.4.6 "After activating a 5G NAS security context resulting from a security
+        // mode control procedure... the UE shall include the entire REGISTRATION REQUEST ... in the ...
+        // NAS message container IE in the SECURITY MODE COMPLETE message."
+        // Without it we can't check the UE security capabilities, so we don't carry on.
+        let Some(container) = nas_message_container else {
+            bail!(Rejection::registration(
+                FgmmCause::INVALID_MANDATORY_INFORMATION,
+                "Registration Request missing from Security Mode Complete".to_string()
+            ))
+        };
+
+        // The container is covered by the integrity protection of the outer message.
+        let nas = decode_nas_5gs_message(&container.value)?;
+        let registration_request = expect_nas!(RegistrationRequest, nas)?;
+
+        // The UE security capabilities in the initial Registration Request were not integrity protected.
+        // If they differ from the integrity protected copy, someone has tampered with them to try to get
+        // us to select a weaker algorithm.
+        let retransmitted = registration_request
+            .ue_security_capability
+            .as_ref()
+            .map(|x| &x.value);
+        if retransmitted != Some(&ue_security_capabilities.value) {
+            bail!(Rejection::registration(
+                FgmmCause::UE_SECURITY_CAPABILITIES_MISMATCH,
+                format!(
+                    "Possible bidding down attack - UE security capabilities {:?} in initial Registration Request but {:?} in Security Mode Complete",
+                    ue_security_capabilities.value, retransmitted
+                )
+            ))
+        }
+        Ok(registration_request)
+    }
+
+    // Check the UE's PEI against the allowed and denied equipment identities, so that a stolen device can't
+    // register even with a valid SIM.
+    fn check_equipment_identity(&self) -> Result<()> {
+        let pei = self.ue.pei.as_deref();
+        if !self.config().equipment_identities.allows(pei) {
+            bail!(Rejection::registration(
+                FgmmCause::ILLEGAL_ME,
+                format!(
+                    "Equipment {} not allowed",
+                    pei.unwrap_or("with unknown PEI")
+                )
+            ))
+        }
         Ok(())
     }
 
     fn configure_nas_security(
         &mut self,
         kamf: &[u8; 32],
-        _ue_security_capabilities: &NasUeSecurityCapability,
-    ) {
-        let knasint = security::derive_knasint(kamf);
-        // TODO - check UE security capabilities
+        ue_security_capabilities: &NasUeSecurityCapability,
+    ) -> Result<(NasIntegrityAlgorithm, NasCipheringAlgorithm)> {
+        // TS24.501, 9.11.3.54: the UE security capability is a bitmap of 5G-EA0 to 5G-EA7 followed by a
+        // bitmap of 5G-IA0 to 5G-IA7, in each case with algorithm 0 in the most significant bit.
+        let supports = |octet: usize, algorithm_identity: u8| {
+            ue_security_capabilities
+                .value
+                .get(octet)
+                .is_some_and(|x| x & (0x80 >> algorithm_identity) != 0)
+        };
+        let integrity_algorithm = self
+            .config()
+            .nas_integrity_algorithms
+            .iter()
+            .find(|x| supports(1, **x as u8));
+        let ciphering_algorithm = self
+            .config()
+            .nas_ciphering_algorithms
+            .iter()
+            .find(|x| supports(0, **x as u8));
+        let (Some(integrity_algorithm), Some(ciphering_algorithm)) =
+            (integrity_algorithm.copied(), ciphering_algorithm.copied())
+        else {
+            bail!(Rejection::registration(
+                FgmmCause::UE_SECURITY_CAPABILITIES_MISMATCH,
+                format!(
+                    "UE security capabilities mismatch - UE supports {:?}",
+                    ue_security_capabilities.value
+                )
+            ))
+        };
+        info!(
+            self.logger,
+            "Selected NAS algorithms {integrity_algorithm:?} and {ciphering_algorithm:?}"
+        );
+
         // TS33.501, 6.7.2: AMF starts integrity protection before transmitting SecurityModeCommand.
-        self.ue.nas.enable_security(knasint);
+        self.ue
+            .nas
+            .enable_security(*kamf, integrity_algorithm, ciphering_algorithm);
+        Ok((integrity_algorithm, ciphering_algorithm))
     }
 
-    fn configure_rrc_security(&mut self, kamf: &[u8; 32]) {
+    fn configure_rrc_security(&mut self, uplink_nas_count: u32) -> Result<()> {
         // Derive Kgnb, and from that kRRCInt.
 
         /* TS33.501, 6.8.1.1.2.3: "The NAS (uplink and downlink) COUNTs are set to start
         values, and the start value of the uplink NAS COUNT shall be used as freshness parameter in the KgNB derivation from
         the fresh KAMF (after primary authentication) when UE receives AS SMC the KgNB is derived from the current 5G NAS
-        security context, i.e., the fresh KAMF is used to derive the KgNB." */
-        let uplink_nas_count = 0;
+        security context, i.e., the fresh KAMF is used to derive the KgNB."
+
+        When we resume an existing NAS security context instead, the uplink NAS COUNT is that of the initial NAS
+        message (TS33.501, 6.9.2.1.1 and A.9). */
+        let Some(kamf) = self.ue.nas.kamf() else {
+            bail!("RRC security activation with no NAS security context")
+        };
         let kgnb = security::derive_kgnb(kamf, uplink_nas_count);
         let krrcint = security::derive_krrcint(&kgnb);
 
         // Tell the PDCP layer to add NIA2 integrity protection henceforth.
         self.ue.pdcp_tx.enable_security(krrcint);
+        Ok(())
     }
 }
+
+// The PDU sessions that a Registration Request says are active, and the ones that it says have uplink data, if it
+// gives them.
+fn session_status(
+    registration_request: &NasRegistrationRequest,
+) -> (Option<Vec<u8>>, Option<Vec<u8>>) {
+    (
+        registration_request
+            .pdu_session_status
+            .as_ref()
+            .map(|x| crate::nas::parse::pdu_session_ids(&x.value)),
+        registration_request
+            .uplink_data_status
+            .as_ref()
+            .map(|x| crate::nas::parse::pdu_session_ids(&x.value)),
+    )
+}
@@ -1,40 +1,95 @@
+mod configuration_update;
 mod deregistration;
 mod initial_access;
 mod pdu_session_establishment;
+mod pdu_session_modification;
+mod pdu_session_release;
+mod registration_update;
+mod service_request;
 mod ue_context_release;
 mod ue_message_handler;
 mod ul_information_transfer;
 mod uplink_nas;
 
+pub use configuration_update::ConfigurationUpdateProcedure;
 pub use deregistration::DeregistrationProcedure;
 pub use initial_access::InitialAccessProcedure;
 pub use pdu_session_establishment::SessionEstablishmentProcedure;
+pub use pdu_session_modification::SessionModificationProcedure;
+pub use pdu_session_release::SessionReleaseProcedure;
+pub use registration_update::RegistrationUpdateProcedure;
+pub use service_request::ServiceRequestProcedure;
 pub use ue_context_release::UeContextReleaseProcedure;
 pub use ue_message_handler::UeMessageHandler;
 pub use ul_information_transfer::UlInformationTransferProcedure;
 pub use uplink_nas::UplinkNasProcedure;
 
 use super::Procedure;
-use crate::{HandlerApi, UeContext};
-use anyhow::{Result, anyhow, bail};
+use crate::{HandlerApi, SimCreds, UeContext, UeMessage};
+use anyhow::{Result, anyhow, bail, ensure};
 use asn1_per::SerDes;
 use async_channel::Receiver;
 use f1ap::{
-    DlRrcMessageTransferProcedure, F1apPdu, InitiatingMessage, RrcContainer, SrbId,
-    UlRrcMessageTransfer,
+    CellGroupConfig, DlRrcMessageTransferProcedure, DlUpTnlInformationToBeSetupItem,
+    DuToCuRrcInformation, F1apPdu, InitiatingMessage, RrcContainer, SrbId,
+    UeContextModificationProcedure, UeContextModificationRequest, UeContextModificationResponse,
+    UlRrcMessageTransfer, UpTransportLayerInformation,
+};
+use oxirush_nas::{
+    Nas5gmmMessage, Nas5gsMessage, Nas5gsmMessage, decode_nas_5gs_message,
+    messages::{Nas5gsmHeader, NasUlNasTransport},
 };
-use oxirush_nas::Nas5gsMessage;
 use pdcp::{PdcpPdu, PdcpTx};
 use rrc::{
-    C1_6, CriticalExtensions37, DedicatedNasMessage, UlDcchMessage, UlDcchMessageType,
-    UlInformationTransfer, UlInformationTransferIEs,
+    C1_6, CriticalExtensions37, DedicatedNasMessage, DlDcchMessage, UlDcchMessage,
+    UlDcchMessageType, UlInformationTransfer, UlInformationTransferIEs,
 };
-use slog::Logger;
+use slog::{Logger, warn};
+use std::fmt;
+use xxap::GtpTunnel;
+
+// The most messages that a procedure will set aside while it waits for a particular message from the UE.
+const MAX_DEFERRED_MESSAGES: usize = 16;
+
+// NAS timers that guard the network's 5GMM requests - see TS24.501, 10.2.
+#[derive(Debug, Clone, Copy)]
+pub enum NasTimer {
+    // Deregistration Request
+    T3522,
+    // Registration Accept
+    T3550,
+    // Configuration Update Command
+    T3555,
+    // Authentication Request and Security Mode Command
+    T3560,
+    // Identity Request
+    T3570,
+}
+
+// TS24.501, 5.4.1.3.7 etc: the procedure is aborted on the fifth expiry of the timer.
+const NAS_MAX_RETRANSMISSIONS: usize = 4;
+
+/// Error returned when a NAS timer expires for the last time.  The UE message handler then releases the UE's context.
+#[derive(Debug)]
+pub struct NasTimerExpiry(pub NasTimer);
+
+impl fmt::Display for NasTimerExpiry {
+    fn fmt(&self, f: &mut fmt::Formatter<'_>) -> fmt::Result {
+        write!(
+            f,
+            "{:?} expired {} times - abort procedure",
+            self.0,
+            NAS_MAX_RETRANSMISSIONS + 1
+        )
+    }
+}
+
+impl std::error::Error for NasTimerExpiry {}
 
 pub struct UeProcedure<'a, A: HandlerApi> {
     base: Procedure<'a, A>,
     ue: &'a mut UeContext,
-    receiver: &'a Receiver<F1apPdu>,
+    receiver: &'a Receiver<UeMessage>,
 }
 
 impl<'a, A: HandlerApi> std::ops::Deref for UeProcedure<'a, A> {
@@ -50,7 +105,7 @@ impl<'a, A: HandlerApi> UeProcedure<'a, A> {
         api: &'a A,
         ue: &'a mut UeContext,
         logger: &'a Logger,
-        receiver: &'a Receiver<F1apPdu>,
+        receiver: &'a Receiver<UeMessage>,
     ) -> Self {
         UeProcedure {
             base: Procedure::new(api, logger),
@@ -59,11 +114,36 @@ impl<'a, A: HandlerApi> UeProcedure<'a, A> {
         }
     }
 
+    // The subscription data of the UE's SIM, once we know its IMSI.
+    fn sim(&self) -> Option<&'static SimCreds> {
+        self.lookup_sim(self.ue.imsi.as_deref()?)
+    }
+
     async fn rrc_request<T: Send + SerDes>(
         &mut self,
         srb_id: SrbId,
         rrc: T,
     ) -> Result<UlDcchMessage> {
+        self.rrc_indication(srb_id, rrc).await?;
+        self.receive_rrc().await
+    }
+
+    async fn receive_rrc(&mut self) -> Result<UlDcchMessage> {
+        let message = self.receiver.recv().await?;
+        let UeMessage::F1ap(pdu) = message else {
+            bail!("Expected UlRrcMessageTransfer, got {message:?}");
+        };
+        let F1apPdu::InitiatingMessage(InitiatingMessage::UlRrcMessageTransfer(
+            ul_rrc_message_transfer,
+        )) = *pdu
+        else {
+            bail!("Expected UlRrcMessageTransfer, got {pdu:?}");
+        };
+        self.log_message(">> F1ap UlRrcMessageTransfer");
+        self.extract_ul_dcch_message(ul_rrc_message_transfer)
+    }
+
+    async fn rrc_indication<T: Send + SerDes>(&mut self, srb_id: SrbId, rrc: T) -> Result<()> {
         let rrc_bytes = rrc.into_bytes()?;
         let rrc_container = maybe_pdcp_encapsulate(rrc_bytes, srb_id.0, &mut self.ue.pdcp_tx);
         let dl_message = crate::f1ap::build::dl_rrc_message_transfer(
@@ -76,15 +156,7 @@ impl<'a, A: HandlerApi> UeProcedure<'a, A> {
         self.api
             .f1ap_indication::<DlRrcMessageTransferProcedure>(dl_message, self.logger)
             .await;
-        let pdu = self.receiver.recv().await?;
-        let F1apPdu::InitiatingMessage(InitiatingMessage::UlRrcMessageTransfer(
-            ul_rrc_message_transfer,
-        )) = pdu
-        else {
-            bail!("Expected UlRrcMessageTransfer, got {pdu:?}");
-        };
-        self.log_message(">> F1ap UlRrcMessageTransfer");
-        self.extract_ul_dcch_message(ul_rrc_message_transfer)
+        Ok(())
     }
 
     fn extract_ul_dcch_message(
@@ -96,33 +168,192 @@ impl<'a, A: HandlerApi> UeProcedure<'a, A> {
         Ok(UlDcchMessage::from_bytes(rrc_message_bytes)?)
     }
 
-    async fn nas_request(&mut self, nas: Nas5gsMessage) -> Result<Nas5gsMessage> {
-        let nas_bytes = self.ue.nas.encode(nas)?;
-        let rrc = crate::rrc::build::dl_information_transfer(
-            1, // TODO transaction ID
-            DedicatedNasMessage(nas_bytes),
-        );
+    // Send a NAS message to which the UE does not reply.
+    async fn nas_indication(&mut self, nas: Nas5gsMessage) -> Result<()> {
+        let rrc = self.nas_dl_information_transfer(nas)?;
+        self.rrc_indication(SrbId(1), rrc).await
+    }
+
+    // Send a NAS message and wait for the UE to reply, retransmitting the message each time the NAS timer expires.
+    // Each retransmission is encoded afresh, so that it has a new downlink NAS COUNT and MAC and gets past the UE's
+    // replay protection (TS33.501, 6.4.3.2).
+    async fn nas_request(&mut self, nas: Nas5gsMessage, timer: NasTimer) -> Result<Nas5gsMessage> {
+        let timeout = self.config().nas_retransmission_timer;
+        for retransmission in 0..=NAS_MAX_RETRANSMISSIONS {
+            if retransmission > 0 {
+                warn!(self.logger, "{timer:?} expired - retransmit NAS message");
+            }
+            let rrc = self.nas_dl_information_transfer(nas.clone())?;
+            self.rrc_indication(SrbId(1), rrc).await?;
+            if let Ok(response) = async_std::future::timeout(timeout, self.receive_nas()).await {
+                return response;
+            }
+        }
+        bail!(NasTimerExpiry(timer))
+    }
 
-        self.rrc_request(SrbId(1), rrc)
-            .await
-            .and_then(|x| match x.message {
-                UlDcchMessageType::C1(C1_6::UlInformationTransfer(UlInformationTransfer {
-                    critical_extensions:
-                        CriticalExtensions37::UlInformationTransfer(UlInformationTransferIEs {
-                            dedicated_nas_message: Some(DedicatedNasMessage(response_bytes)),
-                            ..
-                        }),
-                })) => {
-                    let msg = self.ue.nas.decode(&response_bytes)?;
-                    Ok(msg)
+    async fn receive_nas(&mut self) -> Result<Nas5gsMessage> {
+        match self.receive_rrc().await?.message {
+            UlDcchMessageType::C1(C1_6::UlInformationTransfer(UlInformationTransfer {
+                critical_extensions:
+                    CriticalExtensions37::UlInformationTransfer(UlInformationTransferIEs {
+                        dedicated_nas_message: Some(DedicatedNasMessage(response_bytes)),
+                        ..
+                    }),
+            })) => {
+                let msg = self.ue.nas.decode(&response_bytes)?;
+                Ok(msg)
+            }
+            _ => Err(anyhow!(
+                "Expected RrcUlInformationTransfer with DedicatedNasMessage"
+            )),
+        }
+    }
+
+    // Receive the next uplink NAS message for which `wanted` returns true.  Any other messages that arrive in the
+    // meantime are set aside, to be handled once the current procedure has finished.
+    async fn receive_nas_deferring_others(
+        &mut self,
+        wanted: impl Fn(&Nas5gsMessage) -> bool,
+    ) -> Result<Nas5gsMessage> {
+        loop {
+            let message = self.receiver.recv().await?;
+            let UeMessage::F1ap(pdu) = message else {
+                self.defer(message)?;
+                continue;
+            };
+            let ul_rrc_message_transfer = match *pdu {
+                F1apPdu::InitiatingMessage(InitiatingMessage::UlRrcMessageTransfer(x)) => x,
+                pdu => {
+                    self.defer(UeMessage::F1ap(Box::new(pdu)))?;
+                    continue;
                 }
-                _ => Err(anyhow!(
-                    "Expected RrcUlInformationTransfer with DedicatedNasMessage"
-                )),
-            })
+            };
+            self.log_message(">> F1ap UlRrcMessageTransfer");
+            let rrc = self.extract_ul_dcch_message(ul_rrc_message_transfer)?;
+            let UlDcchMessageType::C1(C1_6::UlInformationTransfer(UlInformationTransfer {
+                critical_extensions:
+                    CriticalExtensions37::UlInformationTransfer(UlInformationTransferIEs {
+                        dedicated_nas_message: Some(DedicatedNasMessage(nas_bytes)),
+                        ..
+                    }),
+            })) = rrc.message
+            else {
+                warn!(self.logger, "Ignoring unexpected RRC message {rrc:?}");
+                continue;
+            };
+            let nas = match self.ue.nas.decode(&nas_bytes) {
+                Ok(nas) => nas,
+                Err(e) => {
+                    // TS24.501, 4.4.4.3: discard messages that fail the integrity check.
+                    warn!(self.logger, "{e}");
+                    continue;
+                }
+            };
+            if wanted(&nas) {
+                return Ok(nas);
+            }
+            self.defer(UeMessage::Nas(Box::new(nas)))?;
+        }
+    }
+
+    fn defer(&mut self, message: UeMessage) -> Result<()> {
+        ensure!(
+            self.ue.deferred_messages.len() < MAX_DEFERRED_MESSAGES,
+            "Too many messages from UE while waiting for a response"
+        );
+        self.ue.deferred_messages.push_back(message);
+        Ok(())
+    }
+
+    // Receive a 5GSM message from the UE in a UL NAS Transport.
+    async fn receive_5gsm(&mut self) -> Result<(Nas5gsmHeader, Nas5gsmMessage)> {
+        let nas = self.receive_nas().await?;
+        let Nas5gsMessage::Gmm(
+            _header,
+            Nas5gmmMessage::UlNasTransport(NasUlNasTransport {
+                payload_container, ..
+            }),
+        ) = nas
+        else {
+            bail!("Expected UlNasTransport, got {nas:?}")
+        };
+        match decode_nas_5gs_message(&payload_container.value)? {
+            Nas5gsMessage::Gsm(header, message) => Ok((header, message)),
+            m => Err(anyhow!("Expected 5GSM message, got {m:?}")),
+        }
+    }
+
+    fn check_rrc_reconfiguration_complete(&self, message: UlDcchMessage) -> Result<()> {
+        let UlDcchMessage {
+            message: UlDcchMessageType::C1(C1_6::RrcReconfigurationComplete(_response)),
+        } = message
+        else {
+            bail!("Expected RrcReconfigurationComplete, got {:?}", message);
+        };
+        self.log_message(">> RrcReconfigurationComplete");
+        Ok(())
+    }
+
+    // Send the DU a UE Context Modification Request.  Returns the CellGroupConfig for us to pass on to the UE, if
+    // the DU supplies one (TS38.473, 8.3.4.2), and the downlink tunnels of any DRBs that it set up, as (DRB ID,
+    // tunnel) pairs.
+    async fn perform_f1_ue_context_modification(
+        &self,
+        ue_context_modification_request: UeContextModificationRequest,
+    ) -> Result<(Option<CellGroupConfig>, Vec<(u8, GtpTunnel)>)> {
+        self.log_message("<< UeContextModificationRequest");
+        let rsp = self
+            .f1ap_request::<UeContextModificationProcedure>(
+                ue_context_modification_request,
+                self.logger,
+            )
+            .await?;
+        self.log_message(">> UeContextModificationResponse");
+        Ok(check_ue_context_modification_response(rsp))
+    }
+
+    fn nas_dl_information_transfer(&mut self, nas: Nas5gsMessage) -> Result<DlDcchMessage> {
+        let nas_bytes = self.ue.nas.encode(nas)?;
+        Ok(dl_information_transfer(nas_bytes))
     }
 }
 
+fn check_ue_context_modification_response(
+    ue_context_modification_response: UeContextModificationResponse,
+) -> (Option<CellGroupConfig>, Vec<(u8, GtpTunnel)>) {
+    let UeContextModificationResponse {
+        du_to_cu_rrc_information,
+        drbs_setup_mod_list,
+        ..
+    } = ue_context_modification_response;
+    let remote_tunnels = drbs_setup_mod_list
+        .map(|x| {
+            x.0.into_iter()
+                .map(|drb| {
+                    let DlUpTnlInformationToBeSetupItem {
+                        dl_up_tnl_information: UpTransportLayerInformation::GtpTunnel(tunnel),
+                    } = drb.dl_up_tnl_information_to_be_setup_list.0.head;
+                    (drb.drb_id.0, tunnel)
+                })
+                .collect()
+        })
+        .unwrap_or_default();
+    let cell_group_config = du_to_cu_rrc_information.map(
+        |DuToCuRrcInformation {
+             cell_group_config, ..
+         }| cell_group_config,
+    );
+    (cell_group_config, remote_tunnels)
+}
+
+fn dl_information_transfer(nas_bytes: Vec<u8>) -> DlDcchMessage {
+    crate::rrc::build::dl_information_transfer(
+        1, // TODO transaction ID
+        DedicatedNasMessage(nas_bytes),
+    )
+}
+
 fn maybe_pdcp_encapsulate(rrc_bytes: Vec<u8>, srb_id: u8, pdcp: &mut PdcpTx) -> RrcContainer {
     RrcContainer(if srb_id == 0 {
         rrc_bytes
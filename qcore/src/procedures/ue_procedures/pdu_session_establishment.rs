@@ -1,16 +1,25 @@
 use super::UeProcedure;
-use crate::{HandlerApi, PduSession};
+use crate::nas::{FgsmCause, PduSessionType};
+use crate::userplane::DrbTunnel;
+use crate::{Drb, HandlerApi, PduSession, QosFlow, QosRule};
 use anyhow::{Result, bail};
-use asn1_per::nonempty;
+use asn1_per::{NonEmpty, nonempty};
 use derive_deref::{Deref, DerefMut};
 use f1ap::{
     CellGroupConfig, DlUpTnlInformationToBeSetupItem, DuToCuRrcInformation, SrbId,
     UeContextSetupProcedure, UeContextSetupResponse, UpTransportLayerInformation,
 };
 use oxirush_nas::messages::{Nas5gsmHeader, NasPduSessionEstablishmentRequest};
-use rrc::{C1_6, UlDcchMessage, UlDcchMessageType};
+use slog::warn;
 use xxap::{GtpTunnel, Snssai};
 
+// TS38.331 - DRB-Identity is an integer in the range 1..32.
+const MAX_DRB_ID: u8 = 32;
+
+// Precedence of the QoS rule made from a data network's first QoS flow template.  The default QoS rule has the
+// lowest precedence, 255, and any rules that the UE adds later can go either side of these.
+const TEMPLATE_RULE_PRECEDENCE: u8 = 0x80;
+
 #[derive(Deref, DerefMut)]
 pub struct SessionEstablishmentProcedure<'a, A: HandlerApi>(UeProcedure<'a, A>);
 
@@ -22,45 +31,298 @@ impl<'a, A: HandlerApi> SessionEstablishmentProcedure<'a, A> {
     pub async fn run(
         &mut self,
         hdr: Nas5gsmHeader,
-        _r: NasPduSessionEstablishmentRequest,
+        r: NasPduSessionEstablishmentRequest,
         dnn: Option<Vec<u8>>,
+        s_nssai: Option<Vec<u8>>,
     ) -> Result<()> {
         self.log_message(">> NasPduSessionEstablishmentRequest");
         // TODO: check request
         let session_id = hdr.pdu_session_identity;
+        let pti = hdr.procedure_transaction_identity;
+        if self.ue.pdu_sessions.iter().any(|x| x.id == session_id) {
+            warn!(self.logger, "PDU session {session_id} already exists");
+            return self
+                .reject(session_id, pti, FgsmCause::INVALID_PDU_SESSION_IDENTITY)
+                .await;
+        }
+
+        let (data_network_idx, dnn) = match self.select_data_network(dnn) {
+            Ok(x) => x,
+            Err(e) => {
+                warn!(self.logger, "{e}");
+                return self
+                    .reject(session_id, pti, FgsmCause::MISSING_OR_UNKNOWN_DNN)
+                    .await;
+            }
+        };
+
+        let snssai = self.select_snssai(s_nssai)?;
+
+        let requested_pdu_session_type = r.pdu_session_type.as_ref().map(|x| x.value & 0b111);
+        let (pdu_session_type, fgsm_cause) =
+            match self.select_pdu_session_type(requested_pdu_session_type, data_network_idx) {
+                Ok(x) => x,
+                Err(e) => {
+                    warn!(self.logger, "{e}");
+                    let fgsm_cause = if requested_pdu_session_type == Some(PduSessionType::IPV6) {
+                        FgsmCause::PDU_SESSION_TYPE_IPV4_ONLY_ALLOWED
+                    } else {
+                        FgsmCause::UNKNOWN_PDU_SESSION_TYPE
+                    };
+                    return self.reject(session_id, pti, fgsm_cause).await;
+                }
+            };
+
+        // Each PDU session gets its own DRBs, one for each 5QI of its QoS flows.
+        let (qos_rules, qos_flows) = self.initial_qos(data_network_idx, pdu_session_type);
+        let free_drb_ids: Vec<u8> = (1..=MAX_DRB_ID)
+            .filter(|id| {
+                !self
+                    .ue
+                    .pdu_sessions
+                    .iter()
+                    .any(|x| x.drbs.iter().any(|drb| drb.id == *id))
+            })
+            .collect();
+        let drbs = match Drb::map_qos_flows(&qos_flows, &free_drb_ids) {
+            Ok(x) => x,
+            Err(e) => {
+                warn!(
+                    self.logger,
+                    "No free DRB for PDU session {session_id} - {e}"
+                );
+                return self
+                    .reject(session_id, pti, FgsmCause::INSUFFICIENT_RESOURCES)
+                    .await;
+            }
+        };
+        let session_ambr = self.sim().and_then(|x| x.session_ambr).unwrap_or_default();
+        let userplane_info = match self
+            .api
+            .reserve_userplane_session(
+                data_network_idx,
+                pdu_session_type,
+                &session_ambr,
+                self.ue.imsi.as_deref(),
+                &self.logger,
+            )
+            .await
+        {
+            Ok(x) => x,
+            Err(e) => {
+                warn!(self.logger, "Failed to reserve userplane session - {e}");
+                return self
+                    .reject(session_id, pti, FgsmCause::INSUFFICIENT_RESOURCES)
+                    .await;
+            }
+        };
         let session = PduSession {
             id: session_id,
-            snssai: Snssai(self.config().sst, None),
-            userplane_info: self.api.reserve_userplane_session(&self.logger).await?,
-            dnn: dnn.unwrap_or_default(),
+            snssai,
+            userplane_info,
+            dnn,
+            session_ambr,
+            qos_rules,
+            qos_flows,
+            drbs,
+        };
+        session.userplane_info.set_qos_rules(&session.qos_rules);
+
+        // TS24.501, 6.4.1.2: answer any requests for DNS servers and the like in the UE's extended PCO.
+        let pco_requests = match &r.extended_protocol_configuration_options {
+            Some(epco) => crate::nas::parse::pco_requests(&epco.value).unwrap_or_else(|e| {
+                warn!(self.logger, "Ignoring extended PCO - {e}");
+                vec![]
+            }),
+            None => vec![],
         };
 
-        let (cell_group_config, remote_tunnel_info) =
-            self.perform_f1_ue_context_setup(&session).await?;
+        // Until the session is stored in the UE context, we are responsible for freeing its userplane resources.
+        let (cell_group_config, accept) = match self
+            .setup_session(&session, pti, fgsm_cause, &pco_requests, data_network_idx)
+            .await
+        {
+            Ok(x) => x,
+            Err(e) => {
+                self.delete_userplane_session(&session.userplane_info, self.logger)
+                    .await;
+                return Err(e);
+            }
+        };
+        let drbs = session.drbs.clone();
+        self.ue.pdu_sessions.push(session);
+
+        self.log_message("<< NasPduSessionEstablishmentAccept");
+        self.perform_rrc_reconfiguration(accept, cell_group_config, session_id, drbs)
+            .await
+    }
+
+    // The default QoS rule and QoS flow, plus, for an IP session, a QoS rule and QoS flow for each of the data
+    // network's QoS flow templates.  The templates' rules take precedence over the default rule in the order that
+    // they are configured.  Their packet filters are for IP traffic, so an Ethernet session doesn't get them.
+    fn initial_qos(
+        &self,
+        data_network_idx: usize,
+        pdu_session_type: u8,
+    ) -> (Vec<QosRule>, Vec<QosFlow>) {
+        let mut qos_rules = vec![QosRule::default_rule()];
+        let mut qos_flows = vec![QosFlow::default_flow()];
+        if pdu_session_type != PduSessionType::ETHERNET {
+            let templates = &self.config().data_networks[data_network_idx].qos_flows;
+            for (template, n) in templates.iter().zip(0u8..) {
+                // Rule IDs and QFIs 2 onwards, following those of the default rule and flow.
+                let qfi = n + 2;
+                qos_rules.push(template.qos_rule(qfi, TEMPLATE_RULE_PRECEDENCE + n, qfi));
+                qos_flows.push(template.qos_flow(qfi));
+            }
+        }
+        (qos_rules, qos_flows)
+    }
+
+    // Find the data network the UE asked for, falling back to the default (first) data network if
+    // it didn't specify one.  Returns its index in the data network table and its name.
+    fn select_data_network(&self, dnn: Option<Vec<u8>>) -> Result<(usize, String)> {
+        let data_networks = &self.config().data_networks;
+        let Some(dnn) = dnn else {
+            let Some(default) = data_networks.first() else {
+                bail!("No data networks configured");
+            };
+            return Ok((0, default.dnn.clone()));
+        };
+        let dnn = crate::nas::parse::dnn(&dnn)?;
+        let Some(idx) = data_networks
+            .iter()
+            .position(|x| x.dnn.eq_ignore_ascii_case(&dnn))
+        else {
+            bail!("Unknown DNN {dnn}");
+        };
+        Ok((idx, data_networks[idx].dnn.clone()))
+    }
+
+    // Use the slice the UE asked for, falling back to the first S-NSSAI in its Allowed NSSAI if it didn't specify
+    // one.  A request for a slice that the UE isn't allowed doesn't get this far - see UplinkNasProcedure.
+    fn select_snssai(&self, s_nssai: Option<Vec<u8>>) -> Result<Snssai> {
+        let Some(s_nssai) = s_nssai else {
+            let Some(default) = self.ue.allowed_nssai.first() else {
+                bail!("UE has no allowed S-NSSAIs");
+            };
+            return Ok(*default);
+        };
+        crate::nas::parse::s_nssai(&s_nssai)
+    }
+
+    // Choose between IPv4, IPv6, dual stack and Ethernet, based on what the UE asked for and whether the data
+    // network has an IPv6 prefix and a tap device - see TS24.501, 6.4.1.2.  Returns the PDU session type, and the
+    // 5GSM cause to send if the UE gets less than it asked for.
+    fn select_pdu_session_type(
+        &self,
+        requested: Option<u8>,
+        data_network_idx: usize,
+    ) -> Result<(u8, Option<u8>)> {
+        let data_network = &self.config().data_networks[data_network_idx];
+        let ipv6_supported = data_network.ue_ipv6_prefix.is_some();
+        let ethernet_supported = data_network.n6_tap_name.is_some();
+        Ok(match requested {
+            Some(PduSessionType::IPV6) if ipv6_supported => (PduSessionType::IPV6, None),
+            Some(PduSessionType::IPV6) => {
+                bail!("IPv6 PDU session requested on IPv4 only data network")
+            }
+            Some(PduSessionType::IPV4V6) if ipv6_supported => (PduSessionType::IPV4V6, None),
+            Some(PduSessionType::IPV4V6) => (
+                PduSessionType::IPV4,
+                Some(FgsmCause::PDU_SESSION_TYPE_IPV4_ONLY_ALLOWED),
+            ),
+            Some(PduSessionType::ETHERNET) if ethernet_supported => {
+                (PduSessionType::ETHERNET, None)
+            }
+            Some(PduSessionType::ETHERNET) => {
+                bail!("Ethernet PDU session requested on data network without a tap device")
+            }
+            Some(PduSessionType::IPV4) | None => (PduSessionType::IPV4, None),
+            Some(other) => bail!("Unsupported PDU session type {other} requested"),
+        })
+    }
+
+    async fn reject(&mut self, session_id: u8, pti: u8, fgsm_cause: u8) -> Result<()> {
+        let reject =
+            crate::nas::build::pdu_session_establishment_reject(session_id, pti, fgsm_cause)?;
+        self.log_message("<< NasPduSessionEstablishmentReject");
+        self.nas_indication(reject).await
+    }
+
+    // Set up the session's DRBs and userplane forwarding, and return the DU's CellGroupConfig, if any, and the
+    // encoded PDU Session Establishment Accept.
+    async fn setup_session(
+        &mut self,
+        session: &PduSession,
+        pti: u8,
+        fgsm_cause: Option<u8>,
+        pco_requests: &[u16],
+        data_network_idx: usize,
+    ) -> Result<(Option<CellGroupConfig>, Vec<u8>)> {
+        let (cell_group_config, drb_tunnels) = self.perform_f1_drb_setup(session).await?;
 
         let accept = crate::nas::build::pdu_session_establishment_accept(
-            &session,
-            hdr.procedure_transaction_identity,
+            session,
+            pti,
+            fgsm_cause,
+            pco_requests,
+            &self.config().data_networks[data_network_idx],
         )?;
         let accept = self.ue.nas.encode(accept)?;
 
-        self.commit_userplane_session(&session.userplane_info, remote_tunnel_info, &self.logger)
-            .await?;
-        self.ue.pdu_sessions.push(session);
+        self.commit_userplane_session(
+            &session.userplane_info,
+            drb_tunnels,
+            self.ue.imsi.as_deref(),
+            &self.logger,
+        )
+        .await?;
+        Ok((cell_group_config, accept))
+    }
 
-        self.log_message("<< NasPduSessionEstablishmentAccept");
-        self.perform_rrc_reconfiguration(accept, cell_group_config, session_id)
+    // The first session's DRBs set up the F1 UE context.  Later ones are added to it.  Returns the DU's
+    // CellGroupConfig, if any, and the downlink tunnel of each of the session's DRBs.
+    async fn perform_f1_drb_setup(
+        &mut self,
+        session: &PduSession,
+    ) -> Result<(Option<CellGroupConfig>, Vec<DrbTunnel>)> {
+        let (cell_group_config, remote_tunnels) = if self.ue.f1_ue_context_established {
+            self.setup_drbs(session).await?
+        } else {
+            let (cell_group_config, remote_tunnels) =
+                self.perform_f1_ue_context_setup(session).await?;
+            self.ue.f1_ue_context_established = true;
+            (Some(cell_group_config), remote_tunnels)
+        };
+        Ok((cell_group_config, session.drb_tunnels(&remote_tunnels)?))
+    }
+
+    // Add the session's DRBs to the existing F1 UE context.
+    async fn setup_drbs(
+        &self,
+        session: &PduSession,
+    ) -> Result<(Option<CellGroupConfig>, Vec<(u8, GtpTunnel)>)> {
+        let ue_context_modification_request =
+            crate::f1ap::build::ue_context_modification_request_setup_drbs(
+                self.ue,
+                self.config().ip_addr.into(),
+                session,
+                self.sim().and_then(|x| x.ue_ambr),
+            )?;
+        self.perform_f1_ue_context_modification(ue_context_modification_request)
             .await
     }
 
     async fn perform_f1_ue_context_setup(
         &self,
         session: &PduSession,
-    ) -> Result<(CellGroupConfig, GtpTunnel)> {
+    ) -> Result<(CellGroupConfig, Vec<(u8, GtpTunnel)>)> {
         let ue_context_setup_request = crate::f1ap::build::ue_context_setup_request(
             self.ue,
             self.config().ip_addr.into(),
-            session,
+            std::slice::from_ref(session),
+            self.sim().and_then(|x| x.ue_ambr),
         )?;
         self.log_message("<< UeContextSetupRequest");
         let rsp = self
@@ -73,26 +335,33 @@ impl<'a, A: HandlerApi> SessionEstablishmentProcedure<'a, A> {
     async fn perform_rrc_reconfiguration(
         &mut self,
         nas: Vec<u8>,
-        cell_group_config: CellGroupConfig,
+        cell_group_config: Option<CellGroupConfig>,
         pdu_session_id: u8,
+        drbs: Vec<Drb>,
     ) -> Result<()> {
+        let Some(drbs) = NonEmpty::from_vec(
+            drbs.into_iter()
+                .map(|drb| (pdu_session_id, drb.id, drb.qfis))
+                .collect(),
+        ) else {
+            bail!("PDU session {pdu_session_id} has no DRBs");
+        };
         let rrc_reconfiguration = crate::rrc::build::reconfiguration(
             0,
             Some(nonempty![nas]),
-            cell_group_config.0,
-            pdu_session_id,
+            cell_group_config.map(|x| x.0),
+            drbs,
+            self.config().sdap_header_dl,
         );
         self.log_message("<< RrcReconfiguration(Nas)");
         let response = self.rrc_request(SrbId(1), rrc_reconfiguration).await?;
-        self.check_rrc_reconfiguration_complete(response)?;
-        self.log_message(">> RrcReconfigurationComplete");
-        Ok(())
+        self.check_rrc_reconfiguration_complete(response)
     }
 
     fn check_ue_context_setup_response(
         &self,
         ue_context_setup_response: UeContextSetupResponse,
-    ) -> Result<(CellGroupConfig, GtpTunnel)> {
+    ) -> Result<(CellGroupConfig, Vec<(u8, GtpTunnel)>)> {
         // TODO further checking of message - e.g. was SRB2 confirmed?
 
         // TS38.473, 8.3.1.2: "If the CellGroupConfig IE is included in the DU to CU RRC Information IE contained
@@ -110,26 +379,17 @@ impl<'a, A: HandlerApi> SessionEstablishmentProcedure<'a, A> {
         else {
             bail!("UeContextSetupResponse missed expected information");
         };
-        let DlUpTnlInformationToBeSetupItem {
-            dl_up_tnl_information: UpTransportLayerInformation::GtpTunnel(remote_tunnel_info),
-        } = drbs_setup_list
+        let remote_tunnels = drbs_setup_list
             .0
-            .head
-            .dl_up_tnl_information_to_be_setup_list
-            .0
-            .head;
+            .into_iter()
+            .map(|drb| {
+                let DlUpTnlInformationToBeSetupItem {
+                    dl_up_tnl_information: UpTransportLayerInformation::GtpTunnel(tunnel),
+                } = drb.dl_up_tnl_information_to_be_setup_list.0.head;
+                (drb.drb_id.0, tunnel)
+            })
+            .collect();
 
-        Ok((cell_group_config, remote_tunnel_info))
-    }
-
-    fn check_rrc_reconfiguration_complete(&self, message: UlDcchMessage) -> Result<()> {
-        let UlDcchMessage {
-            message: UlDcchMessageType::C1(C1_6::RrcReconfigurationComplete(_response)),
-        } = message
-        else {
-            bail!("Expected RrcReconfigurationComplete, got {:?}", message);
-        };
-        // TODO: check more thoroughly
-        Ok(())
+        Ok((cell_group_config, remote_tunnels))
     }
 }
@@ -0,0 +1,255 @@
+use super::UeProcedure;
+use crate::nas::FgsmCause;
+use crate::{HandlerApi, PduSessionModification, QosFlow};
+use anyhow::{Result, anyhow, bail};
+use asn1_per::NonEmpty;
+use derive_deref::{Deref, DerefMut};
+use f1ap::{CellGroupConfig, SrbId};
+use oxirush_nas::Nas5gsmMessage;
+use oxirush_nas::messages::{Nas5gsmHeader, NasPduSessionModificationRequest};
+use slog::{info, warn};
+
+// TS24.501, 9.6 - PTI used in a network-requested procedure.
+const NO_PROCEDURE_TRANSACTION_IDENTITY_ASSIGNED: u8 = 0;
+
+#[derive(Deref, DerefMut)]
+pub struct SessionModificationProcedure<'a, A: HandlerApi>(UeProcedure<'a, A>);
+
+impl<'a, A: HandlerApi> SessionModificationProcedure<'a, A> {
+    pub fn new(ue_procedure: UeProcedure<'a, A>) -> Self {
+        SessionModificationProcedure(ue_procedure)
+    }
+
+    pub async fn ue_requested(
+        &mut self,
+        hdr: Nas5gsmHeader,
+        r: NasPduSessionModificationRequest,
+    ) -> Result<()> {
+        self.log_message(">> NasPduSessionModificationRequest");
+        let session_id = hdr.pdu_session_identity;
+        let pti = hdr.procedure_transaction_identity;
+        let Some(session) = self.ue.pdu_sessions.iter().find(|x| x.id == session_id) else {
+            warn!(
+                self.logger,
+                "Modification request for unknown PDU session {session_id}"
+            );
+            return self
+                .reject(session_id, pti, FgsmCause::INVALID_PDU_SESSION_IDENTITY)
+                .await;
+        };
+
+        let requested_qos = r
+            .requested_qos_rules
+            .as_ref()
+            .map(crate::nas::parse::qos_rules)
+            .transpose()
+            .and_then(|rules| {
+                let flows = r
+                    .requested_qos_flow_descriptions
+                    .as_ref()
+                    .map(crate::nas::parse::qos_flow_descriptions)
+                    .transpose()?;
+                Ok((rules.unwrap_or_default(), flows.unwrap_or_default()))
+            });
+        let ((mut add_qos_rules, delete_qos_rules), mut add_qos_flows) = match requested_qos {
+            Ok(x) => x,
+            Err(e) => {
+                warn!(self.logger, "Bad requested QoS - {e}");
+                return self
+                    .reject(
+                        session_id,
+                        pti,
+                        FgsmCause::SYNTACTICAL_ERROR_IN_THE_QOS_OPERATION,
+                    )
+                    .await;
+            }
+        };
+
+        // The UE leaves the QoS rule identifier and QFI of a new QoS rule as 0 for the network to assign.  Its
+        // QoS flow description for the new QoS flow, with the 5QI that it wants, has QFI 0 too.
+        let mut next_id = session.qos_rules.iter().map(|x| x.id).max().unwrap_or(0);
+        let mut next_qfi = session.qos_flows.iter().map(|x| x.qfi).max().unwrap_or(0);
+        for rule in add_qos_rules.iter_mut() {
+            if rule.id == 0 {
+                next_id += 1;
+                rule.id = next_id;
+            }
+            if rule.qfi == 0 {
+                next_qfi += 1;
+                rule.qfi = next_qfi;
+                if let Some(flow) = add_qos_flows.iter_mut().find(|x| x.qfi == 0) {
+                    flow.qfi = next_qfi;
+                }
+            }
+        }
+
+        // The UE has no say over the session AMBR in a PDU Session Modification Request, so it keeps what the
+        // subscription gives it.
+        let modification = PduSessionModification {
+            session_ambr: None,
+            add_qos_rules,
+            add_qos_flows,
+            delete_qos_rules,
+        };
+        self.modify(session_id, pti, modification).await
+    }
+
+    pub async fn network_requested(
+        &mut self,
+        session_id: u8,
+        modification: PduSessionModification,
+    ) -> Result<()> {
+        // An operator can ask for a modification that isn't possible.  Rather than fail the procedure,
+        // which would take down the UE, we just log and ignore it.
+        if !self.ue.pdu_sessions.iter().any(|x| x.id == session_id) {
+            warn!(self.logger, "Can't modify unknown PDU session {session_id}");
+            return Ok(());
+        }
+        self.modify(
+            session_id,
+            NO_PROCEDURE_TRANSACTION_IDENTITY_ASSIGNED,
+            modification,
+        )
+        .await
+    }
+
+    // Work out the session's new QoS and get the DU and UE to accept it.  The session is only updated once the UE
+    // has completed the RRC Reconfiguration, so that it doesn't get out of step with them if either fails.
+    async fn modify(
+        &mut self,
+        session_id: u8,
+        pti: u8,
+        modification: PduSessionModification,
+    ) -> Result<()> {
+        let Some(session) = self.ue.pdu_sessions.iter().find(|x| x.id == session_id) else {
+            bail!("PDU session {session_id} disappeared");
+        };
+        let qos = match session.modified_qos(&modification) {
+            Ok(x) => x,
+            Err(e) => {
+                warn!(self.logger, "Can't modify PDU session {session_id} - {e}");
+                if pti == NO_PROCEDURE_TRANSACTION_IDENTITY_ASSIGNED {
+                    return Ok(());
+                }
+                return self
+                    .reject(
+                        session_id,
+                        pti,
+                        FgsmCause::SEMANTIC_ERROR_IN_THE_QOS_OPERATION,
+                    )
+                    .await;
+            }
+        };
+
+        let added_qos_flows: Vec<QosFlow> = qos
+            .qos_flows
+            .iter()
+            .filter(|flow| !session.qos_flows.iter().any(|x| x.qfi == flow.qfi))
+            .copied()
+            .collect();
+        let deleted_qos_flows: Vec<u8> = session
+            .qos_flows
+            .iter()
+            .filter(|flow| !qos.qos_flows.iter().any(|x| x.qfi == flow.qfi))
+            .map(|flow| flow.qfi)
+            .collect();
+
+        // Deleted QoS flows come off the DRB that carries them, releasing it if it has none left, and new ones go on
+        // the default DRB.
+        let (modified_drbs, released_drb_ids) = session.drb_modifications(&qos);
+        let ue_context_modification_request =
+            crate::f1ap::build::ue_context_modification_request_modify_drbs(
+                self.ue,
+                self.config().ip_addr.into(),
+                session,
+                &qos,
+                &modified_drbs,
+                &released_drb_ids,
+                self.sim().and_then(|x| x.ue_ambr),
+            )?;
+        let (cell_group_config, _) = self
+            .perform_f1_ue_context_modification(ue_context_modification_request)
+            .await?;
+
+        let command = crate::nas::build::pdu_session_modification_command(
+            session_id,
+            pti,
+            &modification,
+            &added_qos_flows,
+            &deleted_qos_flows,
+        )?;
+        let command = self.ue.nas.encode(command)?;
+        self.log_message("<< NasPduSessionModificationCommand");
+        self.perform_rrc_reconfiguration(
+            command,
+            cell_group_config,
+            session_id,
+            modified_drbs.map(|x| (x.drb.id, x.qfis_to_add, x.qfis_to_release)),
+            released_drb_ids,
+        )
+        .await?;
+
+        let Some(session) = self.ue.pdu_sessions.iter_mut().find(|x| x.id == session_id) else {
+            bail!("PDU session {session_id} disappeared");
+        };
+        let drb_qfis = qos
+            .drbs
+            .iter()
+            .map(|drb| (drb.id, drb.qfis.clone()))
+            .collect();
+        session.set_qos(qos);
+        self.receive_modification_complete(session_id).await?;
+
+        // Only move downlink QoS flows onto their new DRBs once the UE has them.
+        let Some(session) = self.ue.pdu_sessions.iter().find(|x| x.id == session_id) else {
+            bail!("PDU session {session_id} disappeared");
+        };
+        self.remap_userplane_qos_flows(&session.userplane_info, drb_qfis, self.logger)
+            .await;
+        info!(self.logger, "Modified PDU session {session_id}");
+        Ok(())
+    }
+
+    async fn reject(&mut self, session_id: u8, pti: u8, fgsm_cause: u8) -> Result<()> {
+        let reject =
+            crate::nas::build::pdu_session_modification_reject(session_id, pti, fgsm_cause)?;
+        self.log_message("<< NasPduSessionModificationReject");
+        self.nas_indication(reject).await
+    }
+
+    async fn perform_rrc_reconfiguration(
+        &mut self,
+        nas: Vec<u8>,
+        cell_group_config: Option<CellGroupConfig>,
+        session_id: u8,
+        drbs: NonEmpty<(u8, Vec<u8>, Vec<u8>)>,
+        drb_ids_to_release: Vec<u8>,
+    ) -> Result<()> {
+        let rrc_reconfiguration = crate::rrc::build::reconfiguration_modify_drbs(
+            0,
+            nas,
+            cell_group_config.map(|x| x.0),
+            session_id,
+            drbs,
+            drb_ids_to_release,
+            self.config().sdap_header_dl,
+        );
+        self.log_message("<< RrcReconfiguration(Nas)");
+        let response = self.rrc_request(SrbId(1), rrc_reconfiguration).await?;
+        self.check_rrc_reconfiguration_complete(response)
+    }
+
+    async fn receive_modification_complete(&mut self, session_id: u8) -> Result<()> {
+        match self.receive_5gsm().await? {
+            (header, Nas5gsmMessage::PduSessionModificationComplete(_))
+                if header.pdu_session_identity == session_id =>
+            {
+                self.log_message(">> NasPduSessionModificationComplete");
+                Ok(())
+            }
+            m => Err(anyhow!(
+                "Expected NasPduSessionModificationComplete, got {m:?}"
+            )),
+        }
+    }
+}
@@ -0,0 +1,174 @@
+use super::UeProcedure;
+use crate::nas::FgsmCause;
+use crate::{HandlerApi, PduSession};
+use anyhow::{Result, anyhow};
+use asn1_per::NonEmpty;
+use derive_deref::{Deref, DerefMut};
+use f1ap::{CellGroupConfig, SrbId};
+use oxirush_nas::messages::{Nas5gsmHeader, NasPduSessionReleaseRequest, NasUlNasTransport};
+use oxirush_nas::{Nas5gmmMessage, Nas5gsMessage, Nas5gsmMessage, decode_nas_5gs_message};
+use slog::{info, warn};
+use std::time::Duration;
+
+// TS24.501, 10.3: the network's guard timer for the PDU session release procedure, and the number of times it
+// resends the Release Command before giving up.
+const T3592: Duration = Duration::from_secs(16);
+const T3592_MAX_RETRANSMISSIONS: usize = 4;
+
+#[derive(Deref, DerefMut)]
+pub struct SessionReleaseProcedure<'a, A: HandlerApi>(UeProcedure<'a, A>);
+
+impl<'a, A: HandlerApi> SessionReleaseProcedure<'a, A> {
+    pub fn new(ue_procedure: UeProcedure<'a, A>) -> Self {
+        SessionReleaseProcedure(ue_procedure)
+    }
+
+    pub async fn run(&mut self, hdr: Nas5gsmHeader, _r: NasPduSessionReleaseRequest) -> Result<()> {
+        self.log_message(">> NasPduSessionReleaseRequest");
+        let session_id = hdr.pdu_session_identity;
+        let pti = hdr.procedure_transaction_identity;
+        let Some(index) = self.ue.pdu_sessions.iter().position(|x| x.id == session_id) else {
+            warn!(
+                self.logger,
+                "Release request for unknown PDU session {session_id}"
+            );
+            let reject = crate::nas::build::pdu_session_release_reject(
+                session_id,
+                pti,
+                FgsmCause::INVALID_PDU_SESSION_IDENTITY,
+            )?;
+            self.log_message("<< NasPduSessionReleaseReject");
+            return self.nas_indication(reject).await;
+        };
+        let session = self.ue.pdu_sessions.remove(index);
+        let drb_ids = drb_ids(&session)?;
+
+        // Free up the userplane resources even if the DU fails to release the DRBs.
+        let cell_group_config = self.release_drbs(&drb_ids).await;
+        self.delete_userplane_session(&session.userplane_info, self.logger)
+            .await;
+        let cell_group_config = cell_group_config?;
+
+        let command = crate::nas::build::pdu_session_release_command(
+            session_id,
+            pti,
+            FgsmCause::REGULAR_DEACTIVATION,
+        )?;
+        let command = self.ue.nas.encode(command)?;
+        self.log_message("<< NasPduSessionReleaseCommand");
+        self.perform_rrc_reconfiguration(Some(command), cell_group_config, drb_ids)
+            .await?;
+        self.receive_release_complete(session_id, pti).await?;
+        info!(self.logger, "Released PDU session {session_id}");
+        Ok(())
+    }
+
+    /// Release PDU sessions that the UE has already released on its side, without any 5GSM signaling.
+    pub async fn release_locally(&mut self, sessions: Vec<PduSession>) -> Result<()> {
+        for session in sessions {
+            let drb_ids = drb_ids(&session)?;
+            let cell_group_config = self.release_drbs(&drb_ids).await;
+            self.delete_userplane_session(&session.userplane_info, self.logger)
+                .await;
+            self.perform_rrc_reconfiguration(None, cell_group_config?, drb_ids)
+                .await?;
+            info!(self.logger, "Locally released PDU session {}", session.id);
+        }
+        Ok(())
+    }
+
+    // Remove the session's DRBs from the F1 UE context.
+    async fn release_drbs(&self, drb_ids: &NonEmpty<u8>) -> Result<Option<CellGroupConfig>> {
+        let ue_context_modification_request =
+            crate::f1ap::build::ue_context_modification_request_release_drbs(
+                self.ue,
+                drb_ids,
+                self.sim().and_then(|x| x.ue_ambr),
+            );
+        let (cell_group_config, _) = self
+            .perform_f1_ue_context_modification(ue_context_modification_request)
+            .await?;
+        Ok(cell_group_config)
+    }
+
+    async fn perform_rrc_reconfiguration(
+        &mut self,
+        nas: Option<Vec<u8>>,
+        cell_group_config: Option<CellGroupConfig>,
+        drb_ids: NonEmpty<u8>,
+    ) -> Result<()> {
+        let has_nas = nas.is_some();
+        let rrc_reconfiguration = crate::rrc::build::reconfiguration_release_drbs(
+            0,
+            nas,
+            cell_group_config.map(|x| x.0),
+            drb_ids,
+        );
+        self.log_message(if has_nas {
+            "<< RrcReconfiguration(Nas)"
+        } else {
+            "<< RrcReconfiguration"
+        });
+        let response = self.rrc_request(SrbId(1), rrc_reconfiguration).await?;
+        self.check_rrc_reconfiguration_complete(response)
+    }
+
+    // Wait for the UE's Release Complete, resending the Release Command each time T3592 expires (TS24.501,
+    // 6.3.3.5).  Other messages from the UE, such as a request for a new PDU session, are handled once the
+    // release is over.
+    async fn receive_release_complete(&mut self, session_id: u8, pti: u8) -> Result<()> {
+        let mut retransmissions = 0;
+        loop {
+            let release_complete =
+                self.receive_nas_deferring_others(|nas| is_release_complete(nas, session_id));
+            match async_std::future::timeout(T3592, release_complete).await {
+                Ok(result) => {
+                    result?;
+                    self.log_message(">> NasPduSessionReleaseComplete");
+                    return Ok(());
+                }
+                Err(_) if retransmissions == T3592_MAX_RETRANSMISSIONS => {
+                    // The session is already gone on our side, so there is nothing left to clean up.
+                    warn!(
+                        self.logger,
+                        "T3592 expired - no Release Complete for PDU session {session_id}"
+                    );
+                    return Ok(());
+                }
+                Err(_) => {
+                    retransmissions += 1;
+                    let command = crate::nas::build::pdu_session_release_command(
+                        session_id,
+                        pti,
+                        FgsmCause::REGULAR_DEACTIVATION,
+                    )?;
+                    self.log_message("<< NasPduSessionReleaseCommand (retransmission)");
+                    self.nas_indication(command).await?;
+                }
+            }
+        }
+    }
+}
+
+fn is_release_complete(nas: &Nas5gsMessage, pdu_session_id: u8) -> bool {
+    let Nas5gsMessage::Gmm(
+        _header,
+        Nas5gmmMessage::UlNasTransport(NasUlNasTransport {
+            payload_container, ..
+        }),
+    ) = nas
+    else {
+        return false;
+    };
+    matches!(
+        decode_nas_5gs_message(&payload_container.value),
+        Ok(Nas5gsMessage::Gsm(header, Nas5gsmMessage::PduSessionReleaseComplete(_)))
+            if header.pdu_session_identity == pdu_session_id
+    )
+}
+
+// The IDs of all of a session's DRBs.
+fn drb_ids(session: &PduSession) -> Result<NonEmpty<u8>> {
+    NonEmpty::from_vec(session.drbs.iter().map(|x| x.id).collect())
+        .ok_or(anyhow!("PDU session {} has no DRBs", session.id))
+}
@@ -0,0 +1,105 @@
+//! registration_update - mobility or periodic registration update by a UE that is already registered
+
+use super::{NasTimer, SessionReleaseProcedure, UeContextReleaseProcedure, UeProcedure};
+use crate::nas::{FgmmCause, FgsRegistrationType, PduSessionStatus};
+use crate::{HandlerApi, PduSession, expect_nas};
+use anyhow::{Result, anyhow, bail};
+use derive_deref::{Deref, DerefMut};
+use f1ap::{Cause, CauseRadioNetwork};
+use oxirush_nas::messages::NasRegistrationRequest;
+use oxirush_nas::{Nas5gmmMessage, Nas5gsMessage};
+use slog::{info, warn};
+
+#[derive(Deref, DerefMut)]
+pub struct RegistrationUpdateProcedure<'a, A: HandlerApi>(UeProcedure<'a, A>);
+
+impl<'a, A: HandlerApi> RegistrationUpdateProcedure<'a, A> {
+    pub fn new(inner: UeProcedure<'a, A>) -> Self {
+        RegistrationUpdateProcedure(inner)
+    }
+
+    // Registration Update Procedure - see TS24.501, 5.5.1.3
+    // 1.    Nas Registration Request >>
+    // 2.    Nas Registration Accept <<
+    // 3.    Nas Registration Complete >>
+    // 4.    Local release of any PDU sessions that the UE no longer has
+    //
+    // The Registration Request arrives on the UE's existing NAS security context, so there is no need to
+    // authenticate the UE again.
+    pub async fn run(mut self, r: NasRegistrationRequest) -> Result<()> {
+        self.log_message(">> NasRegistrationRequest");
+        let registration_type = r.fgs_registration_type.value & 0b111;
+        let description = match registration_type {
+            FgsRegistrationType::MOBILITY_REGISTRATION_UPDATING => "Mobility",
+            FgsRegistrationType::PERIODIC_REGISTRATION_UPDATING => "Periodic",
+            _ => {
+                warn!(
+                    self.logger,
+                    "Unsupported registration type {registration_type} from registered UE"
+                );
+                return self.reject(FgmmCause::PROTOCOL_ERROR_UNSPECIFIED).await;
+            }
+        };
+
+        // TS24.501, 5.5.1.3.4: release any PDU sessions that the UE doesn't think are active.
+        let released_sessions = match &r.pdu_session_status {
+            Some(pdu_session_status) => {
+                let active = crate::nas::parse::pdu_session_ids(&pdu_session_status.value);
+                let (released, retained): (Vec<PduSession>, Vec<PduSession>) = self
+                    .ue
+                    .pdu_sessions
+                    .drain(..)
+                    .partition(|x| !active.contains(&x.id));
+                self.ue.pdu_sessions = retained;
+                released
+            }
+            None => vec![],
+        };
+
+        // The user plane resources of our remaining sessions are already in place, so any other session that the UE
+        // has uplink data for can't be reactivated.
+        let uplink_data_status = r.uplink_data_status.as_ref().map(|x| x.value.as_slice());
+        let pdu_session_status = (r.pdu_session_status.is_some() || uplink_data_status.is_some())
+            .then(|| {
+                PduSessionStatus::new(
+                    self.ue.pdu_sessions.iter().map(|x| x.id).collect(),
+                    uplink_data_status,
+                )
+            });
+
+        let registration_accept = crate::nas::build::registration_accept(
+            &self.ue.allowed_nssai,
+            &[],
+            None,
+            self.config(),
+            &self.ue.tmsi,
+            &self.tracking_areas(&self.ue.nr_cgi),
+            pdu_session_status.as_ref(),
+        );
+        self.log_message("<< NasRegistrationAccept");
+        let _rsp = expect_nas!(
+            RegistrationComplete,
+            self.nas_request(registration_accept, NasTimer::T3550)
+                .await?
+        )?;
+        self.log_message(">> NasRegistrationComplete");
+        info!(self.logger, "{description} registration update complete");
+
+        SessionReleaseProcedure::new(self.0)
+            .release_locally(released_sessions)
+            .await
+    }
+
+    // TS24.501, 5.5.1.3.5: a Registration Reject leaves the UE deregistered, so release its context.  As for a
+    // deregistration, the error gets the UE message handler to self-destruct and free up its PDU sessions.
+    async fn reject(mut self, fgmm_cause: u8) -> Result<()> {
+        self.ue.registered = false;
+        self.log_message("<< NasRegistrationReject");
+        self.nas_indication(crate::nas::build::registration_reject(fgmm_cause))
+            .await?;
+        UeContextReleaseProcedure::new(self.0)
+            .cu_initiated(Cause::RadioNetwork(CauseRadioNetwork::NormalRelease))
+            .await?;
+        bail!("Registration update rejected with 5GMM cause #{fgmm_cause}")
+    }
+}
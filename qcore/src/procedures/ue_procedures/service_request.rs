@@ -0,0 +1,167 @@
+//! service_request - a UE in CM-IDLE re-establishes the user plane of its PDU sessions
+
+use super::{ConfigurationUpdateProcedure, UeProcedure};
+use crate::nas::{PduSessionStatus, ServiceType};
+use crate::{HandlerApi, PduSession, UeConfigurationUpdate};
+use anyhow::{Result, bail};
+use asn1_per::{NonEmpty, nonempty};
+use derive_deref::{Deref, DerefMut};
+use f1ap::{
+    DlUpTnlInformationToBeSetupItem, DuToCuRrcInformation, SrbId, UeContextSetupProcedure,
+    UeContextSetupResponse, UpTransportLayerInformation,
+};
+use oxirush_nas::messages::NasServiceRequest;
+use rrc::{C1_6, UlDcchMessage, UlDcchMessageType};
+use slog::info;
+use xxap::GtpTunnel;
+
+#[derive(Deref, DerefMut)]
+pub struct ServiceRequestProcedure<'a, A: HandlerApi>(UeProcedure<'a, A>);
+
+impl<'a, A: HandlerApi> ServiceRequestProcedure<'a, A> {
+    pub fn new(inner: UeProcedure<'a, A>) -> Self {
+        ServiceRequestProcedure(inner)
+    }
+
+    // Service Request Procedure - see TS23.502, 4.2.3.2
+    // 1.    Nas Service Request >> (handled by the initial access procedure, which resumes NAS security
+    //       and activates RRC security)
+    // 2.    F1ap UeContextSetupRequest << (with a DRB for each PDU session)
+    // 3.    F1ap UeContextSetupResponse >>
+    // 4.    Rrc Reconfiguration + Nas Service Accept <<
+    // 5.    Rrc Reconfiguration Complete >>
+    // 6.    Downlink packets buffered while the UE was idle are sent
+    // 7.    Nas Configuration Update Command << (with a new 5G-GUTI, if the UE was paged)
+    // 8.    Nas Configuration Update Complete >>
+    pub async fn run(mut self, r: NasServiceRequest) -> Result<()> {
+        // TS24.501, 5.6.1.4.1: release any PDU sessions that the UE doesn't think are active.  They
+        // have no DRB, so we only need to free their userplane resources.
+        if let Some(pdu_session_status) = &r.pdu_session_status {
+            let active = crate::nas::parse::pdu_session_ids(&pdu_session_status.value);
+            let (released, retained): (Vec<PduSession>, Vec<PduSession>) = self
+                .ue
+                .pdu_sessions
+                .drain(..)
+                .partition(|x| !active.contains(&x.id));
+            self.ue.pdu_sessions = retained;
+            for session in released {
+                self.delete_userplane_session(&session.userplane_info, self.logger)
+                    .await;
+                info!(self.logger, "Locally released PDU session {}", session.id);
+            }
+        }
+
+        // We re-establish the user plane of all of the UE's PDU sessions, so any other session that the UE has
+        // uplink data for can't be reactivated.
+        let pdu_session_status = PduSessionStatus::new(
+            self.ue.pdu_sessions.iter().map(|x| x.id).collect(),
+            r.uplink_data_status.as_ref().map(|x| x.value.as_slice()),
+        );
+        let service_accept = crate::nas::build::service_accept(&pdu_session_status);
+
+        // A UE with no PDU sessions only wanted signalling.
+        let Some(drbs) = NonEmpty::from_vec(
+            self.ue
+                .pdu_sessions
+                .iter()
+                .flat_map(|x| x.drbs.iter().map(|drb| (x.id, drb.id, drb.qfis.clone())))
+                .collect(),
+        ) else {
+            self.log_message("<< NasServiceAccept");
+            return self.nas_indication(service_accept).await;
+        };
+
+        let (cell_group_config, remote_tunnels) = self.perform_f1_ue_context_setup().await?;
+        let drb_tunnels = self
+            .ue
+            .pdu_sessions
+            .iter()
+            .map(|session| session.drb_tunnels(&remote_tunnels))
+            .collect::<Result<Vec<_>>>()?;
+
+        let service_accept = self.ue.nas.encode(service_accept)?;
+        let rrc_reconfiguration = crate::rrc::build::reconfiguration(
+            0,
+            Some(nonempty![service_accept]),
+            Some(cell_group_config),
+            drbs,
+            self.config().sdap_header_dl,
+        );
+        self.log_message("<< RrcReconfiguration(NasServiceAccept)");
+        let response = self.rrc_request(SrbId(1), rrc_reconfiguration).await?;
+        let UlDcchMessage {
+            message: UlDcchMessageType::C1(C1_6::RrcReconfigurationComplete(_)),
+        } = response
+        else {
+            bail!("Expected RrcReconfigurationComplete, got {:?}", response);
+        };
+        self.log_message(">> RrcReconfigurationComplete");
+
+        // Now that the UE has its DRBs, forward downlink packets to it, starting with any that we buffered.
+        for (session, drb_tunnels) in self.ue.pdu_sessions.iter().zip(drb_tunnels) {
+            self.commit_userplane_session(
+                &session.userplane_info,
+                drb_tunnels,
+                self.ue.imsi.as_deref(),
+                self.logger,
+            )
+            .await?;
+        }
+        info!(
+            self.logger,
+            "Re-established user plane of {} PDU sessions",
+            self.ue.pdu_sessions.len()
+        );
+
+        // TS33.501, 6.12.3: the 5G-S-TMSI was exposed in the paging, so give the UE a new 5G-GUTI.
+        let service_type = r.ngksi.value >> 4;
+        if service_type == ServiceType::MOBILE_TERMINATED_SERVICES {
+            ConfigurationUpdateProcedure::new(self.0)
+                .run(UeConfigurationUpdate {
+                    reallocate_guti: true,
+                    ..Default::default()
+                })
+                .await?;
+        }
+        Ok(())
+    }
+
+    // Set up a new F1 UE context with the UE's DRBs, returning the DU's CellGroupConfig and its
+    // downlink tunnel for each DRB.
+    async fn perform_f1_ue_context_setup(&self) -> Result<(Vec<u8>, Vec<(u8, GtpTunnel)>)> {
+        let ue_context_setup_request = crate::f1ap::build::ue_context_setup_request(
+            self.ue,
+            self.config().ip_addr.into(),
+            &self.ue.pdu_sessions,
+            self.sim().and_then(|x| x.ue_ambr),
+        )?;
+        self.log_message("<< UeContextSetupRequest");
+        let rsp = self
+            .f1ap_request::<UeContextSetupProcedure>(ue_context_setup_request, self.logger)
+            .await?;
+        self.log_message(">> UeContextSetupResponse");
+
+        let UeContextSetupResponse {
+            du_to_cu_rrc_information:
+                DuToCuRrcInformation {
+                    cell_group_config, ..
+                },
+            drbs_setup_list: Some(drbs_setup_list),
+            ..
+        } = rsp
+        else {
+            bail!("UeContextSetupResponse missed expected information");
+        };
+        let remote_tunnels = drbs_setup_list
+            .0
+            .into_iter()
+            .map(|drb| {
+                let DlUpTnlInformationToBeSetupItem {
+                    dl_up_tnl_information: UpTransportLayerInformation::GtpTunnel(tunnel),
+                } = drb.dl_up_tnl_information_to_be_setup_list.0.head;
+                (drb.drb_id.0, tunnel)
+            })
+            .collect();
+        Ok((cell_group_config.0, remote_tunnels))
+    }
+}
@@ -1,6 +1,9 @@
 use anyhow::Result;
 use derive_deref::{Deref, DerefMut};
-use f1ap::{Cause, UeContextReleaseComplete, UeContextReleaseRequest};
+use f1ap::{
+    Cause, CauseRadioNetwork, DrbActivity, UeContextReleaseComplete, UeContextReleaseRequest,
+    UeInactivityNotification,
+};
 use slog::info;
 
 use crate::HandlerApi;
@@ -27,11 +30,31 @@ impl<'a, A: HandlerApi> UeContextReleaseProcedure<'a, A> {
         self.perform_f1_ue_context_release(r.cause).await
     }
 
-    async fn perform_f1_ue_context_release(&self, cause: Cause) -> Result<()> {
+    // TS38.473, 8.3.6: the DU tells us when the UE's DRBs go quiet.  Once none of them is active, we
+    // release the UE's F1 context, which moves the UE to CM-IDLE.  Returns whether we released it.
+    pub async fn du_inactivity(&mut self, r: UeInactivityNotification) -> Result<bool> {
+        self.log_message(">> F1ap UeInactivityNotification");
+        if r.drb_activity_list
+            .0
+            .iter()
+            .any(|x| matches!(x.drb_activity, Some(DrbActivity::Active)))
+        {
+            return Ok(false);
+        }
+        info!(self.logger, "UE inactive - release context");
+        self.perform_f1_ue_context_release(Cause::RadioNetwork(CauseRadioNetwork::NormalRelease))
+            .await?;
+        Ok(true)
+    }
+
+    async fn perform_f1_ue_context_release(&mut self, cause: Cause) -> Result<()> {
         // TODO: are we also meant to RRC Release the UE?
 
         let ue_context_release_command =
             crate::f1ap::build::ue_context_release_command(self.ue, cause);
+
+        // Once told to release, the DU drops the UE context, so any later DRBs need a fresh UE Context Setup.
+        self.ue.f1_ue_context_established = false;
         self.log_message("<< UeContextReleaseCommand");
         let rsp = self
             .f1ap_request::<f1ap::UeContextReleaseProcedure>(
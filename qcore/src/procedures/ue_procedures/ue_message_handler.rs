@@ -1,21 +1,23 @@
 use super::{
-    InitialAccessProcedure, UeContextReleaseProcedure, UeProcedure, UlInformationTransferProcedure,
+    ConfigurationUpdateProcedure, DeregistrationProcedure, InitialAccessProcedure, NasTimer,
+    NasTimerExpiry, SessionModificationProcedure, UeContextReleaseProcedure, UeProcedure,
+    UlInformationTransferProcedure, UplinkNasProcedure,
 };
-use crate::{HandlerApi, UeContext};
+use crate::{HandlerApi, RegisteredUe, UeContext, UeMessage};
 use anyhow::{Result, bail};
 use async_channel::{Receiver, Sender};
-use f1ap::{F1apPdu, InitialUlRrcMessageTransfer, InitiatingMessage};
+use f1ap::{Cause, CauseRadioNetwork, F1apPdu, InitialUlRrcMessageTransfer, InitiatingMessage};
 use rrc::{C1_6, UlDcchMessageType};
-use slog::{Logger, warn};
+use slog::{Logger, info, warn};
 
 pub struct UeMessageHandler<A: HandlerApi> {
-    receiver: Receiver<F1apPdu>,
+    receiver: Receiver<UeMessage>,
     api: A,
     logger: Logger,
 }
 
 impl<A: HandlerApi> UeMessageHandler<A> {
-    pub fn spawn(ue_id: u32, api: A, logger: Logger) -> Sender<F1apPdu> {
+    pub fn spawn(ue_id: u32, api: A, logger: Logger) -> Sender<UeMessage> {
         let (sender, receiver) = async_channel::unbounded();
         let handler = UeMessageHandler {
             receiver,
@@ -33,12 +35,41 @@ impl<A: HandlerApi> UeMessageHandler<A> {
     async fn run(&self, ue_id: u32) -> Result<()> {
         // Create a UE context.
         let message = self.receiver.recv().await?;
-        let F1apPdu::InitiatingMessage(InitiatingMessage::InitialUlRrcMessageTransfer(r)) = message
-        else {
+        let UeMessage::F1ap(pdu) = message else {
             bail!("Expected InitialUlRrcMessageTransfer, got {message:?}");
         };
+        let F1apPdu::InitiatingMessage(InitiatingMessage::InitialUlRrcMessageTransfer(r)) = *pdu
+        else {
+            bail!("Expected InitialUlRrcMessageTransfer, got {pdu:?}");
+        };
         let mut ue_context = UeContext::new(ue_id, r.gnb_du_ue_f1ap_id, r.nr_cgi.clone());
         let result = self.run_inner(&mut ue_context, r).await;
+        if let Some(NasTimerExpiry(timer)) = result
+            .as_ref()
+            .err()
+            .and_then(|e| e.downcast_ref::<NasTimerExpiry>())
+        {
+            // We have given up on the UE, so tell the DU to release it.
+            info!(self.logger, "Release UE context after {timer:?} expiry");
+
+            // TS24.501, 5.5.2.3.5: a UE that doesn't answer the Deregistration Request is deregistered all the
+            // same, so we don't keep its NAS context or PDU sessions for when it comes back.
+            if let NasTimer::T3522 = timer {
+                ue_context.registered = false;
+            }
+            if let Err(e) = UeContextReleaseProcedure::new(UeProcedure::new(
+                &self.api,
+                &mut ue_context,
+                &self.logger,
+                &self.receiver,
+            ))
+            .cu_initiated(Cause::RadioNetwork(CauseRadioNetwork::Unspecified))
+            .await
+            {
+                // We still clean up the UE below, so that its PDU sessions don't leak.
+                warn!(self.logger, "Failed to release UE context - {e}");
+            }
+        }
         self.destroy(&mut ue_context).await;
         result
     }
@@ -58,11 +89,54 @@ impl<A: HandlerApi> UeMessageHandler<A> {
         .run(r)
         .await?;
 
-        // Run successive procedures on the UE.
-        while let Ok(pdu) = self.receiver.recv().await {
+        if let Some(imsi) = &ue_context.imsi {
+            self.api
+                .add_connected_ue(imsi.clone(), ue_context.tmsi, ue_context.key);
+        }
+
+        // Run successive procedures on the UE, starting with any messages that an earlier procedure set aside.
+        loop {
+            let message = match ue_context.deferred_messages.pop_front() {
+                Some(message) => message,
+                None => match self.receiver.recv().await {
+                    Ok(message) => message,
+                    Err(_) => break,
+                },
+            };
             let ue_procedure =
                 UeProcedure::new(&self.api, ue_context, &self.logger, &self.receiver);
 
+            let pdu = match message {
+                UeMessage::F1ap(pdu) => *pdu,
+                UeMessage::Nas(nas) => {
+                    UplinkNasProcedure::new(ue_procedure).handle(*nas).await?;
+                    continue;
+                }
+                UeMessage::Deregister {
+                    reregistration_required,
+                } => {
+                    DeregistrationProcedure::new(ue_procedure)
+                        .network_initiated(reregistration_required)
+                        .await?;
+                    continue;
+                }
+                UeMessage::ModifyPduSession {
+                    pdu_session_id,
+                    modification,
+                } => {
+                    SessionModificationProcedure::new(ue_procedure)
+                        .network_requested(pdu_session_id, modification)
+                        .await?;
+                    continue;
+                }
+                UeMessage::UpdateConfiguration(update) => {
+                    ConfigurationUpdateProcedure::new(ue_procedure)
+                        .run(update)
+                        .await?;
+                    continue;
+                }
+            };
+
             match pdu {
                 F1apPdu::InitiatingMessage(InitiatingMessage::UlRrcMessageTransfer(r)) => {
                     ue_procedure.log_message(">> F1ap UlRrcMessageTransfer");
@@ -86,6 +160,15 @@ impl<A: HandlerApi> UeMessageHandler<A> {
                         .await?;
                     bail!("DU initiated context release")
                 }
+                F1apPdu::InitiatingMessage(InitiatingMessage::UeInactivityNotification(r)) => {
+                    if UeContextReleaseProcedure::new(ue_procedure)
+                        .du_inactivity(r)
+                        .await?
+                    {
+                        info!(self.logger, "UE moved to CM-IDLE");
+                        return Ok(());
+                    }
+                }
                 _ => {
                     bail!("Unsupported F1apPdu {pdu:?}");
                 }
@@ -95,6 +178,31 @@ impl<A: HandlerApi> UeMessageHandler<A> {
     }
 
     async fn destroy(&self, ue_context: &mut UeContext) {
+        if let Some(imsi) = &ue_context.imsi {
+            self.api
+                .remove_connected_ue(imsi, &ue_context.tmsi, ue_context.key);
+        }
+
+        // A registered UE moves to CM-IDLE.  We keep its NAS context, so that it can come back with a
+        // Service Request or re-register with its 5G-GUTI, and its PDU sessions, which keep their IP
+        // addresses but stop carrying downlink packets until the UE's DRBs are set up again.
+        if let (true, Some(imsi)) = (ue_context.registered, ue_context.imsi.take()) {
+            for session in &ue_context.pdu_sessions {
+                self.api
+                    .deactivate_userplane_session(&session.userplane_info, &self.logger)
+                    .await;
+            }
+            let registered_ue = RegisteredUe::new(
+                imsi,
+                std::mem::take(&mut ue_context.nas),
+                std::mem::take(&mut ue_context.allowed_nssai),
+                ue_context.pei.take(),
+                std::mem::take(&mut ue_context.pdu_sessions),
+                ue_context.nr_cgi.clone(),
+            );
+            self.api.store_registered_ue(ue_context.tmsi, registered_ue);
+        }
+
         for session in ue_context.pdu_sessions.drain(..) {
             self.api
                 .delete_userplane_session(&session.userplane_info, &self.logger)
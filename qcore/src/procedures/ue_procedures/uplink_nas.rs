@@ -1,14 +1,18 @@
 //! uplink_nas - transfer of a Nas message from UE to AMF
 
-use super::{DeregistrationProcedure, SessionEstablishmentProcedure, UeProcedure};
+use super::{
+    DeregistrationProcedure, RegistrationUpdateProcedure, SessionEstablishmentProcedure,
+    SessionModificationProcedure, SessionReleaseProcedure, UeProcedure,
+};
 use crate::HandlerApi;
+use crate::nas::FgmmCause;
 use anyhow::Result;
 use derive_deref::{Deref, DerefMut};
 use oxirush_nas::{
     Nas5gmmMessage, Nas5gsMessage, Nas5gsmMessage, decode_nas_5gs_message,
     messages::NasUlNasTransport,
 };
-use slog::{Logger, warn};
+use slog::warn;
 
 #[derive(Deref, DerefMut)]
 pub struct UplinkNasProcedure<'a, A: HandlerApi>(UeProcedure<'a, A>);
@@ -18,29 +22,70 @@ impl<'a, A: HandlerApi> UplinkNasProcedure<'a, A> {
         UplinkNasProcedure(ue_procedure)
     }
 
-    pub async fn run(mut self, mut nas_bytes: Vec<u8>) -> Result<()> {
-        patch_nas_for_oai_deregistration_security_header(&mut nas_bytes, self.logger);
+    pub async fn run(mut self, nas_bytes: Vec<u8>) -> Result<()> {
+        let nas = match self.ue.nas.decode(&nas_bytes) {
+            Ok(nas) => nas,
+            Err(e) => {
+                // TS24.501, 4.4.4.3: discard messages that fail the integrity check.
+                warn!(self.logger, "{e}");
+                return Ok(());
+            }
+        };
+        self.handle(nas).await
+    }
 
-        match self.ue.nas.decode(&nas_bytes)? {
-            Nas5gsMessage::Gmm(
-                _header,
-                Nas5gmmMessage::UlNasTransport(NasUlNasTransport {
+    // Handle a NAS message that has already been deciphered and integrity checked.
+    pub async fn handle(mut self, nas: Nas5gsMessage) -> Result<()> {
+        match nas {
+            Nas5gsMessage::Gmm(_header, Nas5gmmMessage::UlNasTransport(ul_nas_transport)) => {
+                self.log_message(">> UlNasTransport");
+
+                // TS24.501, 5.4.5.2.5: a 5GSM message for a slice that the UE isn't allowed isn't forwarded.
+                if let Some(s_nssai) = &ul_nas_transport.s_nssai {
+                    let allowed = crate::nas::parse::s_nssai(&s_nssai.value)
+                        .is_ok_and(|x| self.ue.allowed_nssai.contains(&x));
+                    if !allowed {
+                        warn!(
+                            self.logger,
+                            "S-NSSAI {:x?} is not in the UE's Allowed NSSAI", s_nssai.value
+                        );
+                        let dl_nas_transport = crate::nas::build::payload_not_forwarded(
+                            ul_nas_transport,
+                            FgmmCause::PAYLOAD_WAS_NOT_FORWARDED,
+                        );
+                        self.log_message("<< DlNasTransport(5GMM cause)");
+                        return self.nas_indication(dl_nas_transport).await;
+                    }
+                }
+
+                let NasUlNasTransport {
                     payload_container,
+                    s_nssai,
                     dnn,
                     ..
-                }),
-            ) => {
-                self.log_message(">> UlNasTransport");
+                } = ul_nas_transport;
                 let dnn_bytes = dnn.map(|nas_dnn| nas_dnn.value);
+                let s_nssai_bytes = s_nssai.map(|nas_s_nssai| nas_s_nssai.value);
                 match decode_nas_5gs_message(&payload_container.value)? {
                     Nas5gsMessage::Gsm(
                         header,
                         Nas5gsmMessage::PduSessionEstablishmentRequest(r),
                     ) => {
                         SessionEstablishmentProcedure::new(self.0)
-                            .run(header, r, dnn_bytes)
+                            .run(header, r, dnn_bytes, s_nssai_bytes)
                             .await?;
                     }
+                    Nas5gsMessage::Gsm(
+                        header,
+                        Nas5gsmMessage::PduSessionModificationRequest(r),
+                    ) => {
+                        SessionModificationProcedure::new(self.0)
+                            .ue_requested(header, r)
+                            .await?;
+                    }
+                    Nas5gsMessage::Gsm(header, Nas5gsmMessage::PduSessionReleaseRequest(r)) => {
+                        SessionReleaseProcedure::new(self.0).run(header, r).await?;
+                    }
                     m => {
                         warn!(
                             self.logger,
@@ -48,12 +93,13 @@ impl<'a, A: HandlerApi> UplinkNasProcedure<'a, A> {
                         );
                     }
                 }
-                // TODO: PduSessionModificationRequest(NasPduSessionModificationRequest)
-                // TODO: PduSessionReleaseRequest(NasPduSessionReleaseRequest)
+            }
+            Nas5gsMessage::Gmm(_header, Nas5gmmMessage::RegistrationRequest(r)) => {
+                RegistrationUpdateProcedure::new(self.0).run(r).await?;
             }
             Nas5gsMessage::Gmm(_header, Nas5gmmMessage::DeregistrationRequestFromUe(r)) => {
                 self.log_message(">> DeregistrationRequestFromUe");
-                DeregistrationProcedure::new(self.0).run(r).await?;
+                DeregistrationProcedure::new(self.0).ue_initiated(r).await?;
             }
 
             m => {
@@ -63,31 +109,3 @@ impl<'a, A: HandlerApi> UplinkNasProcedure<'a, A> {
         Ok(())
     }
 }
-
-// OAI UE sends a security protected deregistration request where the inner
-// message has security header type 0x0100 - INTEGRITY_PROTECTED_AND_CIPHERED_WITH_NEW_SECU_CTX -
-// but no security header.
-// Wireshark parses this OK, but our Oxirush NAS decoder doesn't.
-// Current hypothesis is that OAI is getting it wrong, and Wireshark is tolerating it because
-// it calculates inner messsage offsets assuming that it cannot have a security header.
-//
-// For now, we have this hack to patch the message to pacify the NAS decoder.
-fn patch_nas_for_oai_deregistration_security_header(nas_bytes: &mut Vec<u8>, logger: &Logger) {
-    const INNER_SECURITY_HEADER_TYPE_OFFSET: usize = 8;
-    if nas_bytes.len() < (INNER_SECURITY_HEADER_TYPE_OFFSET + 1) {
-        return;
-    }
-
-    if nas_bytes[0] == 0x7e && nas_bytes[1] == 0x02 {
-        // Security protected MM message.
-        // The inner message header starts at byte 7, and its security header type is at byte 8.
-        if nas_bytes[INNER_SECURITY_HEADER_TYPE_OFFSET] != 0x00 {
-            warn!(
-                logger,
-                "Patching NAS message to change inner message security header type from {:?} to 0",
-                nas_bytes[INNER_SECURITY_HEADER_TYPE_OFFSET]
-            );
-            nas_bytes[INNER_SECURITY_HEADER_TYPE_OFFSET] = 0x00;
-        }
-    }
-}
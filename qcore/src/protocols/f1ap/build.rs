@@ -1,6 +1,8 @@
 //! build_f1ap - construction of F1AP messages
-use crate::{PduSession, UeContext};
-use anyhow::Result;
+use crate::{
+    Drb, DrbModification, PduSession, QosFlow, SessionAmbr, SessionQos, UeAmbr, UeContext,
+};
+use anyhow::{Result, bail};
 use asn1_per::*;
 use f1ap::*;
 use rrc::{
@@ -135,65 +137,21 @@ fn served_cell_to_activated(
     }
 }
 
+// Every DRB of a session has the session's uplink tunnel, since the QFI in the SDAP header tells us which QoS flow
+// an uplink packet belongs to.
 pub fn drb_to_be_setup_item(
-    snssai: Snssai,
+    session: &PduSession,
+    drb: &Drb,
     gtp_tunnel: GtpTunnel,
-    pdu_session_id: u8,
-    qfi: u8,
-) -> DrbsToBeSetupItem {
-    DrbsToBeSetupItem {
-        drb_id: DrbId(1),
-        qos_information: QosInformation::DrbInformation(DrbInformation {
-            drb_qos: QosFlowLevelQosParameters {
-                qos_characteristics: QosCharacteristics::NonDynamic5qi(NonDynamic5qiDescriptor {
-                    five_qi: 82,
-                    qos_priority_level: None,
-                    averaging_window: None,
-                    max_data_burst_volume: None,
-                    cn_packet_delay_budget_downlink: None,
-                    cn_packet_delay_budget_uplink: None,
-                }),
-                ngran_allocation_retention_priority: NgranAllocationAndRetentionPriority {
-                    priority_level: PriorityLevel(14),
-                    pre_emption_capability: PreEmptionCapability::MayTriggerPreEmption,
-                    pre_emption_vulnerability: PreEmptionVulnerability::NotPreEmptable,
-                },
-                gbr_qos_flow_information: None,
-                reflective_qos_attribute: None,
-                pdu_session_id: Some(PduSessionId(pdu_session_id)),
-                ulpdu_session_aggregate_maximum_bit_rate: None,
-                qos_monitoring_request: None,
-            },
-            snssai: snssai.into(),
-            notification_control: None,
-            flows_mapped_to_drb_list: FlowsMappedToDrbList(nonempty![FlowsMappedToDrbItem {
-                qos_flow_identifier: QosFlowIdentifier(qfi),
-                qos_flow_level_qos_parameters: QosFlowLevelQosParameters {
-                    qos_characteristics: QosCharacteristics::NonDynamic5qi(
-                        NonDynamic5qiDescriptor {
-                            five_qi: 82,
-                            qos_priority_level: None,
-                            averaging_window: None,
-                            max_data_burst_volume: None,
-                            cn_packet_delay_budget_downlink: None,
-                            cn_packet_delay_budget_uplink: None,
-                        },
-                    ),
-                    ngran_allocation_retention_priority: NgranAllocationAndRetentionPriority {
-                        priority_level: PriorityLevel(14),
-                        pre_emption_capability: PreEmptionCapability::MayTriggerPreEmption,
-                        pre_emption_vulnerability: PreEmptionVulnerability::NotPreEmptable,
-                    },
-                    gbr_qos_flow_information: None,
-                    reflective_qos_attribute: None,
-                    pdu_session_id: None,
-                    ulpdu_session_aggregate_maximum_bit_rate: None,
-                    qos_monitoring_request: None,
-                },
-                qos_flow_mapping_indication: None,
-                tsc_traffic_characteristics: None,
-            }]),
-        }),
+) -> Result<DrbsToBeSetupItem> {
+    Ok(DrbsToBeSetupItem {
+        drb_id: DrbId(drb.id),
+        qos_information: QosInformation::DrbInformation(drb_information(
+            session,
+            &session.session_ambr,
+            &session.qos_flows,
+            drb,
+        )?),
         ul_up_tnl_information_to_be_setup_list: UlUpTnlInformationToBeSetupList(nonempty![
             UlUpTnlInformationToBeSetupItem {
                 ul_up_tnl_information: UpTransportLayerInformation::GtpTunnel(gtp_tunnel),
@@ -209,6 +167,69 @@ pub fn drb_to_be_setup_item(
         ulpdcpsn_length: Some(PdcpsnLength::TwelveBits),
         additional_pdcp_duplication_tnl_list: None,
         rlc_duplication_information: None,
+    })
+}
+
+// The QoS of a DRB, given the session AMBR and QoS flows that the session has or is about to have.
+fn drb_information(
+    session: &PduSession,
+    session_ambr: &SessionAmbr,
+    qos_flows: &[QosFlow],
+    drb: &Drb,
+) -> Result<DrbInformation> {
+    let qos_flows = drb.qos_flows(qos_flows);
+    let flows = qos_flows
+        .iter()
+        .map(|flow| FlowsMappedToDrbItem {
+            qos_flow_identifier: QosFlowIdentifier(flow.qfi),
+            qos_flow_level_qos_parameters: qos_flow_level_qos_parameters(flow),
+            qos_flow_mapping_indication: None,
+            tsc_traffic_characteristics: None,
+        })
+        .collect();
+    let Some(flows) = NonEmpty::from_vec(flows) else {
+        bail!(
+            "DRB {} of PDU session {} has no QoS flows",
+            drb.id,
+            session.id
+        );
+    };
+
+    // The DRB has the QoS of its first QoS flow, which, on the default DRB, is the default QoS flow.
+    Ok(DrbInformation {
+        drb_qos: QosFlowLevelQosParameters {
+            pdu_session_id: Some(PduSessionId(session.id)),
+            ulpdu_session_aggregate_maximum_bit_rate: Some(BitRate(
+                session_ambr.uplink_kbps as u64 * 1000,
+            )),
+            ..qos_flow_level_qos_parameters(&qos_flows[0])
+        },
+        snssai: session.snssai.into(),
+        notification_control: None,
+        flows_mapped_to_drb_list: FlowsMappedToDrbList(flows),
+    })
+}
+
+fn qos_flow_level_qos_parameters(flow: &QosFlow) -> QosFlowLevelQosParameters {
+    QosFlowLevelQosParameters {
+        qos_characteristics: QosCharacteristics::NonDynamic5qi(NonDynamic5qiDescriptor {
+            five_qi: flow.five_qi,
+            qos_priority_level: None,
+            averaging_window: None,
+            max_data_burst_volume: None,
+            cn_packet_delay_budget_downlink: None,
+            cn_packet_delay_budget_uplink: None,
+        }),
+        ngran_allocation_retention_priority: NgranAllocationAndRetentionPriority {
+            priority_level: PriorityLevel(flow.arp_priority_level),
+            pre_emption_capability: PreEmptionCapability::MayTriggerPreEmption,
+            pre_emption_vulnerability: PreEmptionVulnerability::NotPreEmptable,
+        },
+        gbr_qos_flow_information: None,
+        reflective_qos_attribute: None,
+        pdu_session_id: None,
+        ulpdu_session_aggregate_maximum_bit_rate: None,
+        qos_monitoring_request: None,
     }
 }
 
@@ -221,23 +242,36 @@ fn scell_to_be_setup_item(nr_cgi: NrCgi) -> SCellToBeSetupItem {
     }
 }
 
+/// Build a UE Context Setup Request with the DRBs of each of the given PDU sessions.
 pub fn ue_context_setup_request(
     ue: &UeContext,
     transport_layer_address: TransportLayerAddress,
-    session: &PduSession,
+    sessions: &[PduSession],
+    ue_ambr: Option<UeAmbr>,
 ) -> Result<UeContextSetupRequest> {
-    // TODO: avoid hardcoding
-    let gnb_du_ue_ambr_ul = Some(BitRate(1_000_000));
+    let gnb_du_ue_ambr_ul = Some(gnb_du_ue_ambr_ul(
+        sessions.iter().map(|session| &session.session_ambr),
+        ue_ambr,
+    ));
 
-    let drbs_to_be_setup_list = Some(DrbsToBeSetupList(nonempty![drb_to_be_setup_item(
-        session.snssai,
-        GtpTunnel {
-            transport_layer_address,
-            gtp_teid: session.userplane_info.uplink_gtp_teid.clone()
-        },
-        session.id,
-        session.userplane_info.qfi
-    )]));
+    let drbs = sessions
+        .iter()
+        .flat_map(|session| session.drbs.iter().map(move |drb| (session, drb)))
+        .map(|(session, drb)| {
+            drb_to_be_setup_item(
+                session,
+                drb,
+                GtpTunnel {
+                    transport_layer_address: transport_layer_address.clone(),
+                    gtp_teid: session.userplane_info.uplink_gtp_teid.clone(),
+                },
+            )
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let Some(drbs) = NonEmpty::from_vec(drbs) else {
+        bail!("UE context setup with no PDU sessions");
+    };
+    let drbs_to_be_setup_list = Some(DrbsToBeSetupList(drbs));
 
     Ok(UeContextSetupRequest {
         gnb_cu_ue_f1ap_id: GnbCuUeF1apId(ue.key),
@@ -309,3 +343,240 @@ pub fn ue_context_release_command(ue: &UeContext, cause: Cause) -> UeContextRele
         target_cells_to_cancel: None,
     }
 }
+
+/// Page a UE in CM-IDLE in the given cells, identifying it by its 5G-S-TMSI.
+pub fn paging(amf_ids: &[u8; 3], tmsi: &[u8; 4], cells: Vec<NrCgi>) -> Result<Paging> {
+    let Some(cells) = NonEmpty::from_vec(cells) else {
+        bail!("No cells to page in");
+    };
+
+    // The 5G-S-TMSI is the AMF set ID, AMF pointer and 5G-TMSI - TS23.003, 2.11.
+    let mut five_g_s_tmsi = amf_ids[1..].to_vec();
+    five_g_s_tmsi.extend_from_slice(tmsi);
+
+    // TS38.304, 7.1: the UE identity index is the 5G-S-TMSI mod 1024, which is the bottom 10 bits of the 5G-TMSI.
+    let ue_identity_index = ((u32::from_be_bytes(*tmsi) % 1024) as u16).to_be_bytes();
+
+    Ok(Paging {
+        ue_identity_index_value: UeIdentityIndexValue::IndexLength10(
+            ue_identity_index.view_bits::<Msb0>()[6..].to_bitvec(),
+        ),
+        paging_identity: PagingIdentity::CnUePagingIdentity(CnUePagingIdentity::FiveGSTmsi(
+            BitString::from_vec(five_g_s_tmsi),
+        )),
+        paging_drx: None,
+        paging_priority: None,
+        paging_cell_list: PagingCellList(cells.map(|nr_cgi| PagingCellItem { nr_cgi })),
+        paging_origin: None,
+    })
+}
+
+// Without a subscribed UE AMBR, the UE AMBR is the sum of its session AMBRs - see TS23.501, 5.7.2.6.
+fn gnb_du_ue_ambr_ul<'a>(
+    session_ambrs: impl Iterator<Item = &'a SessionAmbr>,
+    ue_ambr: Option<UeAmbr>,
+) -> BitRate {
+    let kbps = match ue_ambr {
+        Some(ue_ambr) => ue_ambr.uplink_kbps as u64,
+        None => session_ambrs.map(|x| x.uplink_kbps as u64).sum(),
+    };
+    BitRate(kbps * 1000)
+}
+
+/// Release a session's DRBs.  The session must already have been removed from the UE context, so that the UE AMBR
+/// is worked out from the remaining sessions.
+pub fn ue_context_modification_request_release_drbs(
+    ue: &UeContext,
+    drb_ids: &NonEmpty<u8>,
+    ue_ambr: Option<UeAmbr>,
+) -> UeContextModificationRequest {
+    let gnb_du_ue_ambr_ul = Some(gnb_du_ue_ambr_ul(
+        ue.pdu_sessions.iter().map(|session| &session.session_ambr),
+        ue_ambr,
+    ));
+    UeContextModificationRequest {
+        drbs_to_be_released_list: Some(DrbsToBeReleasedList(drb_ids.clone().map(|drb_id| {
+            DrbsToBeReleasedItem {
+                drb_id: DrbId(drb_id),
+            }
+        }))),
+        gnb_du_ue_ambr_ul,
+        ..ue_context_modification_request(ue)
+    }
+}
+
+/// Modify the QoS flows mapped to some of a session's DRBs, to match the QoS that the session is about to have,
+/// release the DRBs that are left with no QoS flows, and update the UE AMBR to take account of its new session AMBR.
+pub fn ue_context_modification_request_modify_drbs(
+    ue: &UeContext,
+    transport_layer_address: TransportLayerAddress,
+    session: &PduSession,
+    qos: &SessionQos,
+    modified_drbs: &NonEmpty<DrbModification>,
+    released_drb_ids: &[u8],
+    ue_ambr: Option<UeAmbr>,
+) -> Result<UeContextModificationRequest> {
+    let gnb_du_ue_ambr_ul = Some(gnb_du_ue_ambr_ul(
+        ue.pdu_sessions.iter().map(|x| {
+            if x.id == session.id {
+                &qos.session_ambr
+            } else {
+                &x.session_ambr
+            }
+        }),
+        ue_ambr,
+    ));
+    let mut drbs_to_be_modified = vec![];
+    for DrbModification { drb, .. } in modified_drbs.iter() {
+        drbs_to_be_modified.push(DrbsToBeModifiedItem {
+            drb_id: DrbId(drb.id),
+            qos_information: Some(QosInformation::DrbInformation(drb_information(
+                session,
+                &qos.session_ambr,
+                &qos.qos_flows,
+                drb,
+            )?)),
+            ul_up_tnl_information_to_be_setup_list: UlUpTnlInformationToBeSetupList(nonempty![
+                UlUpTnlInformationToBeSetupItem {
+                    ul_up_tnl_information: UpTransportLayerInformation::GtpTunnel(GtpTunnel {
+                        transport_layer_address: transport_layer_address.clone(),
+                        gtp_teid: session.userplane_info.uplink_gtp_teid.clone(),
+                    }),
+                    bh_info: None,
+                },
+            ]),
+            ul_configuration: None,
+            dlpdcpsn_length: None,
+            ulpdcpsn_length: None,
+            bearer_type_change: None,
+            rlc_mode: None,
+            duplication_activation: None,
+            dc_based_duplication_configured: None,
+            dc_based_duplication_activation: None,
+            additional_pdcp_duplication_tnl_list: None,
+            rlc_duplication_information: None,
+            transmission_stop_indicator: None,
+        });
+    }
+    let drbs_to_be_released = released_drb_ids
+        .iter()
+        .map(|drb_id| DrbsToBeReleasedItem {
+            drb_id: DrbId(*drb_id),
+        })
+        .collect();
+    Ok(UeContextModificationRequest {
+        drbs_to_be_modified_list: NonEmpty::from_vec(drbs_to_be_modified).map(DrbsToBeModifiedList),
+        drbs_to_be_released_list: NonEmpty::from_vec(drbs_to_be_released).map(DrbsToBeReleasedList),
+        gnb_du_ue_ambr_ul,
+        ..ue_context_modification_request(ue)
+    })
+}
+
+/// Set up the DRBs of a new session, and update the UE AMBR to take account of it.
+pub fn ue_context_modification_request_setup_drbs(
+    ue: &UeContext,
+    transport_layer_address: TransportLayerAddress,
+    session: &PduSession,
+    ue_ambr: Option<UeAmbr>,
+) -> Result<UeContextModificationRequest> {
+    let gnb_du_ue_ambr_ul = Some(gnb_du_ue_ambr_ul(
+        ue.pdu_sessions
+            .iter()
+            .chain(std::iter::once(session))
+            .map(|x| &x.session_ambr),
+        ue_ambr,
+    ));
+    let drbs = session
+        .drbs
+        .iter()
+        .map(|drb| {
+            Ok(DrbsToBeSetupModItem {
+                drb_id: DrbId(drb.id),
+                qos_information: QosInformation::DrbInformation(drb_information(
+                    session,
+                    &session.session_ambr,
+                    &session.qos_flows,
+                    drb,
+                )?),
+                ul_up_tnl_information_to_be_setup_list: UlUpTnlInformationToBeSetupList(nonempty![
+                    UlUpTnlInformationToBeSetupItem {
+                        ul_up_tnl_information: UpTransportLayerInformation::GtpTunnel(GtpTunnel {
+                            transport_layer_address: transport_layer_address.clone(),
+                            gtp_teid: session.userplane_info.uplink_gtp_teid.clone(),
+                        }),
+                        bh_info: None,
+                    },
+                ]),
+                rlc_mode: RlcMode::RlcUmBidirectional,
+                ul_configuration: None,
+                duplication_activation: None,
+                dc_based_duplication_configured: None,
+                dc_based_duplication_activation: None,
+                dlpdcpsn_length: Some(PdcpsnLength::TwelveBits),
+                ulpdcpsn_length: Some(PdcpsnLength::TwelveBits),
+                additional_pdcp_duplication_tnl_list: None,
+                rlc_duplication_information: None,
+            })
+        })
+        .collect::<Result<Vec<_>>>()?;
+    let Some(drbs) = NonEmpty::from_vec(drbs) else {
+        bail!("PDU session {} has no DRBs", session.id);
+    };
+    Ok(UeContextModificationRequest {
+        drbs_to_be_setup_mod_list: Some(DrbsToBeSetupModList(drbs)),
+        gnb_du_ue_ambr_ul,
+        ..ue_context_modification_request(ue)
+    })
+}
+
+fn ue_context_modification_request(ue: &UeContext) -> UeContextModificationRequest {
+    UeContextModificationRequest {
+        gnb_cu_ue_f1ap_id: GnbCuUeF1apId(ue.key),
+        gnb_du_ue_f1ap_id: ue.gnb_du_ue_f1ap_id,
+        sp_cell_id: None,
+        serv_cell_index: None,
+        sp_cell_ul_configured: None,
+        drx_cycle: None,
+        cu_to_du_rrc_information: None,
+        transmission_action_indicator: None,
+        resource_coordination_transfer_container: None,
+        rrc_reconfiguration_complete_indicator: None,
+        rrc_container: None,
+        s_cell_to_be_setup_mod_list: None,
+        s_cell_to_be_removed_list: None,
+        srbs_to_be_setup_mod_list: None,
+        drbs_to_be_setup_mod_list: None,
+        drbs_to_be_modified_list: None,
+        srbs_to_be_released_list: None,
+        drbs_to_be_released_list: None,
+        inactivity_monitoring_request: None,
+        rat_frequency_priority_information: None,
+        drx_configuration_indicator: None,
+        rlc_failure_indication: None,
+        uplink_tx_direct_current_list_information: None,
+        gnb_du_configuration_query: None,
+        gnb_du_ue_ambr_ul: None,
+        execute_duplication: None,
+        rrc_delivery_status_request: None,
+        resource_coordination_transfer_information: None,
+        serving_cell_mo: None,
+        needfor_gap: None,
+        full_configuration: None,
+        additional_rrm_priority_index: None,
+        lower_layer_presence_status_change: None,
+        bh_channels_to_be_setup_mod_list: None,
+        bh_channels_to_be_modified_list: None,
+        bh_channels_to_be_released_list: None,
+        nr_v2x_services_authorized: None,
+        ltev2x_services_authorized: None,
+        nr_ue_sidelink_aggregate_maximum_bitrate: None,
+        lte_ue_sidelink_aggregate_maximum_bitrate: None,
+        pc5_link_ambr: None,
+        sl_drbs_to_be_setup_mod_list: None,
+        sl_drbs_to_be_modified_list: None,
+        sl_drbs_to_be_released_list: None,
+        conditional_intra_du_mobility_information: None,
+        f1c_transfer_path: None,
+        scg_indicator: None,
+    }
+}
@@ -1,20 +1,35 @@
 #![allow(clippy::unusual_byte_groupings)]
-use crate::PduSession;
+use super::{PcoContainerId, PduSessionStatus, PduSessionType};
+use crate::{
+    Config, DataNetworkConfig, NasCipheringAlgorithm, NasIntegrityAlgorithm, PduSession,
+    PduSessionModification, QosFlow, QosRule, SessionAmbr, UserplaneSession,
+};
 use anyhow::{Result, bail};
 use oxirush_nas::{
     Nas5gmmMessage, Nas5gmmMessageType, Nas5gsMessage, Nas5gsmMessage, Nas5gsmMessageType, NasAbba,
     NasAdditionalFGSecurityInformation, NasAuthenticationParameterAutn,
-    NasAuthenticationParameterRand, NasDnn, NasFGsMobileIdentity, NasFGsRegistrationResult,
-    NasKeySetIdentifier, NasNssai, NasPayloadContainer, NasPayloadContainerType, NasPduAddress,
-    NasPduSessionType, NasQosRules, NasSecurityAlgorithms, NasSessionAmbr, NasUeSecurityCapability,
-    encode_nas_5gs_message,
+    NasAuthenticationParameterRand, NasConfigurationUpdateIndication, NasDeRegistrationType,
+    NasDnn, NasExtendedProtocolConfigurationOptions, NasFGmmCause, NasFGsIdentityType,
+    NasFGsMobileIdentity, NasFGsRegistrationResult, NasFGsTrackingAreaIdentityList, NasFGsmCause,
+    NasGprsTimer3, NasImeisvRequest, NasKeySetIdentifier, NasNetworkName, NasNssai,
+    NasPayloadContainer, NasPayloadContainerType, NasPduAddress, NasPduSessionReactivationResult,
+    NasPduSessionStatus, NasPduSessionType, NasQosFlowDescriptions, NasQosRules, NasRejectedNssai,
+    NasSNssai, NasSecurityAlgorithms, NasSessionAmbr, NasTimeZone, NasTimeZoneAndTime,
+    NasUeSecurityCapability, encode_nas_5gs_message,
     messages::{
-        NasAuthenticationRequest, NasDlNasTransport, NasPduSessionEstablishmentAccept,
-        NasRegistrationAccept, NasSecurityModeCommand,
+        NasAuthenticationReject, NasAuthenticationRequest, NasConfigurationUpdateCommand,
+        NasDeregistrationAcceptFromUe, NasDeregistrationRequestToUe, NasDlNasTransport,
+        NasIdentityRequest, NasPduSessionEstablishmentAccept, NasPduSessionEstablishmentReject,
+        NasPduSessionModificationCommand, NasPduSessionModificationReject,
+        NasPduSessionReleaseCommand, NasPduSessionReleaseReject, NasRegistrationAccept,
+        NasRegistrationReject, NasSecurityModeCommand, NasServiceAccept, NasServiceReject,
+        NasUlNasTransport,
     },
 };
 use security::NAS_ABBA;
 use std::net::IpAddr;
+use std::time::{Duration, SystemTime};
+use xxap::Snssai;
 
 pub fn authentication_request(rand: &[u8; 16], autn: &[u8; 16]) -> Nas5gsMessage {
     // "The SEAF shall set the ABBA parameter as defined in Annex A.7.1."
@@ -30,8 +45,27 @@ pub fn authentication_request(rand: &[u8; 16], autn: &[u8; 16]) -> Nas5gsMessage
     )
 }
 
+pub fn authentication_reject() -> Nas5gsMessage {
+    Nas5gsMessage::new_5gmm(
+        Nas5gmmMessageType::AuthenticationReject,
+        Nas5gmmMessage::AuthenticationReject(NasAuthenticationReject::new()),
+    )
+}
+
+pub fn identity_request() -> Nas5gsMessage {
+    // TS24.501, 9.11.3.3 - type of identity = 001 = SUCI
+    Nas5gsMessage::new_5gmm(
+        Nas5gmmMessageType::IdentityRequest,
+        Nas5gmmMessage::IdentityRequest(NasIdentityRequest {
+            identity_type: NasFGsIdentityType::new(0b001),
+        }),
+    )
+}
+
 pub fn security_mode_command(
     replayed_ue_security_capabilities: NasUeSecurityCapability,
+    integrity_algorithm: NasIntegrityAlgorithm,
+    ciphering_algorithm: NasCipheringAlgorithm,
 ) -> Nas5gsMessage {
     // Request retransmission of initial NAS message.
     let additional_fg_security_information =
@@ -39,10 +73,14 @@ pub fn security_mode_command(
     Nas5gsMessage::new_5gmm(
         Nas5gmmMessageType::SecurityModeCommand,
         Nas5gmmMessage::SecurityModeCommand(NasSecurityModeCommand {
-            selected_nas_security_algorithms: NasSecurityAlgorithms::new(2), // AES integrity and NULL encryption,
+            // TS24.501, 9.11.3.34: ciphering algorithm in the top nibble, and integrity algorithm in the bottom.
+            selected_nas_security_algorithms: NasSecurityAlgorithms::new(
+                ((ciphering_algorithm as u8) << 4) | integrity_algorithm as u8,
+            ),
             ngksi: NasKeySetIdentifier { value: 0 },
             replayed_ue_security_capabilities,
-            imeisv_request: None,
+            // TS24.501, 9.11.3.28: IMEISV requested.
+            imeisv_request: Some(NasImeisvRequest::new(0b001)),
             selected_eps_nas_security_algorithms: None,
             additional_fg_security_information,
             eap_message: None,
@@ -66,21 +104,41 @@ fn nas_mobile_identity_guti(
 }
 
 pub fn registration_accept(
-    allowed_sst: u8,
-    plmn: &[u8; 3],
-    amf_ids: &[u8; 3],
+    allowed_nssai: &[Snssai],
+    rejected_nssai: &[Snssai],
+    configured_nssai: Option<&[Snssai]>,
+    config: &Config,
     tmsi: &[u8; 4],
+    tacs: &[[u8; 3]],
+    pdu_session_status: Option<&PduSessionStatus>,
 ) -> Nas5gsMessage {
-    // TS24.501, 9.11.3.37 defines as a list of NSSAI length and value from TS24.501, 9.11.2.8.
-    // This is a single NSSAI with an SST and no SSD.
-    let nas_allowed_nssais = vec![0x01, allowed_sst];
-    let fg_guti = Some(nas_mobile_identity_guti(plmn, amf_ids, tmsi));
+    let fg_guti = Some(nas_mobile_identity_guti(
+        &config.plmn,
+        &config.amf_ids,
+        tmsi,
+    ));
+    let rejected_nssai = (!rejected_nssai.is_empty()).then(|| nas_rejected_nssai(rejected_nssai));
+    let tai_list = (!tacs.is_empty()).then(|| tai_list(&config.plmn, tacs));
+    // TS24.501, 10.2: a UE that isn't given T3512 uses the default of 54 minutes, which GPRS timer 3 can't encode.
+    let t3512_value = (config.t3512 != DEFAULT_T3512)
+        .then(|| gprs_timer_3(config.t3512))
+        .flatten()
+        .map(|x| NasGprsTimer3::new(vec![x]));
 
     Nas5gsMessage::new_5gmm(
         Nas5gmmMessageType::RegistrationAccept,
         Nas5gmmMessage::RegistrationAccept(NasRegistrationAccept {
             fg_guti,
-            allowed_nssai: Some(NasNssai::new(nas_allowed_nssais)),
+            tai_list,
+            allowed_nssai: Some(nas_nssai(allowed_nssai)),
+            rejected_nssai,
+            configured_nssai: configured_nssai.map(nas_nssai),
+            pdu_session_status: pdu_session_status
+                .map(|x| NasPduSessionStatus::new(pdu_session_bitmap(&x.active))),
+            pdu_session_reactivation_result: pdu_session_status
+                .and_then(|x| x.reactivation_failed.as_ref())
+                .map(|x| NasPduSessionReactivationResult::new(pdu_session_bitmap(x))),
+            t3512_value,
             ..NasRegistrationAccept::new(NasFGsRegistrationResult::new(
                 vec![0b00_0_0_0_001], // no emergency, no slice-specific auth, no SMS, 3GPP access
             ))
@@ -88,65 +146,140 @@ pub fn registration_accept(
     )
 }
 
+pub fn registration_reject(fgmm_cause: u8) -> Nas5gsMessage {
+    Nas5gsMessage::new_5gmm(
+        Nas5gmmMessageType::RegistrationReject,
+        Nas5gmmMessage::RegistrationReject(NasRegistrationReject::new(NasFGmmCause::new(
+            fgmm_cause,
+        ))),
+    )
+}
+
+pub fn service_accept(pdu_session_status: &PduSessionStatus) -> Nas5gsMessage {
+    Nas5gsMessage::new_5gmm(
+        Nas5gmmMessageType::ServiceAccept,
+        Nas5gmmMessage::ServiceAccept(NasServiceAccept {
+            pdu_session_status: Some(NasPduSessionStatus::new(pdu_session_bitmap(
+                &pdu_session_status.active,
+            ))),
+            pdu_session_reactivation_result: pdu_session_status
+                .reactivation_failed
+                .as_ref()
+                .map(|x| NasPduSessionReactivationResult::new(pdu_session_bitmap(x))),
+            ..NasServiceAccept::new()
+        }),
+    )
+}
+
+pub fn service_reject(fgmm_cause: u8) -> Nas5gsMessage {
+    Nas5gsMessage::new_5gmm(
+        Nas5gmmMessageType::ServiceReject,
+        Nas5gmmMessage::ServiceReject(NasServiceReject::new(NasFGmmCause::new(fgmm_cause))),
+    )
+}
+
+/// A UE Configuration Update Command carrying whichever of a new 5G-GUTI, TAI list and Allowed NSSAI are supplied,
+/// and, if a time is supplied, the network identity and time (NITZ).
+pub fn configuration_update_command(
+    config: &Config,
+    acknowledgement_requested: bool,
+    tmsi: Option<&[u8; 4]>,
+    tacs: Option<&[[u8; 3]]>,
+    allowed_nssai: Option<&[Snssai]>,
+    send_network_name: bool,
+    time: Option<SystemTime>,
+) -> Nas5gsMessage {
+    // TS24.501, 9.11.3.18: bit 1 = acknowledgement requested.
+    let configuration_update_indication =
+        acknowledgement_requested.then(|| NasConfigurationUpdateIndication::new(0b0001));
+    let name_for_network = |name: &Option<String>| {
+        name.as_ref()
+            .filter(|_| send_network_name)
+            .map(|x| NasNetworkName::new(network_name(x)))
+    };
+
+    // Both time IEs carry the local time zone, so we can only send the time if we know the time zone.
+    let time = time
+        .zip(config.time_zone_offset_mins)
+        .map(|(time, offset_mins)| (time, time_zone(offset_mins)));
+
+    Nas5gsMessage::new_5gmm(
+        Nas5gmmMessageType::ConfigurationUpdateCommand,
+        Nas5gmmMessage::ConfigurationUpdateCommand(NasConfigurationUpdateCommand {
+            configuration_update_indication,
+            fg_guti: tmsi.map(|x| nas_mobile_identity_guti(&config.plmn, &config.amf_ids, x)),
+            tai_list: tacs
+                .filter(|x| !x.is_empty())
+                .map(|x| tai_list(&config.plmn, x)),
+            allowed_nssai: allowed_nssai.map(nas_nssai),
+            full_name_for_network: name_for_network(&config.full_network_name),
+            short_name_for_network: name_for_network(&config.short_network_name),
+            local_time_zone: time.map(|(_, time_zone)| NasTimeZone::new(vec![time_zone])),
+            universal_time_and_local_time_zone: time.map(|(time, time_zone)| {
+                NasTimeZoneAndTime::new(universal_time_and_local_time_zone(time, time_zone))
+            }),
+            ..NasConfigurationUpdateCommand::new()
+        }),
+    )
+}
+
+pub fn deregistration_accept() -> Nas5gsMessage {
+    Nas5gsMessage::new_5gmm(
+        Nas5gmmMessageType::DeregistrationAcceptFromUe,
+        Nas5gmmMessage::DeregistrationAcceptFromUe(NasDeregistrationAcceptFromUe::new()),
+    )
+}
+
+pub fn deregistration_request(reregistration_required: bool) -> Nas5gsMessage {
+    // TS24.501, figure 9.11.3.20.1: spare; re-registration required bit; access type = 01 (3GPP access)
+    let de_registration_type = if reregistration_required {
+        0b0_1_01
+    } else {
+        0b0_0_01
+    };
+    Nas5gsMessage::new_5gmm(
+        Nas5gmmMessageType::DeregistrationRequestToUe,
+        Nas5gmmMessage::DeregistrationRequestToUe(NasDeregistrationRequestToUe::new(
+            NasDeRegistrationType::new(de_registration_type),
+        )),
+    )
+}
+
 pub fn pdu_session_establishment_accept(
     pdu_session: &PduSession,
     pti: u8,
+    fgsm_cause: Option<u8>,
+    pco_requests: &[u16],
+    data_network: &DataNetworkConfig,
 ) -> Result<Nas5gsMessage> {
-    let ue_ip_addr = pdu_session.userplane_info.ue_ip_addr;
-    let IpAddr::V4(ue_ipv4) = ue_ip_addr else {
-        bail!("IPv6 not implemented")
-    };
+    let session_ambr = session_ambr(&pdu_session.session_ambr);
+    let authorized_qos_rules = qos_rules(&pdu_session.qos_rules, &[]);
+    let authorized_qos_flow_descriptions = Some(qos_flow_descriptions(&pdu_session.qos_flows, &[]));
 
-    // TODO - make configurable
-    let session_ambr = NasSessionAmbr::new(vec![
-        // TS24.501, 9.11.4.14
-        0b00000110, // Unit for downlink = Mbps
-        0x00, 0x01,       // Downlink session AMBR = 1 Mbps
-        0b00000110, // Unit for uplink = Mbps
-        0x00, 0x01, // Uplink session AMBR = 1 Mbps
-    ]);
-
-    let authorized_qos_rules = NasQosRules::new(vec![
-        // TS24.501, 9.11.4.13
-        0x01, // Qos Rule Identifier = 1
-        0x00,
-        0x06,         // Length of QoS rule
-        0b001_1_0001, // Rule operation code 001 (create new); default Qos Rule 1; number of packet filters = 1,
-        // Packet filter 1
-        0b00_11_1111, // Packet filter direction = 11 (bidirectional); packet filter identifier = 1111
-        0x01,         // Length of packet filter contents
-        // Packet filter 1 contents
-        0b00000001,  // Packet filter type = match all
-        0xff,        // QoS rule precedence,
-        0b00_000001, // spare; QFI 1
-    ]);
-
-    let pdu_address = Some(NasPduAddress::new(vec![
-        // TS24.501, 9.11.4.10
-        0b0000_0_001, // spare; no SMF IPv6 link local address; PDU session type = 001 (IPv4)
-        ue_ipv4.octets()[0],
-        ue_ipv4.octets()[1],
-        ue_ipv4.octets()[2],
-        ue_ipv4.octets()[3],
-    ]));
-
-    let dnn = Some(NasDnn::new(pdu_session.dnn.clone()));
+    let (pdu_session_type, pdu_address) = pdu_address(&pdu_session.userplane_info)?;
+    let dnn = Some(NasDnn::new(crate::data_networks::encode_dnn(
+        &pdu_session.dnn,
+    )));
 
     let inner_message = Nas5gsMessage::new_5gsm(
         Nas5gsmMessageType::PduSessionEstablishmentAccept,
         Nas5gsmMessage::PduSessionEstablishmentAccept(NasPduSessionEstablishmentAccept {
-            selected_pdu_session_type: NasPduSessionType::new(0b001), // IPv4
+            selected_pdu_session_type: NasPduSessionType::new(pdu_session_type),
             authorized_qos_rules,
             session_ambr,
-            fgsm_cause: None,
+            fgsm_cause: fgsm_cause.map(NasFGsmCause::new),
             pdu_address,
             rq_timer_value: None,
-            s_nssai: None,
+            s_nssai: Some(NasSNssai::new(s_nssai_value(&pdu_session.snssai))),
             always_on_pdu_session_indication: None,
             mapped_eps_bearer_contexts: None,
             eap_message: None,
-            authorized_qos_flow_descriptions: None,
-            extended_protocol_configuration_options: None,
+            authorized_qos_flow_descriptions,
+            extended_protocol_configuration_options: extended_pco(
+                pco_requests,
+                data_network,
+                pdu_session_type,
+            ),
             dnn,
             fgsm_network_feature_support: None,
             serving_plmn_rate_control: None,
@@ -160,6 +293,120 @@ pub fn pdu_session_establishment_accept(
         pdu_session.id,
         pti,
     );
+    dl_nas_transport(inner_message)
+}
+
+pub fn pdu_session_establishment_reject(
+    pdu_session_id: u8,
+    pti: u8,
+    fgsm_cause: u8,
+) -> Result<Nas5gsMessage> {
+    let inner_message = Nas5gsMessage::new_5gsm(
+        Nas5gsmMessageType::PduSessionEstablishmentReject,
+        Nas5gsmMessage::PduSessionEstablishmentReject(NasPduSessionEstablishmentReject::new(
+            NasFGsmCause::new(fgsm_cause),
+        )),
+        pdu_session_id,
+        pti,
+    );
+    dl_nas_transport(inner_message)
+}
+
+pub fn pdu_session_modification_command(
+    pdu_session_id: u8,
+    pti: u8,
+    modification: &PduSessionModification,
+    added_qos_flows: &[QosFlow],
+    deleted_qos_flows: &[u8],
+) -> Result<Nas5gsMessage> {
+    let rules_changed =
+        !modification.add_qos_rules.is_empty() || !modification.delete_qos_rules.is_empty();
+    let flows_changed = !added_qos_flows.is_empty() || !deleted_qos_flows.is_empty();
+    let authorized_qos_rules = rules_changed
+        .then(|| qos_rules(&modification.add_qos_rules, &modification.delete_qos_rules));
+    let authorized_qos_flow_descriptions =
+        flows_changed.then(|| qos_flow_descriptions(added_qos_flows, deleted_qos_flows));
+
+    let inner_message = Nas5gsMessage::new_5gsm(
+        Nas5gsmMessageType::PduSessionModificationCommand,
+        Nas5gsmMessage::PduSessionModificationCommand(NasPduSessionModificationCommand {
+            session_ambr: modification.session_ambr.as_ref().map(session_ambr),
+            authorized_qos_rules,
+            authorized_qos_flow_descriptions,
+            ..NasPduSessionModificationCommand::new()
+        }),
+        pdu_session_id,
+        pti,
+    );
+    dl_nas_transport(inner_message)
+}
+
+pub fn pdu_session_modification_reject(
+    pdu_session_id: u8,
+    pti: u8,
+    fgsm_cause: u8,
+) -> Result<Nas5gsMessage> {
+    let inner_message = Nas5gsMessage::new_5gsm(
+        Nas5gsmMessageType::PduSessionModificationReject,
+        Nas5gsmMessage::PduSessionModificationReject(NasPduSessionModificationReject::new(
+            NasFGsmCause::new(fgsm_cause),
+        )),
+        pdu_session_id,
+        pti,
+    );
+    dl_nas_transport(inner_message)
+}
+
+pub fn pdu_session_release_command(
+    pdu_session_id: u8,
+    pti: u8,
+    fgsm_cause: u8,
+) -> Result<Nas5gsMessage> {
+    let inner_message = Nas5gsMessage::new_5gsm(
+        Nas5gsmMessageType::PduSessionReleaseCommand,
+        Nas5gsmMessage::PduSessionReleaseCommand(NasPduSessionReleaseCommand::new(
+            NasFGsmCause::new(fgsm_cause),
+        )),
+        pdu_session_id,
+        pti,
+    );
+    dl_nas_transport(inner_message)
+}
+
+pub fn pdu_session_release_reject(
+    pdu_session_id: u8,
+    pti: u8,
+    fgsm_cause: u8,
+) -> Result<Nas5gsMessage> {
+    let inner_message = Nas5gsMessage::new_5gsm(
+        Nas5gsmMessageType::PduSessionReleaseReject,
+        Nas5gsmMessage::PduSessionReleaseReject(NasPduSessionReleaseReject::new(
+            NasFGsmCause::new(fgsm_cause),
+        )),
+        pdu_session_id,
+        pti,
+    );
+    dl_nas_transport(inner_message)
+}
+
+// TS24.501, 5.4.5.2.5 - send a 5GSM message that the AMF couldn't forward back to the UE.
+pub fn payload_not_forwarded(ul_nas_transport: NasUlNasTransport, fgmm_cause: u8) -> Nas5gsMessage {
+    Nas5gsMessage::new_5gmm(
+        Nas5gmmMessageType::DlNasTransport,
+        Nas5gmmMessage::DlNasTransport(NasDlNasTransport {
+            payload_container_type: ul_nas_transport.payload_container_type,
+            payload_container: ul_nas_transport.payload_container,
+            pdu_session_id: ul_nas_transport.pdu_session_id,
+            additional_information: None,
+            fgmm_cause: Some(NasFGmmCause::new(fgmm_cause)),
+            back_off_timer_value: None,
+            lower_bound_timer_value: None,
+        }),
+    )
+}
+
+// Wrap a 5GSM message in a DL NAS Transport.
+fn dl_nas_transport(inner_message: Nas5gsMessage) -> Result<Nas5gsMessage> {
     let inner_message = encode_nas_5gs_message(&inner_message)?;
     let outer_message = Nas5gsMessage::new_5gmm(
         Nas5gmmMessageType::DlNasTransport,
@@ -175,3 +422,316 @@ pub fn pdu_session_establishment_accept(
     );
     Ok(outer_message)
 }
+
+// TS24.501, 9.11.3.9 - a partial tracking area identity list of type 00, meaning a list of non-consecutive
+// TACs in one PLMN.  It can hold at most 16 TACs.
+fn tai_list(plmn: &[u8; 3], tacs: &[[u8; 3]]) -> NasFGsTrackingAreaIdentityList {
+    let tacs = &tacs[..tacs.len().min(16)];
+    let mut value = vec![tacs.len() as u8 - 1]; // spare; type of list = 00; number of elements - 1
+    value.extend_from_slice(plmn);
+    for tac in tacs {
+        value.extend_from_slice(tac);
+    }
+    NasFGsTrackingAreaIdentityList::new(value)
+}
+
+// TS24.501, 10.2 - the T3512 that a UE uses if we don't give it one.
+const DEFAULT_T3512: Duration = Duration::from_secs(54 * 60);
+
+/// Whether we can give UEs this T3512 exactly.
+pub fn t3512_supported(t3512: Duration) -> bool {
+    t3512 == DEFAULT_T3512 || gprs_timer_3(t3512).is_some()
+}
+
+// TS24.008, 10.5.7.4a - a GPRS timer 3 value is a 3-bit unit and a 5-bit multiplier.  We use the finest unit
+// whose multiplier fits.  Since each unit is a whole number of the finer ones, this encodes the duration exactly
+// if any unit does, and otherwise there is no exact encoding.
+fn gprs_timer_3(duration: Duration) -> Option<u8> {
+    const UNITS: [(u8, u64); 7] = [
+        (0b011, 2),
+        (0b100, 30),
+        (0b101, 60),
+        (0b000, 600),
+        (0b001, 3600),
+        (0b010, 36000),
+        (0b110, 1152000),
+    ];
+    let secs = duration.as_secs();
+    if duration.subsec_nanos() != 0 {
+        return None;
+    }
+    let (unit, unit_secs) = UNITS
+        .into_iter()
+        .find(|(_, unit_secs)| secs / unit_secs < 32)?;
+    (secs % unit_secs == 0).then_some((unit << 5) | (secs / unit_secs) as u8)
+}
+
+// TS24.008, 10.5.3.5a - a network name coded in UCS2, with no spare bits in the last octet and no country
+// initials added.
+fn network_name(name: &str) -> Vec<u8> {
+    let mut value = vec![0b1_001_0_000]; // ext; coding scheme = 001 = UCS2; add CI = 0; spare bits = 000
+    for c in name.encode_utf16() {
+        value.extend_from_slice(&c.to_be_bytes());
+    }
+    value
+}
+
+// TS24.008, 10.5.3.8 - the time zone is a number of quarter hours, in two swapped BCD digits, with the sign in
+// bit 4 of the first octet.
+fn time_zone(offset_mins: i16) -> u8 {
+    let quarter_hours = (offset_mins.unsigned_abs() / 15).min(79) as u8;
+    let sign = if offset_mins < 0 { 0b1000 } else { 0 };
+    swapped_bcd(quarter_hours) | sign
+}
+
+// TS24.008, 10.5.3.9 - year, month, day, hour, minute and second of the universal time, followed by the time zone.
+fn universal_time_and_local_time_zone(time: SystemTime, time_zone: u8) -> Vec<u8> {
+    let secs = time
+        .duration_since(SystemTime::UNIX_EPOCH)
+        .unwrap_or_default()
+        .as_secs();
+    let (year, month, day) = civil_from_days((secs / 86400) as i64);
+    let secs_of_day = secs % 86400;
+    vec![
+        swapped_bcd((year % 100) as u8),
+        swapped_bcd(month),
+        swapped_bcd(day),
+        swapped_bcd((secs_of_day / 3600) as u8),
+        swapped_bcd((secs_of_day / 60 % 60) as u8),
+        swapped_bcd((secs_of_day % 60) as u8),
+        time_zone,
+    ]
+}
+
+// Two decimal digits, with the tens in the bottom nibble and the units in the top.
+fn swapped_bcd(x: u8) -> u8 {
+    ((x % 10) << 4) | (x / 10)
+}
+
+// The (year, month, day) of a number of days since 1970-01-01 in the Gregorian calendar - see
+// http://howardhinnant.github.io/date_algorithms.html#civil_from_days.
+fn civil_from_days(days: i64) -> (i64, u8, u8) {
+    let z = days + 719468;
+    let era = z.div_euclid(146097);
+    let doe = z.rem_euclid(146097);
+    let yoe = (doe - doe / 1460 + doe / 36524 - doe / 146096) / 365;
+    let doy = doe - (365 * yoe + yoe / 4 - yoe / 100);
+    let mp = (5 * doy + 2) / 153;
+    let day = (doy - (153 * mp + 2) / 5 + 1) as u8;
+    let month = (if mp < 10 { mp + 3 } else { mp - 9 }) as u8;
+    let year = yoe + era * 400 + if month <= 2 { 1 } else { 0 };
+    (year, month, day)
+}
+
+// TS24.501, 9.11.4.6 - answer the UE's requests for DNS servers, P-CSCFs and link MTU, as far as the data network
+// is configured with them.  The UE only gets server addresses that it can reach over this PDU session, and only the
+// MTU that applies to it - the IPv4 link MTU for an IP PDU session, or the Ethernet frame payload MTU for an
+// Ethernet one.
+fn extended_pco(
+    requests: &[u16],
+    data_network: &DataNetworkConfig,
+    pdu_session_type: u8,
+) -> Option<NasExtendedProtocolConfigurationOptions> {
+    let family_allowed = |ipv6: bool| match pdu_session_type {
+        PduSessionType::IPV4 => !ipv6,
+        PduSessionType::IPV6 => ipv6,
+        PduSessionType::IPV4V6 => true,
+        _ => false,
+    };
+    // TS24.008, figure 10.5.136: ext bit set; configuration protocol = PPP for use with IP PDP type or IP PDN type
+    let mut epco = vec![0b1_0000_000];
+    for request in requests {
+        let (addresses, ipv6) = match *request {
+            PcoContainerId::DNS_SERVER_IPV4_ADDRESS => (&data_network.dns_servers, false),
+            PcoContainerId::DNS_SERVER_IPV6_ADDRESS => (&data_network.dns_servers, true),
+            PcoContainerId::P_CSCF_IPV4_ADDRESS => (&data_network.p_cscf_servers, false),
+            PcoContainerId::P_CSCF_IPV6_ADDRESS => (&data_network.p_cscf_servers, true),
+            PcoContainerId::IPV4_LINK_MTU | PcoContainerId::ETHERNET_FRAME_PAYLOAD_MTU => {
+                let ethernet_mtu = *request == PcoContainerId::ETHERNET_FRAME_PAYLOAD_MTU;
+                let ethernet_session = pdu_session_type == PduSessionType::ETHERNET;
+                if ethernet_mtu != ethernet_session {
+                    continue;
+                }
+                if let Some(mtu) = data_network.mtu {
+                    pco_container(&mut epco, *request, &mtu.to_be_bytes());
+                }
+                continue;
+            }
+            _ => continue,
+        };
+        if !family_allowed(ipv6) {
+            continue;
+        }
+        for address in addresses.iter().filter(|x| x.is_ipv6() == ipv6) {
+            match address {
+                IpAddr::V4(x) => pco_container(&mut epco, *request, &x.octets()),
+                IpAddr::V6(x) => pco_container(&mut epco, *request, &x.octets()),
+            }
+        }
+    }
+    (epco.len() > 1).then(|| NasExtendedProtocolConfigurationOptions::new(epco))
+}
+
+// TS24.501, 9.11.4.10.  For IPv6, the UE gets an interface identifier for its link-local address, and then its /64
+// prefix from our Router Advertisement.  An Ethernet PDU session has no PDU address.
+fn pdu_address(userplane_info: &UserplaneSession) -> Result<(u8, Option<NasPduAddress>)> {
+    if userplane_info.ethernet {
+        return Ok((PduSessionType::ETHERNET, None));
+    }
+    let interface_identifier = userplane_info.ue_ipv6_interface_identifier;
+    let (pdu_session_type, address_information) =
+        match (userplane_info.ue_ipv4_addr, userplane_info.ue_ipv6_prefix) {
+            (Some(ipv4), None) => (PduSessionType::IPV4, ipv4.octets().to_vec()),
+            (None, Some(_)) => (PduSessionType::IPV6, interface_identifier.to_vec()),
+            (Some(ipv4), Some(_)) => (
+                PduSessionType::IPV4V6,
+                [&interface_identifier[..], &ipv4.octets()[..]].concat(),
+            ),
+            (None, None) => bail!("Userplane session {userplane_info} has no IP address"),
+        };
+    // spare; no SMF IPv6 link local address; PDU session type
+    let mut pdu_address = vec![pdu_session_type];
+    pdu_address.extend(address_information);
+    Ok((pdu_session_type, Some(NasPduAddress::new(pdu_address))))
+}
+
+// TS24.008, figure 10.5.136 - a two byte container identifier, a one byte length and the contents.
+fn pco_container(epco: &mut Vec<u8>, id: u16, contents: &[u8]) {
+    epco.extend_from_slice(&id.to_be_bytes());
+    epco.push(contents.len() as u8);
+    epco.extend_from_slice(contents);
+}
+
+// TS24.501, 9.11.3.44 - a two octet bitmap, with PSI(0) in bit 1 of the first octet and PSI(15) in bit 8 of the
+// second.
+fn pdu_session_bitmap(pdu_session_ids: &[u8]) -> Vec<u8> {
+    let mut bitmap = vec![0, 0];
+    for id in pdu_session_ids.iter().filter(|x| **x < 16) {
+        bitmap[*id as usize / 8] |= 1 << (id % 8);
+    }
+    bitmap
+}
+
+// TS24.501, 9.11.2.8 - the S-NSSAI IE value is the SST, followed by the SD if there is one.
+fn s_nssai_value(snssai: &Snssai) -> Vec<u8> {
+    let mut value = vec![snssai.0];
+    if let Some(sd) = snssai.1 {
+        value.extend_from_slice(&sd);
+    }
+    value
+}
+
+fn nas_nssai(snssais: &[Snssai]) -> NasNssai {
+    // TS24.501, 9.11.3.37 - a list of length-prefixed S-NSSAI values.
+    let mut value = vec![];
+    for snssai in snssais {
+        let s_nssai = s_nssai_value(snssai);
+        value.push(s_nssai.len() as u8);
+        value.extend(s_nssai);
+    }
+    NasNssai::new(value)
+}
+
+fn nas_rejected_nssai(snssais: &[Snssai]) -> NasRejectedNssai {
+    // TS24.501, 9.11.3.46 - each rejected S-NSSAI is preceded by a byte giving its length and the cause.
+    const NOT_AVAILABLE_IN_THE_CURRENT_PLMN: u8 = 0b0000;
+    let mut value = vec![];
+    for snssai in snssais {
+        let s_nssai = s_nssai_value(snssai);
+        value.push(((s_nssai.len() as u8) << 4) | NOT_AVAILABLE_IN_THE_CURRENT_PLMN);
+        value.extend(s_nssai);
+    }
+    NasRejectedNssai::new(value)
+}
+
+fn session_ambr(session_ambr: &SessionAmbr) -> NasSessionAmbr {
+    // TS24.501, 9.11.4.14
+    let (downlink_unit, downlink) = bit_rate(session_ambr.downlink_kbps);
+    let (uplink_unit, uplink) = bit_rate(session_ambr.uplink_kbps);
+    let mut value = vec![downlink_unit];
+    value.extend_from_slice(&downlink.to_be_bytes());
+    value.push(uplink_unit);
+    value.extend_from_slice(&uplink.to_be_bytes());
+    NasSessionAmbr::new(value)
+}
+
+// Express a bit rate as a unit and 16-bit value - see TS24.501, table 9.11.4.14.1.  We use the smallest unit that
+// gives the bit rate exactly, or, if there isn't one, round up so that the UE is never told a lower rate.
+fn bit_rate(kbps: u32) -> (u8, u16) {
+    // (Unit, Kbps per unit) - each unit is 4 times the one before, except that 6 is 1 Mbps and 11 is 1 Gbps.
+    const UNITS: [(u8, u32); 11] = [
+        (0b00000001, 1),
+        (0b00000010, 4),
+        (0b00000011, 16),
+        (0b00000100, 64),
+        (0b00000101, 256),
+        (0b00000110, 1_000),
+        (0b00000111, 4_000),
+        (0b00001000, 16_000),
+        (0b00001001, 64_000),
+        (0b00001010, 256_000),
+        (0b00001011, 1_000_000),
+    ];
+    let fits = |unit_kbps: u32| kbps.div_ceil(unit_kbps) <= 0xffff;
+    let (unit, unit_kbps) = UNITS
+        .into_iter()
+        .find(|(_, x)| kbps % x == 0 && fits(*x))
+        .or_else(|| UNITS.into_iter().find(|(_, x)| fits(*x)))
+        .unwrap_or(UNITS[UNITS.len() - 1]);
+    (unit, kbps.div_ceil(unit_kbps) as u16)
+}
+
+fn qos_rules(created: &[QosRule], deleted: &[u8]) -> NasQosRules {
+    // TS24.501, 9.11.4.13
+    const CREATE_NEW_QOS_RULE: u8 = 0b001;
+    const DELETE_EXISTING_QOS_RULE: u8 = 0b010;
+
+    let mut value = vec![];
+    for rule in created {
+        let default = (rule.id == crate::DEFAULT_QOS_RULE_ID) as u8;
+        let mut contents =
+            vec![(CREATE_NEW_QOS_RULE << 5) | (default << 4) | rule.packet_filters.len() as u8];
+        for filter in &rule.packet_filters {
+            contents.push((filter.direction << 4) | filter.id);
+            contents.push(filter.contents.len() as u8);
+            contents.extend_from_slice(&filter.contents);
+        }
+        contents.push(rule.precedence);
+        contents.push(rule.qfi); // spare; segregation = 0; QFI
+        value.push(rule.id);
+        value.extend_from_slice(&(contents.len() as u16).to_be_bytes());
+        value.extend_from_slice(&contents);
+    }
+    for id in deleted {
+        // A deleted QoS rule has no packet filters, precedence or QFI.
+        value.extend_from_slice(&[*id, 0x00, 0x01, DELETE_EXISTING_QOS_RULE << 5]);
+    }
+    NasQosRules::new(value)
+}
+
+fn qos_flow_descriptions(created: &[QosFlow], deleted: &[u8]) -> NasQosFlowDescriptions {
+    // TS24.501, 9.11.4.12
+    const CREATE_NEW_QOS_FLOW_DESCRIPTION: u8 = 0b001;
+    const DELETE_EXISTING_QOS_FLOW_DESCRIPTION: u8 = 0b010;
+    const PARAMETER_5QI: u8 = 0x01;
+
+    let mut value = vec![];
+    for flow in created {
+        value.extend_from_slice(&[
+            flow.qfi,
+            CREATE_NEW_QOS_FLOW_DESCRIPTION << 5,
+            0b0_1_000001, // spare; E = 1 (parameters list is included); number of parameters = 1
+            PARAMETER_5QI,
+            0x01,
+            flow.five_qi,
+        ]);
+    }
+    for qfi in deleted {
+        value.extend_from_slice(&[
+            *qfi,
+            DELETE_EXISTING_QOS_FLOW_DESCRIPTION << 5,
+            0b0_0_000000,
+        ]);
+    }
+    NasQosFlowDescriptions::new(value)
+}
@@ -1,6 +1,96 @@
 pub mod build;
 pub mod parse;
 
+// 5GMM causes - TS24.501, table 9.11.3.2.1.
+pub struct FgmmCause;
+impl FgmmCause {
+    pub const ILLEGAL_UE: u8 = 0b00000011;
+    pub const ILLEGAL_ME: u8 = 0b00000110;
+    pub const FGS_SERVICES_NOT_ALLOWED: u8 = 0b00000111;
+    pub const UE_IDENTITY_CANNOT_BE_DERIVED_BY_THE_NETWORK: u8 = 0b00001001;
+    pub const PLMN_NOT_ALLOWED: u8 = 0b00001011;
+    pub const MAC_FAILURE: u8 = 0b00010100;
+    pub const SYNCH_FAILURE: u8 = 0b00010101;
+    pub const UE_SECURITY_CAPABILITIES_MISMATCH: u8 = 0b00010111;
+    pub const NON_5G_AUTHENTICATION_UNACCEPTABLE: u8 = 0b00011010;
+    pub const NO_NETWORK_SLICES_AVAILABLE: u8 = 0b00111110;
+    pub const PAYLOAD_WAS_NOT_FORWARDED: u8 = 0b01011010;
+    pub const INVALID_MANDATORY_INFORMATION: u8 = 0b01100000;
+    pub const PROTOCOL_ERROR_UNSPECIFIED: u8 = 0b01101111;
+}
+
+// 5GSM causes - TS24.501, table 9.11.4.2.1.
+pub struct FgsmCause;
+impl FgsmCause {
+    pub const INSUFFICIENT_RESOURCES: u8 = 0b00011010;
+    pub const MISSING_OR_UNKNOWN_DNN: u8 = 0b00011011;
+    pub const UNKNOWN_PDU_SESSION_TYPE: u8 = 0b00011100;
+    pub const REGULAR_DEACTIVATION: u8 = 0b00100100;
+    pub const INVALID_PDU_SESSION_IDENTITY: u8 = 0b00101011;
+    pub const PDU_SESSION_TYPE_IPV4_ONLY_ALLOWED: u8 = 0b00110010;
+    pub const SEMANTIC_ERROR_IN_THE_QOS_OPERATION: u8 = 0b01010011;
+    pub const SYNTACTICAL_ERROR_IN_THE_QOS_OPERATION: u8 = 0b01010100;
+}
+
+// PDU session type - TS24.501, table 9.11.4.11.1.
+pub struct PduSessionType;
+impl PduSessionType {
+    pub const IPV4: u8 = 0b001;
+    pub const IPV6: u8 = 0b010;
+    pub const IPV4V6: u8 = 0b011;
+    pub const ETHERNET: u8 = 0b101;
+}
+
+// Protocol configuration options container identifiers - TS24.008, 10.5.6.3.  The same identifiers are used in
+// both directions, for the UE's request and the network's answer.
+pub struct PcoContainerId;
+impl PcoContainerId {
+    pub const P_CSCF_IPV6_ADDRESS: u16 = 0x0001;
+    pub const DNS_SERVER_IPV6_ADDRESS: u16 = 0x0003;
+    pub const P_CSCF_IPV4_ADDRESS: u16 = 0x000C;
+    pub const DNS_SERVER_IPV4_ADDRESS: u16 = 0x000D;
+    pub const IPV4_LINK_MTU: u16 = 0x0010;
+    pub const ETHERNET_FRAME_PAYLOAD_MTU: u16 = 0x0014;
+}
+
+// 5GS registration type - TS24.501, table 9.11.3.7.1.
+pub struct FgsRegistrationType;
+impl FgsRegistrationType {
+    pub const MOBILITY_REGISTRATION_UPDATING: u8 = 0b010;
+    pub const PERIODIC_REGISTRATION_UPDATING: u8 = 0b011;
+}
+
+// Service type - TS24.501, table 9.11.3.50.1.
+pub struct ServiceType;
+impl ServiceType {
+    pub const MOBILE_TERMINATED_SERVICES: u8 = 0b0010;
+}
+
+// The PDU session IEs of a Registration Accept or Service Accept - see TS24.501, 5.5.1.3.4 and 5.6.1.4.1.
+pub struct PduSessionStatus {
+    // The UE's PDU sessions that are active in the network.
+    pub active: Vec<u8>,
+
+    // The PDU sessions that the UE has uplink data for but whose user plane resources can't be re-established,
+    // if the UE sent an Uplink Data Status.
+    pub reactivation_failed: Option<Vec<u8>>,
+}
+
+impl PduSessionStatus {
+    pub fn new(active: Vec<u8>, uplink_data_status: Option<&[u8]>) -> Self {
+        let reactivation_failed = uplink_data_status.map(|x| {
+            parse::pdu_session_ids(x)
+                .into_iter()
+                .filter(|id| !active.contains(id))
+                .collect()
+        });
+        PduSessionStatus {
+            active,
+            reactivation_failed,
+        }
+    }
+}
+
 #[macro_export]
 macro_rules! expect_nas {
     ($t:ident, $m:expr) => {
@@ -1,26 +1,139 @@
-use anyhow::{Result, bail};
-use oxirush_nas::NasFGsMobileIdentity;
+use crate::{EciesProfile, HomeNetworkKeys, PacketFilter, QosFlow, QosRule};
+use anyhow::{Result, anyhow, bail, ensure};
+use oxirush_nas::{NasFGsMobileIdentity, NasNssai, NasQosFlowDescriptions, NasQosRules};
 use std::fmt::Write; // Import the Write trait for String
+use xxap::Snssai;
 
-pub struct MobileIdentity {
-    pub imsi: String,
-    pub plmn: [u8; 3],
+pub enum MobileIdentity {
+    Suci {
+        imsi: String,
+        plmn: [u8; 3],
+    },
+    Guti {
+        plmn: [u8; 3],
+        amf_ids: [u8; 3],
+        tmsi: [u8; 4],
+    },
 }
 
-pub fn fgs_mobile_identity(fgs_mobile_identity: &NasFGsMobileIdentity) -> Result<MobileIdentity> {
-    // Get the SUPI.  TODO: SUCI + GUTI support.
+// TS24.501, table 9.11.3.4.1 - type of identity.
+const IDENTITY_TYPE_SUCI: u8 = 0b001;
+const IDENTITY_TYPE_GUTI: u8 = 0b010;
+const IDENTITY_TYPE_IMEI: u8 = 0b011;
+const IDENTITY_TYPE_5G_S_TMSI: u8 = 0b100;
+const IDENTITY_TYPE_IMEISV: u8 = 0b101;
+
+// TS24.501, table 9.11.3.4.1 - protection scheme identifiers from TS33.501, Annex C.
+const PROTECTION_SCHEME_NULL: u8 = 0b0000;
+const PROTECTION_SCHEME_PROFILE_A: u8 = 0b0001;
+const PROTECTION_SCHEME_PROFILE_B: u8 = 0b0010;
+
+pub fn fgs_mobile_identity(
+    fgs_mobile_identity: &NasFGsMobileIdentity,
+    home_network_keys: &HomeNetworkKeys,
+) -> Result<MobileIdentity> {
     let NasFGsMobileIdentity {
         value: mobile_identity_ie,
         ..
     } = fgs_mobile_identity;
-    if mobile_identity_ie.len() < 12 {
+    match mobile_identity_ie.first().map(|x| x & 0b111) {
+        Some(IDENTITY_TYPE_SUCI) => suci(mobile_identity_ie, home_network_keys),
+        Some(IDENTITY_TYPE_GUTI) => guti(mobile_identity_ie),
+        _ => bail!(
+            "Unsupported mobile identity {:?} - expected SUCI or 5G-GUTI",
+            mobile_identity_ie
+        ),
+    }
+}
+
+/// Get the AMF Set ID, AMF Pointer and 5G-TMSI out of a 5G-S-TMSI, as sent by the UE in a Service Request.
+pub fn five_g_s_tmsi(fgs_mobile_identity: &NasFGsMobileIdentity) -> Result<([u8; 2], [u8; 4])> {
+    // See TS24.501, Figure 9.11.3.4.5.
+    let mobile_identity_ie = &fgs_mobile_identity.value;
+    if mobile_identity_ie.first().map(|x| x & 0b111) != Some(IDENTITY_TYPE_5G_S_TMSI)
+        || mobile_identity_ie.len() != 7
+    {
+        bail!("Expected 5G-S-TMSI, got {:?}", mobile_identity_ie)
+    }
+    Ok((
+        mobile_identity_ie[1..3].try_into().unwrap(),
+        mobile_identity_ie[3..7].try_into().unwrap(),
+    ))
+}
+
+/// Get the digits of a PEI that is an IMEI or IMEISV, as sent by the UE in a Security Mode Complete.
+pub fn pei(fgs_mobile_identity: &NasFGsMobileIdentity) -> Result<String> {
+    // See TS24.501, Figure 9.11.3.4.3.  The first digit is in the top half of the first octet, and the
+    // rest are in the following octets, low nibble first.  An even number of digits is padded with 0xf.
+    let mobile_identity_ie = &fgs_mobile_identity.value;
+    let Some(first) = mobile_identity_ie.first() else {
+        bail!("Empty PEI")
+    };
+    if !matches!(first & 0b111, IDENTITY_TYPE_IMEI | IDENTITY_TYPE_IMEISV) {
+        bail!(
+            "Unsupported PEI {:?} - expected IMEI or IMEISV",
+            mobile_identity_ie
+        )
+    }
+    let mut digits = vec![first >> 4];
+    for byte in &mobile_identity_ie[1..] {
+        digits.push(byte & 0xf);
+        if (byte >> 4) != 0xf {
+            digits.push(byte >> 4);
+        }
+    }
+    ensure!(
+        digits.iter().all(|x| *x < 10),
+        "PEI {:?} contains a non-decimal digit",
+        mobile_identity_ie
+    );
+    Ok(digits.iter().fold(String::new(), |mut s, b| {
+        let _ = write!(s, "{b}");
+        s
+    }))
+}
+
+fn guti(mobile_identity_ie: &[u8]) -> Result<MobileIdentity> {
+    // See TS24.501, Figure 9.11.3.4.1.
+    if mobile_identity_ie.len() != 11 {
+        bail!("5G-GUTI has wrong length: {:?}", mobile_identity_ie)
+    }
+    Ok(MobileIdentity::Guti {
+        plmn: mobile_identity_ie[1..4].try_into().unwrap(),
+        amf_ids: mobile_identity_ie[4..7].try_into().unwrap(),
+        tmsi: mobile_identity_ie[7..11].try_into().unwrap(),
+    })
+}
+
+fn suci(mobile_identity_ie: &[u8], home_network_keys: &HomeNetworkKeys) -> Result<MobileIdentity> {
+    // Get the SUPI from the SUCI.
+    // The header of a SUCI takes 8 octets and the scheme output must have at least one more.
+    if mobile_identity_ie.len() < 9 {
         bail!("Mobile identity IE is too short: {:?}", mobile_identity_ie)
     }
     if mobile_identity_ie[0] != 0x01 {
-        bail!("Only supported identity type is SUPI");
+        bail!("Only supported SUCI format is SUPI format IMSI");
     }
     let plmn: [u8; 3] = mobile_identity_ie[1..4].try_into().unwrap();
-    let msin = &mobile_identity_ie[8..];
+
+    // See TS24.501, Figure 9.11.3.4.3.
+    let protection_scheme_id = mobile_identity_ie[6] & 0x0f;
+    let home_network_public_key_id = mobile_identity_ie[7];
+    let scheme_output = &mobile_identity_ie[8..];
+    let msin = match protection_scheme_id {
+        PROTECTION_SCHEME_NULL => scheme_output.to_vec(),
+        PROTECTION_SCHEME_PROFILE_A | PROTECTION_SCHEME_PROFILE_B => deconceal_msin(
+            protection_scheme_id,
+            home_network_public_key_id,
+            scheme_output,
+            home_network_keys,
+        )?,
+        x => bail!("Unsupported SUCI protection scheme {x}"),
+    };
+    ensure!(
+        msin.first().is_some_and(|byte| byte & 0xf != 0xf),
+        "SUCI has no MSIN digits"
+    );
 
     // Build a 16-byte IMSI as needed by the authentication algorithm.
     let mut imsi = vec![];
@@ -34,7 +147,10 @@ pub fn fgs_mobile_identity(fgs_mobile_identity: &NasFGsMobileIdentity) -> Result
     imsi.push(plmn[2] >> 4);
     msin.iter().for_each(|byte| {
         imsi.push(byte & 0xf);
-        imsi.push(byte >> 4);
+        // An odd number of MSIN digits is padded with a filler of 0xf.
+        if (byte >> 4) != 0xf {
+            imsi.push(byte >> 4);
+        }
     });
     // Convert to string
 
@@ -43,5 +159,196 @@ pub fn fgs_mobile_identity(fgs_mobile_identity: &NasFGsMobileIdentity) -> Result
         s
     });
 
-    Ok(MobileIdentity { imsi, plmn })
+    Ok(MobileIdentity::Suci { imsi, plmn })
+}
+
+fn deconceal_msin(
+    protection_scheme_id: u8,
+    home_network_public_key_id: u8,
+    scheme_output: &[u8],
+    home_network_keys: &HomeNetworkKeys,
+) -> Result<Vec<u8>> {
+    let Some(key) = home_network_keys.get(&home_network_public_key_id) else {
+        bail!("SUCI uses unknown home network public key identifier {home_network_public_key_id}");
+    };
+    let msin = match (protection_scheme_id, key.profile) {
+        (PROTECTION_SCHEME_PROFILE_A, EciesProfile::A) => {
+            security::suci::deconceal_profile_a(&key.private_key, scheme_output)
+        }
+        (PROTECTION_SCHEME_PROFILE_B, EciesProfile::B) => {
+            security::suci::deconceal_profile_b(&key.private_key, scheme_output)
+        }
+        _ => bail!(
+            "SUCI protection scheme {protection_scheme_id} does not match profile {:?} of home network key {home_network_public_key_id}",
+            key.profile
+        ),
+    };
+    msin.ok_or_else(|| {
+        anyhow!("SUCI de-concealment failed with home network key {home_network_public_key_id}")
+    })
+}
+
+// Get the S-NSSAIs out of an NSSAI.  See TS24.501, 9.11.3.37.
+pub fn nssai(nssai: &NasNssai) -> Result<Vec<Snssai>> {
+    let mut snssais = vec![];
+    let mut remaining = nssai.value.as_slice();
+    while let [len, rest @ ..] = remaining {
+        let len = *len as usize;
+        if len == 0 || rest.len() < len {
+            bail!("Badly formed NSSAI {:?}", nssai.value);
+        }
+        snssais.push(s_nssai(&rest[..len])?);
+        remaining = &rest[len..];
+    }
+    Ok(snssais)
+}
+
+// Get the SST and SD out of the value of an S-NSSAI IE, ignoring any mapped HPLMN values.
+// See TS24.501, 9.11.2.8.
+pub fn s_nssai(s_nssai: &[u8]) -> Result<Snssai> {
+    match s_nssai {
+        // SST, optionally followed by a mapped HPLMN SST
+        [sst] | [sst, _] => Ok(Snssai(*sst, None)),
+        // SST and SD, optionally followed by mapped HPLMN SST and SD
+        [sst, sd0, sd1, sd2, ..] if matches!(s_nssai.len(), 4 | 5 | 8) => {
+            Ok(Snssai(*sst, Some([*sd0, *sd1, *sd2])))
+        }
+        _ => bail!("Badly formed S-NSSAI {:?}", s_nssai),
+    }
+}
+
+// Get the PDU session identities that are flagged in a PDU session status, uplink data status or similar
+// bitmap.  See TS24.501, 9.11.3.44.
+pub fn pdu_session_ids(bitmap: &[u8]) -> Vec<u8> {
+    (1..16)
+        .filter(|id| {
+            bitmap
+                .get(*id as usize / 8)
+                .is_some_and(|x| x & (1 << (id % 8)) != 0)
+        })
+        .collect()
+}
+
+// Get the DNN out of a DNN IE value, which is encoded as a series of length-prefixed labels.
+// See TS24.501, 9.11.2.1B and TS23.003, 9.1.
+pub fn dnn(dnn: &[u8]) -> Result<String> {
+    let mut labels = vec![];
+    let mut remaining = dnn;
+    while let [len, rest @ ..] = remaining {
+        let len = *len as usize;
+        ensure!(len > 0 && rest.len() >= len, "Badly formed DNN {:?}", dnn);
+        let (label, next) = rest.split_at(len);
+        labels.push(std::str::from_utf8(label)?);
+        remaining = next;
+    }
+    ensure!(!labels.is_empty(), "Empty DNN");
+    Ok(labels.join("."))
+}
+
+/// Get the container identifiers of the requests in an Extended Protocol Configuration Options IE.
+pub fn pco_requests(epco: &[u8]) -> Result<Vec<u16>> {
+    // See TS24.008, figure 10.5.136.  After the configuration protocol octet, each protocol or container is a two
+    // byte identifier followed by a one byte length and its contents.
+    ensure!(
+        epco.first().is_some_and(|x| x & 0x80 != 0),
+        "Badly formed PCO {epco:?}"
+    );
+    let mut requests = vec![];
+    let mut remaining = &epco[1..];
+    while !remaining.is_empty() {
+        let [id_1, id_2, length, rest @ ..] = remaining else {
+            bail!("Truncated PCO {epco:?}")
+        };
+        let length = *length as usize;
+        ensure!(rest.len() >= length, "Truncated PCO {epco:?}");
+        requests.push(u16::from_be_bytes([*id_1, *id_2]));
+        remaining = &rest[length..];
+    }
+    Ok(requests)
+}
+
+// Get the QoS rules to create and the identifiers of the QoS rules to delete out of a QoS rules IE.
+// See TS24.501, 9.11.4.13.  Only the 'create new' and 'delete existing' operations are supported.
+pub fn qos_rules(qos_rules: &NasQosRules) -> Result<(Vec<QosRule>, Vec<u8>)> {
+    const CREATE_NEW_QOS_RULE: u8 = 0b001;
+    const DELETE_EXISTING_QOS_RULE: u8 = 0b010;
+
+    let mut created = vec![];
+    let mut deleted = vec![];
+    let mut remaining = qos_rules.value.as_slice();
+    while let [id, len_hi, len_lo, rest @ ..] = remaining {
+        let len = u16::from_be_bytes([*len_hi, *len_lo]) as usize;
+        ensure!(
+            len > 0 && rest.len() >= len,
+            "Badly formed QoS rules {:?}",
+            qos_rules.value
+        );
+        let (rule, next) = rest.split_at(len);
+        remaining = next;
+        match rule[0] >> 5 {
+            CREATE_NEW_QOS_RULE => created.push(qos_rule(*id, rule)?),
+            DELETE_EXISTING_QOS_RULE => deleted.push(*id),
+            x => bail!("Unsupported QoS rule operation code {x}"),
+        }
+    }
+    Ok((created, deleted))
+}
+
+// Get the QoS flows that the UE asks to create - see TS24.501, 9.11.4.12.
+pub fn qos_flow_descriptions(
+    qos_flow_descriptions: &NasQosFlowDescriptions,
+) -> Result<Vec<QosFlow>> {
+    const CREATE_NEW_QOS_FLOW_DESCRIPTION: u8 = 0b001;
+    const PARAMETER_5QI: u8 = 0x01;
+
+    let mut created = vec![];
+    let mut remaining = qos_flow_descriptions.value.as_slice();
+    while let [qfi, operation_code, num_parameters, rest @ ..] = remaining {
+        let mut flow = QosFlow::new(qfi & 0x3f);
+        let mut parameters = rest;
+        for _ in 0..(num_parameters & 0x3f) {
+            let [id, len, rest @ ..] = parameters else {
+                bail!("QoS flow description {qfi} is truncated");
+            };
+            let len = *len as usize;
+            ensure!(rest.len() >= len, "QoS flow description {qfi} is truncated");
+            if *id == PARAMETER_5QI && len == 1 {
+                flow.five_qi = rest[0];
+            }
+            parameters = &rest[len..];
+        }
+        if operation_code >> 5 == CREATE_NEW_QOS_FLOW_DESCRIPTION {
+            created.push(flow);
+        }
+        remaining = parameters;
+    }
+    Ok(created)
+}
+
+fn qos_rule(id: u8, rule: &[u8]) -> Result<QosRule> {
+    let num_packet_filters = rule[0] & 0x0f;
+    let mut remaining = &rule[1..];
+    let mut packet_filters = vec![];
+    for _ in 0..num_packet_filters {
+        let [header, len, rest @ ..] = remaining else {
+            bail!("QoS rule {id} is truncated");
+        };
+        let len = *len as usize;
+        ensure!(rest.len() >= len, "QoS rule {id} is truncated");
+        packet_filters.push(PacketFilter {
+            id: header & 0x0f,
+            direction: (header >> 4) & 0b11,
+            contents: rest[..len].to_vec(),
+        });
+        remaining = &rest[len..];
+    }
+    let [precedence, qfi] = remaining else {
+        bail!("QoS rule {id} has wrong length");
+    };
+    Ok(QosRule {
+        id,
+        precedence: *precedence,
+        qfi: qfi & 0x3f,
+        packet_filters,
+    })
 }
@@ -67,15 +67,49 @@ pub fn dl_information_transfer(
     }
 }
 
+/// Build an RRC Reconfiguration that adds each of the given DRBs, identified by their PDU session ID and their
+/// own ID, with the QFIs of the QoS flows mapped to them.  The first DRB of each session is its default DRB.
 pub fn reconfiguration(
     rrc_transaction_identifier: u8,
     nas_messages: Option<NonEmpty<Vec<u8>>>,
-    cell_group_config: Vec<u8>,
-    session_id: u8,
+    cell_group_config: Option<Vec<u8>>,
+    drbs: NonEmpty<(u8, u8, Vec<u8>)>,
+    sdap_header_dl: bool,
 ) -> DlDcchMessage {
     let dedicated_nas_message_list = nas_messages.map(|x| (x.map(DedicatedNasMessage)));
 
     // TODO - lots of hardcoding here
+    let mut sessions_seen = vec![];
+    let drb_to_add_mod_list = drbs.map(|(session_id, drb_id, qfis)| {
+        let default_drb = !sessions_seen.contains(&session_id);
+        sessions_seen.push(session_id);
+        DrbToAddMod {
+            cn_association: Some(CnAssociation::SdapConfig(SdapConfig {
+                pdu_session: PduSessionId(session_id),
+                sdap_header_dl: sdap_header_dl_config(sdap_header_dl),
+                sdap_header_ul: SdapHeaderUl::Present,
+                default_drb,
+                mapped_qos_flows_to_add: NonEmpty::from_vec(qfis.into_iter().map(Qfi).collect()),
+                mapped_qos_flows_to_release: None,
+            })),
+            drb_identity: DrbIdentity(drb_id),
+            reestablish_pdcp: None,
+            recover_pdcp: None,
+            pdcp_config: Some(PdcpConfig {
+                drb: Some(Drb {
+                    discard_timer: Some(DiscardTimer::Ms10),
+                    pdcp_sn_size_ul: Some(PdcpSnSizeUl::Len12bits),
+                    pdcp_sn_size_dl: Some(PdcpSnSizeDl::Len12bits),
+                    header_compression: HeaderCompression::NotUsed,
+                    integrity_protection: None,
+                    status_report_required: None,
+                    out_of_order_delivery: None,
+                }),
+                more_than_one_rlc: None,
+                t_reordering: None,
+            }),
+        }
+    });
 
     DlDcchMessage {
         message: DlDcchMessageType::C1(C1_2::RrcReconfiguration(rrc::RrcReconfiguration {
@@ -84,33 +118,7 @@ pub fn reconfiguration(
                 radio_bearer_config: Some(RadioBearerConfig {
                     srb_to_add_mod_list: None,
                     srb_3_to_release: None,
-                    drb_to_add_mod_list: Some(DrbToAddModList(nonempty![DrbToAddMod {
-                        cn_association: Some(CnAssociation::SdapConfig(SdapConfig {
-                            pdu_session: PduSessionId(session_id),
-                            // SRS RAN UE does not support SdapHeaderDl::Present
-                            sdap_header_dl: SdapHeaderDl::Absent,
-                            sdap_header_ul: SdapHeaderUl::Present,
-                            default_drb: true,
-                            mapped_qos_flows_to_add: Some(nonempty![Qfi(1)]),
-                            mapped_qos_flows_to_release: None
-                        })),
-                        drb_identity: DrbIdentity(1),
-                        reestablish_pdcp: None,
-                        recover_pdcp: None,
-                        pdcp_config: Some(PdcpConfig {
-                            drb: Some(Drb {
-                                discard_timer: Some(DiscardTimer::Ms10),
-                                pdcp_sn_size_ul: Some(PdcpSnSizeUl::Len12bits),
-                                pdcp_sn_size_dl: Some(PdcpSnSizeDl::Len12bits),
-                                header_compression: HeaderCompression::NotUsed,
-                                integrity_protection: None,
-                                status_report_required: None,
-                                out_of_order_delivery: None
-                            }),
-                            more_than_one_rlc: None,
-                            t_reordering: None
-                        })
-                    }])),
+                    drb_to_add_mod_list: Some(DrbToAddModList(drb_to_add_mod_list)),
                     drb_to_release_list: None,
                     security_config: None,
                 }),
@@ -118,7 +126,7 @@ pub fn reconfiguration(
                 meas_config: None,
                 late_non_critical_extension: None,
                 non_critical_extension: Some(RrcReconfigurationV1530IEs {
-                    master_cell_group: Some(cell_group_config),
+                    master_cell_group: cell_group_config,
                     full_config: None,
                     dedicated_nas_message_list,
                     master_key_update: None,
@@ -131,3 +139,115 @@ pub fn reconfiguration(
         })),
     }
 }
+
+pub fn reconfiguration_release_drbs(
+    rrc_transaction_identifier: u8,
+    nas_message: Option<Vec<u8>>,
+    cell_group_config: Option<Vec<u8>>,
+    drb_ids: NonEmpty<u8>,
+) -> DlDcchMessage {
+    DlDcchMessage {
+        message: DlDcchMessageType::C1(C1_2::RrcReconfiguration(rrc::RrcReconfiguration {
+            rrc_transaction_identifier: RrcTransactionIdentifier(rrc_transaction_identifier),
+            critical_extensions: CriticalExtensions15::RrcReconfiguration(RrcReconfigurationIEs {
+                radio_bearer_config: Some(RadioBearerConfig {
+                    srb_to_add_mod_list: None,
+                    srb_3_to_release: None,
+                    drb_to_add_mod_list: None,
+                    drb_to_release_list: Some(DrbToReleaseList(drb_ids.map(DrbIdentity))),
+                    security_config: None,
+                }),
+                secondary_cell_group: None,
+                meas_config: None,
+                late_non_critical_extension: None,
+                non_critical_extension: Some(RrcReconfigurationV1530IEs {
+                    master_cell_group: cell_group_config,
+                    full_config: None,
+                    dedicated_nas_message_list: nas_message
+                        .map(|x| nonempty![DedicatedNasMessage(x)]),
+                    master_key_update: None,
+                    dedicated_sib_1_delivery: None,
+                    dedicated_system_information_delivery: None,
+                    other_config: None,
+                    non_critical_extension: None,
+                }),
+            }),
+        })),
+    }
+}
+
+/// Change the QoS flows mapped to some of a session's DRBs, given as (DRB ID, QFIs to add, QFIs to release), the
+/// first of which is the session's default DRB, and release the DRBs with the given IDs.
+pub fn reconfiguration_modify_drbs(
+    rrc_transaction_identifier: u8,
+    nas_message: Vec<u8>,
+    cell_group_config: Option<Vec<u8>>,
+    session_id: u8,
+    drbs: NonEmpty<(u8, Vec<u8>, Vec<u8>)>,
+    drb_ids_to_release: Vec<u8>,
+    sdap_header_dl: bool,
+) -> DlDcchMessage {
+    let mut default_drb = true;
+    let drb_to_add_mod_list = drbs.map(|(drb_id, qfis_to_add, qfis_to_release)| {
+        let drb = DrbToAddMod {
+            cn_association: Some(CnAssociation::SdapConfig(SdapConfig {
+                pdu_session: PduSessionId(session_id),
+                sdap_header_dl: sdap_header_dl_config(sdap_header_dl),
+                sdap_header_ul: SdapHeaderUl::Present,
+                default_drb,
+                mapped_qos_flows_to_add: NonEmpty::from_vec(
+                    qfis_to_add.into_iter().map(Qfi).collect(),
+                ),
+                mapped_qos_flows_to_release: NonEmpty::from_vec(
+                    qfis_to_release.into_iter().map(Qfi).collect(),
+                ),
+            })),
+            drb_identity: DrbIdentity(drb_id),
+            reestablish_pdcp: None,
+            recover_pdcp: None,
+            // Leave the DRB's PDCP configuration alone.
+            pdcp_config: None,
+        };
+        default_drb = false;
+        drb
+    });
+    let drb_to_release_list = NonEmpty::from_vec(drb_ids_to_release)
+        .map(|drb_ids| DrbToReleaseList(drb_ids.map(DrbIdentity)));
+
+    DlDcchMessage {
+        message: DlDcchMessageType::C1(C1_2::RrcReconfiguration(rrc::RrcReconfiguration {
+            rrc_transaction_identifier: RrcTransactionIdentifier(rrc_transaction_identifier),
+            critical_extensions: CriticalExtensions15::RrcReconfiguration(RrcReconfigurationIEs {
+                radio_bearer_config: Some(RadioBearerConfig {
+                    srb_to_add_mod_list: None,
+                    srb_3_to_release: None,
+                    drb_to_add_mod_list: Some(DrbToAddModList(drb_to_add_mod_list)),
+                    drb_to_release_list,
+                    security_config: None,
+                }),
+                secondary_cell_group: None,
+                meas_config: None,
+                late_non_critical_extension: None,
+                non_critical_extension: Some(RrcReconfigurationV1530IEs {
+                    master_cell_group: cell_group_config,
+                    full_config: None,
+                    dedicated_nas_message_list: Some(nonempty![DedicatedNasMessage(nas_message)]),
+                    master_key_update: None,
+                    dedicated_sib_1_delivery: None,
+                    dedicated_system_information_delivery: None,
+                    other_config: None,
+                    non_critical_extension: None,
+                }),
+            }),
+        })),
+    }
+}
+
+// SRS RAN UE does not support SdapHeaderDl::Present, so it is off unless configured.
+fn sdap_header_dl_config(sdap_header_dl: bool) -> SdapHeaderDl {
+    if sdap_header_dl {
+        SdapHeaderDl::Present
+    } else {
+        SdapHeaderDl::Absent
+    }
+}
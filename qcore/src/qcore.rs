@@ -1,22 +1,31 @@
 use crate::f1ap::{F1AP_BIND_PORT, F1AP_SCTP_PPID};
-use crate::procedures::{F1apHandler, UeMessageHandler};
-use crate::userplane::PacketProcessor;
-use crate::{Config, HandlerApi, UserplaneSession};
-use crate::{SimCreds, SimTable};
-use anyhow::{Result, bail};
-use async_channel::Sender;
+use crate::procedures::{F1apHandler, PagingProcedure, UeMessageHandler};
+use crate::userplane::{
+    AmbrBuckets, DownlinkBufferLimits, DrbTunnel, PacketProcessor, SessionIndex,
+};
+use crate::{
+    Config, HandlerApi, PduSessionModification, RegisteredUe, SessionAmbr, UeConfigurationUpdate,
+    UeMessage, UserplaneSession,
+};
+use crate::{SimCreds, SimTable, SqnStore};
+use anyhow::{Result, bail, ensure};
+use async_channel::{Receiver, Sender};
 use async_std::sync::Mutex;
 use async_trait::async_trait;
 use dashmap::DashMap;
-use f1ap::F1apPdu;
-use slog::{Logger, info, o};
-use std::net::IpAddr;
+use f1ap::{FiveGsTac, NrCgi, ServedCellInformation};
+use slog::{Logger, info, o, warn};
+use std::net::{IpAddr, Ipv4Addr};
 use std::sync::Arc;
+use std::time::Instant;
 use xxap::{
-    GtpTunnel, Indication, IndicationHandler, Procedure, RequestError, RequestProvider,
-    SctpTransportProvider, ShutdownHandle, Stack,
+    Indication, IndicationHandler, Procedure, RequestError, RequestProvider, SctpTransportProvider,
+    ShutdownHandle, Stack,
 };
 
+// How many times we page a UE again if it doesn't respond.
+const PAGING_MAX_RETRANSMISSIONS: usize = 3;
+
 #[derive(Clone)]
 pub struct QCore {
     config: Config,
@@ -24,8 +33,21 @@ pub struct QCore {
     logger: Logger,
     server_handle: Arc<Mutex<Option<ShutdownHandle>>>,
     packet_processor: PacketProcessor,
-    ue_tasks: Arc<DashMap<u32, Sender<F1apPdu>>>,
+    ue_tasks: Arc<DashMap<u32, Sender<UeMessage>>>,
     sim_auth_data: &'static SimTable,
+    sqn_store: Arc<SqnStore>,
+    registered_ues: Arc<DashMap<[u8; 4], RegisteredUe>>,
+    // The 5G-TMSI of the idle UE that owns each userplane session index, so that a paging trigger can find the UE
+    // without a search.
+    idle_session_owners: Arc<DashMap<usize, [u8; 4]>>,
+    // The host number that each SIM last had on each data network, for sticky UE IPs.
+    last_session_indices: Arc<DashMap<(String, usize), usize>>,
+    // The token buckets of each SIM's UE AMBR, shared by all of its PDU sessions.
+    ue_ambr_buckets: Arc<DashMap<String, AmbrBuckets>>,
+    connected_ues: Arc<DashMap<String, u32>>,
+    // The 5G-TMSI of each connected UE, so that we don't reallocate it to another UE.
+    connected_tmsis: Arc<DashMap<[u8; 4], u32>>,
+    served_cells: Arc<DashMap<u64, Vec<ServedCellInformation>>>,
 }
 
 impl QCore {
@@ -33,18 +55,53 @@ impl QCore {
         config: Config,
         logger: Logger,
         sim_auth_data: &'static SimTable,
+        sqn_store: SqnStore,
     ) -> Result<Self> {
-        let mut qc = Self::new(config, logger, sim_auth_data).await?;
+        // The userplane tells us when downlink data arrives for an idle UE, so that we can page it.
+        let (paging_trigger, paging_triggers) = async_channel::unbounded();
+        let mut qc = Self::new(config, logger, sim_auth_data, sqn_store, paging_trigger).await?;
         qc.run().await.expect("Startup failure");
+        let _paging_task = async_std::task::spawn(qc.clone().page_ues(paging_triggers));
         Ok(qc)
     }
 
-    async fn new(config: Config, logger: Logger, sim_auth_data: &'static SimTable) -> Result<Self> {
+    async fn new(
+        config: Config,
+        logger: Logger,
+        sim_auth_data: &'static SimTable,
+        sqn_store: SqnStore,
+        paging_trigger: Sender<usize>,
+    ) -> Result<Self> {
         let local_ip = config.ip_addr;
+        ensure!(
+            crate::nas::build::t3512_supported(config.t3512),
+            "T3512 of {}s can't be sent to UEs - use 54 minutes, or a whole number of 2s, 30s, 1m, 10m, 1h, 10h or 320h units, up to 31 of them",
+            config.t3512.as_secs()
+        );
+        crate::sims::check_static_ipv4_addrs(sim_auth_data, &config.data_networks)?;
+
+        // Keep the host numbers of static addresses out of each data network's pool of dynamic ones.
+        let static_indices: Vec<Vec<usize>> = config
+            .data_networks
+            .iter()
+            .map(|data_network| {
+                sim_auth_data
+                    .values()
+                    .filter_map(|x| x.static_ipv4_addr(&data_network.dnn))
+                    .map(|x| x.octets()[3] as usize)
+                    .collect()
+            })
+            .collect();
         let packet_processor = PacketProcessor::new(
             local_ip,
-            &config.n6_tun_name,
-            config.ue_subnet.clone(),
+            &config.data_networks,
+            &static_indices,
+            config.sdap_header_dl,
+            DownlinkBufferLimits {
+                max_packets: config.downlink_buffer_max_packets,
+                max_age: config.downlink_buffer_max_age,
+            },
+            paging_trigger,
             &logger,
         )
         .await?;
@@ -56,6 +113,14 @@ impl QCore {
             ue_tasks: Arc::new(DashMap::new()),
             packet_processor,
             sim_auth_data,
+            sqn_store: Arc::new(sqn_store),
+            registered_ues: Arc::new(DashMap::new()),
+            idle_session_owners: Arc::new(DashMap::new()),
+            last_session_indices: Arc::new(DashMap::new()),
+            ue_ambr_buckets: Arc::new(DashMap::new()),
+            connected_ues: Arc::new(DashMap::new()),
+            connected_tmsis: Arc::new(DashMap::new()),
+            served_cells: Arc::new(DashMap::new()),
         })
     }
 
@@ -91,6 +156,169 @@ impl QCore {
     pub fn ip_addr(&self) -> &IpAddr {
         &self.config.ip_addr
     }
+
+    /// Deregister a UE, for example because its SIM has been revoked.  If reregistration_required is set,
+    /// the UE registers again straight away.
+    pub async fn deregister_ue(&self, imsi: &str, reregistration_required: bool) -> Result<()> {
+        let ue_id = self.connected_ues.get(imsi).map(|x| *x);
+        if let Some(ue_id) = ue_id {
+            info!(&self.logger, "Deregister imsi-{imsi}");
+            self.dispatch_ue_message(
+                ue_id,
+                UeMessage::Deregister {
+                    reregistration_required,
+                },
+            )
+            .await
+        } else {
+            // The UE is not connected, so we can't tell it.  Forget its NAS context, so that it has to
+            // register from scratch next time it connects.
+            ensure!(
+                self.forget_registered_ue(imsi),
+                "imsi-{imsi} is not registered"
+            );
+            info!(&self.logger, "Implicitly deregistered imsi-{imsi}");
+            Ok(())
+        }
+    }
+
+    // Remove any stored state for an idle UE, freeing the userplane resources of its PDU sessions.  Returns
+    // whether there was any.
+    fn forget_registered_ue(&self, imsi: &str) -> bool {
+        let tmsis: Vec<[u8; 4]> = self
+            .registered_ues
+            .iter()
+            .filter(|x| x.imsi == imsi)
+            .map(|x| *x.key())
+            .collect();
+        for tmsi in &tmsis {
+            if let Some((_, ue)) = self.registered_ues.remove(tmsi) {
+                self.forget_idle_sessions(tmsi, &ue);
+                let qc = self.clone();
+                async_std::task::spawn(async move { qc.delete_pdu_sessions(ue).await });
+            }
+        }
+        !tmsis.is_empty()
+    }
+
+    // Stop finding an idle UE's PDU sessions for paging, once the UE has left registered_ues.
+    fn forget_idle_sessions(&self, tmsi: &[u8; 4], ue: &RegisteredUe) {
+        for session in &ue.pdu_sessions {
+            self.idle_session_owners
+                .remove_if(&session.userplane_info.index(), |_, x| x == tmsi);
+        }
+    }
+
+    // Page each idle UE that the userplane has received downlink data for.
+    async fn page_ues(self, paging_triggers: Receiver<usize>) {
+        while let Ok(session_idx) = paging_triggers.recv().await {
+            let Some(tmsi) = self.idle_session_owners.get(&session_idx).map(|x| *x) else {
+                continue;
+            };
+            let page = self.registered_ues.get_mut(&tmsi).and_then(|mut ue| {
+                (!ue.paging).then(|| {
+                    ue.paging = true;
+                    (ue.released_at, ue.nr_cgi.clone())
+                })
+            });
+            if let Some((released_at, nr_cgi)) = page {
+                async_std::task::spawn(self.clone().page_ue(tmsi, released_at, nr_cgi));
+            }
+        }
+    }
+
+    // TS23.502, 4.2.3.3: page a UE in the cells of the DU that it was last connected to, until it comes back
+    // with a Service Request, which takes it out of registered_ues, or until we give up.
+    async fn page_ue(self, tmsi: [u8; 4], released_at: Instant, nr_cgi: NrCgi) {
+        for attempt in 0..=PAGING_MAX_RETRANSMISSIONS {
+            if !self
+                .registered_ues
+                .get(&tmsi)
+                .is_some_and(|x| x.released_at == released_at)
+            {
+                return;
+            }
+            if attempt > 0 {
+                warn!(&self.logger, "T3513 expired - page UE again");
+            }
+            if let Err(e) = PagingProcedure::new(&self, &self.logger)
+                .run(&tmsi, &nr_cgi)
+                .await
+            {
+                warn!(&self.logger, "Failed to page UE - {e}");
+                break;
+            }
+            async_std::task::sleep(self.config.paging_retransmission_timer).await;
+        }
+
+        // Let the next downlink packet trigger paging again.
+        if let Some(mut ue) = self.registered_ues.get_mut(&tmsi) {
+            if ue.released_at == released_at {
+                ue.paging = false;
+                warn!(&self.logger, "imsi-{} did not respond to paging", ue.imsi);
+            }
+        }
+    }
+
+    async fn delete_pdu_sessions(&self, ue: RegisteredUe) {
+        for session in ue.pdu_sessions {
+            self.packet_processor
+                .delete_userplane_session(&session.userplane_info, &self.logger)
+                .await;
+        }
+    }
+
+    /// Change the QoS of a PDU session - for example, to add a QoS rule or change the session AMBR.
+    pub async fn modify_pdu_session(
+        &self,
+        imsi: &str,
+        pdu_session_id: u8,
+        modification: PduSessionModification,
+    ) -> Result<()> {
+        let Some(ue_id) = self.connected_ues.get(imsi).map(|x| *x) else {
+            bail!("imsi-{imsi} is not connected");
+        };
+        info!(
+            &self.logger,
+            "Modify PDU session {pdu_session_id} of imsi-{imsi}"
+        );
+        self.dispatch_ue_message(
+            ue_id,
+            UeMessage::ModifyPduSession {
+                pdu_session_id,
+                modification,
+            },
+        )
+        .await
+    }
+
+    /// Push a UE Configuration Update Command to a connected UE.
+    pub async fn update_ue_configuration(
+        &self,
+        imsi: &str,
+        update: UeConfigurationUpdate,
+    ) -> Result<()> {
+        let Some(ue_id) = self.connected_ues.get(imsi).map(|x| *x) else {
+            bail!("imsi-{imsi} is not connected");
+        };
+        if let Some(allowed_nssai) = &update.allowed_nssai {
+            ensure!(
+                allowed_nssai
+                    .iter()
+                    .all(|x| self.config.snssais.contains(x)),
+                "Allowed NSSAI must be a subset of the configured S-NSSAIs"
+            );
+        }
+        info!(&self.logger, "Update configuration of imsi-{imsi}");
+        self.dispatch_ue_message(ue_id, UeMessage::UpdateConfiguration(update))
+            .await
+    }
+
+    // The SIM's static IPv4 address on the data network, if it has one.
+    fn static_ipv4_addr(&self, imsi: &str, data_network_idx: usize) -> Option<Ipv4Addr> {
+        let dnn = &self.config.data_networks[data_network_idx].dnn;
+        self.sim_auth_data.get(imsi)?.static_ipv4_addr(dnn)
+    }
 }
 
 #[async_trait]
@@ -103,6 +331,93 @@ impl HandlerApi for QCore {
         self.sim_auth_data.get(imsi)
     }
 
+    fn sqn_store(&self) -> &SqnStore {
+        &self.sqn_store
+    }
+
+    fn store_registered_ue(&self, tmsi: [u8; 4], ue: RegisteredUe) {
+        // A UE only has one GUTI at a time, so forget any older entry for the same SIM.
+        self.forget_registered_ue(&ue.imsi);
+
+        // TS24.501, 5.3.7: if the UE doesn't get back in touch before the mobile reachable timer expires,
+        // implicitly deregister it.
+        let released_at = ue.released_at;
+        for session in &ue.pdu_sessions {
+            self.idle_session_owners
+                .insert(session.userplane_info.index(), tmsi);
+        }
+        let qc = self.clone();
+        async_std::task::spawn(async move {
+            async_std::task::sleep(qc.config.mobile_reachable_timer).await;
+            if let Some((_, ue)) = qc
+                .registered_ues
+                .remove_if(&tmsi, |_, x| x.released_at == released_at)
+            {
+                qc.forget_idle_sessions(&tmsi, &ue);
+                info!(
+                    &qc.logger,
+                    "Mobile reachable timer expired - implicitly deregistered imsi-{}", ue.imsi
+                );
+                qc.delete_pdu_sessions(ue).await;
+            }
+        });
+        self.registered_ues.insert(tmsi, ue);
+    }
+
+    fn take_registered_ue(&self, tmsi: &[u8; 4]) -> Option<RegisteredUe> {
+        let (_, ue) = self.registered_ues.remove(tmsi)?;
+        self.forget_idle_sessions(tmsi, &ue);
+        Some(ue)
+    }
+
+    fn tmsi_in_use(&self, tmsi: &[u8; 4]) -> bool {
+        self.registered_ues.contains_key(tmsi) || self.connected_tmsis.contains_key(tmsi)
+    }
+
+    fn set_served_cells(&self, gnb_du_id: u64, cells: Vec<ServedCellInformation>) {
+        self.served_cells.insert(gnb_du_id, cells);
+    }
+
+    fn tracking_areas(&self, nr_cgi: &NrCgi) -> Vec<[u8; 3]> {
+        let mut tacs = vec![];
+        for du_cells in self.served_cells.iter() {
+            if !du_cells.iter().any(|x| same_cell(&x.nr_cgi, nr_cgi)) {
+                continue;
+            }
+            for FiveGsTac(tac) in du_cells.iter().filter_map(|x| x.five_gs_tac.as_ref()) {
+                if !tacs.contains(tac) {
+                    tacs.push(*tac);
+                }
+            }
+        }
+        tacs
+    }
+
+    fn paging_cells(&self, nr_cgi: &NrCgi) -> Vec<NrCgi> {
+        let mut cells = vec![];
+        for du_cells in self.served_cells.iter() {
+            if du_cells.iter().any(|x| same_cell(&x.nr_cgi, nr_cgi)) {
+                cells.extend(du_cells.iter().map(|x| x.nr_cgi.clone()));
+            }
+        }
+        cells
+    }
+
+    fn add_connected_ue(&self, imsi: String, tmsi: [u8; 4], ue_id: u32) {
+        self.connected_ues.insert(imsi, ue_id);
+        self.connected_tmsis.insert(tmsi, ue_id);
+    }
+
+    fn remove_connected_ue(&self, imsi: &str, tmsi: &[u8; 4], ue_id: u32) {
+        self.connected_ues.remove_if(imsi, |_, x| *x == ue_id);
+        self.connected_tmsis.remove_if(tmsi, |_, x| *x == ue_id);
+    }
+
+    fn change_connected_tmsi(&self, old_tmsi: &[u8; 4], new_tmsi: [u8; 4], ue_id: u32) {
+        self.connected_tmsis.remove_if(old_tmsi, |_, x| *x == ue_id);
+        self.connected_tmsis.insert(new_tmsi, ue_id);
+    }
+
     fn spawn_ue_message_handler(&self) -> u32 {
         let mut ue_id = rand::random::<u32>();
         while self.ue_tasks.contains_key(&ue_id) {
@@ -115,7 +430,7 @@ impl HandlerApi for QCore {
         ue_id
     }
 
-    async fn dispatch_ue_message(&self, ue_id: u32, message: F1apPdu) -> Result<()> {
+    async fn dispatch_ue_message(&self, ue_id: u32, message: UeMessage) -> Result<()> {
         if let Some(sender) = self.ue_tasks.get(&ue_id) {
             sender.send(message).await?;
         } else {
@@ -145,20 +460,88 @@ impl HandlerApi for QCore {
         <Stack as IndicationHandler<P>>::handle(&self.f1ap, r, logger).await
     }
 
-    async fn reserve_userplane_session(&self, logger: &Logger) -> Result<UserplaneSession> {
+    async fn reserve_userplane_session(
+        &self,
+        data_network_idx: usize,
+        pdu_session_type: u8,
+        session_ambr: &SessionAmbr,
+        imsi: Option<&str>,
+        logger: &Logger,
+    ) -> Result<UserplaneSession> {
+        // A SIM with a static address on this data network gets its reserved index.  Otherwise, with sticky UE
+        // IPs, it gets the same index as last time if it is free.
+        let session_index = match imsi {
+            Some(imsi) => match self.static_ipv4_addr(imsi, data_network_idx) {
+                Some(addr) => SessionIndex::Static(addr.octets()[3] as usize),
+                None if self.config.sticky_ue_ips => match self
+                    .last_session_indices
+                    .get(&(imsi.to_string(), data_network_idx))
+                {
+                    Some(idx) => SessionIndex::Preferred(*idx),
+                    None => SessionIndex::Any,
+                },
+                None => SessionIndex::Any,
+            },
+            None => SessionIndex::Any,
+        };
+
+        let ue_ambr_buckets = imsi.and_then(|imsi| {
+            let ue_ambr = self.lookup_sim(imsi)?.ue_ambr?;
+            let buckets = self
+                .ue_ambr_buckets
+                .entry(imsi.to_string())
+                .or_insert_with(|| AmbrBuckets::new(ue_ambr.downlink_kbps, ue_ambr.uplink_kbps));
+            Some(buckets.clone())
+        });
+
         self.packet_processor
-            .reserve_userplane_session(logger)
+            .reserve_userplane_session(
+                data_network_idx,
+                pdu_session_type,
+                session_index,
+                session_ambr,
+                ue_ambr_buckets.as_ref(),
+                logger,
+            )
             .await
     }
 
     async fn commit_userplane_session(
         &self,
         session: &UserplaneSession,
-        remote_tunnel_info: GtpTunnel,
+        drb_tunnels: Vec<DrbTunnel>,
+        imsi: Option<&str>,
         logger: &Logger,
     ) -> Result<()> {
         self.packet_processor
-            .commit_userplane_session(session, remote_tunnel_info, logger)
+            .commit_userplane_session(session, drb_tunnels, logger)
+            .await?;
+
+        // Only remember the SIM's index for sticky UE IPs once its session is set up.
+        if let Some(imsi) = imsi.filter(|_| self.config.sticky_ue_ips) {
+            let data_network_idx = session.data_network_idx();
+            if self.static_ipv4_addr(imsi, data_network_idx).is_none() {
+                self.last_session_indices
+                    .insert((imsi.to_string(), data_network_idx), session.host_number());
+            }
+        }
+        Ok(())
+    }
+
+    async fn remap_userplane_qos_flows(
+        &self,
+        session: &UserplaneSession,
+        drb_qfis: Vec<(u8, Vec<u8>)>,
+        logger: &Logger,
+    ) {
+        self.packet_processor
+            .remap_qos_flows(session, drb_qfis, logger)
+            .await
+    }
+
+    async fn deactivate_userplane_session(&self, session: &UserplaneSession, logger: &Logger) {
+        self.packet_processor
+            .deactivate_userplane_session(session, logger)
             .await
     }
 
@@ -168,3 +551,7 @@ impl HandlerApi for QCore {
             .await
     }
 }
+
+fn same_cell(a: &NrCgi, b: &NrCgi) -> bool {
+    a.plmn_identity.0 == b.plmn_identity.0 && a.nr_cell_identity.0 == b.nr_cell_identity.0
+}
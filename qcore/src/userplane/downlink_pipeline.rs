@@ -4,8 +4,16 @@ use crate::userplane::{
     DOWNLINK_INNER_PACKET_OFFSET, GTP_BASE_HEADER_LEN, GTP_EXT_HEADER_LEN_NRUP_DL_USER_DATA,
 };
 
-use super::{GTP_MESSAGE_TYPE_GPDU, GTPU_PORT, IPV4_HEADER_LEN, MAX_UES};
+use super::ethernet::{ETHERNET_HEADER_LEN, MacTable, destination_mac, is_group_address};
+use super::qos_classifier::QosClassifier;
+use super::rate_limiter::RateLimiter;
+use super::{
+    GTP_MESSAGE_TYPE_GPDU, GTPU_PORT, IPV4_HEADER_LEN, IPV6_HEADER_LEN, MAX_PDU_SESSIONS,
+    SDAP_HEADER_LEN, forwarding_table_index,
+};
+use crate::UserplaneSession;
 use anyhow::Result;
+use async_channel::Sender;
 use async_std::{
     io::ReadExt,
     net::{IpAddr, UdpSocket},
@@ -15,51 +23,242 @@ use async_std::{
 use async_tun::Tun;
 use atomic_counter::{AtomicCounter, RelaxedCounter};
 use derive_deref::Deref;
-use std::net::{Ipv4Addr, SocketAddr};
+use std::collections::VecDeque;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::sync::Arc;
-use xxap::GtpTunnel;
+use std::time::{Duration, Instant};
+use xxap::{GtpTeid, GtpTunnel};
 
 //pub type ForwardingRule = (u32, ForwardingAction);
 
-// TODO - this could be compressed to use a 1-byte DU index to avoid heavy duplication of remote GTP address.
-// Right now, IP addr is also deterministic from the table slot ID.
+/// Limits on the downlink packets that we buffer for each PDU session of an idle UE while it is paged.
+#[derive(Clone, Copy, Debug)]
+pub struct DownlinkBufferLimits {
+    pub max_packets: usize,
+    pub max_age: Duration,
+}
+
+/// The downlink tunnel of one of a session's DRBs, and the QFIs of the QoS flows mapped to it.
+#[derive(Clone, Debug)]
+pub struct DrbTunnel {
+    pub drb_id: u8,
+    pub qfis: Vec<u8>,
+    pub remote_tunnel_info: GtpTunnel,
+}
+
 #[derive(Clone)]
-struct DownlinkForwardingRule {
+struct DownlinkDrb {
+    pub drb_id: u8,
+    pub qfis: Vec<u8>,
     pub remote_tunnel_info: GtpTunnel,
-    pub ue_ip_addr: IpAddr,
     pub pdcp_seq_num: u16,
     pub nr_seq_num: u32,
 }
 
+// The headers to put on a downlink packet.
+struct DownlinkHeaders {
+    remote_tunnel_info: GtpTunnel,
+    pdcp_seq_num: u16,
+    nr_seq_num: u32,
+    // Set if the DRB has an SDAP header.
+    sdap_qfi: Option<u8>,
+}
+
+// TODO - this could be compressed to use a 1-byte DU index to avoid heavy duplication of remote GTP address.
+// Right now, IP addr is also deterministic from the table slot ID.
+#[derive(Clone)]
+struct DownlinkForwardingRule {
+    // The session's DRBs, starting with its default DRB.  Empty while the UE is idle, in which case we buffer its
+    // packets and get it paged.
+    pub drbs: Vec<DownlinkDrb>,
+    pub sdap_header_dl: bool,
+    pub qos_classifier: QosClassifier,
+    pub ue_ipv4_addr: Option<Ipv4Addr>,
+    pub ue_ipv6_prefix: Option<[u8; 8]>,
+    pub ethernet: bool,
+    pub rate_limiter: RateLimiter,
+    pub buffer: VecDeque<(Instant, Vec<u8>)>,
+    pub paging_triggered_at: Option<Instant>,
+}
+
+impl DownlinkForwardingRule {
+    fn new(drb_tunnels: Vec<DrbTunnel>, sdap_header_dl: bool, session: &UserplaneSession) -> Self {
+        DownlinkForwardingRule {
+            drbs: drb_tunnels
+                .into_iter()
+                .map(|x| DownlinkDrb {
+                    drb_id: x.drb_id,
+                    qfis: x.qfis,
+                    remote_tunnel_info: x.remote_tunnel_info,
+                    pdcp_seq_num: 0,
+                    nr_seq_num: 0,
+                })
+                .collect(),
+            sdap_header_dl,
+            qos_classifier: session.qos_classifier.clone(),
+            ue_ipv4_addr: session.ue_ipv4_addr,
+            ue_ipv6_prefix: session.ue_ipv6_prefix.map(ipv6_prefix),
+            ethernet: session.ethernet,
+            rate_limiter: session.downlink_rate_limiter.clone(),
+            buffer: VecDeque::new(),
+            paging_triggered_at: None,
+        }
+    }
+
+    // Classify a packet onto a QoS flow, and take the next sequence numbers of the DRB that the QoS flow is mapped
+    // to.  A packet that matches no QoS rule goes on the default DRB.  Returns None if the UE is idle.
+    fn next_headers(&mut self, inner_packet: &[u8]) -> Option<DownlinkHeaders> {
+        let qfi = self.qos_classifier.classify_downlink(inner_packet);
+        let drb_idx = qfi
+            .and_then(|qfi| self.drbs.iter().position(|x| x.qfis.contains(&qfi)))
+            .unwrap_or(0);
+        let drb = self.drbs.get_mut(drb_idx)?;
+        let qfi = qfi
+            .filter(|x| drb.qfis.contains(x))
+            .or(drb.qfis.first().copied());
+        let headers = DownlinkHeaders {
+            remote_tunnel_info: drb.remote_tunnel_info.clone(),
+            pdcp_seq_num: drb.pdcp_seq_num,
+            nr_seq_num: drb.nr_seq_num,
+            sdap_qfi: qfi.filter(|_| self.sdap_header_dl),
+        };
+        drb.pdcp_seq_num = drb.pdcp_seq_num.wrapping_add(1);
+        drb.nr_seq_num = drb.nr_seq_num.wrapping_add(1);
+        Some(headers)
+    }
+
+    fn serves(&self, ue_ip_addr: &IpAddr) -> bool {
+        match ue_ip_addr {
+            IpAddr::V4(x) => self.ue_ipv4_addr.as_ref() == Some(x),
+            IpAddr::V6(x) => self.ue_ipv6_prefix == Some(ipv6_prefix(*x)),
+        }
+    }
+
+    fn same_session(&self, other: &DownlinkForwardingRule) -> bool {
+        self.ue_ipv4_addr == other.ue_ipv4_addr
+            && self.ue_ipv6_prefix == other.ue_ipv6_prefix
+            && self.ethernet == other.ethernet
+    }
+}
+
 // TODO - these could be converted to an atomic rather than locked structure
 #[derive(Clone)]
 pub struct DownlinkForwardingTable(Arc<Mutex<Vec<Option<DownlinkForwardingRule>>>>);
 
 impl DownlinkForwardingTable {
     pub fn new() -> Self {
-        Self(Arc::new(Mutex::new(vec![None; MAX_UES])))
+        Self(Arc::new(Mutex::new(vec![None; MAX_PDU_SESSIONS])))
     }
-    pub async fn add_rule(&self, remote_tunnel_info: GtpTunnel, ue_ipv4: Ipv4Addr) {
-        let idx = downlink_table_index_from_ip(ue_ipv4);
+    /// Forward downlink packets for the session's UE IP address and prefix down the tunnels of its DRBs, first
+    /// sending any packets that were buffered while the UE was idle, unless they are older than max_buffered_age.
+    /// The first DRB is the default DRB.  Returns the number of buffered packets sent.
+    pub async fn add_rule(
+        &self,
+        session: &UserplaneSession,
+        drb_tunnels: Vec<DrbTunnel>,
+        sdap_header_dl: bool,
+        max_buffered_age: Duration,
+        f1u_socket: &UdpSocket,
+    ) -> Result<usize> {
+        let idx = session.index();
 
-        self.0.lock().await[idx] = Some(DownlinkForwardingRule {
-            remote_tunnel_info,
-            ue_ip_addr: IpAddr::V4(ue_ipv4),
-            pdcp_seq_num: 0,
-            nr_seq_num: 0,
+        // -- critical section --
+        // The buffered packets take their sequence numbers before the rule goes in, so they come ahead of any new
+        // packet in PDCP order even if the pipeline sends the new packet first.
+        let mut table = self.0.lock().await;
+        let mut rule = DownlinkForwardingRule::new(drb_tunnels, sdap_header_dl, session);
+        let buffer = match table[idx].take() {
+            Some(old_rule) if old_rule.same_session(&rule) => old_rule.buffer,
+            _ => VecDeque::new(),
+        };
+        let mut flush = vec![];
+        for (_, packet) in buffer
+            .into_iter()
+            .filter(|(received_at, _)| received_at.elapsed() < max_buffered_age)
+        {
+            let Some(headers) = rule.next_headers(&packet[DOWNLINK_INNER_PACKET_OFFSET..]) else {
+                break;
+            };
+            flush.push((packet, headers));
+        }
+        table[idx] = Some(rule);
+        drop(table);
+        // -- end critical section --
+
+        let flushed = flush.len();
+        for (mut packet, headers) in flush {
+            let inner_packet_len = packet.len() - DOWNLINK_INNER_PACKET_OFFSET;
+            send_to_du(f1u_socket, &mut packet, inner_packet_len, headers).await?;
+        }
+        Ok(flushed)
+    }
+
+    /// Change the QFIs mapped to each of the session's DRBs, given as (DRB ID, QFIs) pairs, after a PDU session
+    /// modification.  A DRB that isn't in the list has been released, and its tunnel is no longer used.
+    pub async fn remap_qos_flows(&self, session: &UserplaneSession, drb_qfis: Vec<(u8, Vec<u8>)>) {
+        let idx = session.index();
+        let mut table = self.0.lock().await;
+        let Some(ref mut entry) = table[idx] else {
+            return;
+        };
+        entry.drbs.retain_mut(|drb| {
+            let Some((_, qfis)) = drb_qfis.iter().find(|(id, _)| *id == drb.drb_id) else {
+                return false;
+            };
+            drb.qfis = qfis.clone();
+            true
         });
     }
-    pub async fn remove_rule(&self, ue_ipv4: Ipv4Addr) {
-        let idx = downlink_table_index_from_ip(ue_ipv4);
-        self.0.lock().await[idx] = None;
+
+    /// Buffer downlink packets for the session, rather than forwarding them, because its UE is idle.
+    pub async fn buffer_rule(&self, session: &UserplaneSession) {
+        self.0.lock().await[session.index()] =
+            Some(DownlinkForwardingRule::new(vec![], false, session));
+    }
+
+    pub async fn remove_rule(&self, session: &UserplaneSession) {
+        self.0.lock().await[session.index()] = None;
+    }
+
+    /// Send a packet that QCore generated itself down the tunnel of the session with the given table index.  The
+    /// inner packet of the given length starts at DOWNLINK_INNER_PACKET_OFFSET in the buffer.  Returns false if
+    /// the session has no active tunnel.
+    pub async fn send(
+        &self,
+        idx: usize,
+        buf: &mut [u8],
+        inner_packet_len: usize,
+        f1u_socket: &UdpSocket,
+    ) -> Result<bool> {
+        // -- critical section --
+        let mut table = self.0.lock().await;
+        let Some(ref mut entry) = table[idx] else {
+            return Ok(false);
+        };
+        let inner_packet =
+            &buf[DOWNLINK_INNER_PACKET_OFFSET..DOWNLINK_INNER_PACKET_OFFSET + inner_packet_len];
+        let Some(headers) = entry.next_headers(inner_packet) else {
+            return Ok(false);
+        };
+        drop(table);
+        // -- end critical section --
+
+        send_to_du(f1u_socket, buf, inner_packet_len, headers).await?;
+        Ok(true)
     }
 }
 
 pub struct DownlinkPipeline {
     f1u_socket: UdpSocket,
     n6_tun_device: Tun,
+    data_network_idx: usize,
+
+    // Set if the N6 device is a tap device carrying Ethernet frames rather than a tun device carrying IP packets.
+    mac_table: Option<MacTable>,
     forwarding_table: DownlinkForwardingTable,
+    buffer_limits: DownlinkBufferLimits,
     counters: Arc<DownlinkCounters>,
+    paging_trigger: Sender<usize>,
 }
 
 pub mod downlink_counter_indices {
@@ -68,7 +267,14 @@ pub mod downlink_counter_indices {
     pub const DL_DROP_TOO_SHORT: usize = 2;
     pub const DL_DROP_UNKNOWN_IP_1: usize = 3;
     pub const DL_DROP_UNKNOWN_IP_2: usize = 4;
-    pub const DL_NUM_COUNTERS: usize = 5;
+    pub const DL_DROP_BUFFER_FULL: usize = 5;
+    pub const DL_DROP_BUFFER_EXPIRED: usize = 6;
+    pub const DL_BUFFERED_PKTS: usize = 7;
+    pub const DL_DROP_NOT_IP: usize = 8;
+    pub const DL_FLOODED_FRAMES: usize = 9;
+    pub const DL_DROP_NO_ETHERNET_SESSION: usize = 10;
+    pub const DL_DROP_AMBR: usize = 11;
+    pub const DL_NUM_COUNTERS: usize = 12;
 }
 use downlink_counter_indices::*;
 
@@ -79,14 +285,22 @@ impl DownlinkPipeline {
     pub fn new(
         f1u_socket: UdpSocket,
         n6_tun_device: Tun,
+        data_network_idx: usize,
+        mac_table: Option<MacTable>,
         forwarding_table: DownlinkForwardingTable,
+        buffer_limits: DownlinkBufferLimits,
         counters: Arc<DownlinkCounters>,
+        paging_trigger: Sender<usize>,
     ) -> Self {
         Self {
             f1u_socket,
             n6_tun_device,
+            data_network_idx,
+            mac_table,
             forwarding_table,
+            buffer_limits,
             counters,
+            paging_trigger,
         }
     }
 
@@ -108,112 +322,291 @@ impl DownlinkPipeline {
         counters[DL_RX_PKTS].inc();
         counters[DL_RX_BYTES].add(bytes_read);
 
+        if let Some(mac_table) = &self.mac_table {
+            return self.handle_ethernet_frame(buf, bytes_read, mac_table).await;
+        }
+
         if bytes_read < IPV4_HEADER_LEN {
             counters[DL_DROP_TOO_SHORT].inc();
             return Ok(());
         }
         let ip_header =
-            &buf[DOWNLINK_INNER_PACKET_OFFSET..DOWNLINK_INNER_PACKET_OFFSET + IPV4_HEADER_LEN];
-        // TODO: check IP type
-        let ue_ip_addr = Ipv4Addr::new(ip_header[16], ip_header[17], ip_header[18], ip_header[19]);
+            &buf[DOWNLINK_INNER_PACKET_OFFSET..DOWNLINK_INNER_PACKET_OFFSET + bytes_read];
+        let ue_ip_addr = match ip_header[0] >> 4 {
+            4 => IpAddr::V4(Ipv4Addr::new(
+                ip_header[16],
+                ip_header[17],
+                ip_header[18],
+                ip_header[19],
+            )),
+            6 if bytes_read < IPV6_HEADER_LEN => {
+                counters[DL_DROP_TOO_SHORT].inc();
+                return Ok(());
+            }
+            6 => {
+                let mut dst = [0u8; 16];
+                dst.copy_from_slice(&ip_header[24..40]);
+                IpAddr::V6(Ipv6Addr::from(dst))
+            }
+            _ => {
+                counters[DL_DROP_NOT_IP].inc();
+                return Ok(());
+            }
+        };
 
-        let idx = downlink_table_index_from_ip(ue_ip_addr);
-        //println!("Incoming packet on UE tun if with dst IP {:x?}", ue_ip_addr);
+        let idx = downlink_table_index_from_ip(self.data_network_idx, &ue_ip_addr);
+        self.forward_to_session(buf, bytes_read, idx, |entry| entry.serves(&ue_ip_addr))
+            .await
+    }
+
+    // Bridge a frame from the tap device to the Ethernet PDU session that its destination MAC address was learned
+    // on.  Broadcast, multicast and unknown unicast frames go to every connected Ethernet PDU session of the data
+    // network.
+    async fn handle_ethernet_frame(
+        &self,
+        buf: &mut [u8; 2000],
+        bytes_read: usize,
+        mac_table: &MacTable,
+    ) -> Result<()> {
+        if bytes_read < ETHERNET_HEADER_LEN {
+            self.counters[DL_DROP_TOO_SHORT].inc();
+            return Ok(());
+        }
+        let dst = destination_mac(&buf[DOWNLINK_INNER_PACKET_OFFSET..]);
+        let session_idx = if is_group_address(&dst) {
+            None
+        } else {
+            mac_table.lookup(&dst).await
+        };
+        match session_idx {
+            Some(idx) => {
+                self.forward_to_session(buf, bytes_read, idx, |entry| entry.ethernet)
+                    .await
+            }
+            None => self.flood(buf, bytes_read).await,
+        }
+    }
+
+    // Send a packet down the tunnel of the session with the given index, provided that it is the session that
+    // the packet is meant for, or buffer it if the session's UE is idle.
+    async fn forward_to_session(
+        &self,
+        buf: &mut [u8; 2000],
+        bytes_read: usize,
+        idx: usize,
+        is_for_session: impl Fn(&DownlinkForwardingRule) -> bool,
+    ) -> Result<()> {
+        let counters = &self.counters;
 
         // -- critical section --
         let Some(ref mut entry) = self.forwarding_table.0.lock().await[idx] else {
             counters[DL_DROP_UNKNOWN_IP_1].inc();
             return Ok(());
         };
-        if ue_ip_addr != entry.ue_ip_addr {
+        if !is_for_session(entry) {
             counters[DL_DROP_UNKNOWN_IP_2].inc();
             return Ok(());
         }
-        let du_ip = entry.remote_tunnel_info.transport_layer_address.clone();
+        if !entry.rate_limiter.allow(bytes_read) {
+            counters[DL_DROP_AMBR].inc();
+            return Ok(());
+        }
+        let inner_packet =
+            &buf[DOWNLINK_INNER_PACKET_OFFSET..DOWNLINK_INNER_PACKET_OFFSET + bytes_read];
+        let Some(headers) = entry.next_headers(inner_packet) else {
+            self.buffer_packet(
+                entry,
+                &buf[0..(bytes_read + DOWNLINK_INNER_PACKET_OFFSET)],
+                idx,
+            );
+            return Ok(());
+        };
+        // -- end critical section --
+
+        send_to_du(&self.f1u_socket, buf, bytes_read, headers).await
+    }
 
-        let pdcp_seq_num = entry.pdcp_seq_num;
-        entry.pdcp_seq_num += 1;
-        let nr_seq_num = entry.nr_seq_num;
-        entry.nr_seq_num += 1;
+    // Send a frame down the tunnel of every connected Ethernet PDU session on this pipeline's data network.  We
+    // don't page idle UEs for flooded frames, since they would be woken by every broadcast.
+    async fn flood(&self, buf: &mut [u8; 2000], bytes_read: usize) -> Result<()> {
+        let mut all_headers = vec![];
+        let mut connected_sessions = 0;
+        let frame = &buf[DOWNLINK_INNER_PACKET_OFFSET..DOWNLINK_INNER_PACKET_OFFSET + bytes_read];
+
+        // -- critical section --
+        let first_idx = forwarding_table_index(self.data_network_idx, 0);
+        let last_idx = forwarding_table_index(self.data_network_idx, u8::MAX);
+        let mut table = self.forwarding_table.0.lock().await;
+        for entry in table[first_idx..=last_idx].iter_mut().flatten() {
+            if !entry.ethernet || entry.drbs.is_empty() {
+                continue;
+            }
+            connected_sessions += 1;
+            if !entry.rate_limiter.allow(bytes_read) {
+                self.counters[DL_DROP_AMBR].inc();
+                continue;
+            }
+            all_headers.extend(entry.next_headers(frame));
+        }
+        drop(table);
         // -- end critical section --
 
-        // The payload is the message length following the inital 8 byte GTP header.
-        let gtp_payload_length = ((bytes_read + DOWNLINK_INNER_PACKET_OFFSET - GTP_BASE_HEADER_LEN)
-            as u16)
-            .to_be_bytes();
-        //println!("GTP length {:x?}", gtp_payload_length);
+        if connected_sessions == 0 {
+            self.counters[DL_DROP_NO_ETHERNET_SESSION].inc();
+            return Ok(());
+        }
+        self.counters[DL_FLOODED_FRAMES].inc();
+        for headers in all_headers {
+            send_to_du(&self.f1u_socket, buf, bytes_read, headers).await?;
+        }
+        Ok(())
+    }
 
-        // Add the GTP, PDCP and SDAP headers.
+    // Hold on to a packet for an idle UE, and get the UE paged.  The buffer is bounded in size and in the age of
+    // its packets.
+    fn buffer_packet(&self, entry: &mut DownlinkForwardingRule, packet: &[u8], idx: usize) {
+        let counters = &self.counters;
+        let DownlinkBufferLimits {
+            max_packets,
+            max_age,
+        } = self.buffer_limits;
+        while entry
+            .buffer
+            .front()
+            .is_some_and(|(received_at, _)| received_at.elapsed() >= max_age)
+        {
+            entry.buffer.pop_front();
+            counters[DL_DROP_BUFFER_EXPIRED].inc();
+        }
+        if entry.buffer.len() >= max_packets {
+            counters[DL_DROP_BUFFER_FULL].inc();
+            return;
+        }
+        entry.buffer.push_back((Instant::now(), packet.to_vec()));
+        counters[DL_BUFFERED_PKTS].inc();
+
+        // Ask for the UE to be paged, unless we already asked recently.  QCore repeats the paging itself, so we only
+        // ask again if it might have given up.
+        if entry
+            .paging_triggered_at
+            .is_none_or(|x| x.elapsed() >= max_age)
+        {
+            entry.paging_triggered_at = Some(Instant::now());
+            let _ = self.paging_trigger.try_send(idx);
+        }
+    }
+}
 
-        // ---- GTP header, TS29.281, 5.2.1 ----
-        buf[0] = 0b001_1_0_1_0_0; // version=1, PT=1, R, E=1, S=0, PN=0
-        buf[1] = GTP_MESSAGE_TYPE_GPDU;
-        buf[2] = gtp_payload_length[0];
-        buf[3] = gtp_payload_length[1];
+// Add the headers to an inner packet of the given length and send it to the DU.
+async fn send_to_du(
+    f1u_socket: &UdpSocket,
+    buf: &mut [u8],
+    bytes_read: usize,
+    headers: DownlinkHeaders,
+) -> Result<()> {
+    let start = add_headers(buf, bytes_read, &headers);
+
+    let du_ip = IpAddr::try_from(headers.remote_tunnel_info.transport_layer_address)?;
+    f1u_socket
+        .send_to(
+            &buf[start..(bytes_read + DOWNLINK_INNER_PACKET_OFFSET)],
+            SocketAddr::new(du_ip, GTPU_PORT),
+        )
+        .await?;
+
+    Ok(())
+}
 
-        // TEID
-        buf[4] = entry.remote_tunnel_info.gtp_teid.0[0];
-        buf[5] = entry.remote_tunnel_info.gtp_teid.0[1];
-        buf[6] = entry.remote_tunnel_info.gtp_teid.0[2];
-        buf[7] = entry.remote_tunnel_info.gtp_teid.0[3];
+// Add the GTP, PDCP and SDAP headers in front of an inner packet of the given length, which starts at
+// DOWNLINK_INNER_PACKET_OFFSET in the buffer.  Returns the offset in the buffer at which the headers start, which
+// is past the room left for the SDAP header if the DRB doesn't have one.
+fn add_headers(buf: &mut [u8], bytes_read: usize, headers: &DownlinkHeaders) -> usize {
+    let start = if headers.sdap_qfi.is_some() {
+        0
+    } else {
+        SDAP_HEADER_LEN
+    };
+    let buf = &mut buf[start..];
+
+    // The payload is the message length following the inital 8 byte GTP header.
+    let gtp_payload_length =
+        ((bytes_read + DOWNLINK_INNER_PACKET_OFFSET - start - GTP_BASE_HEADER_LEN) as u16)
+            .to_be_bytes();
 
-        // Since E=1 above, this is an extended GTP header with 4 extra bytes.
-        // Sequence + PDU number - ignored since their bit is set to 0 above
-        buf[8] = 0;
-        buf[9] = 0;
-        buf[10] = 0;
+    // Add the GTP, PDCP and SDAP headers.
 
-        // Next extension header type = 0x84 = NR RAN container (TS29.281, 5.2.1.3)
-        buf[11] = 0x84;
+    // ---- GTP header, TS29.281, 5.2.1 ----
+    buf[0] = 0b001_1_0_1_0_0; // version=1, PT=1, R, E=1, S=0, PN=0
+    buf[1] = GTP_MESSAGE_TYPE_GPDU;
+    buf[2] = gtp_payload_length[0];
+    buf[3] = gtp_payload_length[1];
 
-        // --- GTP extension header - NR RAN Container - Downlink User Data ---
-        // See TS29.281, 5.2.2.6 and TS38.425, 5.5.2.1
+    // TEID
+    let remote_gtp_teid: &GtpTeid = &headers.remote_tunnel_info.gtp_teid;
+    buf[4] = remote_gtp_teid.0[0];
+    buf[5] = remote_gtp_teid.0[1];
+    buf[6] = remote_gtp_teid.0[2];
+    buf[7] = remote_gtp_teid.0[3];
 
-        // Extension header length / 4.  (TS29.281, 5.2.1)
-        buf[12] = (GTP_EXT_HEADER_LEN_NRUP_DL_USER_DATA / 4) as u8;
+    // Since E=1 above, this is an extended GTP header with 4 extra bytes.
+    // Sequence + PDU number - ignored since their bit is set to 0 above
+    buf[8] = 0;
+    buf[9] = 0;
+    buf[10] = 0;
 
-        // PDU type 0; spare; discard blocks; flush; report polling
-        buf[13] = 0b0000_0_0_0_0;
+    // Next extension header type = 0x84 = NR RAN container (TS29.281, 5.2.1.3)
+    buf[11] = 0x84;
 
-        // spare; request out of seq; report delivered; user data; assistance info; transmission
-        buf[14] = 0b000_0_0_0_0_0;
+    // --- GTP extension header - NR RAN Container - Downlink User Data ---
+    // See TS29.281, 5.2.2.6 and TS38.425, 5.5.2.1
 
-        // 3 bytes of NR seq num
-        let nr_seq_num = nr_seq_num.to_be_bytes();
-        buf[15] = nr_seq_num[1];
-        buf[16] = nr_seq_num[2];
-        buf[17] = nr_seq_num[3];
+    // Extension header length / 4.  (TS29.281, 5.2.1)
+    buf[12] = (GTP_EXT_HEADER_LEN_NRUP_DL_USER_DATA / 4) as u8;
 
-        // Pad extension header to multiple of four bytes
-        buf[18] = 0;
+    // PDU type 0; spare; discard blocks; flush; report polling
+    buf[13] = 0b0000_0_0_0_0;
 
-        // Next extension header type = None
-        buf[19] = 0;
+    // spare; request out of seq; report delivered; user data; assistance info; transmission
+    buf[14] = 0b000_0_0_0_0_0;
 
-        // --- PDCP Data PDU for DRB with 12 bit PDCP SN ---
-        buf[20] = 0b1_0_0_0_0000 | (((pdcp_seq_num & 0x0f00) >> 8) as u8); // D/C, R,R,R, SN
-        buf[21] = (pdcp_seq_num & 0xff) as u8; // SN
+    // 3 bytes of NR seq num
+    let nr_seq_num = headers.nr_seq_num.to_be_bytes();
+    buf[15] = nr_seq_num[1];
+    buf[16] = nr_seq_num[2];
+    buf[17] = nr_seq_num[3];
 
-        assert!(DOWNLINK_INNER_PACKET_OFFSET == 22);
+    // Pad extension header to multiple of four bytes
+    buf[18] = 0;
 
-        // Not supported by SRS UE
-        // // ---- SDAP DOWNLINK DATA PDU ----
-        // buf[23] = 0b0_0_000001; // RDI, RQI, QFI - see TS37.324
+    // Next extension header type = None
+    buf[19] = 0;
 
-        let du_ip = IpAddr::try_from(du_ip)?;
-        self.f1u_socket
-            .send_to(
-                &buf[0..(bytes_read + DOWNLINK_INNER_PACKET_OFFSET)],
-                SocketAddr::new(du_ip, GTPU_PORT),
-            )
-            .await?;
+    // --- PDCP Data PDU for DRB with 12 bit PDCP SN ---
+    let pdcp_seq_num = headers.pdcp_seq_num;
+    buf[20] = 0b1_0_0_0_0000 | (((pdcp_seq_num & 0x0f00) >> 8) as u8); // D/C, R,R,R, SN
+    buf[21] = (pdcp_seq_num & 0xff) as u8; // SN
 
-        Ok(())
+    // ---- SDAP DOWNLINK DATA PDU ----
+    // Only if the DRB is configured with a downlink SDAP header, which SRS UE doesn't support.
+    if let Some(qfi) = headers.sdap_qfi {
+        buf[22] = qfi & 0x3f; // RDI, RQI, QFI - see TS37.324, 6.2.2.2
     }
+
+    assert!(DOWNLINK_INNER_PACKET_OFFSET == 23);
+    start
+}
+
+fn downlink_table_index_from_ip(data_network_idx: usize, ue_ip: &IpAddr) -> usize {
+    // TODO - for now, we just use the last byte of the IPv4 address, or of the IPv6 /64 prefix.
+    let last_byte = match ue_ip {
+        IpAddr::V4(x) => x.octets()[3],
+        IpAddr::V6(x) => x.octets()[7],
+    };
+    forwarding_table_index(data_network_idx, last_byte)
 }
 
-fn downlink_table_index_from_ip(ue_ip: Ipv4Addr) -> usize {
-    // TODO - for now, we just use the last byte of the IP address.
-    let last_byte = ue_ip.octets()[3];
-    last_byte as usize
+fn ipv6_prefix(ipv6: Ipv6Addr) -> [u8; 8] {
+    let mut prefix = [0u8; 8];
+    prefix.copy_from_slice(&ipv6.octets()[0..8]);
+    prefix
 }
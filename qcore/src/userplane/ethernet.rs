@@ -0,0 +1,97 @@
+//! Bridging of Ethernet PDU sessions onto a data network's tap device - see TS23.501, 5.8.2.5.  We learn the
+//! source MAC addresses of uplink frames, so that we know which session to send downlink frames to.
+use async_std::sync::Mutex;
+use std::collections::HashMap;
+use std::sync::Arc;
+use std::time::{Duration, Instant};
+
+pub const ETHERNET_HEADER_LEN: usize = 14;
+
+// The most MAC addresses that we learn behind any one Ethernet PDU session.
+const MAX_MACS_PER_SESSION: usize = 64;
+
+// How long we remember a MAC address that we haven't seen in an uplink frame - the default ageing time of
+// IEEE 802.1Q, 8.8.3.
+const MAC_AGEING_TIME: Duration = Duration::from_secs(300);
+
+struct MacEntry {
+    session_idx: usize,
+    last_seen: Instant,
+}
+
+impl MacEntry {
+    fn expired(&self, now: Instant) -> bool {
+        now.duration_since(self.last_seen) >= MAC_AGEING_TIME
+    }
+}
+
+/// Maps the MAC addresses of devices behind each UE to the index of its Ethernet PDU session.  Entries age out,
+/// and each session has a limit on how many it can have, so that a UE can't fill up the table.
+#[derive(Clone, Default)]
+pub struct MacTable(Arc<Mutex<HashMap<[u8; 6], MacEntry>>>);
+
+impl MacTable {
+    /// Learn that a MAC address is behind the given session.  Returns false if it can't be, because another
+    /// session has used the MAC address within the ageing time, or because the session already has as many
+    /// MAC addresses as it is allowed.
+    pub async fn learn(&self, mac: [u8; 6], session_idx: usize) -> bool {
+        let now = Instant::now();
+        let mut table = self.0.lock().await;
+        match table.get_mut(&mac) {
+            Some(entry) if entry.session_idx == session_idx => {
+                entry.last_seen = now;
+                return true;
+            }
+            Some(entry) if !entry.expired(now) => return false,
+            _ => {}
+        }
+        table.retain(|_, entry| !entry.expired(now));
+        let session_macs = table.values().filter(|x| x.session_idx == session_idx);
+        if session_macs.count() >= MAX_MACS_PER_SESSION {
+            return false;
+        }
+        table.insert(
+            mac,
+            MacEntry {
+                session_idx,
+                last_seen: now,
+            },
+        );
+        true
+    }
+
+    pub async fn lookup(&self, mac: &[u8; 6]) -> Option<usize> {
+        let now = Instant::now();
+        self.0
+            .lock()
+            .await
+            .get(mac)
+            .filter(|entry| !entry.expired(now))
+            .map(|entry| entry.session_idx)
+    }
+
+    /// Forget the MAC addresses learned on a session that has been deleted.
+    pub async fn forget_session(&self, session_idx: usize) {
+        self.0
+            .lock()
+            .await
+            .retain(|_, entry| entry.session_idx != session_idx);
+    }
+}
+
+pub fn destination_mac(frame: &[u8]) -> [u8; 6] {
+    let mut mac = [0u8; 6];
+    mac.copy_from_slice(&frame[0..6]);
+    mac
+}
+
+pub fn source_mac(frame: &[u8]) -> [u8; 6] {
+    let mut mac = [0u8; 6];
+    mac.copy_from_slice(&frame[6..12]);
+    mac
+}
+
+/// Whether a MAC address is a broadcast or multicast group address, as given by its I/G bit.
+pub fn is_group_address(mac: &[u8; 6]) -> bool {
+    mac[0] & 0x01 != 0
+}
@@ -0,0 +1,108 @@
+//! IPv6 stateless address autoconfiguration for the UE - see TS23.501, 5.8.2.2.2 and TS29.061, 11.2.1.3.
+//! We send a Router Advertisement for the session's /64 prefix when the session is set up, and again whenever the UE
+//! sends a Router Solicitation on its DRB.  The UE builds its global address from the prefix.
+use super::IPV6_HEADER_LEN;
+
+const NEXT_HEADER_ICMPV6: u8 = 58;
+const ICMPV6_ROUTER_SOLICITATION: u8 = 133;
+const ICMPV6_ROUTER_ADVERTISEMENT: u8 = 134;
+const ROUTER_SOLICITATION_LEN: usize = 8;
+const ROUTER_ADVERTISEMENT_LEN: usize = 16;
+const PREFIX_INFORMATION_OPTION_LEN: usize = 32;
+
+/// The length of the Router Advertisement written by write_router_advertisement().
+pub const ROUTER_ADVERTISEMENT_PACKET_LEN: usize =
+    IPV6_HEADER_LEN + ROUTER_ADVERTISEMENT_LEN + PREFIX_INFORMATION_OPTION_LEN;
+
+// Our link-local address, fe80::1.
+const LINK_LOCAL_ADDRESS: [u8; 16] = [0xfe, 0x80, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+
+// The all-nodes multicast address, ff02::1.
+const ALL_NODES_ADDRESS: [u8; 16] = [0xff, 0x02, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 0, 1];
+
+/// Whether an uplink IP packet is an ICMPv6 Router Solicitation (RFC4861, 4.1).
+pub fn is_router_solicitation(packet: &[u8]) -> bool {
+    packet.len() >= IPV6_HEADER_LEN + ROUTER_SOLICITATION_LEN
+        && packet[0] >> 4 == 6
+        && packet[6] == NEXT_HEADER_ICMPV6
+        && packet[IPV6_HEADER_LEN] == ICMPV6_ROUTER_SOLICITATION
+}
+
+/// Write a Router Advertisement (RFC4861, 4.2) for the given /64 prefix into buf, in answer to the Router
+/// Solicitation rs, or unsolicited if rs is None.  Returns the length of the packet.
+pub fn write_router_advertisement(buf: &mut [u8], rs: Option<&[u8]>, prefix: &[u8; 8]) -> usize {
+    let buf = &mut buf[0..ROUTER_ADVERTISEMENT_PACKET_LEN];
+    buf.fill(0);
+
+    // ---- IPv6 header, RFC8200, 3 ----
+    let payload_len =
+        ((ROUTER_ADVERTISEMENT_LEN + PREFIX_INFORMATION_OPTION_LEN) as u16).to_be_bytes();
+    buf[0] = 0x60; // version=6, traffic class and flow label 0
+    buf[4] = payload_len[0];
+    buf[5] = payload_len[1];
+    buf[6] = NEXT_HEADER_ICMPV6;
+    buf[7] = 255; // hop limit - must be 255 for neighbor discovery
+    buf[8..24].copy_from_slice(&LINK_LOCAL_ADDRESS);
+
+    // Reply to the solicitation's source, unless it didn't have an address yet or this is unsolicited.
+    match rs {
+        Some(rs) if rs[8..24].iter().any(|x| *x != 0) => buf[24..40].copy_from_slice(&rs[8..24]),
+        _ => buf[24..40].copy_from_slice(&ALL_NODES_ADDRESS),
+    }
+
+    // ---- Router Advertisement ----
+    let ra = &mut buf[IPV6_HEADER_LEN..];
+    ra[0] = ICMPV6_ROUTER_ADVERTISEMENT;
+    ra[1] = 0; // code
+    // ra[2..4] is the checksum, filled in below
+    ra[4] = 64; // cur hop limit
+    ra[5] = 0b0_0_000000; // M=0 - no DHCPv6 address; O=0 - no other DHCPv6 configuration
+
+    // Router lifetime - we are the UE's default router, for the maximum 9000 seconds.  TS29.061 asks for a
+    // long lifetime, and we send a new advertisement whenever the session is set up or the UE solicits one.
+    ra[6..8].copy_from_slice(&9000u16.to_be_bytes());
+    // ra[8..16] is reachable time and retrans timer, unspecified
+
+    // ---- Prefix Information option, RFC4861, 4.6.2 ----
+    let pio = &mut ra[ROUTER_ADVERTISEMENT_LEN..];
+    pio[0] = 3; // type
+    pio[1] = (PREFIX_INFORMATION_OPTION_LEN / 8) as u8; // length in units of 8 bytes
+    pio[2] = 64; // prefix length
+
+    // L=0 - the prefix isn't on-link, so the UE sends everything to us (TS29.061, 11.2.1.3.4);
+    // A=1 - the UE autoconfigures its address from the prefix.
+    pio[3] = 0b0_1_000000;
+    pio[4..8].fill(0xff); // infinite valid lifetime
+    pio[8..12].fill(0xff); // infinite preferred lifetime
+    pio[16..24].copy_from_slice(prefix);
+
+    let checksum = icmpv6_checksum(buf).to_be_bytes();
+    buf[IPV6_HEADER_LEN + 2] = checksum[0];
+    buf[IPV6_HEADER_LEN + 3] = checksum[1];
+
+    ROUTER_ADVERTISEMENT_PACKET_LEN
+}
+
+// The ICMPv6 checksum of an IPv6 packet, covering the pseudo-header and the ICMPv6 message - see RFC8200, 8.1.
+fn icmpv6_checksum(packet: &[u8]) -> u16 {
+    let icmpv6 = &packet[IPV6_HEADER_LEN..];
+    let mut sum: u32 = 0;
+    let mut add = |bytes: &[u8]| {
+        for chunk in bytes.chunks(2) {
+            let hi = chunk[0] as u32;
+            let lo = chunk.get(1).copied().unwrap_or(0) as u32;
+            sum += (hi << 8) | lo;
+        }
+    };
+
+    // Pseudo-header - source and destination addresses, upper-layer packet length and next header.
+    add(&packet[8..40]);
+    add(&(icmpv6.len() as u32).to_be_bytes());
+    add(&[0, 0, 0, NEXT_HEADER_ICMPV6]);
+    add(icmpv6);
+
+    while sum >> 16 != 0 {
+        sum = (sum & 0xffff) + (sum >> 16);
+    }
+    !(sum as u16)
+}
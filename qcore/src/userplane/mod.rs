@@ -1,11 +1,18 @@
 mod downlink_pipeline;
+mod ethernet;
+mod ipv6;
 mod packet_processor;
+mod qos_classifier;
+mod rate_limiter;
 mod uplink_pipeline;
 
 use downlink_pipeline::{DownlinkForwardingTable, DownlinkPipeline};
 use uplink_pipeline::{UplinkForwardingTable, UplinkPipeline};
 
-pub use packet_processor::PacketProcessor;
+pub use downlink_pipeline::{DownlinkBufferLimits, DrbTunnel};
+pub use packet_processor::{PacketProcessor, SessionIndex};
+pub use qos_classifier::QosClassifier;
+pub use rate_limiter::{AmbrBuckets, RateLimiter};
 
 const GTP_BASE_HEADER_LEN: usize = 8;
 const GTP_EXTENDED_HEADER_LEN: usize = 12;
@@ -13,12 +20,23 @@ const GTP_EXT_HEADER_LEN_NRUP_DL_USER_DATA: usize = 8;
 const PDCP_HEADER_LEN: usize = 2;
 const SDAP_HEADER_LEN: usize = 1;
 const IPV4_HEADER_LEN: usize = 20;
+const IPV6_HEADER_LEN: usize = 40;
 
-// Downlink direction - inner packet starts at offset 22
-const DOWNLINK_INNER_PACKET_OFFSET: usize =
-    GTP_EXTENDED_HEADER_LEN + GTP_EXT_HEADER_LEN_NRUP_DL_USER_DATA + PDCP_HEADER_LEN;
+// Downlink direction - inner packet starts at offset 23, leaving room for an SDAP header, which not every DRB has.
+const DOWNLINK_INNER_PACKET_OFFSET: usize = GTP_EXTENDED_HEADER_LEN
+    + GTP_EXT_HEADER_LEN_NRUP_DL_USER_DATA
+    + PDCP_HEADER_LEN
+    + SDAP_HEADER_LEN;
 
 const GTP_MESSAGE_TYPE_GPDU: u8 = 255; // TS29.281, table 6.1-1
 const GTPU_PORT: u16 = 2152; // TS29.281
 
-const MAX_UES: usize = 254;
+// Each data network has its own pool of host numbers, and its own block of 256 slots in the forwarding tables, so
+// that a session's slot is its data network index followed by its host number.
+const MAX_DATA_NETWORKS: usize = 16;
+pub const MAX_SESSIONS_PER_DATA_NETWORK: usize = 254;
+const MAX_PDU_SESSIONS: usize = MAX_DATA_NETWORKS * 256;
+
+fn forwarding_table_index(data_network_idx: usize, host: u8) -> usize {
+    (data_network_idx << 8) | host as usize
+}
@@ -1,73 +1,182 @@
 #![allow(clippy::unusual_byte_groupings)]
 use super::downlink_pipeline::DownlinkCounters;
+use super::ethernet::MacTable;
+use super::ipv6::{ROUTER_ADVERTISEMENT_PACKET_LEN, write_router_advertisement};
+use super::qos_classifier::QosClassifier;
+use super::rate_limiter::{AmbrBuckets, RateLimiter};
 use super::uplink_pipeline::UplinkCounters;
 use super::{
-    DownlinkForwardingTable, DownlinkPipeline, GTPU_PORT, MAX_UES, UplinkForwardingTable,
-    UplinkPipeline,
+    DOWNLINK_INNER_PACKET_OFFSET, DownlinkBufferLimits, DownlinkForwardingTable, DownlinkPipeline,
+    DrbTunnel, GTPU_PORT, MAX_DATA_NETWORKS, MAX_SESSIONS_PER_DATA_NETWORK, UplinkForwardingTable,
+    UplinkPipeline, forwarding_table_index,
+};
+use crate::nas::PduSessionType;
+use crate::{DataNetworkConfig, SessionAmbr, UserplaneSession};
+use anyhow::{Context, Result, anyhow, bail, ensure};
+use async_channel::Sender;
+use async_std::{
+    fs::File,
+    net::{IpAddr, UdpSocket},
+    sync::Mutex,
 };
-use crate::UserplaneSession;
-use anyhow::{Context, Result, bail, ensure};
-use async_std::{fs::File, net::IpAddr, sync::Mutex};
 use async_tun::{Tun, TunBuilder};
 use atomic_counter::AtomicCounter;
 use index_pool::IndexPool;
 use rand::RngCore;
 use slog::{Logger, info, warn};
 use socket2::{Domain, Protocol, Socket, Type};
-use std::net::{Ipv4Addr, SocketAddr};
+use std::collections::HashMap;
+use std::net::{Ipv4Addr, Ipv6Addr, SocketAddr};
 use std::os::fd::{AsRawFd, FromRawFd};
 use std::sync::Arc;
-use xxap::{GtpTeid, GtpTunnel};
+use xxap::GtpTeid;
+
+/// Which index a new userplane session should get on its data network.  The index is the host number of the
+/// session's IPv4 address and the last byte of its IPv6 prefix.
+pub enum SessionIndex {
+    /// Any free index.
+    Any,
+    /// This index if it is free, and otherwise any free index.
+    Preferred(usize),
+    /// This index, which is reserved for a SIM with a static address.
+    Static(usize),
+}
 
 #[derive(Clone)]
 pub struct PacketProcessor {
-    index_pool: Arc<Mutex<IndexPool>>,
+    // A pool of host numbers for each data network.
+    index_pools: Arc<Vec<Mutex<IndexPool>>>,
+
+    // Host numbers kept out of each data network's pool for SIMs with static addresses, and whether each is in
+    // use.
+    static_indices: Arc<Vec<Mutex<HashMap<usize, bool>>>>,
     downlink_forwarding_table: DownlinkForwardingTable,
     uplink_forwarding_table: UplinkForwardingTable,
-    ue_subnet: Ipv4Addr,
+    ue_subnets: Vec<Ipv4Addr>,
+    ue_ipv6_prefixes: Vec<Option<Ipv6Addr>>,
+    mac_tables: Vec<Option<MacTable>>,
+    f1u_socket: Arc<UdpSocket>,
+    downlink_buffer_limits: DownlinkBufferLimits,
+
+    // Whether DRBs have an SDAP header in the downlink.
+    sdap_header_dl: bool,
 }
 
 impl PacketProcessor {
+    /// Create the packet processor.  The static indices of each data network are reserved for SIMs with static
+    /// addresses.  When a downlink packet arrives for an idle UE, it buffers it within downlink_buffer_limits and
+    /// sends the index of the UE's userplane session on paging_trigger.
     pub async fn new(
         local_ip: IpAddr,
-        n6_tun_dev_name: &str,
-        ue_subnet: Ipv4Addr,
+        data_networks: &[DataNetworkConfig],
+        static_indices: &[Vec<usize>],
+        sdap_header_dl: bool,
+        downlink_buffer_limits: DownlinkBufferLimits,
+        paging_trigger: Sender<usize>,
         logger: &Logger,
     ) -> Result<Self> {
+        ensure!(
+            data_networks.len() <= MAX_DATA_NETWORKS,
+            "At most {MAX_DATA_NETWORKS} data networks are supported"
+        );
+
         // Create the packet source/sinks.
         let f1u_socket = create_f1u_socket(local_ip, logger)?;
-        let f1u_socket_clone = f1u_socket.try_clone()?;
-        let n6_tun = open_n6_tun_device(n6_tun_dev_name, logger).await?;
-        let n6_tun_clone = unsafe { File::from_raw_fd(n6_tun.as_raw_fd()) };
+        let mut n6_tuns = vec![];
+        let mut n6_taps = vec![];
+        for data_network in data_networks {
+            n6_tuns.push(open_n6_device(&data_network.n6_tun_name, false, logger).await?);
+            n6_taps.push(match &data_network.n6_tap_name {
+                Some(n6_tap_name) => Some(open_n6_device(n6_tap_name, true, logger).await?),
+                None => None,
+            });
+        }
+
+        // Each data network with a tap device learns the MAC addresses behind its Ethernet PDU sessions.
+        let mac_tables: Vec<Option<MacTable>> = n6_taps
+            .iter()
+            .map(|x| x.as_ref().map(|_| MacTable::default()))
+            .collect();
 
         // Initialize the forwarding tables.
         let downlink_forwarding_table = DownlinkForwardingTable::new();
         let uplink_forwarding_table = UplinkForwardingTable::new();
 
-        // Start the downlink pipeline (N6 -> F1U).
+        // The uplink pipeline writes to every N6 tun device, choosing the one that belongs to the
+        // session's data network.
+        let n6_tun_clones = n6_tuns
+            .iter()
+            .map(|n6_tun| unsafe { File::from_raw_fd(n6_tun.as_raw_fd()) })
+            .collect();
+        // Likewise every tap device, along with its MAC table.
+        let n6_tap_clones = n6_taps
+            .iter()
+            .zip(mac_tables.iter())
+            .map(|(n6_tap, mac_table)| {
+                let n6_tap = n6_tap.as_ref()?;
+                let n6_tap_clone = unsafe { File::from_raw_fd(n6_tap.as_raw_fd()) };
+                Some((n6_tap_clone, mac_table.clone()?))
+            })
+            .collect();
+
+        // Start a downlink pipeline (N6 -> F1U) per tun and tap device.
         let downlink_counters = Arc::new(DownlinkCounters::default());
-        let downlink_pipeline = DownlinkPipeline::new(
-            f1u_socket.into(),
-            n6_tun,
-            downlink_forwarding_table.clone(),
-            downlink_counters.clone(),
-        );
-        let _downlink_task = downlink_pipeline.run();
+        let mut n6_devices = vec![];
+        for (data_network_idx, n6_tun) in n6_tuns.into_iter().enumerate() {
+            n6_devices.push((data_network_idx, n6_tun, None));
+        }
+        for (data_network_idx, n6_tap) in n6_taps.into_iter().enumerate() {
+            if let Some(n6_tap) = n6_tap {
+                let mac_table = mac_tables[data_network_idx].clone();
+                n6_devices.push((data_network_idx, n6_tap, mac_table));
+            }
+        }
+        for (data_network_idx, n6_device, mac_table) in n6_devices {
+            let downlink_pipeline = DownlinkPipeline::new(
+                f1u_socket.try_clone()?.into(),
+                n6_device,
+                data_network_idx,
+                mac_table,
+                downlink_forwarding_table.clone(),
+                downlink_buffer_limits,
+                downlink_counters.clone(),
+                paging_trigger.clone(),
+            );
+            let _downlink_task = downlink_pipeline.run();
+        }
+
+        // Keep a handle on the F1U socket for flushing downlink buffers.
+        let f1u_socket_clone = Arc::new(f1u_socket.try_clone()?.into());
 
         // Start the uplink pipeline (F1U -> N6).
         let uplink_counters = Arc::new(UplinkCounters::default());
         let uplink_pipeline = UplinkPipeline::new(
-            f1u_socket_clone.into(),
-            n6_tun_clone,
+            f1u_socket.into(),
+            n6_tun_clones,
+            n6_tap_clones,
             uplink_forwarding_table.clone(),
+            downlink_forwarding_table.clone(),
             uplink_counters.clone(),
         );
         let _uplink_task = uplink_pipeline.run(logger.clone());
 
-        let mut index_pool = IndexPool::new();
-        // Take the 0 slot, so that the first UE gets an IP address ending in .1.
-        let _ = index_pool.request_id(0);
-        let index_pool = Arc::new(Mutex::new(index_pool));
+        let index_pools = static_indices
+            .iter()
+            .map(|static_indices| {
+                let mut index_pool = IndexPool::new();
+                // Take the 0 slot, so that the first UE gets an IP address ending in .1.
+                let _ = index_pool.request_id(0);
+                for idx in static_indices {
+                    let _ = index_pool.request_id(*idx);
+                }
+                Mutex::new(index_pool)
+            })
+            .collect();
+        let index_pools = Arc::new(index_pools);
+        let static_indices = static_indices
+            .iter()
+            .map(|x| Mutex::new(x.iter().map(|idx| (*idx, false)).collect()))
+            .collect();
 
         // Spawn the stats task
         let _stats_task = async_std::task::spawn(dump_stats(
@@ -77,77 +186,258 @@ impl PacketProcessor {
         ));
 
         Ok(PacketProcessor {
-            index_pool,
+            index_pools,
+            static_indices: Arc::new(static_indices),
             downlink_forwarding_table,
             uplink_forwarding_table,
-            ue_subnet,
+            ue_subnets: data_networks.iter().map(|x| x.ue_subnet).collect(),
+            ue_ipv6_prefixes: data_networks.iter().map(|x| x.ue_ipv6_prefix).collect(),
+            mac_tables,
+            f1u_socket: f1u_socket_clone,
+            downlink_buffer_limits,
+            sdap_header_dl,
         })
     }
 
-    pub async fn reserve_userplane_session(&self, _logger: &Logger) -> Result<UserplaneSession> {
-        let idx = self.index_pool.lock().await.new_id();
-        ensure!(idx < MAX_UES, "No more slots available");
+    /// Reserve a userplane session of the given PDU session type on the data network with the given index in the
+    /// data network table.  An IP session gets an IPv4 address, an IPv6 prefix or both.  Its traffic is limited to
+    /// the session AMBR and, if supplied, the token buckets of the UE's AMBR.
+    pub async fn reserve_userplane_session(
+        &self,
+        data_network_idx: usize,
+        pdu_session_type: u8,
+        session_index: SessionIndex,
+        session_ambr: &SessionAmbr,
+        ue_ambr_buckets: Option<&AmbrBuckets>,
+        _logger: &Logger,
+    ) -> Result<UserplaneSession> {
+        let ipv4 = matches!(
+            pdu_session_type,
+            PduSessionType::IPV4 | PduSessionType::IPV4V6
+        );
+        let ipv6 = matches!(
+            pdu_session_type,
+            PduSessionType::IPV6 | PduSessionType::IPV4V6
+        );
+        let ethernet = pdu_session_type == PduSessionType::ETHERNET;
+        let ue_subnet = self
+            .ue_subnets
+            .get(data_network_idx)
+            .ok_or(anyhow!("No data network with index {data_network_idx}"))?;
+        let ue_ipv6_prefix = self.ue_ipv6_prefixes[data_network_idx];
+        ensure!(
+            !ipv6 || ue_ipv6_prefix.is_some(),
+            "No IPv6 prefix configured for data network {data_network_idx}"
+        );
+        ensure!(
+            !ethernet || self.mac_tables[data_network_idx].is_some(),
+            "No tap device configured for data network {data_network_idx}"
+        );
+        let idx = match session_index {
+            SessionIndex::Any => self.index_pools[data_network_idx].lock().await.new_id(),
+            SessionIndex::Preferred(idx) => {
+                let mut index_pool = self.index_pools[data_network_idx].lock().await;
+                match index_pool.request_id(idx) {
+                    Ok(()) => idx,
+                    Err(_) => index_pool.new_id(),
+                }
+            }
+            SessionIndex::Static(idx) => {
+                let mut static_indices = self.static_indices[data_network_idx].lock().await;
+                let Some(in_use) = static_indices.get_mut(&idx) else {
+                    bail!("Index {idx} is not reserved for a static address");
+                };
+                ensure!(
+                    !*in_use,
+                    "Static address with index {idx} is already in use"
+                );
+                *in_use = true;
+                idx
+            }
+        };
+        if idx >= MAX_SESSIONS_PER_DATA_NETWORK {
+            let _ = self.index_pools[data_network_idx]
+                .lock()
+                .await
+                .return_id(idx);
+            bail!("No more PDU session slots available on data network {data_network_idx}");
+        }
         let idx = idx as u8;
 
-        // Randomize the top part of the TEID.  It is meant to be unpredictable.
-        let mut teid = (idx as u32).to_be_bytes();
-        rand::rng().fill_bytes(&mut teid[0..3]);
-        teid[3] = idx as u8;
+        // Randomize the top part of the TEID.  It is meant to be unpredictable.  The bottom part is the
+        // session's slot in the forwarding tables.
+        let mut teid = (forwarding_table_index(data_network_idx, idx) as u32).to_be_bytes();
+        rand::rng().fill_bytes(&mut teid[0..2]);
 
-        // Generate a UE IP.  We currently hardcode assumptions of 1 PDU session
-        // per UE, and max 254 UEs.
-        let mut ue_addr_octets = self.ue_subnet.octets().clone();
-        ue_addr_octets[3] = idx;
-        let ue_ipv4_addr = Ipv4Addr::from(ue_addr_octets);
+        // Generate a UE IP.  Each PDU session on the data network has its own index, and so its own
+        // address, which limits us to 254 PDU sessions on each data network.
+        let ue_ipv4_addr = ipv4.then(|| {
+            let mut ue_addr_octets = ue_subnet.octets();
+            ue_addr_octets[3] = idx;
+            Ipv4Addr::from(ue_addr_octets)
+        });
         //info!(self.logger, "Allocated UE IP address {:?}", ue_ipv4_addr);
 
+        // Likewise, the session's /64 prefix is the data network's /56 with the index in its last byte.
+        let ue_ipv6_prefix = ue_ipv6_prefix.filter(|_| ipv6).map(|prefix| {
+            let mut prefix_octets = prefix.octets();
+            prefix_octets[7] = idx;
+            Ipv6Addr::from(prefix_octets)
+        });
+
+        // Any interface identifier will do for the UE's link-local address, as long as it isn't the same as ours
+        // (::1), so we pick a random one with a nonzero first byte.
+        let mut ue_ipv6_interface_identifier = [0u8; 8];
+        rand::rng().fill_bytes(&mut ue_ipv6_interface_identifier);
+        ue_ipv6_interface_identifier[0] |= 0x80;
+
+        // Police the session's traffic in each direction against its session AMBR and its UE's AMBR.
+        let session_ambr_buckets =
+            AmbrBuckets::new(session_ambr.downlink_kbps, session_ambr.uplink_kbps);
+        let uplink_rate_limiter = RateLimiter::new(
+            session_ambr_buckets.uplink,
+            ue_ambr_buckets.map(|x| x.uplink.clone()),
+        );
+        let downlink_rate_limiter = RateLimiter::new(
+            session_ambr_buckets.downlink,
+            ue_ambr_buckets.map(|x| x.downlink.clone()),
+        );
+
         // Create the uplink forwarding rule.
         self.uplink_forwarding_table
-            .add_rule(ue_ipv4_addr, teid)
+            .add_rule(
+                teid,
+                data_network_idx,
+                ue_ipv6_prefix.map(|x| {
+                    let mut prefix = [0u8; 8];
+                    prefix.copy_from_slice(&x.octets()[0..8]);
+                    prefix
+                }),
+                ethernet,
+                uplink_rate_limiter.clone(),
+            )
             .await;
 
         Ok(UserplaneSession {
             uplink_gtp_teid: GtpTeid(teid),
-            ue_ip_addr: IpAddr::V4(ue_ipv4_addr),
+            ethernet,
+            ue_ipv4_addr,
+            ue_ipv6_prefix,
+            ue_ipv6_interface_identifier,
+            uplink_rate_limiter,
+            downlink_rate_limiter,
+            qos_classifier: QosClassifier::default(),
             qfi: 0,
         })
     }
 
+    /// Start forwarding the session's downlink packets down the tunnels of its DRBs, the first of which is its
+    /// default DRB.
     pub async fn commit_userplane_session(
         &self,
         session: &UserplaneSession,
-        remote_tunnel_info: GtpTunnel,
+        drb_tunnels: Vec<DrbTunnel>,
         logger: &Logger,
     ) -> Result<()> {
-        // TODO: Once we implement downlink buffering, could split this into a command that starts buffering downlink packets, and
-        // then a second one that flushes the buffer (after the RRC Reconfiguration Complete).  Otherwise, the UE could
-        // receive a packet before it has confirmed setup of the new DRB.
-        let IpAddr::V4(ue_ipv4) = session.ue_ip_addr else {
-            bail!("IPv6 not implemented");
-        };
-        info!(
-            logger,
-            "Set up userplane session {}, remote {}-{}",
-            session,
-            remote_tunnel_info.transport_layer_address,
-            remote_tunnel_info.gtp_teid,
-        );
+        // TODO: For a new PDU session, we could buffer downlink packets until the RRC Reconfiguration Complete, as we
+        // do for an idle UE.  Otherwise, the UE could receive a packet before it has confirmed setup of the new DRB.
+        for drb_tunnel in &drb_tunnels {
+            info!(
+                logger,
+                "Set up userplane session {}, QFIs {:?}, remote {}-{}",
+                session,
+                drb_tunnel.qfis,
+                drb_tunnel.remote_tunnel_info.transport_layer_address,
+                drb_tunnel.remote_tunnel_info.gtp_teid,
+            );
+        }
+
+        let flushed = self
+            .downlink_forwarding_table
+            .add_rule(
+                session,
+                drb_tunnels,
+                self.sdap_header_dl,
+                self.downlink_buffer_limits.max_age,
+                &self.f1u_socket,
+            )
+            .await?;
+        if flushed > 0 {
+            info!(logger, "Sent {flushed} buffered downlink packets");
+        }
+
+        // TS29.061, 11.2.1.3.4: advertise the session's IPv6 prefix straight away, rather than waiting for the UE
+        // to solicit it.
+        if let Some(prefix) = session.ue_ipv6_prefix {
+            let mut prefix_octets = [0u8; 8];
+            prefix_octets.copy_from_slice(&prefix.octets()[0..8]);
+            let mut ra = [0u8; DOWNLINK_INNER_PACKET_OFFSET + ROUTER_ADVERTISEMENT_PACKET_LEN];
+            let ra_len = write_router_advertisement(
+                &mut ra[DOWNLINK_INNER_PACKET_OFFSET..],
+                None,
+                &prefix_octets,
+            );
+            self.downlink_forwarding_table
+                .send(session.index(), &mut ra, ra_len, &self.f1u_socket)
+                .await?;
+        }
 
+        Ok(())
+    }
+
+    /// Send the session's downlink packets on the DRBs that their QoS flows are now mapped to, after a PDU session
+    /// modification, given as (DRB ID, QFIs) pairs.  DRBs that aren't in the list have been released.
+    pub async fn remap_qos_flows(
+        &self,
+        session: &UserplaneSession,
+        drb_qfis: Vec<(u8, Vec<u8>)>,
+        logger: &Logger,
+    ) {
+        for (drb_id, qfis) in &drb_qfis {
+            info!(
+                logger,
+                "Userplane session {} DRB {} QFIs {:?}", session, drb_id, qfis
+            );
+        }
         self.downlink_forwarding_table
-            .add_rule(remote_tunnel_info, ue_ipv4)
+            .remap_qos_flows(session, drb_qfis)
             .await;
+    }
 
-        Ok(())
+    /// Stop forwarding downlink packets for a session whose UE has gone idle, and buffer them instead.  The
+    /// session keeps its UE IP address, IPv6 prefix and uplink TEID, and commit_userplane_session() reactivates it with
+    /// the UE's new DU tunnel.
+    pub async fn deactivate_userplane_session(&self, session: &UserplaneSession, logger: &Logger) {
+        self.downlink_forwarding_table.buffer_rule(session).await;
+        info!(logger, "Deactivated userplane session {}", session);
     }
 
     pub async fn delete_userplane_session(&self, session: &UserplaneSession, logger: &Logger) {
-        if let IpAddr::V4(ue_ipv4) = session.ue_ip_addr {
-            self.downlink_forwarding_table.remove_rule(ue_ipv4).await;
-        };
+        let data_network_idx = session.data_network_idx();
+        self.downlink_forwarding_table.remove_rule(session).await;
+        if let Some(mac_table) = &self.mac_tables[data_network_idx] {
+            mac_table.forget_session(session.index()).await;
+        }
         self.uplink_forwarding_table
             .remove_rule(session.uplink_gtp_teid.0)
             .await;
 
+        // Free up the session's index for a future PDU session on the data network to reuse.
+        let idx = session.host_number();
+        match self.static_indices[data_network_idx]
+            .lock()
+            .await
+            .get_mut(&idx)
+        {
+            Some(in_use) => *in_use = false,
+            None => {
+                let _ = self.index_pools[data_network_idx]
+                    .lock()
+                    .await
+                    .return_id(idx);
+            }
+        }
+
         info!(logger, "Deleted userplane session {}", session);
     }
 }
@@ -170,24 +460,26 @@ fn create_f1u_socket(local_ip: IpAddr, logger: &Logger) -> Result<std::net::UdpS
     Ok(gtpu_socket.into())
 }
 
+// Open a tun device, for IP packets, or a tap device, for Ethernet frames.
 // TODO: we probably don't need TunBuilder and can just open this synchronously
-async fn open_n6_tun_device(tun_device_name: &str, logger: &Logger) -> Result<Tun> {
+async fn open_n6_device(device_name: &str, tap: bool, logger: &Logger) -> Result<Tun> {
+    let (kind, other_kind) = if tap { ("tap", "tun") } else { ("tun", "tap") };
     match TunBuilder::new()
-        .name(tun_device_name)
-        .tap(false)
+        .name(device_name)
+        .tap(tap)
         .packet_info(false)
         .try_build()
         .await
     {
         Ok(tun) => {
-            info!(logger, "Opened tun device '{tun_device_name}' for N6");
+            info!(logger, "Opened {kind} device '{device_name}' for N6");
             Ok(tun)
         }
         Err(e) => bail!(
-            "Failed to open TUN device '{tun_device_name}' - have you followed the instructions in the QCore readme?  
+            "Failed to open {kind} device '{device_name}' - have you followed the instructions in the QCore readme?  
 Device open eError code: {e}
  EPERM: may indicate that the device doesn't exist or is not owned by the current user
- EINVAL: may indicate that the device is actually a tap device rather than a tun device
+ EINVAL: may indicate that the device is actually a {other_kind} device rather than a {kind} device
  EBUSY: another process, e.g. another qcore instance, has the device open"
         ),
     }
@@ -200,7 +492,11 @@ async fn dump_stats(logger: Logger, dl: Arc<DownlinkCounters>, ul: Arc<UplinkCou
     let mut last_dl = [0usize; DL_NUM_COUNTERS];
     let mut last_ul = [0usize; UL_NUM_COUNTERS];
     const FIRST_DL_WARN_IDX: usize = DL_DROP_TOO_SHORT;
+    // Counters from the first warning index onwards that don't count drops.
+    const DL_NON_DROP_IDXS: [usize; 2] = [DL_BUFFERED_PKTS, DL_FLOODED_FRAMES];
     const FIRST_UL_WARN_IDX: usize = UL_DROP_TOO_SHORT;
+    // Likewise for the uplink.
+    const UL_NON_DROP_IDXS: [usize; 1] = [UL_ROUTER_SOLICITATIONS];
 
     loop {
         async_std::task::sleep(std::time::Duration::new(5, 0)).await;
@@ -225,14 +521,14 @@ async fn dump_stats(logger: Logger, dl: Arc<DownlinkCounters>, ul: Arc<UplinkCou
 
         let mut dl_warn_needed = false;
         for idx in FIRST_DL_WARN_IDX..DL_NUM_COUNTERS {
-            if last_dl[idx] != dl[idx].get() {
+            if last_dl[idx] != dl[idx].get() && !DL_NON_DROP_IDXS.contains(&idx) {
                 dl_warn_needed = true;
             }
             last_dl[idx] = dl[idx].get();
         }
         let mut ul_warn_needed = false;
         for idx in FIRST_UL_WARN_IDX..UL_NUM_COUNTERS {
-            if last_ul[idx] != ul[idx].get() {
+            if last_ul[idx] != ul[idx].get() && !UL_NON_DROP_IDXS.contains(&idx) {
                 ul_warn_needed = true;
             }
             last_ul[idx] = ul[idx].get();
@@ -241,23 +537,30 @@ async fn dump_stats(logger: Logger, dl: Arc<DownlinkCounters>, ul: Arc<UplinkCou
         if dl_warn_needed {
             warn!(
                 &logger,
-                "DL DROPS too_short={} bad_ip={}",
+                "DL DROPS too_short={} ip_type={} bad_ip={} no_eth_session={} buffer_full={} buffer_expired={} ambr={}",
                 last_dl[DL_DROP_TOO_SHORT],
-                last_dl[DL_DROP_UNKNOWN_IP_1] + last_dl[DL_DROP_UNKNOWN_IP_2]
+                last_dl[DL_DROP_NOT_IP],
+                last_dl[DL_DROP_UNKNOWN_IP_1] + last_dl[DL_DROP_UNKNOWN_IP_2],
+                last_dl[DL_DROP_NO_ETHERNET_SESSION],
+                last_dl[DL_DROP_BUFFER_FULL],
+                last_dl[DL_DROP_BUFFER_EXPIRED],
+                last_dl[DL_DROP_AMBR]
             );
         }
 
         if ul_warn_needed {
             warn!(
                 &logger,
-                "UL DROPS too_short={} gtp_type={} too_short_ext={} pdcp_ctrl={} sdap_ctrl={} ip_type={} bad_teid={}",
+                "UL DROPS too_short={} gtp_type={} too_short_ext={} pdcp_ctrl={} sdap_ctrl={} ip_type={} bad_teid={} bad_mac={} ambr={}",
                 last_ul[UL_DROP_TOO_SHORT],
                 last_ul[UL_DROP_GTP_MESSAGE_TYPE],
                 last_ul[UL_DROP_TOO_SHORT_EXT],
                 last_ul[UL_DROP_PDCP_CONTROL],
                 last_ul[UL_DROP_SDAP_CONTROL],
-                last_ul[UL_DROP_NOT_IPV4],
-                last_ul[UL_DROP_UNKNOWN_TEID_1] + last_ul[UL_DROP_UNKNOWN_TEID_2]
+                last_ul[UL_DROP_NOT_IP],
+                last_ul[UL_DROP_UNKNOWN_TEID_1] + last_ul[UL_DROP_UNKNOWN_TEID_2],
+                last_ul[UL_DROP_BAD_MAC],
+                last_ul[UL_DROP_AMBR]
             );
         }
     }
@@ -0,0 +1,309 @@
+//! Classification of a PDU session's downlink packets onto its QoS flows, using the packet filters of its QoS
+//! rules - see TS23.501, 5.7.1.5.  The UE classifies uplink packets itself, and tells us the QFI in the SDAP header.
+use crate::{
+    IPV4_LOCAL_ADDRESS, IPV4_REMOTE_ADDRESS, IPV6_LOCAL_ADDRESS_PREFIX_LENGTH,
+    IPV6_REMOTE_ADDRESS_PREFIX_LENGTH, LOCAL_PORT_RANGE, MATCH_ALL, PACKET_FILTER_DOWNLINK,
+    PROTOCOL_IDENTIFIER_NEXT_HEADER, QosRule, REMOTE_PORT_RANGE, SINGLE_LOCAL_PORT,
+    SINGLE_REMOTE_PORT,
+};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use std::sync::{Arc, Mutex};
+
+// IP protocols whose packets start with a source and destination port.
+const PROTOCOLS_WITH_PORTS: [u8; 3] = [6, 17, 132]; // TCP, UDP, SCTP
+
+/// The QoS rules of a PDU session, in order of precedence.  Clones share the same rules.
+#[derive(Clone, Debug, Default)]
+pub struct QosClassifier(Arc<Mutex<Vec<QosRule>>>);
+
+impl QosClassifier {
+    pub fn set_qos_rules(&self, qos_rules: &[QosRule]) {
+        let mut qos_rules = qos_rules.to_vec();
+        qos_rules.sort_by_key(|x| x.precedence);
+        *self.0.lock().unwrap() = qos_rules;
+    }
+
+    /// The QFI of the highest precedence QoS rule with a downlink packet filter that matches the packet, if any.
+    pub fn classify_downlink(&self, packet: &[u8]) -> Option<u8> {
+        let fields = PacketFields::parse(packet);
+        self.0
+            .lock()
+            .unwrap()
+            .iter()
+            .find(|rule| {
+                rule.packet_filters.iter().any(|filter| {
+                    filter.direction & PACKET_FILTER_DOWNLINK != 0
+                        && matches(&filter.contents, fields.as_ref())
+                })
+            })
+            .map(|rule| rule.qfi)
+    }
+}
+
+// The fields of a downlink IP packet that packet filters look at.  The remote end is the source, and the local
+// end, the UE, is the destination.
+struct PacketFields {
+    remote_addr: IpAddr,
+    local_addr: IpAddr,
+    protocol: u8,
+    ports: Option<(u16, u16)>,
+}
+
+impl PacketFields {
+    fn parse(packet: &[u8]) -> Option<Self> {
+        let (remote_addr, local_addr, protocol, header_len) = match packet.first()? >> 4 {
+            4 if packet.len() >= 20 => (
+                IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(&packet[12..16]).ok()?)),
+                IpAddr::V4(Ipv4Addr::from(<[u8; 4]>::try_from(&packet[16..20]).ok()?)),
+                packet[9],
+                (packet[0] & 0x0f) as usize * 4,
+            ),
+            // We don't follow IPv6 extension headers.
+            6 if packet.len() >= 40 => (
+                IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(&packet[8..24]).ok()?)),
+                IpAddr::V6(Ipv6Addr::from(<[u8; 16]>::try_from(&packet[24..40]).ok()?)),
+                packet[6],
+                40,
+            ),
+            _ => return None,
+        };
+        let ports = match packet.get(header_len..header_len + 4) {
+            Some(x) if PROTOCOLS_WITH_PORTS.contains(&protocol) => Some((
+                u16::from_be_bytes([x[0], x[1]]),
+                u16::from_be_bytes([x[2], x[3]]),
+            )),
+            _ => None,
+        };
+        Some(PacketFields {
+            remote_addr,
+            local_addr,
+            protocol,
+            ports,
+        })
+    }
+
+    fn remote_port(&self) -> Option<u16> {
+        self.ports.map(|(src, _)| src)
+    }
+
+    fn local_port(&self) -> Option<u16> {
+        self.ports.map(|(_, dst)| dst)
+    }
+}
+
+// Whether a packet matches all of the components of a packet filter - TS24.501, table 9.11.4.13.1.  A packet that
+// isn't IP only matches a match-all packet filter, and a packet filter with a component that we don't support
+// matches nothing.
+fn matches(mut contents: &[u8], fields: Option<&PacketFields>) -> bool {
+    while let [component, rest @ ..] = contents {
+        if *component == MATCH_ALL {
+            contents = rest;
+            continue;
+        }
+        let Some(fields) = fields else {
+            return false;
+        };
+        let (matched, len) = match (*component, rest) {
+            (IPV4_REMOTE_ADDRESS, _) if rest.len() >= 8 => {
+                (ipv4_matches(&fields.remote_addr, &rest[0..8]), 8)
+            }
+            (IPV4_LOCAL_ADDRESS, _) if rest.len() >= 8 => {
+                (ipv4_matches(&fields.local_addr, &rest[0..8]), 8)
+            }
+            (IPV6_REMOTE_ADDRESS_PREFIX_LENGTH, _) if rest.len() >= 17 => {
+                (ipv6_matches(&fields.remote_addr, &rest[0..17]), 17)
+            }
+            (IPV6_LOCAL_ADDRESS_PREFIX_LENGTH, _) if rest.len() >= 17 => {
+                (ipv6_matches(&fields.local_addr, &rest[0..17]), 17)
+            }
+            (PROTOCOL_IDENTIFIER_NEXT_HEADER, [protocol, ..]) => (fields.protocol == *protocol, 1),
+            (SINGLE_LOCAL_PORT, [hi, lo, ..]) => {
+                let port = u16::from_be_bytes([*hi, *lo]);
+                (fields.local_port() == Some(port), 2)
+            }
+            (SINGLE_REMOTE_PORT, [hi, lo, ..]) => {
+                let port = u16::from_be_bytes([*hi, *lo]);
+                (fields.remote_port() == Some(port), 2)
+            }
+            (LOCAL_PORT_RANGE, [low_hi, low_lo, high_hi, high_lo, ..]) => {
+                let range = u16::from_be_bytes([*low_hi, *low_lo])
+                    ..=u16::from_be_bytes([*high_hi, *high_lo]);
+                (fields.local_port().is_some_and(|x| range.contains(&x)), 4)
+            }
+            (REMOTE_PORT_RANGE, [low_hi, low_lo, high_hi, high_lo, ..]) => {
+                let range = u16::from_be_bytes([*low_hi, *low_lo])
+                    ..=u16::from_be_bytes([*high_hi, *high_lo]);
+                (fields.remote_port().is_some_and(|x| range.contains(&x)), 4)
+            }
+            _ => return false,
+        };
+        if !matched {
+            return false;
+        }
+        contents = &rest[len..];
+    }
+    true
+}
+
+// Match an address against an IPv4 address and mask.
+fn ipv4_matches(addr: &IpAddr, addr_and_mask: &[u8]) -> bool {
+    let IpAddr::V4(addr) = addr else {
+        return false;
+    };
+    addr.octets()
+        .iter()
+        .zip(&addr_and_mask[0..4])
+        .zip(&addr_and_mask[4..8])
+        .all(|((x, filter), mask)| x & mask == filter & mask)
+}
+
+// Match an address against an IPv6 address and prefix length.
+fn ipv6_matches(addr: &IpAddr, addr_and_prefix_len: &[u8]) -> bool {
+    let IpAddr::V6(addr) = addr else {
+        return false;
+    };
+    let filter = u128::from_be_bytes(addr_and_prefix_len[0..16].try_into().unwrap_or_default());
+    let prefix_len = addr_and_prefix_len[16].min(128) as u32;
+    let mask = u128::MAX.checked_shl(128 - prefix_len).unwrap_or(0);
+    u128::from(*addr) & mask == filter & mask
+}
+
+#[cfg(test)]
+mod tests {
+    use super::*;
+    use crate::PacketFilter;
+
+    const UDP: u8 = 17;
+
+    fn ipv4_udp_packet(src: [u8; 4], dst: [u8; 4], src_port: u16, dst_port: u16) -> Vec<u8> {
+        let mut packet = vec![0x45, 0, 0, 28, 0, 0, 0, 0, 64, UDP, 0, 0];
+        packet.extend_from_slice(&src);
+        packet.extend_from_slice(&dst);
+        packet.extend_from_slice(&src_port.to_be_bytes());
+        packet.extend_from_slice(&dst_port.to_be_bytes());
+        packet.extend_from_slice(&[0, 8, 0, 0]);
+        packet
+    }
+
+    fn ipv6_udp_packet(src: Ipv6Addr, dst: Ipv6Addr, src_port: u16, dst_port: u16) -> Vec<u8> {
+        let mut packet = vec![0x60, 0, 0, 0, 0, 8, UDP, 64];
+        packet.extend_from_slice(&src.octets());
+        packet.extend_from_slice(&dst.octets());
+        packet.extend_from_slice(&src_port.to_be_bytes());
+        packet.extend_from_slice(&dst_port.to_be_bytes());
+        packet.extend_from_slice(&[0, 8, 0, 0]);
+        packet
+    }
+
+    fn downlink_classifier(contents: Vec<u8>) -> QosClassifier {
+        let classifier = QosClassifier::default();
+        classifier.set_qos_rules(&[QosRule {
+            id: 2,
+            precedence: 0x80,
+            qfi: 2,
+            packet_filters: vec![PacketFilter {
+                id: 1,
+                direction: PACKET_FILTER_DOWNLINK,
+                contents,
+            }],
+        }]);
+        classifier
+    }
+
+    #[test]
+    fn ipv4_remote_address_and_mask() {
+        let classifier =
+            downlink_classifier(vec![IPV4_REMOTE_ADDRESS, 10, 1, 2, 0, 255, 255, 255, 0]);
+        let packet = ipv4_udp_packet([10, 1, 2, 99], [10, 255, 0, 1], 1000, 2000);
+        assert_eq!(classifier.classify_downlink(&packet), Some(2));
+        let packet = ipv4_udp_packet([10, 1, 3, 99], [10, 255, 0, 1], 1000, 2000);
+        assert_eq!(classifier.classify_downlink(&packet), None);
+    }
+
+    #[test]
+    fn ipv6_remote_address_prefix() {
+        let prefix: Ipv6Addr = "2001:db8:1::".parse().unwrap();
+        let mut contents = vec![IPV6_REMOTE_ADDRESS_PREFIX_LENGTH];
+        contents.extend_from_slice(&prefix.octets());
+        contents.push(48);
+        let classifier = downlink_classifier(contents);
+        let ue: Ipv6Addr = "2001:db8:ffff::1".parse().unwrap();
+        let packet = ipv6_udp_packet("2001:db8:1:5::7".parse().unwrap(), ue, 1000, 2000);
+        assert_eq!(classifier.classify_downlink(&packet), Some(2));
+        let packet = ipv6_udp_packet("2001:db8:2::7".parse().unwrap(), ue, 1000, 2000);
+        assert_eq!(classifier.classify_downlink(&packet), None);
+    }
+
+    #[test]
+    fn port_ranges() {
+        let classifier = downlink_classifier(vec![
+            LOCAL_PORT_RANGE,
+            0x13,
+            0x88, // 5000
+            0x13,
+            0x8a, // 5002
+            REMOTE_PORT_RANGE,
+            0x00,
+            0x50, // 80
+            0x00,
+            0x50, // 80
+        ]);
+        let src = [8, 8, 8, 8];
+        let dst = [10, 255, 0, 1];
+        assert_eq!(
+            classifier.classify_downlink(&ipv4_udp_packet(src, dst, 80, 5000)),
+            Some(2)
+        );
+        assert_eq!(
+            classifier.classify_downlink(&ipv4_udp_packet(src, dst, 80, 5002)),
+            Some(2)
+        );
+        assert_eq!(
+            classifier.classify_downlink(&ipv4_udp_packet(src, dst, 80, 5003)),
+            None
+        );
+        assert_eq!(
+            classifier.classify_downlink(&ipv4_udp_packet(src, dst, 81, 5001)),
+            None
+        );
+    }
+
+    #[test]
+    fn unsupported_component_matches_nothing() {
+        // Type of service / traffic class.
+        let classifier = downlink_classifier(vec![0b01110000, 0, 0]);
+        let packet = ipv4_udp_packet([8, 8, 8, 8], [10, 255, 0, 1], 1000, 2000);
+        assert_eq!(classifier.classify_downlink(&packet), None);
+    }
+
+    #[test]
+    fn truncated_filter_matches_nothing() {
+        let classifier = downlink_classifier(vec![IPV4_REMOTE_ADDRESS, 8, 8, 8, 8, 255, 255]);
+        let packet = ipv4_udp_packet([8, 8, 8, 8], [10, 255, 0, 1], 1000, 2000);
+        assert_eq!(classifier.classify_downlink(&packet), None);
+        let classifier = downlink_classifier(vec![
+            PROTOCOL_IDENTIFIER_NEXT_HEADER,
+            UDP,
+            SINGLE_LOCAL_PORT,
+        ]);
+        assert_eq!(classifier.classify_downlink(&packet), None);
+    }
+
+    #[test]
+    fn highest_precedence_rule_wins() {
+        let classifier = QosClassifier::default();
+        let rule = |precedence, qfi| QosRule {
+            id: qfi,
+            precedence,
+            qfi,
+            packet_filters: vec![PacketFilter {
+                id: 1,
+                direction: PACKET_FILTER_DOWNLINK,
+                contents: vec![MATCH_ALL],
+            }],
+        };
+        classifier.set_qos_rules(&[rule(0xff, 1), rule(0x10, 3)]);
+        let packet = ipv4_udp_packet([8, 8, 8, 8], [10, 255, 0, 1], 1000, 2000);
+        assert_eq!(classifier.classify_downlink(&packet), Some(3));
+    }
+}
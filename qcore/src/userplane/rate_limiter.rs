@@ -0,0 +1,105 @@
+//! Enforcement of the session AMBR and UE AMBR with token buckets - see TS23.501, 5.7.2.6.  A packet is dropped
+//! if either of the buckets that it passes through doesn't have enough tokens for it.
+use std::sync::{Arc, Mutex};
+use std::time::Instant;
+
+// A bucket holds 100ms worth of tokens at its rate, and always enough for a maximum size packet.
+const BURST_SECS: f64 = 0.1;
+const MIN_BURST_BYTES: f64 = 2000.0;
+
+#[derive(Debug)]
+struct TokenBucketState {
+    bytes_per_sec: f64,
+    tokens: f64,
+    refilled_at: Instant,
+}
+
+impl TokenBucketState {
+    fn depth(&self) -> f64 {
+        (self.bytes_per_sec * BURST_SECS).max(MIN_BURST_BYTES)
+    }
+
+    fn refill(&mut self) {
+        let now = Instant::now();
+        let elapsed = now.duration_since(self.refilled_at).as_secs_f64();
+        self.tokens = (self.tokens + elapsed * self.bytes_per_sec).min(self.depth());
+        self.refilled_at = now;
+    }
+}
+
+/// A token bucket that limits traffic to a bit rate.  Clones share the same bucket.
+#[derive(Clone, Debug)]
+pub struct TokenBucket(Arc<Mutex<TokenBucketState>>);
+
+impl TokenBucket {
+    pub fn new(kbps: u32) -> Self {
+        let mut state = TokenBucketState {
+            bytes_per_sec: kbps as f64 * 125.0,
+            tokens: 0.0,
+            refilled_at: Instant::now(),
+        };
+        state.tokens = state.depth();
+        TokenBucket(Arc::new(Mutex::new(state)))
+    }
+
+    pub fn set_rate(&self, kbps: u32) {
+        let mut state = self.0.lock().unwrap();
+        state.refill();
+        state.bytes_per_sec = kbps as f64 * 125.0;
+        state.tokens = state.tokens.min(state.depth());
+    }
+}
+
+/// The uplink and downlink token buckets of an AMBR.
+#[derive(Clone, Debug)]
+pub struct AmbrBuckets {
+    pub uplink: TokenBucket,
+    pub downlink: TokenBucket,
+}
+
+impl AmbrBuckets {
+    pub fn new(downlink_kbps: u32, uplink_kbps: u32) -> Self {
+        AmbrBuckets {
+            uplink: TokenBucket::new(uplink_kbps),
+            downlink: TokenBucket::new(downlink_kbps),
+        }
+    }
+}
+
+/// Polices one direction of a userplane session's traffic against its session AMBR and, if it has one, the AMBR of
+/// its UE, whose bucket is shared with the UE's other sessions.
+#[derive(Clone, Debug)]
+pub struct RateLimiter {
+    session: TokenBucket,
+    ue: Option<TokenBucket>,
+}
+
+impl RateLimiter {
+    pub fn new(session: TokenBucket, ue: Option<TokenBucket>) -> Self {
+        RateLimiter { session, ue }
+    }
+
+    /// Whether a packet of the given length is within the AMBRs, in which case it uses up their tokens.
+    pub fn allow(&self, len: usize) -> bool {
+        let len = len as f64;
+        let mut session = self.session.0.lock().unwrap();
+        session.refill();
+        if session.tokens < len {
+            return false;
+        }
+        if let Some(ue) = &self.ue {
+            let mut ue = ue.0.lock().unwrap();
+            ue.refill();
+            if ue.tokens < len {
+                return false;
+            }
+            ue.tokens -= len;
+        }
+        session.tokens -= len;
+        true
+    }
+
+    pub fn set_session_rate(&self, kbps: u32) {
+        self.session.set_rate(kbps);
+    }
+}
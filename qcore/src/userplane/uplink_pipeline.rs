@@ -1,7 +1,12 @@
 #![allow(clippy::unusual_byte_groupings)]
+use super::ethernet::{ETHERNET_HEADER_LEN, MacTable, is_group_address, source_mac};
+use super::ipv6::{
+    ROUTER_ADVERTISEMENT_PACKET_LEN, is_router_solicitation, write_router_advertisement,
+};
+use super::rate_limiter::RateLimiter;
 use super::{
-    GTP_BASE_HEADER_LEN, GTP_EXTENDED_HEADER_LEN, IPV4_HEADER_LEN, MAX_UES, PDCP_HEADER_LEN,
-    SDAP_HEADER_LEN,
+    DOWNLINK_INNER_PACKET_OFFSET, DownlinkForwardingTable, GTP_BASE_HEADER_LEN,
+    GTP_EXTENDED_HEADER_LEN, IPV4_HEADER_LEN, MAX_PDU_SESSIONS, PDCP_HEADER_LEN, SDAP_HEADER_LEN,
 };
 use crate::userplane::GTP_MESSAGE_TYPE_GPDU;
 use anyhow::Result;
@@ -15,23 +20,42 @@ use async_std::{
 use atomic_counter::{AtomicCounter, RelaxedCounter};
 use derive_deref::Deref;
 use slog::{Logger, info};
-use std::{net::Ipv4Addr, sync::Arc};
+use std::sync::Arc;
 
 #[derive(Clone)]
 struct UplinkForwardingRule {
     pub local_teid: [u8; 4],
+    pub n6_idx: usize,
+    pub ue_ipv6_prefix: Option<[u8; 8]>,
+    pub ethernet: bool,
+    pub rate_limiter: RateLimiter,
 }
 
 #[derive(Clone)]
 pub struct UplinkForwardingTable(Arc<Mutex<Vec<Option<UplinkForwardingRule>>>>);
 impl UplinkForwardingTable {
     pub fn new() -> Self {
-        Self(Arc::new(Mutex::new(vec![None; MAX_UES])))
+        Self(Arc::new(Mutex::new(vec![None; MAX_PDU_SESSIONS])))
     }
-    pub async fn add_rule(&self, ue_ipv4: Ipv4Addr, teid: [u8; 4]) {
-        let idx = ue_ipv4.octets()[3] as usize;
-        assert_eq!(uplink_table_index_from_gtp_teid(&teid), idx);
-        self.0.lock().await[idx] = Some(UplinkForwardingRule { local_teid: teid });
+    /// Forward uplink packets on the TEID to the N6 device of the given data network - its tap device for an
+    /// Ethernet PDU session, or its tun device otherwise.  If the session has an IPv6 prefix, we answer the UE's
+    /// Router Solicitations with it.  Packets over the session AMBR or UE AMBR are dropped.
+    pub async fn add_rule(
+        &self,
+        teid: [u8; 4],
+        n6_idx: usize,
+        ue_ipv6_prefix: Option<[u8; 8]>,
+        ethernet: bool,
+        rate_limiter: RateLimiter,
+    ) {
+        let idx = uplink_table_index_from_gtp_teid(&teid);
+        self.0.lock().await[idx] = Some(UplinkForwardingRule {
+            local_teid: teid,
+            n6_idx,
+            ue_ipv6_prefix,
+            ethernet,
+            rate_limiter,
+        });
     }
     pub async fn remove_rule(&self, teid: [u8; 4]) {
         let idx = uplink_table_index_from_gtp_teid(&teid);
@@ -41,8 +65,10 @@ impl UplinkForwardingTable {
 
 pub struct UplinkPipeline {
     f1u_socket: UdpSocket,
-    n6_tun_device: File,
+    n6_tun_devices: Vec<File>,
+    n6_tap_devices: Vec<Option<(File, MacTable)>>,
     forwarding_table: UplinkForwardingTable,
+    downlink_forwarding_table: DownlinkForwardingTable,
     counters: Arc<UplinkCounters>,
 }
 
@@ -54,10 +80,13 @@ pub mod uplink_counter_indices {
     pub const UL_DROP_TOO_SHORT_EXT: usize = 4;
     pub const UL_DROP_PDCP_CONTROL: usize = 5;
     pub const UL_DROP_SDAP_CONTROL: usize = 6;
-    pub const UL_DROP_NOT_IPV4: usize = 7;
+    pub const UL_DROP_NOT_IP: usize = 7;
     pub const UL_DROP_UNKNOWN_TEID_1: usize = 8;
     pub const UL_DROP_UNKNOWN_TEID_2: usize = 9;
-    pub const UL_NUM_COUNTERS: usize = 10;
+    pub const UL_ROUTER_SOLICITATIONS: usize = 10;
+    pub const UL_DROP_BAD_MAC: usize = 11;
+    pub const UL_DROP_AMBR: usize = 12;
+    pub const UL_NUM_COUNTERS: usize = 13;
 }
 use uplink_counter_indices::*;
 
@@ -67,14 +96,18 @@ pub struct UplinkCounters([RelaxedCounter; UL_NUM_COUNTERS]);
 impl UplinkPipeline {
     pub fn new(
         f1u_socket: UdpSocket,
-        n6_tun_device: File,
+        n6_tun_devices: Vec<File>,
+        n6_tap_devices: Vec<Option<(File, MacTable)>>,
         forwarding_table: UplinkForwardingTable,
+        downlink_forwarding_table: DownlinkForwardingTable,
         counters: Arc<UplinkCounters>,
     ) -> Self {
         Self {
             f1u_socket,
-            n6_tun_device,
+            n6_tun_devices,
+            n6_tap_devices,
             forwarding_table,
+            downlink_forwarding_table,
             counters,
         }
     }
@@ -154,13 +187,6 @@ impl UplinkPipeline {
         }
         offset += 1;
 
-        // Next we are expecting an IPv4 header.
-        if buf[offset] & 0xf0 != 0x40 {
-            counters[UL_DROP_NOT_IPV4].inc();
-            //println!("Not IPv4 - first byte of IP header {:x}", buf[offset]);
-            return Ok(());
-        }
-
         // Drop the packet if this is an unknown TEID
         let idx = uplink_table_index_from_gtp_teid(gtp_teid);
 
@@ -175,21 +201,77 @@ impl UplinkPipeline {
             counters[UL_DROP_UNKNOWN_TEID_2].inc();
             return Ok(());
         }
+        if !entry.rate_limiter.allow(bytes_read.saturating_sub(offset)) {
+            counters[UL_DROP_AMBR].inc();
+            return Ok(());
+        }
         // TODO check source IP
+        let n6_idx = entry.n6_idx;
+        let ue_ipv6_prefix = entry.ue_ipv6_prefix;
+        let ethernet = entry.ethernet;
         // -- end critical section --
 
+        // For an Ethernet PDU session, learn which UE the source MAC address is behind and bridge the frame
+        // onto the tap device.
+        if ethernet {
+            // Ethernet PDU sessions are only set up on data networks with a tap device.
+            let Some((n6_tap_device, mac_table)) = &mut self.n6_tap_devices[n6_idx] else {
+                return Ok(());
+            };
+            let frame = &buf[offset..bytes_read];
+            if frame.len() < ETHERNET_HEADER_LEN {
+                counters[UL_DROP_TOO_SHORT].inc();
+                return Ok(());
+            }
+            // Drop frames from a MAC address that we can't learn on this session, so that a UE can't take over
+            // the MAC address of a device behind another UE.
+            let src = source_mac(frame);
+            if is_group_address(&src) || !mac_table.learn(src, idx).await {
+                counters[UL_DROP_BAD_MAC].inc();
+                return Ok(());
+            }
+            n6_tap_device.write(frame).await?;
+            n6_tap_device.flush().await?;
+            return Ok(());
+        }
+
+        // Otherwise we are expecting an IPv4 or IPv6 header.
+        if buf[offset] & 0xf0 != 0x40 && buf[offset] & 0xf0 != 0x60 {
+            counters[UL_DROP_NOT_IP].inc();
+            return Ok(());
+        }
+
+        // We are the UE's IPv6 router, so we answer its Router Solicitations ourselves rather than passing
+        // them to N6.
+        if let Some(prefix) =
+            ue_ipv6_prefix.filter(|_| is_router_solicitation(&buf[offset..bytes_read]))
+        {
+            counters[UL_ROUTER_SOLICITATIONS].inc();
+            let mut ra = [0u8; DOWNLINK_INNER_PACKET_OFFSET + ROUTER_ADVERTISEMENT_PACKET_LEN];
+            let ra_len = write_router_advertisement(
+                &mut ra[DOWNLINK_INNER_PACKET_OFFSET..],
+                Some(&buf[offset..bytes_read]),
+                &prefix,
+            );
+            self.downlink_forwarding_table
+                .send(idx, &mut ra, ra_len, &self.f1u_socket)
+                .await?;
+            return Ok(());
+        }
+
         //println!("Output uplink inner packet to tun device from offset {offset}");
 
         // Skip over the GTP, SDAP and PDCP headers to get to the inner IP packet.
         let inner_ip_packet = &buf[offset..bytes_read];
-        self.n6_tun_device.write(inner_ip_packet).await?;
-        self.n6_tun_device.flush().await?;
+        let n6_tun_device = &mut self.n6_tun_devices[n6_idx];
+        n6_tun_device.write(inner_ip_packet).await?;
+        n6_tun_device.flush().await?;
 
         Ok(())
     }
 }
 
 fn uplink_table_index_from_gtp_teid(teid: &[u8]) -> usize {
-    // TODO - for now, we just use the last byte.
-    teid[3] as usize
+    // TODO - for now, we just use the last two bytes.
+    u16::from_be_bytes([teid[2], teid[3]]) as usize
 }
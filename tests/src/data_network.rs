@@ -3,7 +3,7 @@ use std::{
     time::Duration,
 };
 
-use anyhow::Result;
+use anyhow::{Result, anyhow};
 use async_net::{AsyncToSocketAddrs, UdpSocket};
 use async_std::future;
 use slog::{Logger, info, o};
@@ -11,6 +11,7 @@ use slog::{Logger, info, o};
 pub struct DataNetwork {
     logger: Logger,
     udp_socket: UdpSocket,
+    udp_socket_ipv6: Option<UdpSocket>,
 }
 
 impl DataNetwork {
@@ -26,9 +27,20 @@ impl DataNetwork {
         let bind_addr = SocketAddr::new(IpAddr::V4(udp_server_ip), udp_server_port);
         let udp_socket = UdpSocket::bind(&bind_addr).await.unwrap();
 
+        // Likewise on a local IPv6 address, if there is one.
+        let udp_socket_ipv6 = match local_ip_address::local_ipv6() {
+            Ok(udp_server_ipv6) => {
+                UdpSocket::bind(SocketAddr::new(udp_server_ipv6, udp_server_port))
+                    .await
+                    .ok()
+            }
+            Err(_) => None,
+        };
+
         DataNetwork {
             logger: logger.new(o!("dn" => 1)),
             udp_socket,
+            udp_socket_ipv6,
         }
     }
 
@@ -36,8 +48,26 @@ impl DataNetwork {
         self.udp_socket.local_addr().unwrap()
     }
 
+    pub fn udp_server_addr_ipv6(&self) -> Result<SocketAddr> {
+        Ok(self.udp_socket_ipv6()?.local_addr()?)
+    }
+
+    fn udp_socket_ipv6(&self) -> Result<&UdpSocket> {
+        self.udp_socket_ipv6
+            .as_ref()
+            .ok_or(anyhow!("No local IPv6 address for the data network"))
+    }
+
     pub async fn send_n6_udp_packet<A: AsyncToSocketAddrs>(&self, ue_addr_port: A) -> Result<()> {
-        self.udp_socket.send_to(&[0; 10], ue_addr_port).await?;
+        self.send_n6_udp_payload(ue_addr_port, &[0; 10]).await
+    }
+
+    pub async fn send_n6_udp_payload<A: AsyncToSocketAddrs>(
+        &self,
+        ue_addr_port: A,
+        payload: &[u8],
+    ) -> Result<()> {
+        self.udp_socket.send_to(payload, ue_addr_port).await?;
         info!(self.logger, "Sent in N6 packet");
         Ok(())
     }
@@ -49,4 +79,34 @@ impl DataNetwork {
         info!(&self.logger, ">> Uplink packet from UE");
         Ok(())
     }
+
+    /// Receive uplink packets until none arrive for a second, and return how many there were.
+    pub async fn count_n6_udp_packets(&self) -> usize {
+        let mut buf = [0; 2000];
+        let mut count = 0;
+        while future::timeout(Duration::from_secs(1), self.udp_socket.recv(&mut buf))
+            .await
+            .is_ok_and(|x| x.is_ok())
+        {
+            count += 1;
+        }
+        info!(&self.logger, ">> {count} uplink packets from UE");
+        count
+    }
+
+    pub async fn send_n6_udp_packet_ipv6(&self, ue_addr_port: SocketAddr) -> Result<()> {
+        self.udp_socket_ipv6()?
+            .send_to(&[0; 10], ue_addr_port)
+            .await?;
+        info!(self.logger, "Sent in N6 IPv6 packet");
+        Ok(())
+    }
+
+    pub async fn receive_n6_udp_packet_ipv6(&self) -> Result<()> {
+        let mut buf = [0; 2000];
+        let future_result = self.udp_socket_ipv6()?.recv(&mut buf);
+        let _bytes_received = future::timeout(Duration::from_secs(50), future_result).await??;
+        info!(&self.logger, ">> Uplink IPv6 packet from UE");
+        Ok(())
+    }
 }
@@ -1,10 +1,32 @@
 use super::{DataNetwork, MockDu, MockUe};
-use anyhow::{Result, bail};
-use qcore::{Config, QCore, SimTable};
+use anyhow::{Result, bail, ensure};
+use qcore::{
+    Config, DataNetworkConfig, EquipmentIdentities, NasCipheringAlgorithm, NasIntegrityAlgorithm,
+    PacketFilterTemplate, QCore, QosFlowTemplate, SimCreds, SimTable, SqnStore,
+};
 use slog::{Drain, Logger, o};
-use std::net::{IpAddr, Ipv4Addr, SocketAddr};
+use std::collections::HashMap;
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr, SocketAddr};
+use std::time::Duration;
+use xxap::Snssai;
+
+/// The DNS servers and MTU that QCore gives to UEs on the test data network.
+pub const TEST_DNS_SERVER_IPV4: Ipv4Addr = Ipv4Addr::new(10, 255, 0, 254);
+pub const TEST_DNS_SERVER_IPV6: Ipv6Addr = Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 0x53);
+pub const TEST_MTU: u16 = 1400;
+
+/// IP sessions on the test data network have a QoS flow with 5QI 1 for UDP traffic to this port on the UE.
+pub const TEST_VOICE_PORT: u16 = 5004;
+pub const TEST_VOICE_5QI: u8 = 1;
 
 pub async fn init() -> Result<(MockDu, QCore, DataNetwork, &'static SimTable, Logger)> {
+    init_with_config(|_| {}).await
+}
+
+/// Start QCore with a test-specific change to its configuration.
+pub async fn init_with_config(
+    configure: impl FnOnce(&mut Config),
+) -> Result<(MockDu, QCore, DataNetwork, &'static SimTable, Logger)> {
     exit_on_panic();
     let qc_ip = "127.0.0.1";
     let du_ip = "127.0.0.2";
@@ -12,7 +34,9 @@ pub async fn init() -> Result<(MockDu, QCore, DataNetwork, &'static SimTable, Lo
     let du = MockDu::new(du_ip, &logger).await?;
     let dn = DataNetwork::new(&logger).await;
     let sims = qcore::sims::load_sims_file("test_sims.toml", &logger)?;
-    let qc = start_qcore(&qc_ip, &sims, &logger).await?;
+    let mut config = test_config(qc_ip)?;
+    configure(&mut config);
+    let qc = start_qcore(config, sims, &logger).await?;
     Ok((du, qc, dn, sims, logger))
 }
 
@@ -32,21 +56,59 @@ fn init_logging() -> Logger {
     slog::Logger::root(drain, o!())
 }
 
-async fn start_qcore(addr: &str, sims: &'static SimTable, logger: &Logger) -> Result<QCore> {
-    QCore::start(
-        Config {
-            ip_addr: addr.parse()?,
-            plmn: [0x2, 0xf8, 0x39],
-            amf_ids: [0x01, 0x01, 0x00],
-            name: Some("QCore".to_string()),
-            serving_network_name: "5G:mnc093.mcc208.3gppnetwork.org".to_string(),
-            skip_ue_authentication_check: true, // saves us having to implement milenage etc in test framework
-            sst: 1,
+fn test_config(addr: &str) -> Result<Config> {
+    Ok(Config {
+        ip_addr: addr.parse()?,
+        plmn: [0x2, 0xf8, 0x39],
+        amf_ids: [0x01, 0x01, 0x00],
+        name: Some("QCore".to_string()),
+        serving_network_name: "5G:mnc093.mcc208.3gppnetwork.org".to_string(),
+        skip_ue_authentication_check: false,
+        snssais: vec![Snssai(1, None), Snssai(1, Some([0, 0, 1]))],
+        data_networks: vec![DataNetworkConfig {
+            dnn: "internet".to_string(),
             n6_tun_name: "ue".to_string(),
+            n6_tap_name: None,
             ue_subnet: Ipv4Addr::new(10, 255, 0, 0),
-        },
+            ue_ipv6_prefix: Some(Ipv6Addr::new(0xfd00, 0, 0, 0xff00, 0, 0, 0, 0)),
+            dns_servers: vec![TEST_DNS_SERVER_IPV4.into(), TEST_DNS_SERVER_IPV6.into()],
+            mtu: Some(TEST_MTU),
+            p_cscf_servers: vec![],
+            qos_flows: vec![QosFlowTemplate {
+                five_qi: TEST_VOICE_5QI,
+                arp_priority_level: Some(2),
+                packet_filters: vec![PacketFilterTemplate {
+                    protocol: Some(17), // UDP
+                    local_port: Some(TEST_VOICE_PORT),
+                    ..Default::default()
+                }],
+            }],
+        }],
+        sticky_ue_ips: false,
+        sdap_header_dl: false,
+        nas_integrity_algorithms: vec![NasIntegrityAlgorithm::Nia2],
+        nas_ciphering_algorithms: vec![NasCipheringAlgorithm::Nea2, NasCipheringAlgorithm::Nea0],
+        // Short enough for tests to wait for NAS retransmissions and implicit deregistration.
+        nas_retransmission_timer: Duration::from_secs(1),
+        t3512: Duration::from_secs(2),
+        mobile_reachable_timer: Duration::from_secs(3),
+        full_network_name: Some("QCore Test Network".to_string()),
+        short_network_name: Some("QCore".to_string()),
+        time_zone_offset_mins: None,
+        paging_retransmission_timer: Duration::from_secs(1),
+        downlink_buffer_max_packets: 64,
+        downlink_buffer_max_age: Duration::from_secs(10),
+        home_network_keys: HashMap::new(),
+        equipment_identities: EquipmentIdentities::default(),
+    })
+}
+
+async fn start_qcore(config: Config, sims: &'static SimTable, logger: &Logger) -> Result<QCore> {
+    QCore::start(
+        config,
         logger.new(o!("qcore"=> 1)),
         sims,
+        SqnStore::in_memory(),
     )
     .await
 }
@@ -61,6 +123,25 @@ pub async fn pass_through_downlink_ipv4<'a>(dn: &DataNetwork, ue: &MockUe<'a>) -
     Ok(())
 }
 
+/// Send a downlink packet from the DN to a UE that is in CM-IDLE, so that QCore buffers it and pages the UE.
+pub async fn send_downlink_ipv4_to_idle_ue<'a>(dn: &DataNetwork, ue: &MockUe<'a>) -> Result<()> {
+    dn.send_n6_udp_packet(SocketAddr::new(IpAddr::V4(ue.ipv4_addr), TEST_UDP_PORT))
+        .await
+}
+
+/// Send a downlink packet with the given UDP payload to a UE that is in CM-IDLE.
+pub async fn send_downlink_ipv4_payload_to_idle_ue<'a>(
+    dn: &DataNetwork,
+    ue: &MockUe<'a>,
+    payload: &[u8],
+) -> Result<()> {
+    dn.send_n6_udp_payload(
+        SocketAddr::new(IpAddr::V4(ue.ipv4_addr), TEST_UDP_PORT),
+        payload,
+    )
+    .await
+}
+
 pub async fn pass_through_uplink_ipv4<'a>(ue: &MockUe<'a>, dn: &DataNetwork) -> Result<()> {
     let dst_udp_server = dn.udp_server_addr();
     let IpAddr::V4(dst_ip) = dst_udp_server.ip() else {
@@ -71,6 +152,28 @@ pub async fn pass_through_uplink_ipv4<'a>(ue: &MockUe<'a>, dn: &DataNetwork) ->
     dn.receive_n6_udp_packet().await
 }
 
+/// Send a downlink IPv6 packet from the DN to an arbitrary UDP port on the UE.
+pub async fn pass_through_downlink_ipv6<'a>(dn: &DataNetwork, ue: &MockUe<'a>) -> Result<()> {
+    dn.send_n6_udp_packet_ipv6(SocketAddr::new(IpAddr::V6(ue.ipv6_addr), TEST_UDP_PORT))
+        .await?;
+    let ip_packet = ue.recv_f1u_data_packet().await?;
+    ensure!(
+        ip_packet.first().is_some_and(|x| x >> 4 == 6),
+        "Expected IPv6 packet, got {ip_packet:x?}"
+    );
+    Ok(())
+}
+
+pub async fn pass_through_uplink_ipv6<'a>(ue: &MockUe<'a>, dn: &DataNetwork) -> Result<()> {
+    let dst_udp_server = dn.udp_server_addr_ipv6()?;
+    let IpAddr::V6(dst_ip) = dst_udp_server.ip() else {
+        bail!("Expected IPv6 address");
+    };
+    ue.send_f1u_data_packet_ipv6(&dst_ip, TEST_UDP_PORT, dst_udp_server.port())
+        .await?;
+    dn.receive_n6_udp_packet_ipv6().await
+}
+
 pub async fn pass_through_ue_to_ue_ipv4<'a>(
     src_ue: &MockUe<'a>,
     dst_ue: &MockUe<'a>,
@@ -82,6 +185,10 @@ pub async fn pass_through_ue_to_ue_ipv4<'a>(
     Ok(())
 }
 
-pub fn nth_imsi(n: usize, sims: &SimTable) -> String {
-    sims.keys().nth(n).unwrap().clone()
+/// Get the nth SIM in IMSI order.
+pub fn nth_sim(n: usize, sims: &'static SimTable) -> (String, &'static SimCreds) {
+    let mut sims: Vec<_> = sims.iter().collect();
+    sims.sort_by_key(|(imsi, _)| *imsi);
+    let (imsi, sim_creds) = sims[n];
+    (imsi.clone(), sim_creds)
 }
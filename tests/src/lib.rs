@@ -1,10 +1,11 @@
 mod data_network;
+pub mod framework;
 mod mock;
 mod mock_du;
 mod mock_ue;
 mod userplane;
-pub mod framework;
 
 pub use data_network::DataNetwork;
 pub use mock_du::{MockDu, UeContext as DuUeContext};
-pub use mock_ue::MockUe;
+pub use mock_ue::{MockUe, PduSessionType};
+pub use userplane::{arp_reply_sender, arp_request};
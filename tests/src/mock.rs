@@ -134,6 +134,14 @@ impl<P: Pdu> Mock<P> {
         self.receive_pdu_with_assoc_id().await.map(|r| r.pdu)
     }
 
+    /// Check that no Pdu arrives within the 0.5s receive timeout.
+    pub async fn expect_no_pdu(&self) -> Result<()> {
+        match self.receive_pdu().await {
+            Ok(pdu) => bail!("Expected no Pdu, got {:?}", pdu),
+            Err(_) => Ok(()),
+        }
+    }
+
     /// Receive a Pdu, with a 0.5s timeout.
     pub async fn receive_pdu_with_assoc_id(&self) -> Result<ReceivedPdu<P>> {
         let f = self.receiver.recv();
@@ -1,10 +1,10 @@
 use anyhow::{Result, bail};
-use asn1_per::{Msb0, SerDes, bitvec, nonempty};
+use asn1_per::{Msb0, NonEmpty, SerDes, bitvec, nonempty};
 use f1ap::*;
 use rrc::CellGroupId;
 use xxap::{GtpTunnel, TransportLayerAddress};
 
-use super::UeContext;
+use super::{Drb, UeContext};
 
 pub fn f1_setup_request() -> F1apPdu {
     F1apPdu::InitiatingMessage(InitiatingMessage::F1SetupRequest(F1SetupRequest {
@@ -68,11 +68,29 @@ pub fn ue_context_setup_response(ue: &UeContext, local_ip: &String) -> Result<F1
     let Some(gnb_cu_ue_f1ap_id) = ue.gnb_cu_ue_f1ap_id else {
         bail!("CU F1AP ID should be set on UE");
     };
-    let Some(drb) = &ue.drb else {
-        bail!("Drb should be set on UE");
-    };
     let cell_group_config = f1ap::CellGroupConfig(make_rrc_cell_group_config().into_bytes()?);
     let transport_layer_address = TransportLayerAddress::try_from(local_ip)?;
+    let drbs_setup = ue
+        .drbs
+        .iter()
+        .map(|drb| DrbsSetupItem {
+            drb_id: drb.drb_id,
+            lcid: None,
+            dl_up_tnl_information_to_be_setup_list: DlUpTnlInformationToBeSetupList(nonempty![
+                DlUpTnlInformationToBeSetupItem {
+                    dl_up_tnl_information: UpTransportLayerInformation::GtpTunnel(GtpTunnel {
+                        transport_layer_address: transport_layer_address.clone(),
+                        gtp_teid: drb.local_teid.clone(),
+                    },),
+                },
+            ]),
+            additional_pdcp_duplication_tnl_list: None,
+            current_qos_para_set_index: None,
+        })
+        .collect();
+    let Some(drbs_setup) = NonEmpty::from_vec(drbs_setup) else {
+        bail!("Drb should be set on UE");
+    };
 
     // TODO: confirm setup of SRB2
 
@@ -102,20 +120,7 @@ pub fn ue_context_setup_response(ue: &UeContext, local_ip: &String) -> Result<F1
             c_rnti: None,
             resource_coordination_transfer_container: None,
             full_configuration: None,
-            drbs_setup_list: Some(DrbsSetupList(nonempty![DrbsSetupItem {
-                drb_id: drb.drb_id,
-                lcid: None,
-                dl_up_tnl_information_to_be_setup_list: DlUpTnlInformationToBeSetupList(nonempty![
-                    DlUpTnlInformationToBeSetupItem {
-                        dl_up_tnl_information: UpTransportLayerInformation::GtpTunnel(GtpTunnel {
-                            transport_layer_address,
-                            gtp_teid: drb.local_teid.clone(),
-                        },),
-                    },
-                ]),
-                additional_pdcp_duplication_tnl_list: None,
-                current_qos_para_set_index: None,
-            }])),
+            drbs_setup_list: Some(DrbsSetupList(drbs_setup)),
             srbs_failed_to_be_setup_list: None,
             drbs_failed_to_be_setup_list: None,
             s_cell_failedto_setup_list: None,
@@ -183,6 +188,30 @@ pub fn ue_context_release_request(ue: &UeContext) -> F1apPdu {
     ))
 }
 
+pub fn ue_inactivity_notification(ue: &UeContext) -> Result<F1apPdu> {
+    let Some(gnb_cu_ue_f1ap_id) = ue.gnb_cu_ue_f1ap_id else {
+        bail!("CU F1AP ID should be set on UE");
+    };
+    let drb_activity = ue
+        .drbs
+        .iter()
+        .map(|drb| DrbActivityItem {
+            drb_id: drb.drb_id,
+            drb_activity: Some(DrbActivity::NotActive),
+        })
+        .collect();
+    let Some(drb_activity) = NonEmpty::from_vec(drb_activity) else {
+        bail!("Drb should be set on UE");
+    };
+    Ok(F1apPdu::InitiatingMessage(
+        InitiatingMessage::UeInactivityNotification(UeInactivityNotification {
+            gnb_cu_ue_f1ap_id,
+            gnb_du_ue_f1ap_id: GnbDuUeF1apId(ue.ue_id),
+            drb_activity_list: DrbActivityList(drb_activity),
+        }),
+    ))
+}
+
 fn make_rrc_cell_group_config() -> rrc::CellGroupConfig {
     rrc::CellGroupConfig {
         cell_group_id: CellGroupId(1),
@@ -201,3 +230,77 @@ fn make_du_to_cu_rrc_container() -> DuToCuRrcContainer {
     let cell_group_config_ie = make_rrc_cell_group_config().into_bytes().unwrap();
     DuToCuRrcContainer(cell_group_config_ie)
 }
+
+pub fn ue_context_modification_response(ue: &UeContext) -> Result<F1apPdu> {
+    Ok(F1apPdu::SuccessfulOutcome(
+        SuccessfulOutcome::UeContextModificationResponse(ue_context_modification_response_ies(ue)?),
+    ))
+}
+
+pub fn ue_context_modification_response_with_drb_setup(
+    ue: &UeContext,
+    new_drbs: &[Drb],
+    local_ip: &String,
+) -> Result<F1apPdu> {
+    let transport_layer_address = TransportLayerAddress::try_from(local_ip)?;
+    let drbs_setup = new_drbs
+        .iter()
+        .map(|drb| DrbsSetupModItem {
+            drb_id: drb.drb_id,
+            lcid: None,
+            dl_up_tnl_information_to_be_setup_list: DlUpTnlInformationToBeSetupList(nonempty![
+                DlUpTnlInformationToBeSetupItem {
+                    dl_up_tnl_information: UpTransportLayerInformation::GtpTunnel(GtpTunnel {
+                        transport_layer_address: transport_layer_address.clone(),
+                        gtp_teid: drb.local_teid.clone(),
+                    }),
+                },
+            ]),
+            additional_pdcp_duplication_tnl_list: None,
+            current_qos_para_set_index: None,
+        })
+        .collect();
+    let Some(drbs_setup) = NonEmpty::from_vec(drbs_setup) else {
+        bail!("No new Drbs");
+    };
+    Ok(F1apPdu::SuccessfulOutcome(
+        SuccessfulOutcome::UeContextModificationResponse(UeContextModificationResponse {
+            drbs_setup_mod_list: Some(DrbsSetupModList(drbs_setup)),
+            ..ue_context_modification_response_ies(ue)?
+        }),
+    ))
+}
+
+fn ue_context_modification_response_ies(ue: &UeContext) -> Result<UeContextModificationResponse> {
+    let Some(gnb_cu_ue_f1ap_id) = ue.gnb_cu_ue_f1ap_id else {
+        bail!("CU F1AP ID should be set on UE");
+    };
+    Ok(UeContextModificationResponse {
+        gnb_cu_ue_f1ap_id,
+        gnb_du_ue_f1ap_id: GnbDuUeF1apId(ue.ue_id),
+        resource_coordination_transfer_container: None,
+        du_to_cu_rrc_information: None,
+        drbs_setup_mod_list: None,
+        drbs_modified_list: None,
+        srbs_failed_to_be_setup_mod_list: None,
+        drbs_failed_to_be_setup_mod_list: None,
+        s_cell_failedto_setup_mod_list: None,
+        drbs_failed_to_be_modified_list: None,
+        inactivity_monitoring_response: None,
+        criticality_diagnostics: None,
+        c_rnti: None,
+        associated_s_cell_list: None,
+        srbs_setup_mod_list: None,
+        srbs_modified_list: None,
+        full_configuration: None,
+        bh_channels_setup_mod_list: None,
+        bh_channels_modified_list: None,
+        bh_channels_failed_to_be_setup_mod_list: None,
+        bh_channels_failed_to_be_modified_list: None,
+        sl_drbs_setup_mod_list: None,
+        sl_drbs_modified_list: None,
+        sl_drbs_failed_to_be_setup_mod_list: None,
+        sl_drbs_failed_to_be_modified_list: None,
+        requested_target_cell_global_id: None,
+    })
+}
@@ -34,16 +34,28 @@ pub struct UeContext {
     ue_id: u32,
     gnb_cu_ue_f1ap_id: Option<GnbCuUeF1apId>,
     pub binding: Binding,
-    drb: Option<Drb>,
+    drbs: Vec<Drb>,
     pdcp_tx: PdcpTx,
+    /// The gNB-DU UE AMBR UL that the CU last gave us, in bits per second.
+    pub gnb_du_ue_ambr_ul: Option<u64>,
 }
 
+#[derive(Clone)]
 pub struct Drb {
     remote_tunnel_info: GtpTunnel,
     local_teid: GtpTeid,
     drb_id: DrbId,
 }
 
+impl UeContext {
+    fn drb(&self, drb_id: u8) -> Result<&Drb> {
+        self.drbs
+            .iter()
+            .find(|x| x.drb_id.0 == drb_id)
+            .ok_or(anyhow!("No Drb {drb_id}"))
+    }
+}
+
 impl Deref for MockDu {
     type Target = Mock<F1apPdu>;
 
@@ -81,8 +93,9 @@ impl MockDu {
                 .new_ue_binding_from_ip(&worker_ip.to_string())
                 .await?,
             gnb_cu_ue_f1ap_id: None,
-            drb: None,
+            drbs: vec![],
             pdcp_tx: PdcpTx::default(),
+            gnb_du_ue_ambr_ul: None,
         })
     }
 
@@ -232,31 +245,162 @@ impl MockDu {
         // message, and it is not allowed to release all the DRBs without releasing the RRC
         // Connection)."
 
-        ensure!(ue.drb.is_none());
+        ensure!(ue.drbs.is_empty());
+        ue.gnb_du_ue_ambr_ul = ue_setup_request.gnb_du_ue_ambr_ul.map(|x| x.0);
         let Some(drbs_to_be_setup_list) = ue_setup_request.drbs_to_be_setup_list else {
             bail!("No Drbs supplied")
         };
 
-        let first_drb = &drbs_to_be_setup_list.0[0];
-        let first_tnl_of_first_drb = &first_drb.ul_up_tnl_information_to_be_setup_list.0[0];
-        let UpTransportLayerInformation::GtpTunnel(remote_tunnel_info) =
-            &first_tnl_of_first_drb.ul_up_tnl_information;
+        for drb in drbs_to_be_setup_list.0.iter() {
+            let first_tnl = &drb.ul_up_tnl_information_to_be_setup_list.0[0];
+            let UpTransportLayerInformation::GtpTunnel(remote_tunnel_info) =
+                &first_tnl.ul_up_tnl_information;
 
-        // Check we have been given a real IP address.
-        let Ok(_ip_addr) = IpAddr::try_from(remote_tunnel_info.transport_layer_address.clone())
-        else {
-            bail!(
-                "Bad remote transport layer address in {:?}",
-                first_tnl_of_first_drb
+            // Check we have been given a real IP address.
+            let Ok(_ip_addr) = IpAddr::try_from(remote_tunnel_info.transport_layer_address.clone())
+            else {
+                bail!("Bad remote transport layer address in {:?}", first_tnl);
+            };
+
+            ue.drbs.push(Drb {
+                drb_id: drb.drb_id,
+                remote_tunnel_info: remote_tunnel_info.clone(),
+                local_teid: GtpTeid(rand::random()),
+            });
+        }
+
+        Ok(())
+    }
+
+    /// Handle a UE Context Modification Request that adds the DRBs of a new PDU session.
+    pub async fn handle_f1_ue_context_modification_with_drb_setup(
+        &self,
+        ue: &mut UeContext,
+    ) -> Result<()> {
+        let (r, assoc_id) = self.receive_ue_context_modification_request(ue).await?;
+        let Some(drbs_to_be_setup_mod_list) = r.drbs_to_be_setup_mod_list else {
+            bail!("No Drbs to be setup")
+        };
+        let mut new_drbs = vec![];
+        for new_drb in drbs_to_be_setup_mod_list.0.iter() {
+            ensure!(
+                !ue.drbs.iter().any(|x| x.drb_id.0 == new_drb.drb_id.0),
+                "Drb {} already exists",
+                new_drb.drb_id.0
             );
+            let UpTransportLayerInformation::GtpTunnel(remote_tunnel_info) = &new_drb
+                .ul_up_tnl_information_to_be_setup_list
+                .0
+                .head
+                .ul_up_tnl_information;
+            new_drbs.push(Drb {
+                drb_id: new_drb.drb_id,
+                remote_tunnel_info: remote_tunnel_info.clone(),
+                local_teid: GtpTeid(rand::random()),
+            });
+        }
+        ue.drbs.extend(new_drbs.iter().cloned());
+
+        let ue_context_modification_response =
+            build_f1ap::ue_context_modification_response_with_drb_setup(
+                ue,
+                &new_drbs,
+                &self.local_ip,
+            )?;
+        info!(&self.logger, "UeContextModificationResponse >>");
+        self.send(ue_context_modification_response, Some(assoc_id))
+            .await;
+        Ok(())
+    }
+
+    /// Handle a UE Context Modification Request that releases some of the UE's DRBs.
+    pub async fn handle_f1_ue_context_modification_with_drb_release(
+        &self,
+        ue: &mut UeContext,
+    ) -> Result<()> {
+        let (r, assoc_id) = self.receive_ue_context_modification_request(ue).await?;
+        let Some(drbs_to_be_released_list) = r.drbs_to_be_released_list else {
+            bail!("No Drbs to be released")
         };
+        for released_drb in drbs_to_be_released_list.0.iter() {
+            let drb_id = released_drb.drb_id.0;
+            let count = ue.drbs.len();
+            ue.drbs.retain(|x| x.drb_id.0 != drb_id);
+            ensure!(ue.drbs.len() < count, "UE has no Drb {drb_id} to release");
+        }
+        self.send_ue_context_modification_response(ue, assoc_id)
+            .await
+    }
+
+    /// Handle a UE Context Modification Request that modifies some of the UE's DRBs, and maybe releases others.
+    /// Returns the ID of each modified DRB and the QFIs mapped to it.
+    pub async fn handle_f1_ue_context_modification_with_drb_modification(
+        &self,
+        ue: &mut UeContext,
+    ) -> Result<Vec<(u8, Vec<u8>)>> {
+        let (r, assoc_id) = self.receive_ue_context_modification_request(ue).await?;
+        let Some(drbs_to_be_modified_list) = r.drbs_to_be_modified_list else {
+            bail!("No Drbs to be modified")
+        };
+        let mut modified_drbs = vec![];
+        for modified_drb in drbs_to_be_modified_list.0 {
+            let drb_id = modified_drb.drb_id.0;
+            ensure!(
+                ue.drbs.iter().any(|x| x.drb_id.0 == drb_id),
+                "UE has no Drb {drb_id} to modify"
+            );
+            let Some(QosInformation::DrbInformation(drb_information)) =
+                modified_drb.qos_information
+            else {
+                bail!("No DRB information in modified DRB")
+            };
+            let qfis = drb_information
+                .flows_mapped_to_drb_list
+                .0
+                .iter()
+                .map(|x| x.qos_flow_identifier.0)
+                .collect();
+            modified_drbs.push((drb_id, qfis));
+        }
+        if let Some(drbs_to_be_released_list) = r.drbs_to_be_released_list {
+            for released_drb in drbs_to_be_released_list.0.iter() {
+                let drb_id = released_drb.drb_id.0;
+                let count = ue.drbs.len();
+                ue.drbs.retain(|x| x.drb_id.0 != drb_id);
+                ensure!(ue.drbs.len() < count, "UE has no Drb {drb_id} to release");
+            }
+        }
+        self.send_ue_context_modification_response(ue, assoc_id)
+            .await?;
+        Ok(modified_drbs)
+    }
 
-        ue.drb = Some(Drb {
-            drb_id: first_drb.drb_id,
-            remote_tunnel_info: remote_tunnel_info.clone(),
-            local_teid: GtpTeid(rand::random()),
-        });
+    async fn receive_ue_context_modification_request(
+        &self,
+        ue: &mut UeContext,
+    ) -> Result<(UeContextModificationRequest, u32)> {
+        let ReceivedPdu { pdu, assoc_id } = self.receive_pdu_with_assoc_id().await?;
+        let F1apPdu::InitiatingMessage(InitiatingMessage::UeContextModificationRequest(r)) = pdu
+        else {
+            bail!("Unexpected F1ap message {:?}", pdu)
+        };
+        info!(&self.logger, "UeContextModificationRequest <<");
+        ensure!(ue.ue_id == r.gnb_du_ue_f1ap_id.0);
+        if let Some(gnb_du_ue_ambr_ul) = &r.gnb_du_ue_ambr_ul {
+            ue.gnb_du_ue_ambr_ul = Some(gnb_du_ue_ambr_ul.0);
+        }
+        Ok((r, assoc_id))
+    }
 
+    async fn send_ue_context_modification_response(
+        &self,
+        ue: &UeContext,
+        assoc_id: u32,
+    ) -> Result<()> {
+        let ue_context_modification_response = build_f1ap::ue_context_modification_response(ue)?;
+        info!(&self.logger, "UeContextModificationResponse >>");
+        self.send(ue_context_modification_response, Some(assoc_id))
+            .await;
         Ok(())
     }
 
@@ -267,6 +411,33 @@ impl MockDu {
         Ok(())
     }
 
+    /// Tell QCore that none of the UE's DRBs are active.
+    pub async fn send_ue_inactivity_notification(&self, ue: &UeContext) -> Result<()> {
+        let pdu = build_f1ap::ue_inactivity_notification(ue)?;
+        info!(self.logger, "UeInactivityNotification >>");
+        self.send(pdu, Some(ue.binding.assoc_id)).await;
+        Ok(())
+    }
+
+    /// Receive a Paging, returning the 5G-TMSI of the UE being paged.
+    pub async fn receive_paging(&self) -> Result<[u8; 4]> {
+        let pdu = self.receive_pdu().await?;
+        let F1apPdu::InitiatingMessage(InitiatingMessage::Paging(paging)) = pdu else {
+            bail!("Expected Paging, got {pdu:?}")
+        };
+        info!(&self.logger, "Paging <<");
+        let PagingIdentity::CnUePagingIdentity(CnUePagingIdentity::FiveGSTmsi(five_g_s_tmsi)) =
+            paging.paging_identity
+        else {
+            bail!("Expected 5G-S-TMSI paging identity")
+        };
+
+        // Skip the AMF set ID and AMF pointer.
+        let five_g_s_tmsi = five_g_s_tmsi.into_vec();
+        ensure!(five_g_s_tmsi.len() == 6, "Bad 5G-S-TMSI {five_g_s_tmsi:?}");
+        Ok(five_g_s_tmsi[2..].try_into()?)
+    }
+
     pub async fn handle_ue_context_release(&self, ue: &UeContext) -> Result<()> {
         // Receive release command
         let ReceivedPdu { pdu, assoc_id } = self.receive_pdu_with_assoc_id().await?;
@@ -363,12 +534,13 @@ impl MockDu {
     pub async fn send_f1u_data_packet(
         &self,
         ue: &UeContext,
+        drb_id: u8,
         src_ip: &Ipv4Addr,
         dst_ip: &Ipv4Addr,
         src_port: u16,
         dst_port: u16,
     ) -> Result<()> {
-        let drb = ue.drb.as_ref().ok_or(anyhow!("No pdu session"))?;
+        let drb = ue.drb(drb_id)?;
 
         let GtpTunnel {
             transport_layer_address,
@@ -405,8 +577,53 @@ impl MockDu {
         Ok(())
     }
 
-    pub async fn recv_f1u_data_packet(&self, ue: &UeContext) -> Result<Vec<u8>> {
-        let drb = ue.drb.as_ref().ok_or(anyhow!("No pdu session"))?;
+    pub async fn send_f1u_inner_packet(
+        &self,
+        ue: &UeContext,
+        drb_id: u8,
+        inner_packet: &[u8],
+    ) -> Result<()> {
+        let drb = ue.drb(drb_id)?;
+        let GtpTunnel {
+            transport_layer_address,
+            gtp_teid,
+        } = &drb.remote_tunnel_info;
+        self.userplane
+            .send_f1u_inner_packet(
+                transport_layer_address.clone().try_into()?,
+                gtp_teid.clone(),
+                inner_packet,
+            )
+            .await
+    }
+
+    pub async fn recv_f1u_data_packet(&self, ue: &UeContext, drb_id: u8) -> Result<Vec<u8>> {
+        let drb = ue.drb(drb_id)?;
         self.userplane.recv_data_packet(&drb.local_teid).await
     }
+
+    /// Receive a downlink packet for any of the given UEs, returning the position in `ues` of the UE that it was
+    /// sent to along with the packet.
+    pub async fn recv_f1u_data_packet_for_any(
+        &self,
+        ues: &[&UeContext],
+    ) -> Result<(usize, Vec<u8>)> {
+        let (gtp_teid, packet) = self.userplane.recv_data_packet_with_teid().await?;
+        let Some(idx) = ues
+            .iter()
+            .position(|ue| ue.drbs.iter().any(|x| x.local_teid.0 == gtp_teid.0))
+        else {
+            bail!("Downlink packet on unknown TEID {:?}", gtp_teid.0);
+        };
+        Ok((idx, packet))
+    }
+
+    /// Receive a downlink packet on any of the UE's DRBs, returning the DRB ID along with the packet.
+    pub async fn recv_f1u_data_packet_with_drb_id(&self, ue: &UeContext) -> Result<(u8, Vec<u8>)> {
+        let (gtp_teid, packet) = self.userplane.recv_data_packet_with_teid().await?;
+        let Some(drb) = ue.drbs.iter().find(|x| x.local_teid.0 == gtp_teid.0) else {
+            bail!("Downlink packet on unknown TEID {:?}", gtp_teid.0);
+        };
+        Ok((drb.drb_id.0, packet))
+    }
 }
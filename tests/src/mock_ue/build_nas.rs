@@ -1,16 +1,23 @@
-use anyhow::Result;
+use anyhow::{Result, bail};
 use oxirush_nas::{
     Nas5gmmMessage, Nas5gmmMessageType, Nas5gsMessage, Nas5gsmMessage, Nas5gsmMessageType,
-    NasAuthenticationResponseParameter, NasDeRegistrationType, NasFGsMobileIdentity,
+    NasAuthenticationResponseParameter, NasDeRegistrationType, NasDnn,
+    NasExtendedProtocolConfigurationOptions, NasFGmmCause, NasFGsMobileIdentity,
     NasFGsRegistrationType, NasFGsmCapability, NasIntegrityProtectionMaximumDataRate,
-    NasPayloadContainer, NasPayloadContainerType, NasPduSessionType, NasSscMode,
-    NasUeSecurityCapability, encode_nas_5gs_message,
+    NasKeySetIdentifier, NasMessageContainer, NasNssai, NasPayloadContainer,
+    NasPayloadContainerType, NasPduSessionStatus, NasPduSessionType, NasQosFlowDescriptions,
+    NasQosRules, NasSNssai, NasSscMode, NasUeSecurityCapability, NasUplinkDataStatus,
+    decode_nas_5gs_message, encode_nas_5gs_message,
     messages::{
-        Nas5gmmHeader, Nas5gsmHeader, NasAuthenticationResponse, NasDeregistrationRequestFromUe,
-        NasPduSessionEstablishmentRequest, NasRegistrationComplete, NasRegistrationRequest,
-        NasSecurityModeComplete, NasUlNasTransport,
+        Nas5gmmHeader, Nas5gsmHeader, NasAuthenticationFailure, NasAuthenticationResponse,
+        NasConfigurationUpdateComplete, NasDeregistrationAcceptToUe,
+        NasDeregistrationRequestFromUe, NasIdentityResponse, NasPduSessionEstablishmentRequest,
+        NasPduSessionModificationComplete, NasPduSessionModificationRequest,
+        NasPduSessionReleaseComplete, NasPduSessionReleaseRequest, NasRegistrationComplete,
+        NasRegistrationRequest, NasSecurityModeComplete, NasServiceRequest, NasUlNasTransport,
     },
 };
+use xxap::Snssai;
 
 // 5GS registration type value (octet 1, bits 1 to 3) (9.11.3.7.1)
 pub struct FivegsRegistrationType;
@@ -30,6 +37,25 @@ impl FollowOnRequest {
     pub const PENDING: u8 = 0b1;
 }
 
+// Service type value (9.11.3.50)
+pub struct ServiceType;
+#[allow(dead_code)]
+impl ServiceType {
+    pub const SIGNALLING: u8 = 0b0000;
+    pub const DATA: u8 = 0b0001;
+    pub const MOBILE_TERMINATED_SERVICES: u8 = 0b0010;
+}
+
+// TS24.501, table 9.11.4.11.1
+pub struct PduSessionType;
+impl PduSessionType {
+    pub const IPV4: u8 = 0b001;
+    pub const IPV6: u8 = 0b010;
+    pub const IPV4V6: u8 = 0b011;
+    pub const UNSTRUCTURED: u8 = 0b100;
+    pub const ETHERNET: u8 = 0b101;
+}
+
 // 24.007, table 11.2.3.1A.1
 pub struct ExtendedProtocolDiscriminator;
 impl ExtendedProtocolDiscriminator {
@@ -48,45 +74,222 @@ impl SecurityHeaderType {
     pub const INTEGRITY_PROTECTED_AND_CIPHERED_WITH_NEW_5G_NAS_SECURITY_CONTEXT: u8 = 0b0100;
 }
 
-pub fn registration_request(imsi: &String) -> Result<Vec<u8>> {
-    // Get the MSIN out of the IMSI.
+// TS24.501, table 9.11.3.4.1.
+const PROTECTION_SCHEME_NULL: u8 = 0b0000;
+const PROTECTION_SCHEME_PROFILE_A: u8 = 0b0001;
+
+fn suci_mobile_identity(imsi: &str) -> NasFGsMobileIdentity {
+    suci_mobile_identity_with_scheme(PROTECTION_SCHEME_NULL, 0, msin_bcd(imsi))
+}
+
+// Conceal the MSIN with ECIES Profile A, using a fresh ephemeral key.
+fn concealed_suci_mobile_identity(
+    imsi: &str,
+    home_network_public_key_id: u8,
+    home_network_public_key: &[u8; 32],
+) -> NasFGsMobileIdentity {
+    let scheme_output = security::suci::conceal_profile_a(
+        home_network_public_key,
+        &rand::random(),
+        &msin_bcd(imsi),
+    );
+    suci_mobile_identity_with_scheme(
+        PROTECTION_SCHEME_PROFILE_A,
+        home_network_public_key_id,
+        scheme_output,
+    )
+}
+
+fn suci_mobile_identity_with_scheme(
+    protection_scheme_id: u8,
+    home_network_public_key_id: u8,
+    scheme_output: Vec<u8>,
+) -> NasFGsMobileIdentity {
+    // Figure 9.11.3.4.3 and 9.11.3.4.3a of TS 24.501.
+    let mut suci = vec![
+        0x01, // SUPI
+        0x02,
+        0xf8,
+        0x39, // MCC and MNC = 208, 93
+        0xf0,
+        0xff, // Routing indicator digits = 0
+        protection_scheme_id,
+        home_network_public_key_id,
+    ];
+    suci.extend(scheme_output);
+    NasFGsMobileIdentity::new(suci)
+}
+
+// Get the MSIN out of the IMSI, in BCD.
+fn msin_bcd(imsi: &str) -> Vec<u8> {
     let msin: Vec<u8> = imsi[5..imsi.len()]
         .chars()
         .map(|c| c.to_digit(10).unwrap() as u8)
         .collect();
     assert!(msin.len() == 10);
+    msin.chunks(2).map(|x| x[0] | x[1] << 4).collect()
+}
 
+fn guti_mobile_identity(tmsi: &[u8; 4]) -> NasFGsMobileIdentity {
+    let mut guti = vec![
+        0b11110_010, // octet 4 , type of identity = 010 = GUTI
+        0x02,
+        0xf8,
+        0x39, // MCC and MNC = 208, 93
+        0x01, // AMF region ID
+        0x01, // AMF set ID and pointer
+        0x00,
+    ];
+    guti.extend_from_slice(tmsi);
+    NasFGsMobileIdentity::new(guti)
+}
+
+fn s_tmsi_mobile_identity(tmsi: &[u8; 4]) -> NasFGsMobileIdentity {
+    let mut s_tmsi = vec![
+        0b1111_0_100, // octet 4 , type of identity = 100 = 5G-S-TMSI
+        0x01,         // AMF set ID and pointer
+        0x00,
+    ];
+    s_tmsi.extend_from_slice(tmsi);
+    NasFGsMobileIdentity::new(s_tmsi)
+}
+
+// TS24.501, figure 9.11.3.4.3 - the first digit goes in the top half of octet 4 and the rest go two to an octet,
+// low nibble first, padded with 0xf.
+fn imeisv_mobile_identity(imeisv: &str) -> NasFGsMobileIdentity {
+    let digits: Vec<u8> = imeisv.bytes().map(|x| x - b'0').collect();
+    let odd = if digits.len() % 2 == 1 { 0b1000 } else { 0 };
+    let mut value = vec![(digits[0] << 4) | odd | 0b101]; // type of identity = 101 = IMEISV
+    for pair in digits[1..].chunks(2) {
+        value.push(pair[0] | (pair.get(1).unwrap_or(&0xf) << 4));
+    }
+    NasFGsMobileIdentity::new(value)
+}
+
+pub fn registration_request(imsi: &str, ue_security_capability: &[u8; 2]) -> Result<Vec<u8>> {
+    registration_request_with_identity(
+        FivegsRegistrationType::INITIAL_REGISTRATION,
+        suci_mobile_identity(imsi),
+        ue_security_capability,
+        None,
+        None,
+    )
+}
+
+pub fn registration_request_with_concealed_suci(
+    imsi: &str,
+    home_network_public_key_id: u8,
+    home_network_public_key: &[u8; 32],
+    ue_security_capability: &[u8; 2],
+) -> Result<Vec<u8>> {
+    registration_request_with_identity(
+        FivegsRegistrationType::INITIAL_REGISTRATION,
+        concealed_suci_mobile_identity(imsi, home_network_public_key_id, home_network_public_key),
+        ue_security_capability,
+        None,
+        None,
+    )
+}
+
+pub fn registration_request_with_guti(
+    tmsi: &[u8; 4],
+    ue_security_capability: &[u8; 2],
+) -> Result<Vec<u8>> {
+    registration_request_with_identity(
+        FivegsRegistrationType::INITIAL_REGISTRATION,
+        guti_mobile_identity(tmsi),
+        ue_security_capability,
+        None,
+        None,
+    )
+}
+
+pub fn registration_request_with_nssai(
+    imsi: &str,
+    requested_nssai: &[Snssai],
+    ue_security_capability: &[u8; 2],
+) -> Result<Vec<u8>> {
+    registration_request_with_identity(
+        FivegsRegistrationType::INITIAL_REGISTRATION,
+        suci_mobile_identity(imsi),
+        ue_security_capability,
+        Some(nas_nssai(requested_nssai)),
+        None,
+    )
+}
+
+/// A mobility registration update, giving the UE's active PDU sessions and the ones it has uplink data for.
+pub fn mobility_registration_request(
+    tmsi: &[u8; 4],
+    active_pdu_sessions: &[u8],
+    uplink_data_pdu_sessions: &[u8],
+    ue_security_capability: &[u8; 2],
+) -> Result<Vec<u8>> {
+    registration_request_with_identity(
+        FivegsRegistrationType::MOBILITY_REGISTRATION_UPDATING,
+        guti_mobile_identity(tmsi),
+        ue_security_capability,
+        None,
+        Some((
+            pdu_session_bitmap(active_pdu_sessions),
+            pdu_session_bitmap(uplink_data_pdu_sessions),
+        )),
+    )
+}
+
+/// A periodic registration update, or another registration of the given type that just gives the UE's 5G-GUTI.
+pub fn registration_request_of_type(
+    registration_type: u8,
+    tmsi: &[u8; 4],
+    ue_security_capability: &[u8; 2],
+) -> Result<Vec<u8>> {
+    registration_request_with_identity(
+        registration_type,
+        guti_mobile_identity(tmsi),
+        ue_security_capability,
+        None,
+        None,
+    )
+}
+
+// TS24.501, 9.11.3.44 and 9.11.3.57.
+fn pdu_session_bitmap(pdu_session_ids: &[u8]) -> Vec<u8> {
+    let mut bitmap = vec![0, 0];
+    for id in pdu_session_ids {
+        bitmap[*id as usize / 8] |= 1 << (id % 8);
+    }
+    bitmap
+}
+
+fn registration_request_with_identity(
+    registration_type: u8,
+    fgs_mobile_identity: NasFGsMobileIdentity,
+    ue_security_capability: &[u8; 2],
+    requested_nssai: Option<NasNssai>,
+    pdu_session_status_and_uplink_data_status: Option<(Vec<u8>, Vec<u8>)>,
+) -> Result<Vec<u8>> {
+    let (pdu_session_status, uplink_data_status) = match pdu_session_status_and_uplink_data_status {
+        Some((pdu_session_status, uplink_data_status)) => (
+            Some(NasPduSessionStatus::new(pdu_session_status)),
+            Some(NasUplinkDataStatus::new(uplink_data_status)),
+        ),
+        None => (None, None),
+    };
     let message = Nas5gmmMessage::RegistrationRequest(NasRegistrationRequest {
         fgs_registration_type: NasFGsRegistrationType::new(
-            (FollowOnRequest::PENDING << 3) | FivegsRegistrationType::INITIAL_REGISTRATION,
+            (FollowOnRequest::PENDING << 3) | registration_type,
         ),
-        fgs_mobile_identity: NasFGsMobileIdentity::new(vec![
-            // Figure 9.11.3.4.3 and 9.11.3.4.3a of TS 24.501.
-            0x01, // SUPI
-            0x02,
-            0xf8,
-            0x39, // MCC and MNC = 208, 93
-            0xf0,
-            0xff, // Routing indicator digits = 0
-            0x00, // Protection scheme: 0000 null scheme
-            0x00, // Home network public key identifier
-            msin[0] | msin[1] << 4,
-            msin[2] | msin[3] << 4,
-            msin[4] | msin[5] << 4,
-            msin[6] | msin[7] << 4,
-            msin[8] | msin[9] << 4,
-        ]),
+        fgs_mobile_identity,
         non_current_native_nas_key_set_identifier: None,
         fgmm_capability: None,
-        ue_security_capability: Some(NasUeSecurityCapability::new(vec![
-            0b10000000, // 5G EA0 only
-            0b00100000, // 5G IA2 only
-        ])),
-        requested_nssai: None,
+        ue_security_capability: Some(NasUeSecurityCapability::new(
+            ue_security_capability.to_vec(),
+        )),
+        requested_nssai,
         last_visited_registered_tai: None,
         s1_ue_network_capability: None,
-        uplink_data_status: None,
-        pdu_session_status: None,
+        uplink_data_status,
+        pdu_session_status,
         mico_indication: None,
         ue_status: None,
         additional_guti: None,
@@ -131,9 +334,84 @@ pub fn registration_request(imsi: &String) -> Result<Vec<u8>> {
     Ok(encode_nas_5gs_message(&message)?)
 }
 
-pub fn authentication_response() -> Result<Vec<u8>> {
+/// A Service Request giving the UE's active PDU sessions and the ones it has uplink data for.  Empty lists are
+/// left out.
+pub fn service_request(
+    tmsi: &[u8; 4],
+    service_type: u8,
+    active_pdu_sessions: &[u8],
+    uplink_data_pdu_sessions: &[u8],
+) -> Result<Vec<u8>> {
+    let message = Nas5gmmMessage::ServiceRequest(NasServiceRequest {
+        // Service type in the top half octet, ngKSI = 0 in the bottom
+        ngksi: NasKeySetIdentifier::new(service_type << 4),
+        fg_s_tmsi: s_tmsi_mobile_identity(tmsi),
+        uplink_data_status: (!uplink_data_pdu_sessions.is_empty())
+            .then(|| NasUplinkDataStatus::new(pdu_session_bitmap(uplink_data_pdu_sessions))),
+        pdu_session_status: (!active_pdu_sessions.is_empty())
+            .then(|| NasPduSessionStatus::new(pdu_session_bitmap(active_pdu_sessions))),
+        allowed_pdu_session_status: None,
+        nas_message_container: None,
+    });
+
+    let message = Nas5gsMessage::Gmm(
+        Nas5gmmHeader {
+            extended_protocol_discriminator: ExtendedProtocolDiscriminator::FIVEGMM,
+            security_header_type: SecurityHeaderType::PLAIN_5GS_NAS_MESSAGE_NOT_SECURITY_PROTECTED,
+            message_type: Nas5gmmMessageType::ServiceRequest,
+        },
+        message,
+    );
+    Ok(encode_nas_5gs_message(&message)?)
+}
+
+/// Move the PDU session status and uplink data status of an initial NAS message out of the clear, by putting a
+/// copy of the whole message, as ciphered by the caller, in its NAS message container - see TS24.501, 4.4.6.
+/// A message without either IE is returned unchanged.
+pub fn with_nas_message_container(
+    nas_bytes: &[u8],
+    cipher: impl FnOnce(Vec<u8>) -> Vec<u8>,
+) -> Result<Vec<u8>> {
+    let mut message = decode_nas_5gs_message(nas_bytes)?;
+    let (pdu_session_status, uplink_data_status, nas_message_container) = match &mut message {
+        Nas5gsMessage::Gmm(_, Nas5gmmMessage::RegistrationRequest(r)) => (
+            &mut r.pdu_session_status,
+            &mut r.uplink_data_status,
+            &mut r.nas_message_container,
+        ),
+        Nas5gsMessage::Gmm(_, Nas5gmmMessage::ServiceRequest(r)) => (
+            &mut r.pdu_session_status,
+            &mut r.uplink_data_status,
+            &mut r.nas_message_container,
+        ),
+        _ => bail!("Not an initial NAS message {message:?}"),
+    };
+    if pdu_session_status.is_none() && uplink_data_status.is_none() {
+        return Ok(nas_bytes.to_vec());
+    }
+    *pdu_session_status = None;
+    *uplink_data_status = None;
+    *nas_message_container = Some(NasMessageContainer::new(cipher(nas_bytes.to_vec())));
+    Ok(encode_nas_5gs_message(&message)?)
+}
+
+pub fn identity_response(imsi: &str) -> Result<Vec<u8>> {
+    let message = Nas5gsMessage::Gmm(
+        Nas5gmmHeader {
+            extended_protocol_discriminator: ExtendedProtocolDiscriminator::FIVEGMM,
+            security_header_type: SecurityHeaderType::PLAIN_5GS_NAS_MESSAGE_NOT_SECURITY_PROTECTED,
+            message_type: Nas5gmmMessageType::IdentityResponse {},
+        },
+        Nas5gmmMessage::IdentityResponse(NasIdentityResponse::new(suci_mobile_identity(imsi))),
+    );
+    Ok(encode_nas_5gs_message(&message)?)
+}
+
+pub fn authentication_response(res_star: &[u8; 16]) -> Result<Vec<u8>> {
     let message = Nas5gmmMessage::AuthenticationResponse(NasAuthenticationResponse {
-        authentication_response_parameter: Some(NasAuthenticationResponseParameter::new(vec![])),
+        authentication_response_parameter: Some(NasAuthenticationResponseParameter::new(
+            res_star.to_vec(),
+        )),
         eap_message: None,
     });
 
@@ -148,14 +426,37 @@ pub fn authentication_response() -> Result<Vec<u8>> {
     Ok(encode_nas_5gs_message(&message)?)
 }
 
-pub fn security_mode_complete() -> Result<Vec<u8>> {
+pub fn authentication_failure(fgmm_cause: u8) -> Result<Vec<u8>> {
+    let message = Nas5gsMessage::Gmm(
+        Nas5gmmHeader {
+            extended_protocol_discriminator: ExtendedProtocolDiscriminator::FIVEGMM,
+            security_header_type: SecurityHeaderType::PLAIN_5GS_NAS_MESSAGE_NOT_SECURITY_PROTECTED,
+            message_type: Nas5gmmMessageType::AuthenticationFailure {},
+        },
+        Nas5gmmMessage::AuthenticationFailure(NasAuthenticationFailure::new(NasFGmmCause::new(
+            fgmm_cause,
+        ))),
+    );
+    Ok(encode_nas_5gs_message(&message)?)
+}
+
+/// A Security Mode Complete, which normally carries a copy of the UE's Registration Request (TS24.501, 4.4.6).
+pub fn security_mode_complete(
+    imeisv: Option<&str>,
+    registration_request: Option<&[u8]>,
+) -> Result<Vec<u8>> {
     let message = Nas5gsMessage::Gmm(
         Nas5gmmHeader {
             extended_protocol_discriminator: ExtendedProtocolDiscriminator::FIVEGMM,
             security_header_type: SecurityHeaderType::PLAIN_5GS_NAS_MESSAGE_NOT_SECURITY_PROTECTED,
             message_type: Nas5gmmMessageType::SecurityModeComplete {},
         },
-        Nas5gmmMessage::SecurityModeComplete(NasSecurityModeComplete::new()),
+        Nas5gmmMessage::SecurityModeComplete(NasSecurityModeComplete {
+            imeisv: imeisv.map(imeisv_mobile_identity),
+            nas_message_container: registration_request
+                .map(|x| NasMessageContainer::new(x.to_vec())),
+            ..NasSecurityModeComplete::new()
+        }),
     );
     Ok(encode_nas_5gs_message(&message)?)
 }
@@ -172,28 +473,59 @@ pub fn registration_complete() -> Result<Vec<u8>> {
     Ok(encode_nas_5gs_message(&message)?)
 }
 
-pub fn pdu_session_establishment_request() -> Result<Vec<u8>> {
+pub fn configuration_update_complete() -> Result<Vec<u8>> {
+    let message = Nas5gsMessage::Gmm(
+        Nas5gmmHeader {
+            extended_protocol_discriminator: ExtendedProtocolDiscriminator::FIVEGMM,
+            security_header_type: SecurityHeaderType::PLAIN_5GS_NAS_MESSAGE_NOT_SECURITY_PROTECTED,
+            message_type: Nas5gmmMessageType::ConfigurationUpdateComplete {},
+        },
+        Nas5gmmMessage::ConfigurationUpdateComplete(NasConfigurationUpdateComplete::new()),
+    );
+    Ok(encode_nas_5gs_message(&message)?)
+}
+
+pub fn pdu_session_establishment_request(
+    pdu_session_id: u8,
+    pdu_session_type: u8,
+    dnn: Option<&str>,
+    snssai: Option<Snssai>,
+) -> Result<Vec<u8>> {
     // See https://www.sharetechnote.com/html/5G/5G_PDUSessionEstablishment.html for an example.
+    let mut pco = vec![
+        0x80, // TS24.008, figure 10.5.136 - ext; configuration protocol = PPP
+        0x00, 0x0d, 0x00, // DNS server IPv4 address request
+        0x00, 0x03, 0x00, // DNS server IPv6 address request
+        0x00, 0x0c, 0x00, // P-CSCF IPv4 address request
+        0x00, 0x01, 0x00, // P-CSCF IPv6 address request
+    ];
+    if pdu_session_type == PduSessionType::ETHERNET {
+        pco.extend_from_slice(&[0x00, 0x14, 0x00]); // Ethernet frame payload MTU request
+    } else {
+        pco.extend_from_slice(&[0x00, 0x10, 0x00]); // IPv4 link MTU request
+    }
     let inner_message = Nas5gsMessage::Gsm(
         Nas5gsmHeader {
             extended_protocol_discriminator: ExtendedProtocolDiscriminator::FIVEGSM,
             message_type: Nas5gsmMessageType::PduSessionEstablishmentRequest,
-            pdu_session_identity: 1,
+            pdu_session_identity: pdu_session_id,
             procedure_transaction_identity: 23,
         },
         Nas5gsmMessage::PduSessionEstablishmentRequest(NasPduSessionEstablishmentRequest {
             integrity_protection_maximum_data_rate: NasIntegrityProtectionMaximumDataRate::new(
                 0xffff,
             ),
-            pdu_session_type: Some(NasPduSessionType::new(0b001)), // IPv4 - 9.11.4.11
-            ssc_mode: Some(NasSscMode::new(0b001)),                // SSC Mode 1 - 9.11.4.16.1
+            pdu_session_type: Some(NasPduSessionType::new(pdu_session_type)), // 9.11.4.11
+            ssc_mode: Some(NasSscMode::new(0b001)), // SSC Mode 1 - 9.11.4.16.1
             fgsm_capability: Some(NasFGsmCapability::new(
                 vec![0x00], // No reflective QoS, multi-homed IPv6, Ethernet S1, TPMIC
             )),
             maximum_number_of_supported_packet_filters: None,
             always_on_pdu_session_requested: None,
             sm_pdu_dn_request_container: None,
-            extended_protocol_configuration_options: None,
+            extended_protocol_configuration_options: Some(
+                NasExtendedProtocolConfigurationOptions::new(pco),
+            ),
             ip_header_compression_configuration: None,
             ds_tt_ethernet_port_mac_address: None,
             ue_ds_tt_residence_time: None,
@@ -206,6 +538,95 @@ pub fn pdu_session_establishment_request() -> Result<Vec<u8>> {
             rsn: None,
         }),
     );
+    ul_nas_transport(inner_message, dnn, snssai)
+}
+
+pub fn pdu_session_modification_request(pdu_session_id: u8) -> Result<Vec<u8>> {
+    // Ask for a new QoS rule for uplink TCP packets to port 5201 - see TS24.501, 9.11.4.13.
+    let requested_qos_rules = NasQosRules::new(vec![
+        0x00, // QoS rule identifier = 0 (for the network to assign)
+        0x00,
+        0x0a,         // Length of QoS rule
+        0b001_0_0001, // Rule operation code 001 (create new); not the default QoS rule; 1 packet filter
+        0b00_10_0001, // Packet filter direction = 10 (uplink only); packet filter identifier = 1
+        0x05,         // Length of packet filter contents
+        0x30,         // Protocol identifier type
+        0x06,         // TCP
+        0x50,         // Single remote port type
+        0x14,
+        0x51,        // Port 5201
+        0x80,        // QoS rule precedence
+        0b00_000000, // spare; QFI 0 (for the network to assign)
+    ]);
+    // And a new QoS flow with 5QI 7 for it - see TS24.501, 9.11.4.12.
+    let requested_qos_flow_descriptions = NasQosFlowDescriptions::new(vec![
+        0x00,         // QFI 0 (for the network to assign)
+        0b001_00000,  // Operation code 001 (create new)
+        0b0_1_000001, // E = 1 (parameters list is included); 1 parameter
+        0x01,         // 5QI
+        0x01,         // Length of 5QI
+        0x07,         // 5QI 7
+    ]);
+    let inner_message = Nas5gsMessage::Gsm(
+        Nas5gsmHeader {
+            extended_protocol_discriminator: ExtendedProtocolDiscriminator::FIVEGSM,
+            message_type: Nas5gsmMessageType::PduSessionModificationRequest,
+            pdu_session_identity: pdu_session_id,
+            procedure_transaction_identity: 25,
+        },
+        Nas5gsmMessage::PduSessionModificationRequest(NasPduSessionModificationRequest {
+            requested_qos_rules: Some(requested_qos_rules),
+            requested_qos_flow_descriptions: Some(requested_qos_flow_descriptions),
+            ..NasPduSessionModificationRequest::new()
+        }),
+    );
+    ul_nas_transport(inner_message, None, None)
+}
+
+pub fn pdu_session_modification_complete(pdu_session_id: u8, pti: u8) -> Result<Vec<u8>> {
+    let inner_message = Nas5gsMessage::Gsm(
+        Nas5gsmHeader {
+            extended_protocol_discriminator: ExtendedProtocolDiscriminator::FIVEGSM,
+            message_type: Nas5gsmMessageType::PduSessionModificationComplete,
+            pdu_session_identity: pdu_session_id,
+            procedure_transaction_identity: pti,
+        },
+        Nas5gsmMessage::PduSessionModificationComplete(NasPduSessionModificationComplete::new()),
+    );
+    ul_nas_transport(inner_message, None, None)
+}
+
+pub fn pdu_session_release_request(pdu_session_id: u8) -> Result<Vec<u8>> {
+    let inner_message = Nas5gsMessage::Gsm(
+        Nas5gsmHeader {
+            extended_protocol_discriminator: ExtendedProtocolDiscriminator::FIVEGSM,
+            message_type: Nas5gsmMessageType::PduSessionReleaseRequest,
+            pdu_session_identity: pdu_session_id,
+            procedure_transaction_identity: 24,
+        },
+        Nas5gsmMessage::PduSessionReleaseRequest(NasPduSessionReleaseRequest::new()),
+    );
+    ul_nas_transport(inner_message, None, None)
+}
+
+pub fn pdu_session_release_complete(pdu_session_id: u8, pti: u8) -> Result<Vec<u8>> {
+    let inner_message = Nas5gsMessage::Gsm(
+        Nas5gsmHeader {
+            extended_protocol_discriminator: ExtendedProtocolDiscriminator::FIVEGSM,
+            message_type: Nas5gsmMessageType::PduSessionReleaseComplete,
+            pdu_session_identity: pdu_session_id,
+            procedure_transaction_identity: pti,
+        },
+        Nas5gsmMessage::PduSessionReleaseComplete(NasPduSessionReleaseComplete::new()),
+    );
+    ul_nas_transport(inner_message, None, None)
+}
+
+fn ul_nas_transport(
+    inner_message: Nas5gsMessage,
+    dnn: Option<&str>,
+    snssai: Option<Snssai>,
+) -> Result<Vec<u8>> {
     let inner_message = encode_nas_5gs_message(&inner_message)?;
 
     let outer_message = Nas5gsMessage::new_5gmm(
@@ -216,8 +637,8 @@ pub fn pdu_session_establishment_request() -> Result<Vec<u8>> {
             pdu_session_id: None,
             old_pdu_session_id: None,
             request_type: None,
-            s_nssai: None,
-            dnn: None,
+            s_nssai: snssai.map(|x| NasSNssai::new(s_nssai_value(&x))),
+            dnn: dnn.map(|x| NasDnn::new(qcore::data_networks::encode_dnn(x))),
             additional_information: None,
             ma_pdu_session_information: None,
             release_assistance_indication: None,
@@ -226,6 +647,38 @@ pub fn pdu_session_establishment_request() -> Result<Vec<u8>> {
     Ok(encode_nas_5gs_message(&outer_message)?)
 }
 
+// TS24.501, 9.11.2.8 - SST, followed by the SD if there is one.
+fn s_nssai_value(snssai: &Snssai) -> Vec<u8> {
+    let mut value = vec![snssai.0];
+    if let Some(sd) = snssai.1 {
+        value.extend_from_slice(&sd);
+    }
+    value
+}
+
+// TS24.501, 9.11.3.37 - a list of length-prefixed S-NSSAI values.
+fn nas_nssai(snssais: &[Snssai]) -> NasNssai {
+    let mut value = vec![];
+    for snssai in snssais {
+        let s_nssai = s_nssai_value(snssai);
+        value.push(s_nssai.len() as u8);
+        value.extend(s_nssai);
+    }
+    NasNssai::new(value)
+}
+
+pub fn deregistration_accept() -> Result<Vec<u8>> {
+    let message = Nas5gsMessage::Gmm(
+        Nas5gmmHeader {
+            extended_protocol_discriminator: ExtendedProtocolDiscriminator::FIVEGMM,
+            security_header_type: SecurityHeaderType::PLAIN_5GS_NAS_MESSAGE_NOT_SECURITY_PROTECTED,
+            message_type: Nas5gmmMessageType::DeregistrationAcceptToUe {},
+        },
+        Nas5gmmMessage::DeregistrationAcceptToUe(NasDeregistrationAcceptToUe::new()),
+    );
+    Ok(encode_nas_5gs_message(&message)?)
+}
+
 pub fn deregistration_request() -> Result<Vec<u8>> {
     let dereg_type = 0b0001; // 3GPP normal dereg - TS24.501, table 9.11.3.20.1
     let guti_mobile_identity = vec![
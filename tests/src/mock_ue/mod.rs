@@ -1,27 +1,72 @@
-use anyhow::{Result, anyhow, bail};
+use anyhow::{Result, anyhow, bail, ensure};
 use oxirush_nas::{
     Nas5gmmMessage, Nas5gsMessage, Nas5gsmMessage, NasPduAddress, NasPduSessionType,
     decode_nas_5gs_message,
-    messages::{NasDlNasTransport, NasPduSessionEstablishmentAccept},
+    messages::{
+        NasAuthenticationRequest, NasConfigurationUpdateCommand, NasDlNasTransport,
+        NasPduSessionEstablishmentAccept, NasPduSessionModificationCommand, NasRegistrationAccept,
+        NasSecurityModeCommand, NasServiceAccept,
+    },
 };
+use qcore::SimCreds;
 use rrc::*;
+use security::{nea2::apply_nea2_keystream, nia2::calculate_nia2_mac};
 use slog::{Logger, info, o};
-use std::net::{IpAddr, Ipv4Addr};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+use xxap::Snssai;
 mod build_nas;
 mod build_rrc;
+use crate::userplane::{
+    arp_reply_sender, arp_request, ipv6_udp_packet, router_advertisement_prefix,
+    router_solicitation,
+};
 use crate::{DuUeContext, MockDu};
+pub use build_nas::PduSessionType;
+use build_nas::{FivegsRegistrationType, SecurityHeaderType, ServiceType};
+
+// Must match the PLMN of the SUCI that the mock UE sends.
+const SERVING_NETWORK_NAME: &str = "5G:mnc093.mcc208.3gppnetwork.org";
+
+// TS24.501, table 9.11.3.2.1.
+const MAC_FAILURE: u8 = 0b00010100;
+
+struct NasSecurity {
+    knasint: [u8; 16],
+    knasenc: Option<[u8; 16]>,
+    ul_count: u32,
+}
 
 pub struct MockUe<'a> {
     imsi: String,
+    sim_creds: &'static SimCreds,
+    kamf: Option<[u8; 32]>,
+    nas_security: Option<NasSecurity>,
+    last_ul_nas: Vec<u8>,
+    registration_request: Vec<u8>,
     du: &'a MockDu,
     pub du_ue_context: DuUeContext,
     pub ipv4_addr: Ipv4Addr,
+    pub tmsi: [u8; 4],
+    /// The IMEISV that the UE gives when asked for it, or None for a UE that leaves it out.
+    pub imeisv: Option<String>,
+    /// The UE security capability - a bitmap of 5G-EA0 to 5G-EA7 then a bitmap of 5G-IA0 to 5G-IA7.
+    pub ue_security_capability: [u8; 2],
+    pub ipv6_interface_identifier: [u8; 8],
+    pub ipv6_addr: Ipv6Addr,
+    pub mac_addr: [u8; 6],
+    pub pdu_session_id: u8,
+    pub drb_id: u8,
+    /// The (QFI, DRB ID) of each QoS flow of the latest PDU session.
+    pub qos_flow_drbs: Vec<(u8, u8)>,
+    pub dns_servers: Vec<IpAddr>,
+    pub p_cscf_servers: Vec<IpAddr>,
+    pub mtu: Option<u16>,
     logger: Logger,
 }
 
 impl<'a> MockUe<'a> {
     pub async fn new(
-        imsi: String,
+        (imsi, sim_creds): (String, &'static SimCreds),
         ue_id: u32,
         du: &'a MockDu,
         cu_ip_addr: &IpAddr,
@@ -29,14 +74,198 @@ impl<'a> MockUe<'a> {
     ) -> Result<Self> {
         Ok(MockUe {
             imsi,
+            sim_creds,
+            kamf: None,
+            nas_security: None,
+            last_ul_nas: vec![],
+            registration_request: vec![],
             du,
             du_ue_context: du.new_ue_context(ue_id, cu_ip_addr).await?,
             ipv4_addr: Ipv4Addr::UNSPECIFIED,
+            tmsi: [0; 4],
+            imeisv: Some("3569380356438091".to_string()),
+            ue_security_capability: [
+                0b10100000, // 5G EA0 and EA2
+                0b00100000, // 5G IA2 only
+            ],
+            ipv6_interface_identifier: [0; 8],
+            ipv6_addr: Ipv6Addr::UNSPECIFIED,
+            mac_addr: [0x02, 0, 0, 0, 0, ue_id as u8],
+            pdu_session_id: 0,
+            drb_id: 0,
+            qos_flow_drbs: vec![],
+            dns_servers: vec![],
+            p_cscf_servers: vec![],
+            mtu: None,
             logger: logger.new(o!("ue" => ue_id)),
         })
     }
 
     pub async fn perform_rrc_setup(&mut self) -> Result<()> {
+        let registration_request =
+            build_nas::registration_request(&self.imsi, &self.ue_security_capability)?;
+        self.perform_rrc_setup_with_registration_request(registration_request)
+            .await
+    }
+
+    /// Register with a Registration Request whose UE security capability an attacker has changed on the way to
+    /// QCore.
+    pub async fn perform_rrc_setup_with_tampered_security_capability(
+        &mut self,
+        tampered_ue_security_capability: &[u8; 2],
+    ) -> Result<()> {
+        self.registration_request =
+            build_nas::registration_request(&self.imsi, &self.ue_security_capability)?;
+        let tampered_registration_request =
+            build_nas::registration_request(&self.imsi, tampered_ue_security_capability)?;
+        self.perform_rrc_setup_with_nas(tampered_registration_request)
+            .await
+    }
+
+    /// Register with a SUCI concealed using ECIES Profile A and the given home network public key.
+    pub async fn perform_rrc_setup_with_concealed_suci(
+        &mut self,
+        home_network_public_key_id: u8,
+        home_network_public_key: &[u8; 32],
+    ) -> Result<()> {
+        let registration_request = build_nas::registration_request_with_concealed_suci(
+            &self.imsi,
+            home_network_public_key_id,
+            home_network_public_key,
+            &self.ue_security_capability,
+        )?;
+        self.perform_rrc_setup_with_registration_request(registration_request)
+            .await
+    }
+
+    /// Register asking for particular network slices.
+    pub async fn perform_rrc_setup_with_requested_nssai(
+        &mut self,
+        requested_nssai: &[Snssai],
+    ) -> Result<()> {
+        let registration_request = build_nas::registration_request_with_nssai(
+            &self.imsi,
+            requested_nssai,
+            &self.ue_security_capability,
+        )?;
+        self.perform_rrc_setup_with_registration_request(registration_request)
+            .await
+    }
+
+    /// Register using a 5G-GUTI rather than a SUCI.
+    pub async fn perform_rrc_setup_with_guti(&mut self, tmsi: &[u8; 4]) -> Result<()> {
+        let registration_request =
+            build_nas::registration_request_with_guti(tmsi, &self.ue_security_capability)?;
+        self.perform_rrc_setup_with_registration_request(registration_request)
+            .await
+    }
+
+    /// Register again using the 5G-GUTI that QCore allocated, integrity protecting the Registration Request with
+    /// the UE's existing NAS security context.
+    pub async fn perform_rrc_setup_with_own_guti(&mut self) -> Result<()> {
+        let registration_request =
+            build_nas::registration_request_with_guti(&self.tmsi, &self.ue_security_capability)?;
+        self.perform_rrc_setup_with_protected_registration_request(registration_request)
+            .await
+    }
+
+    /// Come back from CM-IDLE with a periodic registration update.
+    pub async fn perform_rrc_setup_with_periodic_registration(&mut self) -> Result<()> {
+        let registration_request = build_nas::registration_request_of_type(
+            FivegsRegistrationType::PERIODIC_REGISTRATION_UPDATING,
+            &self.tmsi,
+            &self.ue_security_capability,
+        )?;
+        self.perform_rrc_setup_with_protected_registration_request(registration_request)
+            .await
+    }
+
+    /// Come back from CM-IDLE with a mobility registration update, giving the UE's active PDU sessions and the
+    /// ones it has uplink data for.
+    pub async fn perform_rrc_setup_with_mobility_registration(
+        &mut self,
+        active_pdu_sessions: &[u8],
+        uplink_data_pdu_sessions: &[u8],
+    ) -> Result<()> {
+        let registration_request = build_nas::mobility_registration_request(
+            &self.tmsi,
+            active_pdu_sessions,
+            uplink_data_pdu_sessions,
+            &self.ue_security_capability,
+        )?;
+        self.perform_rrc_setup_with_protected_registration_request(registration_request)
+            .await
+    }
+
+    async fn perform_rrc_setup_with_protected_registration_request(
+        &mut self,
+        registration_request: Vec<u8>,
+    ) -> Result<()> {
+        self.registration_request = registration_request.clone();
+        let registration_request = self.protect_initial_nas(registration_request)?;
+        self.perform_rrc_setup_with_nas(registration_request).await
+    }
+
+    /// Give the UE a new UE context at the DU, as when it comes back from CM-IDLE.
+    pub async fn new_du_ue_context(&mut self, ue_id: u32, cu_ip_addr: &IpAddr) -> Result<()> {
+        self.du_ue_context = self.du.new_ue_context(ue_id, cu_ip_addr).await?;
+        Ok(())
+    }
+
+    // Send a Registration Request, keeping a copy to send back in the Security Mode Complete.
+    async fn perform_rrc_setup_with_registration_request(
+        &mut self,
+        registration_request: Vec<u8>,
+    ) -> Result<()> {
+        self.registration_request = registration_request.clone();
+        self.perform_rrc_setup_with_nas(registration_request).await
+    }
+
+    /// Come back from CM-IDLE with a Service Request, giving the UE's active PDU sessions and the ones it has
+    /// uplink data for.
+    pub async fn perform_rrc_setup_with_service_request(
+        &mut self,
+        active_pdu_sessions: &[u8],
+        uplink_data_pdu_sessions: &[u8],
+    ) -> Result<()> {
+        self.perform_rrc_setup_with_service_type(
+            ServiceType::DATA,
+            active_pdu_sessions,
+            uplink_data_pdu_sessions,
+        )
+        .await
+    }
+
+    /// Respond to paging with a Service Request, giving the UE's active PDU sessions.
+    pub async fn perform_rrc_setup_with_paging_response(
+        &mut self,
+        active_pdu_sessions: &[u8],
+    ) -> Result<()> {
+        self.perform_rrc_setup_with_service_type(
+            ServiceType::MOBILE_TERMINATED_SERVICES,
+            active_pdu_sessions,
+            &[],
+        )
+        .await
+    }
+
+    async fn perform_rrc_setup_with_service_type(
+        &mut self,
+        service_type: u8,
+        active_pdu_sessions: &[u8],
+        uplink_data_pdu_sessions: &[u8],
+    ) -> Result<()> {
+        let service_request = build_nas::service_request(
+            &self.tmsi,
+            service_type,
+            active_pdu_sessions,
+            uplink_data_pdu_sessions,
+        )?;
+        let service_request = self.protect_initial_nas(service_request)?;
+        self.perform_rrc_setup_with_nas(service_request).await
+    }
+
+    async fn perform_rrc_setup_with_nas(&mut self, registration_request: Vec<u8>) -> Result<()> {
         let rrc_setup_request = build_rrc::setup_request();
         self.du
             .send_initial_ul_rrc(&self.du_ue_context, rrc_setup_request)
@@ -46,32 +275,200 @@ impl<'a> MockUe<'a> {
             bail!("Unexpected RRC message {:?}", message)
         };
         info!(&self.logger, "DlRrcMessageTransfer(RrcSetup) <<");
-        let registration_request = build_nas::registration_request(&self.imsi)?;
         let rrc_setup_complete =
             build_rrc::setup_complete(rrc_setup.rrc_transaction_identifier, registration_request);
-        info!(
-            &self.logger,
-            "Rrc SetupComplete + NAS Registration Request >>"
-        );
+        info!(&self.logger, "Rrc SetupComplete + NAS >>");
         self.du
             .send_ul_rrc(&mut self.du_ue_context, rrc_setup_complete)
             .await
     }
 
+    pub async fn handle_nas_identity_request(&mut self) -> Result<()> {
+        let _nas_identity_request = self.receive_nas().await?;
+        info!(&self.logger, "NAS Identity request <<");
+        let nas_identity_response = build_nas::identity_response(&self.imsi)?;
+        info!(&self.logger, "NAS Identity response >>");
+        self.send_nas(nas_identity_response).await
+    }
+
     pub async fn handle_nas_authentication(&mut self) -> Result<()> {
-        let _nas_authentication_request = self.receive_nas().await?;
+        self.respond_to_nas_authentication(false).await
+    }
+
+    /// Receive an Authentication Request and respond with a RES* that doesn't match QCore's XRES*.
+    pub async fn handle_nas_authentication_with_wrong_res(&mut self) -> Result<()> {
+        self.respond_to_nas_authentication(true).await
+    }
+
+    async fn respond_to_nas_authentication(&mut self, corrupt_res_star: bool) -> Result<()> {
+        let nas_authentication_request = self.receive_nas().await?;
         info!(&self.logger, "NAS Authentication request >>");
-        let nas_authentication_response = build_nas::authentication_response()?;
+        let Nas5gsMessage::Gmm(
+            _header,
+            Nas5gmmMessage::AuthenticationRequest(NasAuthenticationRequest {
+                authentication_parameter_rand: Some(rand),
+                authentication_parameter_autn: Some(autn),
+                ..
+            }),
+        ) = decode_nas_5gs_message(&nas_authentication_request)?
+        else {
+            bail!("Expected authentication request with RAND and AUTN")
+        };
+        let Some(response) = security::respond_to_challenge(
+            &self.sim_creds.ki,
+            &self.sim_creds.opc,
+            SERVING_NETWORK_NAME.as_bytes(),
+            &rand.value.as_slice().try_into()?,
+            &autn.value.as_slice().try_into()?,
+        ) else {
+            bail!("AUTN failed verification")
+        };
+        self.kamf = Some(security::derive_kamf(&response.kseaf, self.imsi.as_bytes()));
+        let mut res_star = response.res_star;
+        if corrupt_res_star {
+            res_star[0] ^= 0xff;
+        }
+        let nas_authentication_response = build_nas::authentication_response(&res_star)?;
         info!(&self.logger, "NAS Authentication response <<");
         self.send_nas(nas_authentication_response).await
     }
 
-    pub async fn handle_nas_security_mode(&mut self) -> Result<()> {
-        let _nas_security_mode_command = self.receive_nas().await?;
+    /// Receive an Authentication Request whose AUTN fails verification, because this UE has different
+    /// credentials to those that QCore holds for its IMSI, and respond with an Authentication Failure.
+    pub async fn handle_nas_authentication_with_mac_failure(&mut self) -> Result<()> {
+        let nas_authentication_request = self.receive_nas().await?;
+        info!(&self.logger, "NAS Authentication request <<");
+        let Nas5gsMessage::Gmm(
+            _header,
+            Nas5gmmMessage::AuthenticationRequest(NasAuthenticationRequest {
+                authentication_parameter_rand: Some(rand),
+                authentication_parameter_autn: Some(autn),
+                ..
+            }),
+        ) = decode_nas_5gs_message(&nas_authentication_request)?
+        else {
+            bail!("Expected authentication request with RAND and AUTN")
+        };
+        ensure!(
+            security::respond_to_challenge(
+                &self.sim_creds.ki,
+                &self.sim_creds.opc,
+                SERVING_NETWORK_NAME.as_bytes(),
+                &rand.value.as_slice().try_into()?,
+                &autn.value.as_slice().try_into()?,
+            )
+            .is_none(),
+            "AUTN unexpectedly passed verification"
+        );
+        let nas_authentication_failure = build_nas::authentication_failure(MAC_FAILURE)?;
+        info!(&self.logger, "NAS Authentication failure >>");
+        self.send_nas(nas_authentication_failure).await
+    }
+
+    pub async fn receive_nas_authentication_reject(&self) -> Result<()> {
+        let nas = decode_nas_5gs_message(&self.receive_nas().await?)?;
+        let Nas5gsMessage::Gmm(_header, Nas5gmmMessage::AuthenticationReject(_)) = nas else {
+            bail!("Expected authentication reject, got {nas:?}")
+        };
+        info!(&self.logger, "NAS Authentication reject <<");
+        Ok(())
+    }
+
+    /// Receive a Registration Reject, returning its 5GMM cause.
+    pub async fn receive_nas_registration_reject(&self) -> Result<u8> {
+        let mut nas = decode_nas_5gs_message(&self.receive_nas().await?)?;
+
+        // The reject is security protected if it comes after the Security Mode procedure.
+        if let Nas5gsMessage::SecurityProtected(_header, inner) = nas {
+            nas = *inner;
+        }
+        let Nas5gsMessage::Gmm(_header, Nas5gmmMessage::RegistrationReject(registration_reject)) =
+            nas
+        else {
+            bail!("Expected registration reject, got {nas:?}")
+        };
+        info!(&self.logger, "NAS Registration reject <<");
+        Ok(registration_reject.fgmm_cause.value)
+    }
+
+    /// Receive a Service Reject, returning its 5GMM cause.
+    pub async fn receive_nas_service_reject(&self) -> Result<u8> {
+        let mut nas = decode_nas_5gs_message(&self.receive_nas().await?)?;
+        if let Nas5gsMessage::SecurityProtected(_header, inner) = nas {
+            nas = *inner;
+        }
+        let Nas5gsMessage::Gmm(_header, Nas5gmmMessage::ServiceReject(service_reject)) = nas else {
+            bail!("Expected service reject, got {nas:?}")
+        };
+        info!(&self.logger, "NAS Service reject <<");
+        Ok(service_reject.fgmm_cause.value)
+    }
+
+    /// Handle a Security Mode Command, returning the identities of the ciphering and integrity algorithms that
+    /// QCore selected.
+    pub async fn handle_nas_security_mode(&mut self) -> Result<(u8, u8)> {
+        self.handle_nas_security_mode_with_container(true).await
+    }
+
+    /// Handle a Security Mode Command, leaving the copy of the Registration Request out of the Security Mode
+    /// Complete.
+    pub async fn handle_nas_security_mode_without_nas_container(&mut self) -> Result<(u8, u8)> {
+        self.handle_nas_security_mode_with_container(false).await
+    }
+
+    async fn handle_nas_security_mode_with_container(
+        &mut self,
+        include_registration_request: bool,
+    ) -> Result<(u8, u8)> {
+        let nas_security_mode_command = self.receive_nas().await?;
         info!(&self.logger, "NAS Security mode command <<");
-        let nas_security_mode_complete = build_nas::security_mode_complete()?;
+        let Nas5gsMessage::SecurityProtected(_header, inner) =
+            decode_nas_5gs_message(&nas_security_mode_command)?
+        else {
+            bail!("Expected security protected security mode command")
+        };
+        let Nas5gsMessage::Gmm(
+            _header,
+            Nas5gmmMessage::SecurityModeCommand(NasSecurityModeCommand {
+                selected_nas_security_algorithms,
+                imeisv_request,
+                ..
+            }),
+        ) = *inner
+        else {
+            bail!("Expected security mode command, got {inner:?}")
+        };
+        let Some(kamf) = &self.kamf else {
+            bail!("Security mode command before authentication")
+        };
+        // TS24.501, 9.11.3.34.
+        let ciphering_algorithm = selected_nas_security_algorithms.value >> 4;
+        let integrity_algorithm = selected_nas_security_algorithms.value & 0x0f;
+        ensure!(
+            integrity_algorithm == 2,
+            "Unsupported integrity algorithm {integrity_algorithm}"
+        );
+        self.nas_security = Some(NasSecurity {
+            knasint: security::derive_knasint(kamf, integrity_algorithm),
+            knasenc: match ciphering_algorithm {
+                0 => None,
+                2 => Some(security::derive_knasenc(kamf, ciphering_algorithm)),
+                x => bail!("Unsupported ciphering algorithm {x}"),
+            },
+            ul_count: 0,
+        });
+        // TS24.501, 9.11.3.28 - IMEISV requested.
+        let imeisv = self
+            .imeisv
+            .as_deref()
+            .filter(|_| imeisv_request.is_some_and(|x| x.value & 0b111 == 0b001));
+        let nas_security_mode_complete = build_nas::security_mode_complete(
+            imeisv,
+            include_registration_request.then_some(self.registration_request.as_slice()),
+        )?;
         info!(&self.logger, "NAS Security mode complete >>");
-        self.send_nas(nas_security_mode_complete).await
+        self.send_nas(nas_security_mode_complete).await?;
+        Ok((ciphering_algorithm, integrity_algorithm))
     }
 
     pub async fn handle_rrc_security_mode(&mut self) -> Result<()> {
@@ -89,68 +486,505 @@ impl<'a> MockUe<'a> {
             .await
     }
 
-    pub async fn handle_nas_registration_accept(&mut self) -> Result<()> {
-        let _nas_registration_accept = self.receive_nas().await?;
+    pub async fn handle_nas_registration_accept(&mut self) -> Result<NasRegistrationAccept> {
+        let nas = self.receive_security_protected_nas().await?;
+        let Nas5gsMessage::Gmm(_header, Nas5gmmMessage::RegistrationAccept(registration_accept)) =
+            nas
+        else {
+            bail!("Expected registration accept, got {nas:?}")
+        };
         info!(&self.logger, "NAS Registration Accept <<");
+
+        // TS24.501, figure 9.11.3.4.1 - the 5G-TMSI is at the end of the 5G-GUTI.
+        if let Some(guti) = &registration_accept.fg_guti {
+            let Some(tmsi) = guti.value.last_chunk() else {
+                bail!("Badly formed 5G-GUTI {:?}", guti.value)
+            };
+            self.tmsi = *tmsi;
+        }
         let nas_registration_complete = build_nas::registration_complete()?;
         info!(&self.logger, "NAS Registration Complete >>");
-        self.send_nas(nas_registration_complete).await
+        self.send_nas(nas_registration_complete).await?;
+        Ok(registration_accept)
+    }
+
+    /// Handle a UE Configuration Update Command, taking on any new 5G-GUTI and sending a Complete if
+    /// the network asks for an acknowledgement.
+    pub async fn handle_nas_configuration_update(
+        &mut self,
+    ) -> Result<NasConfigurationUpdateCommand> {
+        let nas = self.receive_security_protected_nas().await?;
+        let Nas5gsMessage::Gmm(
+            _header,
+            Nas5gmmMessage::ConfigurationUpdateCommand(configuration_update_command),
+        ) = nas
+        else {
+            bail!("Expected configuration update command, got {nas:?}")
+        };
+        info!(&self.logger, "NAS Configuration Update Command <<");
+
+        if let Some(guti) = &configuration_update_command.fg_guti {
+            let Some(tmsi) = guti.value.last_chunk() else {
+                bail!("Badly formed 5G-GUTI {:?}", guti.value)
+            };
+            self.tmsi = *tmsi;
+        }
+
+        // TS24.501, figure 9.11.3.18.1 - acknowledgement requested bit.
+        if configuration_update_command
+            .configuration_update_indication
+            .as_ref()
+            .is_some_and(|x| x.value & 0b0001 != 0)
+        {
+            let nas_configuration_update_complete = build_nas::configuration_update_complete()?;
+            info!(&self.logger, "NAS Configuration Update Complete >>");
+            self.send_nas(nas_configuration_update_complete).await?;
+        }
+        Ok(configuration_update_command)
+    }
+
+    /// Send a mobility registration update, saying which PDU sessions the UE has and which it has uplink data for.
+    pub async fn send_nas_mobility_registration_request(
+        &mut self,
+        active_pdu_sessions: &[u8],
+        uplink_data_pdu_sessions: &[u8],
+    ) -> Result<()> {
+        let registration_request = build_nas::mobility_registration_request(
+            &self.tmsi,
+            active_pdu_sessions,
+            uplink_data_pdu_sessions,
+            &self.ue_security_capability,
+        )?;
+        info!(&self.logger, "NAS Registration Request (mobility) >>");
+        self.send_nas(registration_request).await
+    }
+
+    pub async fn send_nas_emergency_registration_request(&mut self) -> Result<()> {
+        let registration_request = build_nas::registration_request_of_type(
+            FivegsRegistrationType::EMERGENCY_REGISTRATION,
+            &self.tmsi,
+            &self.ue_security_capability,
+        )?;
+        info!(&self.logger, "NAS Registration Request (emergency) >>");
+        self.send_nas(registration_request).await
     }
 
     pub async fn send_nas_pdu_session_establishment_request(&mut self) -> Result<()> {
-        let nas_session_establishment_request = build_nas::pdu_session_establishment_request()?;
+        self.send_nas_pdu_session_establishment_request_with_id(1)
+            .await
+    }
+
+    pub async fn send_nas_pdu_session_establishment_request_with_id(
+        &mut self,
+        pdu_session_id: u8,
+    ) -> Result<()> {
+        let nas_session_establishment_request = build_nas::pdu_session_establishment_request(
+            pdu_session_id,
+            PduSessionType::IPV4,
+            None,
+            None,
+        )?;
         info!(&self.logger, "NAS PDU session establishment request >>");
         self.send_nas(nas_session_establishment_request).await
     }
 
-    pub async fn handle_rrc_reconfiguration_with_session_accept(&mut self) -> Result<()> {
-        let nas_bytes = self.handle_rrc_reconfiguration().await?;
-        let nas = decode_nas_5gs_message(&nas_bytes)?;
+    /// Send a PDU Session Establishment Request whose NAS MAC gets corrupted on the way to QCore.
+    pub async fn send_nas_pdu_session_establishment_request_with_bad_mac(&mut self) -> Result<()> {
+        let nas_session_establishment_request =
+            build_nas::pdu_session_establishment_request(1, PduSessionType::IPV4, None, None)?;
+        let mut nas_bytes = self.protect_nas(nas_session_establishment_request, true);
+        nas_bytes[2] ^= 0x01;
+        info!(
+            &self.logger,
+            "NAS PDU session establishment request (bad MAC) >>"
+        );
+        self.send_protected_nas(nas_bytes).await
+    }
+
+    /// Send the last uplink NAS message again, as an attacker replaying it would.
+    pub async fn replay_last_nas(&mut self) -> Result<()> {
+        info!(&self.logger, "NAS replay of last uplink message >>");
+        self.send_protected_nas(self.last_ul_nas.clone()).await
+    }
+
+    pub async fn send_nas_pdu_session_establishment_request_with_type(
+        &mut self,
+        pdu_session_type: u8,
+    ) -> Result<()> {
+        let nas_session_establishment_request =
+            build_nas::pdu_session_establishment_request(1, pdu_session_type, None, None)?;
+        info!(
+            &self.logger,
+            "NAS PDU session establishment request (type {pdu_session_type}) >>"
+        );
+        self.send_nas(nas_session_establishment_request).await
+    }
+
+    pub async fn send_nas_pdu_session_establishment_request_with_dnn(
+        &mut self,
+        dnn: &str,
+    ) -> Result<()> {
+        let nas_session_establishment_request =
+            build_nas::pdu_session_establishment_request(1, PduSessionType::IPV4, Some(dnn), None)?;
+        info!(
+            &self.logger,
+            "NAS PDU session establishment request (DNN {dnn}) >>"
+        );
+        self.send_nas(nas_session_establishment_request).await
+    }
+
+    pub async fn send_nas_pdu_session_establishment_request_with_snssai(
+        &mut self,
+        snssai: Snssai,
+    ) -> Result<()> {
+        let nas_session_establishment_request = build_nas::pdu_session_establishment_request(
+            1,
+            PduSessionType::IPV4,
+            None,
+            Some(snssai),
+        )?;
+        info!(
+            &self.logger,
+            "NAS PDU session establishment request (S-NSSAI {snssai}) >>"
+        );
+        self.send_nas(nas_session_establishment_request).await
+    }
+
+    /// Receive a PDU Session Establishment Reject, returning its 5GSM cause.
+    pub async fn receive_nas_pdu_session_establishment_reject(&self) -> Result<u8> {
+        let nas_gsm = self.decode_dl_nas_transport(&self.receive_nas().await?)?;
+        let Nas5gsMessage::Gsm(_header, Nas5gsmMessage::PduSessionEstablishmentReject(reject)) =
+            nas_gsm
+        else {
+            bail!("Expected NasPduSessionEstablishmentReject, got {nas_gsm:?}");
+        };
+        info!(&self.logger, "NAS PDU session establishment reject <<");
+        Ok(reject.fgsm_cause.value)
+    }
+
+    /// Receive a 5GSM message that QCore didn't forward, returning its 5GMM cause.
+    pub async fn receive_nas_payload_not_forwarded(&self) -> Result<u8> {
+        let nas = decode_nas_5gs_message(&self.receive_nas().await?)?;
         let Nas5gsMessage::SecurityProtected(_header, nas_gmm) = nas else {
             bail!("Expected security protected message, got {nas:?}")
         };
         let Nas5gsMessage::Gmm(
             _header,
             Nas5gmmMessage::DlNasTransport(NasDlNasTransport {
-                payload_container, ..
+                fgmm_cause: Some(fgmm_cause),
+                ..
             }),
         ) = *nas_gmm
         else {
-            bail!("Expected NasDlNasTransport, got {nas_gmm:?}")
+            bail!("Expected NasDlNasTransport with 5GMM cause, got {nas_gmm:?}")
         };
+        info!(
+            &self.logger,
+            "NAS DL NAS transport (payload not forwarded) <<"
+        );
+        Ok(fgmm_cause.value)
+    }
 
-        let nas_gsm = decode_nas_5gs_message(&payload_container.value)?;
-        let Nas5gsMessage::Gsm(
-            _header,
-            Nas5gsmMessage::PduSessionEstablishmentAccept(NasPduSessionEstablishmentAccept {
-                selected_pdu_session_type: NasPduSessionType { value: 1, .. },
-                pdu_address:
-                    Some(NasPduAddress {
-                        value: nas_pdu_address_ie,
-                        ..
-                    }),
-                ..
-            }),
-        ) = nas_gsm
+    pub async fn handle_rrc_reconfiguration_with_session_accept(
+        &mut self,
+    ) -> Result<NasPduSessionEstablishmentAccept> {
+        let (nas_bytes, radio_bearer_config) = self.handle_rrc_reconfiguration().await?;
+        let Some(RadioBearerConfig {
+            drb_to_add_mod_list: Some(drb_to_add_mod_list),
+            ..
+        }) = radio_bearer_config
+        else {
+            bail!("Expected a DRB to be added");
+        };
+        let nas_gsm = self.decode_dl_nas_transport(&nas_bytes)?;
+        let Nas5gsMessage::Gsm(header, Nas5gsmMessage::PduSessionEstablishmentAccept(accept)) =
+            nas_gsm
         else {
             bail!("Expected NasPduSessionEstablishmentAccept, got {nas_gsm:?}");
         };
+        let NasPduSessionEstablishmentAccept {
+            selected_pdu_session_type:
+                NasPduSessionType {
+                    value: pdu_session_type,
+                    ..
+                },
+            ref pdu_address,
+            ref extended_protocol_configuration_options,
+            ..
+        } = accept;
+        if let Some(epco) = extended_protocol_configuration_options {
+            self.read_pco(&epco.value)?;
+        }
+        self.pdu_session_id = header.pdu_session_identity;
+        self.drb_id = drb_to_add_mod_list.0.head.drb_identity.0;
+        self.qos_flow_drbs = drb_to_add_mod_list
+            .0
+            .iter()
+            .flat_map(|drb| match &drb.cn_association {
+                Some(CnAssociation::SdapConfig(SdapConfig {
+                    mapped_qos_flows_to_add: Some(qfis),
+                    ..
+                })) => qfis.iter().map(|qfi| (qfi.0, drb.drb_identity.0)).collect(),
+                _ => vec![],
+            })
+            .collect();
 
-        self.ipv4_addr = Ipv4Addr::new(
-            nas_pdu_address_ie[1],
-            nas_pdu_address_ie[2],
-            nas_pdu_address_ie[3],
-            nas_pdu_address_ie[4],
+        // TS24.501, 9.11.4.10 - an Ethernet PDU session has no PDU address.
+        let Some(NasPduAddress {
+            value: nas_pdu_address_ie,
+            ..
+        }) = pdu_address
+        else {
+            ensure!(
+                pdu_session_type & 0b111 == PduSessionType::ETHERNET,
+                "Expected PDU address in {accept:?}"
+            );
+            return Ok(accept);
+        };
+        ensure!(
+            nas_pdu_address_ie[0] & 0b111 == pdu_session_type & 0b111,
+            "PDU address type doesn't match selected PDU session type {pdu_session_type}"
         );
+        let address = &nas_pdu_address_ie[1..];
+        match (pdu_session_type & 0b111, address.len()) {
+            (PduSessionType::IPV4, 4) => self.ipv4_addr = <[u8; 4]>::try_from(address)?.into(),
+            (PduSessionType::IPV6, 8) => self.ipv6_interface_identifier.copy_from_slice(address),
+            (PduSessionType::IPV4V6, 12) => {
+                self.ipv6_interface_identifier
+                    .copy_from_slice(&address[0..8]);
+                self.ipv4_addr = <[u8; 4]>::try_from(&address[8..12])?.into();
+            }
+            _ => bail!("Bad PDU address {nas_pdu_address_ie:?}"),
+        }
+        Ok(accept)
+    }
+
+    /// Send a Router Solicitation from our link-local address, and make up our IPv6 address from the prefix in
+    /// the Router Advertisement that comes back.
+    pub async fn perform_ipv6_address_autoconfiguration(&mut self) -> Result<()> {
+        let mut link_local_addr = [0u8; 16];
+        link_local_addr[0..2].copy_from_slice(&[0xfe, 0x80]);
+        link_local_addr[8..16].copy_from_slice(&self.ipv6_interface_identifier);
+        let rs = router_solicitation(&link_local_addr.into());
+        info!(&self.logger, "Router Solicitation >>");
+        self.du
+            .send_f1u_inner_packet(&self.du_ue_context, self.drb_id, &rs)
+            .await?;
+
+        self.handle_router_advertisement().await
+    }
+
+    /// Receive a Router Advertisement, and make up an IPv6 address from its prefix and the interface identifier
+    /// that QCore gave in the PDU Session Establishment Accept.
+    pub async fn handle_router_advertisement(&mut self) -> Result<()> {
+        let ra = self.recv_f1u_data_packet().await?;
+        let prefix = router_advertisement_prefix(&ra)?;
+        info!(&self.logger, "Router Advertisement << prefix {prefix}/64");
+        let mut ipv6_addr = prefix.octets();
+        ipv6_addr[8..16].copy_from_slice(&self.ipv6_interface_identifier);
+        self.ipv6_addr = ipv6_addr.into();
+        Ok(())
+    }
+
+    /// Over an Ethernet PDU session, broadcast an ARP request for `target` and return the MAC address in the
+    /// ARP reply that comes back.
+    pub async fn perform_arp(&self, src: &Ipv4Addr, target: &Ipv4Addr) -> Result<[u8; 6]> {
+        let request = arp_request(&self.mac_addr, src, target);
+        info!(&self.logger, "ARP request >> who-has {target}");
+        self.du
+            .send_f1u_inner_packet(&self.du_ue_context, self.drb_id, &request)
+            .await?;
+
+        let reply = self.recv_f1u_data_packet().await?;
+        let mac_addr = arp_reply_sender(&reply, &self.mac_addr)?;
+        info!(&self.logger, "ARP reply << {target} is-at {mac_addr:x?}");
+        Ok(mac_addr)
+    }
+
+    // Pick up the DNS servers and MTU from the PCO in a PDU Session Establishment Accept - TS24.008, 10.5.6.3.
+    fn read_pco(&mut self, epco: &[u8]) -> Result<()> {
+        self.dns_servers.clear();
+        self.p_cscf_servers.clear();
+        let mut remaining = &epco[1..];
+        while let [id_1, id_2, length, rest @ ..] = remaining {
+            let length = *length as usize;
+            ensure!(rest.len() >= length, "Truncated PCO {epco:?}");
+            let contents = &rest[..length];
+            match (u16::from_be_bytes([*id_1, *id_2]), length) {
+                (0x000d, 4) => self.dns_servers.push(<[u8; 4]>::try_from(contents)?.into()),
+                (0x0003, 16) => self
+                    .dns_servers
+                    .push(<[u8; 16]>::try_from(contents)?.into()),
+                (0x000c, 4) => self
+                    .p_cscf_servers
+                    .push(<[u8; 4]>::try_from(contents)?.into()),
+                (0x0001, 16) => self
+                    .p_cscf_servers
+                    .push(<[u8; 16]>::try_from(contents)?.into()),
+                (0x0010 | 0x0014, 2) => {
+                    self.mtu = Some(u16::from_be_bytes([contents[0], contents[1]]))
+                }
+                (id, _) => bail!("Unexpected PCO container {id:#06x}"),
+            }
+            remaining = &rest[length..];
+        }
+        ensure!(remaining.is_empty(), "Truncated PCO {epco:?}");
         Ok(())
     }
 
-    async fn handle_rrc_reconfiguration(&mut self) -> Result<Vec<u8>> {
+    pub async fn handle_rrc_reconfiguration_with_service_accept(
+        &mut self,
+    ) -> Result<NasServiceAccept> {
+        let (nas_bytes, radio_bearer_config) = self.handle_rrc_reconfiguration().await?;
+        let Some(RadioBearerConfig {
+            drb_to_add_mod_list: Some(drb_to_add_mod_list),
+            ..
+        }) = radio_bearer_config
+        else {
+            bail!("Expected DRBs to be added");
+        };
+        let nas = decode_nas_5gs_message(&nas_bytes)?;
+        let Nas5gsMessage::SecurityProtected(_header, inner) = nas else {
+            bail!("Expected security protected message, got {nas:?}")
+        };
+        let Nas5gsMessage::Gmm(_header, Nas5gmmMessage::ServiceAccept(service_accept)) = *inner
+        else {
+            bail!("Expected service accept, got {inner:?}")
+        };
+        info!(&self.logger, "NAS Service Accept <<");
+        self.drb_id = drb_to_add_mod_list.0.head.drb_identity.0;
+        Ok(service_accept)
+    }
+
+    pub async fn send_nas_pdu_session_release_request(&mut self, pdu_session_id: u8) -> Result<()> {
+        let nas_session_release_request = build_nas::pdu_session_release_request(pdu_session_id)?;
+        info!(&self.logger, "NAS PDU session release request >>");
+        self.send_nas(nas_session_release_request).await
+    }
+
+    pub async fn send_nas_pdu_session_modification_request(&mut self) -> Result<()> {
+        let nas_session_modification_request = build_nas::pdu_session_modification_request(1)?;
+        info!(&self.logger, "NAS PDU session modification request >>");
+        self.send_nas(nas_session_modification_request).await
+    }
+
+    pub async fn handle_rrc_reconfiguration_with_session_modification(
+        &mut self,
+    ) -> Result<NasPduSessionModificationCommand> {
+        let (nas_bytes, radio_bearer_config) = self.handle_rrc_reconfiguration().await?;
+        if let Some(radio_bearer_config) = radio_bearer_config {
+            self.remap_qos_flows(&radio_bearer_config);
+        }
+        let nas_gsm = self.decode_dl_nas_transport(&nas_bytes)?;
+        let Nas5gsMessage::Gsm(header, Nas5gsmMessage::PduSessionModificationCommand(command)) =
+            nas_gsm
+        else {
+            bail!("Expected NasPduSessionModificationCommand, got {nas_gsm:?}");
+        };
+        info!(&self.logger, "NAS PDU session modification command <<");
+
+        let nas_session_modification_complete = build_nas::pdu_session_modification_complete(
+            header.pdu_session_identity,
+            header.procedure_transaction_identity,
+        )?;
+        info!(&self.logger, "NAS PDU session modification complete >>");
+        self.send_nas(nas_session_modification_complete).await?;
+        Ok(command)
+    }
+
+    // Follow the changes that an RRC Reconfiguration makes to the QoS flows mapped to our DRBs, including the
+    // release of DRBs.
+    fn remap_qos_flows(&mut self, radio_bearer_config: &RadioBearerConfig) {
+        if let Some(DrbToReleaseList(drb_ids)) = &radio_bearer_config.drb_to_release_list {
+            self.qos_flow_drbs
+                .retain(|(_, drb_id)| !drb_ids.iter().any(|x| x.0 == *drb_id));
+        }
+        for drb in radio_bearer_config
+            .drb_to_add_mod_list
+            .iter()
+            .flat_map(|x| x.0.iter())
+        {
+            let Some(CnAssociation::SdapConfig(sdap_config)) = &drb.cn_association else {
+                continue;
+            };
+            if let Some(qfis) = &sdap_config.mapped_qos_flows_to_release {
+                self.qos_flow_drbs
+                    .retain(|(qfi, _)| !qfis.iter().any(|x| x.0 == *qfi));
+            }
+            if let Some(qfis) = &sdap_config.mapped_qos_flows_to_add {
+                self.qos_flow_drbs
+                    .extend(qfis.iter().map(|qfi| (qfi.0, drb.drb_identity.0)));
+            }
+        }
+    }
+
+    pub async fn handle_rrc_reconfiguration_with_session_release(&mut self) -> Result<()> {
+        let (pdu_session_id, pti) = self
+            .receive_rrc_reconfiguration_with_session_release()
+            .await?;
+        self.send_nas_pdu_session_release_complete(pdu_session_id, pti)
+            .await
+    }
+
+    /// Handle an RRC Reconfiguration with a PDU Session Release Command, without yet completing the release.
+    /// Returns the PDU session ID and PTI.
+    pub async fn receive_rrc_reconfiguration_with_session_release(&mut self) -> Result<(u8, u8)> {
+        let (nas_bytes, _) = self.handle_rrc_reconfiguration().await?;
+        let nas_gsm = self.decode_dl_nas_transport(&nas_bytes)?;
+        let Nas5gsMessage::Gsm(header, Nas5gsmMessage::PduSessionReleaseCommand(_)) = nas_gsm
+        else {
+            bail!("Expected NasPduSessionReleaseCommand, got {nas_gsm:?}");
+        };
+        info!(&self.logger, "NAS PDU session release command <<");
+        if header.pdu_session_identity == self.pdu_session_id {
+            self.ipv4_addr = Ipv4Addr::UNSPECIFIED;
+            self.ipv6_addr = Ipv6Addr::UNSPECIFIED;
+            self.pdu_session_id = 0;
+            self.drb_id = 0;
+            self.qos_flow_drbs.clear();
+        }
+        Ok((
+            header.pdu_session_identity,
+            header.procedure_transaction_identity,
+        ))
+    }
+
+    pub async fn send_nas_pdu_session_release_complete(
+        &mut self,
+        pdu_session_id: u8,
+        pti: u8,
+    ) -> Result<()> {
+        let nas_session_release_complete =
+            build_nas::pdu_session_release_complete(pdu_session_id, pti)?;
+        info!(&self.logger, "NAS PDU session release complete >>");
+        self.send_nas(nas_session_release_complete).await
+    }
+
+    // Get the 5GSM message out of a security protected DL NAS Transport.
+    fn decode_dl_nas_transport(&self, nas_bytes: &[u8]) -> Result<Nas5gsMessage> {
+        let nas = decode_nas_5gs_message(nas_bytes)?;
+        let Nas5gsMessage::SecurityProtected(_header, nas_gmm) = nas else {
+            bail!("Expected security protected message, got {nas:?}")
+        };
+        let Nas5gsMessage::Gmm(
+            _header,
+            Nas5gmmMessage::DlNasTransport(NasDlNasTransport {
+                payload_container, ..
+            }),
+        ) = *nas_gmm
+        else {
+            bail!("Expected NasDlNasTransport, got {nas_gmm:?}")
+        };
+        Ok(decode_nas_5gs_message(&payload_container.value)?)
+    }
+
+    async fn handle_rrc_reconfiguration(&mut self) -> Result<(Vec<u8>, Option<RadioBearerConfig>)> {
         let rrc = self.du.receive_rrc_dl_dcch(&self.du_ue_context).await?;
-        let nas_messages = match rrc {
+        let (nas_messages, radio_bearer_config) = match rrc {
             DlDcchMessageType::C1(C1_2::RrcReconfiguration(RrcReconfiguration {
                 critical_extensions:
                     CriticalExtensions15::RrcReconfiguration(RrcReconfigurationIEs {
+                        radio_bearer_config,
                         non_critical_extension:
                             Some(RrcReconfigurationV1530IEs {
                                 dedicated_nas_message_list: Some(x),
@@ -164,13 +998,13 @@ impl<'a> MockUe<'a> {
                     &self.logger,
                     "DlRrcMessageTransfer(RrcReconfiguration(Nas)) <<"
                 );
-                Ok(x)
+                Ok((x, radio_bearer_config))
             }
             _ => Err(anyhow!(
                 "Couldn't find NAS message list in Rrc Reconfiguration"
             )),
         }?;
-        let nas = nas_messages.head.0;
+        let nas = self.decipher_nas(nas_messages.head.0);
 
         let rrc_reconfiguration_complete =
             build_rrc::reconfiguration_complete(RrcTransactionIdentifier(0));
@@ -179,15 +1013,98 @@ impl<'a> MockUe<'a> {
             .send_ul_rrc(&mut self.du_ue_context, rrc_reconfiguration_complete)
             .await?;
 
-        Ok(nas)
+        Ok((nas, radio_bearer_config))
     }
 
     async fn send_nas(&mut self, nas_bytes: Vec<u8>) -> Result<()> {
+        let nas_bytes = self.protect_nas(nas_bytes, true);
+        self.send_protected_nas(nas_bytes).await
+    }
+
+    async fn send_protected_nas(&mut self, nas_bytes: Vec<u8>) -> Result<()> {
+        self.last_ul_nas = nas_bytes.clone();
         let rrc = build_rrc::ul_information_transfer(nas_bytes);
         info!(&self.logger, "UlInformationTransfer(Nas) >>");
         self.du.send_ul_rrc(&mut self.du_ue_context, rrc).await
     }
 
+    // Add the security header and NIA2 MAC - TS24.501, 9.1.1 and TS33.501, 6.4.3.
+    fn protect_nas(&mut self, nas_bytes: Vec<u8>, ciphered: bool) -> Vec<u8> {
+        let Some(nas_security) = &mut self.nas_security else {
+            return nas_bytes;
+        };
+        let security_header_type = if !ciphered {
+            SecurityHeaderType::INTEGRITY_PROTECTED
+        } else if nas_security.ul_count == 0 {
+            SecurityHeaderType::INTEGRITY_PROTECTED_AND_CIPHERED_WITH_NEW_5G_NAS_SECURITY_CONTEXT
+        } else {
+            SecurityHeaderType::INTEGRITY_PROTECTED_AND_CIPHERED
+        };
+        let mut protected = vec![0x7e, security_header_type, 0, 0, 0, 0];
+        protected.push((nas_security.ul_count & 0xff) as u8);
+        protected.extend_from_slice(&nas_bytes);
+        if let (true, Some(knasenc)) = (ciphered, &nas_security.knasenc) {
+            apply_nea2_keystream(
+                knasenc,
+                nas_security.ul_count.to_be_bytes(),
+                1, // bearer
+                0, // uplink
+                &mut protected[7..],
+            );
+        }
+        let mac = calculate_nia2_mac(
+            &nas_security.knasint,
+            nas_security.ul_count.to_be_bytes(),
+            1, // bearer
+            0, // uplink
+            &protected[6..],
+        );
+        protected[2..6].copy_from_slice(&mac);
+        nas_security.ul_count += 1;
+        protected
+    }
+
+    // TS24.501, 4.4.6: an initial NAS message is integrity protected but not ciphered.  Its IEs that aren't
+    // cleartext IEs go in the NAS message container, which is ciphered with the NAS COUNT of the initial message.
+    fn protect_initial_nas(&mut self, nas_bytes: Vec<u8>) -> Result<Vec<u8>> {
+        let nas_bytes = build_nas::with_nas_message_container(&nas_bytes, |mut container| {
+            if let Some(NasSecurity {
+                knasenc: Some(knasenc),
+                ul_count,
+                ..
+            }) = &self.nas_security
+            {
+                apply_nea2_keystream(
+                    knasenc,
+                    ul_count.to_be_bytes(),
+                    1, // bearer
+                    0, // uplink
+                    &mut container,
+                );
+            }
+            container
+        })?;
+        Ok(self.protect_nas(nas_bytes, false))
+    }
+
+    // Decipher a downlink message.  We assume that the downlink NAS COUNT doesn't wrap the 8-bit sequence number.
+    fn decipher_nas(&self, mut nas_bytes: Vec<u8>) -> Vec<u8> {
+        let Some(NasSecurity {
+            knasenc: Some(knasenc),
+            ..
+        }) = &self.nas_security
+        else {
+            return nas_bytes;
+        };
+        if nas_bytes.len() > 7
+            && nas_bytes[1] == SecurityHeaderType::INTEGRITY_PROTECTED_AND_CIPHERED
+        {
+            let count = nas_bytes[6] as u32;
+            apply_nea2_keystream(knasenc, count.to_be_bytes(), 1, 1, &mut nas_bytes[7..]);
+        }
+        nas_bytes
+    }
+
     pub async fn receive_nas(&self) -> Result<Vec<u8>> {
         match self.du.receive_rrc_dl_dcch(&self.du_ue_context).await? {
             DlDcchMessageType::C1(C1_2::DlInformationTransfer(DlInformationTransfer {
@@ -202,7 +1119,7 @@ impl<'a> MockUe<'a> {
                     &self.logger,
                     "DlRrcMessageTransfer(DlInformationTransfer(Nas)) <<"
                 );
-                Ok(x.0)
+                Ok(self.decipher_nas(x.0))
             }
             x => Err(anyhow!("Unexpected RRC message {:?}", x)),
         }
@@ -214,6 +1131,43 @@ impl<'a> MockUe<'a> {
         self.send_nas(nas_deregistration_request).await
     }
 
+    pub async fn receive_nas_deregistration_accept(&self) -> Result<()> {
+        let nas = self.receive_security_protected_nas().await?;
+        let Nas5gsMessage::Gmm(_header, Nas5gmmMessage::DeregistrationAcceptFromUe(_)) = nas else {
+            bail!("Expected deregistration accept, got {nas:?}")
+        };
+        info!(&self.logger, "NAS Deregistration accept <<");
+        Ok(())
+    }
+
+    /// Handle a network initiated Deregistration Request, returning whether it requires the UE to
+    /// register again.
+    pub async fn handle_nas_deregistration_request(&mut self) -> Result<bool> {
+        let nas = self.receive_security_protected_nas().await?;
+        let Nas5gsMessage::Gmm(
+            _header,
+            Nas5gmmMessage::DeregistrationRequestToUe(deregistration_request),
+        ) = nas
+        else {
+            bail!("Expected deregistration request, got {nas:?}")
+        };
+        info!(&self.logger, "NAS Deregistration request <<");
+        let nas_deregistration_accept = build_nas::deregistration_accept()?;
+        info!(&self.logger, "NAS Deregistration accept >>");
+        self.send_nas(nas_deregistration_accept).await?;
+
+        // TS24.501, figure 9.11.3.20.1 - re-registration required bit.
+        Ok(deregistration_request.de_registration_type.value & 0b0100 != 0)
+    }
+
+    async fn receive_security_protected_nas(&self) -> Result<Nas5gsMessage> {
+        let nas = decode_nas_5gs_message(&self.receive_nas().await?)?;
+        let Nas5gsMessage::SecurityProtected(_header, inner) = nas else {
+            bail!("Expected security protected message, got {nas:?}")
+        };
+        Ok(*inner)
+    }
+
     pub async fn send_f1u_data_packet(
         &self,
         dst_ip: &Ipv4Addr,
@@ -223,6 +1177,7 @@ impl<'a> MockUe<'a> {
         self.du
             .send_f1u_data_packet(
                 &self.du_ue_context,
+                self.drb_id,
                 &self.ipv4_addr,
                 dst_ip,
                 src_port,
@@ -231,7 +1186,46 @@ impl<'a> MockUe<'a> {
             .await
     }
 
+    pub async fn send_f1u_data_packet_ipv6(
+        &self,
+        dst_ip: &Ipv6Addr,
+        src_port: u16,
+        dst_port: u16,
+    ) -> Result<()> {
+        let packet = ipv6_udp_packet(&self.ipv6_addr, dst_ip, src_port, dst_port);
+        self.du
+            .send_f1u_inner_packet(&self.du_ue_context, self.drb_id, &packet)
+            .await
+    }
+
+    /// Send an uplink packet, or an Ethernet frame over an Ethernet PDU session, on the default DRB.
+    pub async fn send_f1u_inner_packet(&self, packet: &[u8]) -> Result<()> {
+        self.du
+            .send_f1u_inner_packet(&self.du_ue_context, self.drb_id, packet)
+            .await
+    }
+
     pub async fn recv_f1u_data_packet(&self) -> Result<Vec<u8>> {
-        self.du.recv_f1u_data_packet(&self.du_ue_context).await
+        self.du
+            .recv_f1u_data_packet(&self.du_ue_context, self.drb_id)
+            .await
+    }
+
+    /// Receive a downlink packet on any DRB, returning the DRB ID along with the packet.
+    pub async fn recv_f1u_data_packet_with_drb_id(&self) -> Result<(u8, Vec<u8>)> {
+        self.du
+            .recv_f1u_data_packet_with_drb_id(&self.du_ue_context)
+            .await
+    }
+
+    /// Receive a downlink packet on any DRB, when QCore is configured to send a downlink SDAP header.  Returns the
+    /// DRB ID and the QFI in the SDAP header along with the packet that follows it.
+    pub async fn recv_f1u_data_packet_with_sdap_qfi(&self) -> Result<(u8, u8, Vec<u8>)> {
+        let (drb_id, packet) = self.recv_f1u_data_packet_with_drb_id().await?;
+        let Some((sdap_header, packet)) = packet.split_first() else {
+            bail!("Missing SDAP header");
+        };
+        // RDI, RQI, QFI - see TS37.324, 6.2.2.2.
+        Ok((drb_id, sdap_header & 0x3f, packet.to_vec()))
     }
 }
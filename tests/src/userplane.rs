@@ -1,9 +1,10 @@
 #![allow(clippy::unusual_byte_groupings)]
-use anyhow::Result;
+use anyhow::{Result, bail};
 use async_net::{IpAddr, SocketAddr, UdpSocket};
 use async_std::future;
-use pnet_packet::{ipv4::MutableIpv4Packet, udp::MutableUdpPacket};
+use pnet_packet::{icmpv6::MutableIcmpv6Packet, ipv4::MutableIpv4Packet, udp::MutableUdpPacket};
 use slog::{Logger, info};
+use std::net::{Ipv4Addr, Ipv6Addr};
 use std::time::Duration;
 use xxap::GtpTeid;
 
@@ -31,25 +32,7 @@ impl MockUserplane {
         gtp_teid: GtpTeid,
         ipv4_udp_address_bytes: &[u8],
     ) -> Result<()> {
-        let addr = SocketAddr::new(remote_gtpu_ip, GTPU_PORT);
-        let gtp_teid = gtp_teid.0;
-        const GTP_MESSAGE_TYPE_GPU: u8 = 255; // TS29.281, table 6.1-1
-
         let mut packet = vec![
-            // ---- GTP header ----
-            0b001_1_0_0_0_0,      // version, PT, R, E, S, PN
-            GTP_MESSAGE_TYPE_GPU, // message type
-            0,
-            31, // length of payload
-            gtp_teid[0],
-            gtp_teid[1],
-            gtp_teid[2],
-            gtp_teid[3], // TEID
-            // ---- PDCP Data PDU for DRB with 12 bit PDCP SN ----
-            0b1_0_0_0_0000, // D/C, R,R,R, SN
-            0b00000001,     // SN
-            // ---- SDAP UPLINK DATA PDU ----
-            0b1_0_000001, // D/C, R, QFI - see TS37.324
             // ---- Inner IP header ----
             0b0100_0101, // version and header length
             0x00,        // differentiated services
@@ -73,40 +56,200 @@ impl MockUserplane {
             0x42, // Data
         ]);
 
-        let mut ipv4_packet = MutableIpv4Packet::new(&mut packet[11..31]).unwrap();
+        let mut ipv4_packet = MutableIpv4Packet::new(&mut packet[0..20]).unwrap();
         let src = ipv4_packet.get_source();
         let dst = ipv4_packet.get_destination();
         let checksum = pnet_packet::ipv4::checksum(&ipv4_packet.to_immutable());
         ipv4_packet.set_checksum(checksum);
 
-        let mut udp_packet = MutableUdpPacket::new(&mut packet[31..]).unwrap();
+        let mut udp_packet = MutableUdpPacket::new(&mut packet[20..]).unwrap();
         let checksum = pnet_packet::udp::ipv4_checksum(&udp_packet.to_immutable(), &src, &dst);
         udp_packet.set_checksum(checksum);
 
         info!(
             self.logger,
-            "Send F1U data packet with TEID {:?}, inner UDP {}:{}->{}:{}",gtp_teid, src, udp_packet.get_source(), dst, udp_packet.get_destination();
+            "Send F1U data packet with TEID {:?}, inner UDP {}:{}->{}:{}",gtp_teid.0, src, udp_packet.get_source(), dst, udp_packet.get_destination();
         );
 
+        self.send_f1u_inner_packet(remote_gtpu_ip, gtp_teid, &packet)
+            .await
+    }
+
+    /// Send an inner IP packet on a DRB's tunnel.
+    pub async fn send_f1u_inner_packet(
+        &self,
+        remote_gtpu_ip: IpAddr,
+        gtp_teid: GtpTeid,
+        inner_packet: &[u8],
+    ) -> Result<()> {
+        let addr = SocketAddr::new(remote_gtpu_ip, GTPU_PORT);
+        let gtp_teid = gtp_teid.0;
+        const GTP_MESSAGE_TYPE_GPU: u8 = 255; // TS29.281, table 6.1-1
+
+        // The GTP length covers the PDCP and SDAP headers and the inner packet.
+        let length = ((inner_packet.len() + 3) as u16).to_be_bytes();
+        let mut packet = vec![
+            // ---- GTP header ----
+            0b001_1_0_0_0_0,      // version, PT, R, E, S, PN
+            GTP_MESSAGE_TYPE_GPU, // message type
+            length[0],
+            length[1], // length of payload
+            gtp_teid[0],
+            gtp_teid[1],
+            gtp_teid[2],
+            gtp_teid[3], // TEID
+            // ---- PDCP Data PDU for DRB with 12 bit PDCP SN ----
+            0b1_0_0_0_0000, // D/C, R,R,R, SN
+            0b00000001,     // SN
+            // ---- SDAP UPLINK DATA PDU ----
+            0b1_0_000001, // D/C, R, QFI - see TS37.324
+        ];
+        packet.extend_from_slice(inner_packet);
+
         let _bytes_sent = self.gtpu_socket.send_to(&packet, addr).await?;
         Ok(())
     }
 
     pub async fn recv_data_packet(&self, _gtp_teid: &GtpTeid) -> Result<Vec<u8>> {
+        // TODO - check the TEID is as expected
+        let (_gtp_teid, inner) = self.recv_data_packet_with_teid().await?;
+        Ok(inner)
+    }
+
+    /// Receive a downlink packet, returning the TEID that it was sent to and the inner packet.
+    pub async fn recv_data_packet_with_teid(&self) -> Result<(GtpTeid, Vec<u8>)> {
         let mut buf = vec![0u8; 2000];
         let future_result = self.gtpu_socket.recv_from(&mut buf);
         let (bytes_received, _source_address) =
             future::timeout(Duration::from_secs(1), future_result).await??;
         info!(self.logger, "Received GTP-U packet for UE");
+        if bytes_received < 8 {
+            bail!("Truncated GTP-U packet");
+        }
+        let gtp_teid = GtpTeid([buf[4], buf[5], buf[6], buf[7]]);
 
-        // TODO - check the TEID is as expected (at [4..8])
+        // Extract and return the inner IP packet.  This follows
+        // - an 8-byte GTP header, plus 4 bytes and the extension headers if the E bit is set
+        // - a 2-byte PDCP header.
+        let mut offset = 8;
+        if buf[0] & 0b100 != 0 {
+            offset = 12;
+            while buf[offset - 1] != 0 {
+                offset += buf[offset] as usize * 4;
+            }
+        }
+        offset += 2;
+        if offset > bytes_received {
+            bail!("Truncated GTP-U packet");
+        }
+        let inner = buf[offset..bytes_received].to_vec();
 
-        // Extract and return the inner IP packet.  This is at offset 11, after
-        // - an 8-byte GTP header
-        // - a 2-byte PDCP header
-        // - a 1-byte SDAP header
-        let inner = buf[11..bytes_received].to_vec();
+        Ok((gtp_teid, inner))
+    }
+}
 
-        Ok(inner)
+/// Build an IPv6 UDP packet with a 1 byte payload.
+pub fn ipv6_udp_packet(src: &Ipv6Addr, dst: &Ipv6Addr, src_port: u16, dst_port: u16) -> Vec<u8> {
+    let mut packet = ipv6_header(src, dst, 17, 9); // next header = 17 = UDP
+    let src_port = src_port.to_be_bytes();
+    let dst_port = dst_port.to_be_bytes();
+    packet.extend_from_slice(&[
+        src_port[0],
+        src_port[1],
+        dst_port[0],
+        dst_port[1],
+        0x00,
+        0x09, // Length = 9
+        0x00,
+        0x00, // Checksum
+        0x42, // Data
+    ]);
+    let mut udp_packet = MutableUdpPacket::new(&mut packet[40..]).unwrap();
+    let checksum = pnet_packet::udp::ipv6_checksum(&udp_packet.to_immutable(), src, dst);
+    udp_packet.set_checksum(checksum);
+    packet
+}
+
+/// Build an ICMPv6 Router Solicitation to the all-routers address - RFC4861, 4.1.
+pub fn router_solicitation(src: &Ipv6Addr) -> Vec<u8> {
+    let dst = Ipv6Addr::new(0xff02, 0, 0, 0, 0, 0, 0, 2);
+    let mut packet = ipv6_header(src, &dst, 58, 8); // next header = 58 = ICMPv6
+    packet.extend_from_slice(&[
+        133, // type = Router Solicitation
+        0,   // code
+        0x00, 0x00, // checksum
+        0x00, 0x00, 0x00, 0x00, // reserved
+    ]);
+    let mut icmpv6_packet = MutableIcmpv6Packet::new(&mut packet[40..]).unwrap();
+    let checksum = pnet_packet::icmpv6::checksum(&icmpv6_packet.to_immutable(), src, &dst);
+    icmpv6_packet.set_checksum(checksum);
+    packet
+}
+
+/// Get the /64 prefix out of an ICMPv6 Router Advertisement's Prefix Information option - RFC4861, 4.2 and 4.6.2.
+pub fn router_advertisement_prefix(packet: &[u8]) -> Result<Ipv6Addr> {
+    if packet.len() < 56 || packet[0] >> 4 != 6 || packet[6] != 58 || packet[40] != 134 {
+        bail!("Expected Router Advertisement, got {packet:x?}");
+    }
+    let mut options = &packet[56..];
+    while let [option_type, length, ..] = options {
+        let length = *length as usize * 8;
+        if length == 0 || options.len() < length {
+            bail!("Bad option in Router Advertisement {packet:x?}");
+        }
+        if *option_type == 3 && length == 32 && options[2] == 64 {
+            let prefix = <[u8; 16]>::try_from(&options[16..32])?;
+            return Ok(prefix.into());
+        }
+        options = &options[length..];
     }
+    bail!("No prefix in Router Advertisement {packet:x?}")
+}
+
+/// Build a broadcast ARP request asking for the MAC address of `target` - RFC826.
+pub fn arp_request(src_mac: &[u8; 6], src: &Ipv4Addr, target: &Ipv4Addr) -> Vec<u8> {
+    let mut frame = vec![0xff; 6]; // destination MAC = broadcast
+    frame.extend_from_slice(src_mac);
+    frame.extend_from_slice(&[
+        0x08, 0x06, // ethertype = ARP
+        0x00, 0x01, // hardware type = Ethernet
+        0x08, 0x00, // protocol type = IPv4
+        6,    // hardware address length
+        4,    // protocol address length
+        0x00, 0x01, // operation = request
+    ]);
+    frame.extend_from_slice(src_mac);
+    frame.extend_from_slice(&src.octets());
+    frame.extend_from_slice(&[0; 6]);
+    frame.extend_from_slice(&target.octets());
+    frame
+}
+
+/// Get the sender MAC address out of an ARP reply addressed to `dst_mac` - RFC826.
+pub fn arp_reply_sender(frame: &[u8], dst_mac: &[u8; 6]) -> Result<[u8; 6]> {
+    if frame.len() < 42
+        || frame[0..6] != dst_mac[..]
+        || frame[12..14] != [0x08, 0x06]
+        || frame[20..22] != [0x00, 0x02]
+    {
+        bail!("Expected ARP reply to {dst_mac:x?}, got {frame:x?}");
+    }
+    Ok(<[u8; 6]>::try_from(&frame[22..28])?)
+}
+
+fn ipv6_header(src: &Ipv6Addr, dst: &Ipv6Addr, next_header: u8, payload_length: u16) -> Vec<u8> {
+    let payload_length = payload_length.to_be_bytes();
+    let mut header = vec![
+        0x60, // version 6
+        0x00,
+        0x00,
+        0x00, // traffic class and flow label
+        payload_length[0],
+        payload_length[1],
+        next_header,
+        255, // hop limit
+    ];
+    header.extend_from_slice(&src.octets());
+    header.extend_from_slice(&dst.octets());
+    header
 }
@@ -0,0 +1,152 @@
+use anyhow::{anyhow, bail, ensure};
+use qcore::{SimCreds, SimTable};
+use qcore_tests::{MockUe, framework::*};
+use std::net::{IpAddr, SocketAddr};
+
+// A SIM in test_sims.toml with a session AMBR of 8kbps each way.
+const LOW_AMBR_IMSI: &str = "208934444444444";
+
+// A SIM in test_sims.toml with a session AMBR of 1Mbps and a UE AMBR of 8kbps each way.
+const LOW_UE_AMBR_IMSI: &str = "208935555555555";
+
+const UE_UDP_PORT: u16 = 23216;
+const NUM_PACKETS: usize = 200;
+
+fn sim(sims: &'static SimTable, imsi: &str) -> anyhow::Result<(String, &'static SimCreds)> {
+    let (imsi, sim_creds) = sims
+        .get_key_value(imsi)
+        .ok_or(anyhow!("imsi-{imsi} missing from test SIMs"))?;
+    Ok((imsi.clone(), sim_creds))
+}
+
+#[async_std::test]
+async fn session_ambr_downlink() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) = init().await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given a UE with a PDU session whose session AMBR is 8kbps
+    let mut ue = MockUe::new(sim(sims, LOW_AMBR_IMSI)?, 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    ue.send_nas_pdu_session_establishment_request().await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+
+    // When the DN sends it a burst of downlink packets
+    let ue_addr = SocketAddr::new(IpAddr::V4(ue.ipv4_addr), UE_UDP_PORT);
+    for _ in 0..NUM_PACKETS {
+        dn.send_n6_udp_packet(ue_addr).await?;
+    }
+
+    // Then some get through, but the rest are dropped.
+    let mut received = 0;
+    while ue.recv_f1u_data_packet().await.is_ok() {
+        received += 1;
+    }
+    ensure!(received > 0, "No downlink packets got through");
+    ensure!(
+        received < NUM_PACKETS,
+        "All {NUM_PACKETS} downlink packets got through despite the session AMBR"
+    );
+    Ok(())
+}
+
+#[async_std::test]
+async fn session_ambr_uplink() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) = init().await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given a UE with a PDU session whose session AMBR is 8kbps
+    let mut ue = MockUe::new(sim(sims, LOW_AMBR_IMSI)?, 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    ue.send_nas_pdu_session_establishment_request().await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+
+    // When the UE sends a burst of uplink packets
+    let dst = dn.udp_server_addr();
+    let IpAddr::V4(dst_ip) = dst.ip() else {
+        bail!("Expected IPv4 address");
+    };
+    for _ in 0..NUM_PACKETS {
+        ue.send_f1u_data_packet(&dst_ip, UE_UDP_PORT, dst.port())
+            .await?;
+    }
+
+    // Then some get through to the data network, but the rest are dropped.
+    let received = dn.count_n6_udp_packets().await;
+    ensure!(received > 0, "No uplink packets got through");
+    ensure!(
+        received < NUM_PACKETS,
+        "All {NUM_PACKETS} uplink packets got through despite the session AMBR"
+    );
+    Ok(())
+}
+
+#[async_std::test]
+async fn ue_ambr_shared_by_sessions() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) = init().await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given a UE whose UE AMBR of 8kbps is well below its session AMBR, with two PDU sessions
+    let mut ue = MockUe::new(sim(sims, LOW_UE_AMBR_IMSI)?, 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    ue.send_nas_pdu_session_establishment_request_with_id(1)
+        .await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+    let (first_ip, first_drb) = (ue.ipv4_addr, ue.drb_id);
+    ue.send_nas_pdu_session_establishment_request_with_id(2)
+        .await?;
+    du.handle_f1_ue_context_modification_with_drb_setup(&mut ue.du_ue_context)
+        .await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+    let (second_ip, second_drb) = (ue.ipv4_addr, ue.drb_id);
+
+    // Then the DU should have been given the UE AMBR for the uplink.
+    ensure!(
+        ue.du_ue_context.gnb_du_ue_ambr_ul == Some(8000),
+        "Unexpected gNB-DU UE AMBR UL {:?}",
+        ue.du_ue_context.gnb_du_ue_ambr_ul
+    );
+
+    // When the DN sends interleaved bursts of downlink packets to both sessions
+    for _ in 0..NUM_PACKETS {
+        for ip in [first_ip, second_ip] {
+            dn.send_n6_udp_packet(SocketAddr::new(IpAddr::V4(ip), UE_UDP_PORT))
+                .await?;
+        }
+    }
+
+    // Then both sessions get some packets, but the total is limited by the one UE AMBR bucket.  Each packet is
+    // 38 bytes, so the bucket's 2000 bytes lets through about 52 - whereas, if each session had a bucket of its
+    // own, over 100 would get through.
+    let mut received = [0, 0];
+    while let Ok((drb_id, _packet)) = ue.recv_f1u_data_packet_with_drb_id().await {
+        match drb_id {
+            x if x == first_drb => received[0] += 1,
+            x if x == second_drb => received[1] += 1,
+            x => bail!("Downlink packet on unexpected DRB {x}"),
+        }
+    }
+    ensure!(
+        received[0] > 0 && received[1] > 0,
+        "Downlink packets only got through on one session - {received:?}"
+    );
+    ensure!(
+        received[0] + received[1] < 80,
+        "Too many downlink packets got through despite the UE AMBR - {received:?}"
+    );
+    Ok(())
+}
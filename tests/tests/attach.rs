@@ -10,7 +10,7 @@ async fn attach() -> anyhow::Result<()> {
     du.perform_f1_setup(qc.ip_addr()).await?;
 
     // UE registers
-    let mut ue = MockUe::new(nth_imsi(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
     ue.perform_rrc_setup().await?;
     ue.handle_nas_authentication().await?;
     ue.handle_nas_security_mode().await?;
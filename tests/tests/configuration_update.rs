@@ -0,0 +1,200 @@
+use anyhow::ensure;
+use qcore::UeConfigurationUpdate;
+use qcore_tests::{MockUe, framework::*};
+use xxap::Snssai;
+
+#[async_std::test]
+async fn network_initiated_configuration_update() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) = init().await?;
+
+    // Given a registered UE with a PDU session
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let sim = nth_sim(0, sims);
+    let imsi = sim.0.clone();
+    let mut ue = MockUe::new(sim, 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    ue.send_nas_pdu_session_establishment_request_with_id(1)
+        .await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+    let old_tmsi = ue.tmsi;
+
+    // When the operator reallocates its 5G-GUTI and sends it the TAI list and network name and time
+    qc.update_ue_configuration(
+        &imsi,
+        UeConfigurationUpdate {
+            reallocate_guti: true,
+            network_identity_and_time: true,
+            tai_list: true,
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    // Then QCore should send a Configuration Update Command with all of them, which the UE acknowledges.  Since
+    // QCore has no local time zone configured, it leaves out the time, which would have to carry one.
+    let command = ue.handle_nas_configuration_update().await?;
+    ensure!(ue.tmsi != old_tmsi, "5G-TMSI was not reallocated");
+    ensure!(command.tai_list.is_some(), "Missing TAI list");
+    ensure!(
+        command.full_name_for_network.is_some() && command.short_name_for_network.is_some(),
+        "Missing network name"
+    );
+    ensure!(
+        command.local_time_zone.is_none() && command.universal_time_and_local_time_zone.is_none(),
+        "Unexpected time without a configured time zone"
+    );
+
+    // When the UE goes idle and comes back with a Service Request using its new 5G-S-TMSI
+    du.send_ue_inactivity_notification(&ue.du_ue_context)
+        .await?;
+    du.handle_ue_context_release(&ue.du_ue_context).await?;
+    ue.new_du_ue_context(2, qc.ip_addr()).await?;
+    ue.perform_rrc_setup_with_service_request(&[1], &[1])
+        .await?;
+
+    // Then QCore should recognize it and re-establish its session.
+    ue.handle_rrc_security_mode().await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_service_accept().await?;
+    pass_through_uplink_ipv4(&ue, &dn).await?;
+    pass_through_downlink_ipv4(&dn, &ue).await
+}
+
+#[async_std::test]
+async fn network_name_and_time_after_registration() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) =
+        init_with_config(|config| config.time_zone_offset_mins = Some(60)).await?;
+
+    // Given a local time zone of UTC+1
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+
+    // When a UE registers
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+
+    // Then QCore should send it the network name and time, with the time zone as 4 quarter hours in swapped BCD.
+    let command = ue.handle_nas_configuration_update().await?;
+    ensure!(
+        command.full_name_for_network.is_some() && command.short_name_for_network.is_some(),
+        "Missing network name"
+    );
+    let local_time_zone = command.local_time_zone.map(|x| x.value);
+    ensure!(
+        local_time_zone == Some(vec![0x40]),
+        "Unexpected local time zone {local_time_zone:?}"
+    );
+    let universal_time = command.universal_time_and_local_time_zone.map(|x| x.value);
+    ensure!(
+        universal_time
+            .as_ref()
+            .is_some_and(|x| x.len() == 7 && x[6] == 0x40),
+        "Unexpected universal time and local time zone {universal_time:?}"
+    );
+    Ok(())
+}
+
+#[async_std::test]
+async fn allowed_nssai_update() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init().await?;
+
+    // Given a registered UE that is allowed slices 1 and 1-000001
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let sim = nth_sim(0, sims);
+    let imsi = sim.0.clone();
+    let mut ue = MockUe::new(sim, 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    let old_tmsi = ue.tmsi;
+
+    // When the operator restricts it to slice 1
+    qc.update_ue_configuration(
+        &imsi,
+        UeConfigurationUpdate {
+            allowed_nssai: Some(vec![Snssai(1, None)]),
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    // Then QCore should send a Configuration Update Command with just the new Allowed NSSAI.
+    let command = ue.handle_nas_configuration_update().await?;
+    let allowed_nssai = command.allowed_nssai.map(|x| x.value);
+    ensure!(
+        allowed_nssai == Some(vec![1, 1]),
+        "Unexpected allowed NSSAI {allowed_nssai:?}"
+    );
+    ensure!(ue.tmsi == old_tmsi, "Unexpected 5G-GUTI");
+    ensure!(command.tai_list.is_none(), "Unexpected TAI list");
+    ensure!(
+        command.full_name_for_network.is_none(),
+        "Unexpected network name"
+    );
+
+    // And once the UE has acknowledged it, a PDU session on slice 1-000001 should be refused.
+    ue.send_nas_pdu_session_establishment_request_with_snssai(Snssai(1, Some([0, 0, 1])))
+        .await?;
+    let cause = ue.receive_nas_payload_not_forwarded().await?;
+    ensure!(cause == 90, "Unexpected 5GMM cause {cause}");
+    Ok(())
+}
+
+#[async_std::test]
+async fn tai_list_update() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init().await?;
+
+    // Given a registered UE
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let sim = nth_sim(0, sims);
+    let imsi = sim.0.clone();
+    let mut ue = MockUe::new(sim, 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    let registration_accept = ue.handle_nas_registration_accept().await?;
+    let old_tmsi = ue.tmsi;
+
+    // When the operator sends it the TAI list
+    qc.update_ue_configuration(
+        &imsi,
+        UeConfigurationUpdate {
+            tai_list: true,
+            ..Default::default()
+        },
+    )
+    .await?;
+
+    // Then QCore should send a Configuration Update Command with just the TAI list that it gave at registration,
+    // which the UE acknowledges.
+    let command = ue.handle_nas_configuration_update().await?;
+    let tai_list = command.tai_list.map(|x| x.value);
+    ensure!(
+        tai_list.is_some() && tai_list == registration_accept.tai_list.map(|x| x.value),
+        "Unexpected TAI list {tai_list:?}"
+    );
+    ensure!(ue.tmsi == old_tmsi, "Unexpected 5G-GUTI");
+    ensure!(command.allowed_nssai.is_none(), "Unexpected allowed NSSAI");
+    ensure!(
+        command.full_name_for_network.is_none(),
+        "Unexpected network name"
+    );
+    ensure!(
+        command
+            .configuration_update_indication
+            .is_some_and(|x| x.value & 0b0001 != 0),
+        "Expected acknowledgement to be requested"
+    );
+    Ok(())
+}
@@ -6,7 +6,7 @@ async fn context_release_request() -> anyhow::Result<()> {
 
     // Given an established UE context at the DU
     du.perform_f1_setup(qc.ip_addr()).await?;
-    let mut ue = MockUe::new(nth_imsi(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
     ue.perform_rrc_setup().await?;
     ue.handle_nas_authentication().await?;
     ue.handle_nas_security_mode().await?;
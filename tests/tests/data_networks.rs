@@ -0,0 +1,164 @@
+use anyhow::ensure;
+use qcore::DataNetworkConfig;
+use qcore_tests::{MockUe, framework::*};
+use std::net::{IpAddr, Ipv4Addr, Ipv6Addr};
+
+// TS24.501, table 9.11.4.2.1.
+const MISSING_OR_UNKNOWN_DNN: u8 = 0b00011011;
+
+#[async_std::test]
+async fn data_networks() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) = init().await?;
+
+    // Given a registered UE
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+
+    // When the UE asks for a PDU session on a DNN that QCore doesn't know about
+    ue.send_nas_pdu_session_establishment_request_with_dnn("telemetry")
+        .await?;
+
+    // Then QCore should reject it with cause #27.
+    let cause = ue.receive_nas_pdu_session_establishment_reject().await?;
+    ensure!(
+        cause == MISSING_OR_UNKNOWN_DNN,
+        "Expected cause #27, got {cause}"
+    );
+
+    // And a PDU session on a configured DNN should work.
+    ue.send_nas_pdu_session_establishment_request_with_dnn("internet")
+        .await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+    pass_through_uplink_ipv4(&ue, &dn).await?;
+    pass_through_downlink_ipv4(&dn, &ue).await?;
+
+    // The UE should have been given the data network's DNS servers and MTU in answer to its PCO requests - but not
+    // its IPv6 DNS server, since this is an IPv4 PDU session.
+    ensure!(
+        ue.dns_servers == vec![IpAddr::from(TEST_DNS_SERVER_IPV4)],
+        "Unexpected DNS servers {:?}",
+        ue.dns_servers
+    );
+    ensure!(ue.mtu == Some(TEST_MTU), "Unexpected MTU {:?}", ue.mtu);
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn p_cscf_servers() -> anyhow::Result<()> {
+    // Given a data network with IPv4 and IPv6 P-CSCFs, for IMS
+    let p_cscf_ipv4 = IpAddr::from(Ipv4Addr::new(10, 255, 0, 253));
+    let p_cscf_ipv6 = IpAddr::from(Ipv6Addr::new(0xfd00, 0, 0, 0, 0, 0, 0, 0x5060));
+    let (mut du, qc, _dn, sims, logger) = init_with_config(|config| {
+        config.data_networks[0].p_cscf_servers = vec![p_cscf_ipv4, p_cscf_ipv6];
+    })
+    .await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+
+    // When a UE that asks for P-CSCF addresses in its PCO sets up an IPv4 PDU session
+    ue.send_nas_pdu_session_establishment_request().await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+
+    // Then it should be given the IPv4 P-CSCF only.
+    ensure!(
+        ue.p_cscf_servers == vec![p_cscf_ipv4],
+        "Unexpected P-CSCFs {:?}",
+        ue.p_cscf_servers
+    );
+    Ok(())
+}
+
+#[async_std::test]
+async fn second_data_network() -> anyhow::Result<()> {
+    // Given a second data network on its own tun device and UE subnet, as set up by setup-routing
+    let (mut du, qc, dn, sims, logger) = init_with_config(|config| {
+        config.data_networks.push(DataNetworkConfig {
+            dnn: "telemetry".to_string(),
+            n6_tun_name: "telemetry".to_string(),
+            n6_tap_name: None,
+            ue_subnet: Ipv4Addr::new(10, 254, 0, 0),
+            ue_ipv6_prefix: None,
+            dns_servers: vec![],
+            mtu: None,
+            p_cscf_servers: vec![],
+            qos_flows: vec![],
+        })
+    })
+    .await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // And a UE with a PDU session on the default data network
+    let mut ue_1 = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    let mut ue_2 = MockUe::new(nth_sim(1, sims), 2, &du, qc.ip_addr(), &logger).await?;
+    for ue in [&mut ue_1, &mut ue_2] {
+        ue.perform_rrc_setup().await?;
+        ue.handle_nas_authentication().await?;
+        ue.handle_nas_security_mode().await?;
+        ue.handle_rrc_security_mode().await?;
+        ue.handle_nas_registration_accept().await?;
+    }
+    ue_1.send_nas_pdu_session_establishment_request().await?;
+    du.handle_f1_ue_context_setup(&mut ue_1.du_ue_context)
+        .await?;
+    ue_1.handle_rrc_reconfiguration_with_session_accept()
+        .await?;
+
+    // When another UE asks for a PDU session on the second data network
+    ue_2.send_nas_pdu_session_establishment_request_with_dnn("telemetry")
+        .await?;
+    du.handle_f1_ue_context_setup(&mut ue_2.du_ue_context)
+        .await?;
+    ue_2.handle_rrc_reconfiguration_with_session_accept()
+        .await?;
+
+    // Then it should get the first address in that data network's subnet, since each data network has its own pool
+    // of host numbers.
+    ensure!(
+        ue_2.ipv4_addr == Ipv4Addr::new(10, 254, 0, 1),
+        "Unexpected UE IP {}",
+        ue_2.ipv4_addr
+    );
+
+    // And its traffic should be routed over the second data network's tun device.
+    let (rx_before, tx_before) = tun_packet_counts("telemetry")?;
+    pass_through_uplink_ipv4(&ue_2, &dn).await?;
+    pass_through_downlink_ipv4(&dn, &ue_2).await?;
+    let (rx_after, tx_after) = tun_packet_counts("telemetry")?;
+    ensure!(
+        rx_after > rx_before && tx_after > tx_before,
+        "UE traffic did not go over the telemetry tun device"
+    );
+
+    // While the first UE's carries on using the default one.
+    let (rx_before, tx_before) = tun_packet_counts("telemetry")?;
+    pass_through_uplink_ipv4(&ue_1, &dn).await?;
+    pass_through_downlink_ipv4(&dn, &ue_1).await?;
+    let (rx_after, tx_after) = tun_packet_counts("telemetry")?;
+    ensure!(
+        rx_after == rx_before && tx_after == tx_before,
+        "Default data network traffic went over the telemetry tun device"
+    );
+    Ok(())
+}
+
+// The number of packets that the kernel has received from and sent to a tun device.
+fn tun_packet_counts(tun_name: &str) -> anyhow::Result<(u64, u64)> {
+    let read_count = |name: &str| -> anyhow::Result<u64> {
+        let path = format!("/sys/class/net/{tun_name}/statistics/{name}");
+        Ok(std::fs::read_to_string(path)?.trim().parse()?)
+    };
+    Ok((read_count("rx_packets")?, read_count("tx_packets")?))
+}
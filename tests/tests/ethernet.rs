@@ -0,0 +1,154 @@
+use anyhow::ensure;
+use qcore_tests::{
+    DuUeContext, MockDu, MockUe, PduSessionType, arp_reply_sender, arp_request, framework::*,
+};
+use std::net::Ipv4Addr;
+
+// The host's address on the 'ue-eth' tap device - see setup-routing.
+const ETHERNET_GATEWAY: Ipv4Addr = Ipv4Addr::new(10, 253, 0, 1);
+
+// TS24.501, table 9.11.4.2.1.
+const UNKNOWN_PDU_SESSION_TYPE: u8 = 0b00011100;
+
+#[async_std::test]
+async fn ethernet_pdu_session() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) =
+        init_with_config(|config| config.data_networks[0].n6_tap_name = Some("ue-eth".to_string()))
+            .await?;
+
+    // Given a registered UE
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+
+    // When the UE asks for an Ethernet PDU session on a data network with a tap device
+    ue.send_nas_pdu_session_establishment_request_with_type(PduSessionType::ETHERNET)
+        .await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    let accept = ue.handle_rrc_reconfiguration_with_session_accept().await?;
+
+    // Then QCore should accept it without a PDU address, and give it the Ethernet frame payload MTU.
+    ensure!(
+        accept.selected_pdu_session_type.value & 0b111 == PduSessionType::ETHERNET,
+        "Expected Ethernet PDU session, got type {}",
+        accept.selected_pdu_session_type.value
+    );
+    ensure!(ue.mtu == Some(TEST_MTU), "Unexpected MTU {:?}", ue.mtu);
+
+    // When the UE broadcasts an ARP request for the host's address on the tap device
+    // Then the ARP reply should come back to the UE, now that QCore has learned its MAC address.
+    let gateway_mac = ue
+        .perform_arp(&Ipv4Addr::new(10, 253, 0, 2), &ETHERNET_GATEWAY)
+        .await?;
+    ensure!(
+        gateway_mac != ue.mac_addr,
+        "ARP reply came from the UE's own MAC address"
+    );
+    Ok(())
+}
+
+#[async_std::test]
+async fn ethernet_flooding_and_learning() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) =
+        init_with_config(|config| config.data_networks[0].n6_tap_name = Some("ue-eth".to_string()))
+            .await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given two UEs with Ethernet PDU sessions
+    let mut ue_1 = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    let mut ue_2 = MockUe::new(nth_sim(1, sims), 2, &du, qc.ip_addr(), &logger).await?;
+    for ue in [&mut ue_1, &mut ue_2] {
+        ue.perform_rrc_setup().await?;
+        ue.handle_nas_authentication().await?;
+        ue.handle_nas_security_mode().await?;
+        ue.handle_rrc_security_mode().await?;
+        ue.handle_nas_registration_accept().await?;
+        ue.send_nas_pdu_session_establishment_request_with_type(PduSessionType::ETHERNET)
+            .await?;
+        du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+        ue.handle_rrc_reconfiguration_with_session_accept().await?;
+    }
+
+    // When the first UE sends an ARP request on behalf of a device whose MAC address QCore hasn't learned
+    let unknown_mac = [0x02, 0, 0, 0, 0, 0x99];
+    let mut request = arp_request(
+        &unknown_mac,
+        &Ipv4Addr::new(10, 253, 0, 9),
+        &ETHERNET_GATEWAY,
+    );
+    request[6..12].copy_from_slice(&ue_1.mac_addr);
+    ue_1.send_f1u_inner_packet(&request).await?;
+
+    // Then the host's ARP reply to that MAC address should be flooded to both UEs.
+    let ues = [&ue_1.du_ue_context, &ue_2.du_ue_context];
+    let mut flooded_to = vec![];
+    for _ in 0..2 {
+        let (ue_idx, reply) = du.recv_f1u_data_packet_for_any(&ues).await?;
+        arp_reply_sender(&reply, &unknown_mac)?;
+        flooded_to.push(ue_idx);
+    }
+    flooded_to.sort();
+    ensure!(flooded_to == [0, 1], "Flooded to UEs {flooded_to:?}");
+
+    // When each UE does an ARP from its own MAC address
+    // Then the reply should only go to that UE, now that QCore has learned its MAC address.
+    ue_2.perform_arp(&Ipv4Addr::new(10, 253, 0, 3), &ETHERNET_GATEWAY)
+        .await?;
+    expect_no_downlink_frame(&du, &ues).await?;
+    ue_1.perform_arp(&Ipv4Addr::new(10, 253, 0, 2), &ETHERNET_GATEWAY)
+        .await?;
+    expect_no_downlink_frame(&du, &ues).await?;
+
+    // When the second UE sends a frame from the first UE's MAC address
+    // Then QCore should drop it rather than move the MAC address to the second UE's session.
+    let request = arp_request(
+        &ue_1.mac_addr,
+        &Ipv4Addr::new(10, 253, 0, 2),
+        &ETHERNET_GATEWAY,
+    );
+    ue_2.send_f1u_inner_packet(&request).await?;
+    expect_no_downlink_frame(&du, &ues).await?;
+
+    // And the first UE should keep its MAC address.
+    ue_1.perform_arp(&Ipv4Addr::new(10, 253, 0, 2), &ETHERNET_GATEWAY)
+        .await?;
+    Ok(())
+}
+
+#[async_std::test]
+async fn unstructured_pdu_session_reject() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init().await?;
+
+    // Given a registered UE
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+
+    // When the UE asks for an Unstructured PDU session, which QCore doesn't support
+    ue.send_nas_pdu_session_establishment_request_with_type(PduSessionType::UNSTRUCTURED)
+        .await?;
+
+    // Then QCore should reject it with cause #28.
+    let cause = ue.receive_nas_pdu_session_establishment_reject().await?;
+    ensure!(
+        cause == UNKNOWN_PDU_SESSION_TYPE,
+        "Expected cause #28, got {cause}"
+    );
+    Ok(())
+}
+
+async fn expect_no_downlink_frame(du: &MockDu, ues: &[&DuUeContext]) -> anyhow::Result<()> {
+    ensure!(
+        du.recv_f1u_data_packet_for_any(ues).await.is_err(),
+        "Unexpected downlink frame"
+    );
+    Ok(())
+}
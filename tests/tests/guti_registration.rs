@@ -0,0 +1,45 @@
+use qcore_tests::{MockUe, framework::*};
+
+#[async_std::test]
+async fn unknown_guti_registration() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init().await?;
+
+    // DU connects to CU
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // UE registers with a 5G-GUTI that QCore did not allocate, so QCore asks for its SUCI.
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup_with_guti(&[0x12, 0x34, 0x56, 0x78])
+        .await?;
+    ue.handle_nas_identity_request().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await
+}
+
+#[async_std::test]
+async fn known_guti_registration() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init().await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given a registered UE whose context has been released
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    du.send_ue_context_release_request(&ue.du_ue_context)
+        .await?;
+    du.handle_ue_context_release(&ue.du_ue_context).await?;
+
+    // When it registers again with the 5G-GUTI that QCore gave it, protected by its NAS security context
+    ue.new_du_ue_context(2, qc.ip_addr()).await?;
+    ue.perform_rrc_setup_with_own_guti().await?;
+
+    // Then QCore should carry on with that security context rather than authenticating it again.
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    Ok(())
+}
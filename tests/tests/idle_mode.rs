@@ -0,0 +1,222 @@
+use anyhow::ensure;
+use qcore_tests::{MockUe, framework::*};
+use std::time::Duration;
+
+#[async_std::test]
+async fn service_request_after_inactivity() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) = init().await?;
+
+    // Given a UE with PDU session 1 that the DU reports as inactive
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    ue.send_nas_pdu_session_establishment_request_with_id(1)
+        .await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+    du.send_ue_inactivity_notification(&ue.du_ue_context)
+        .await?;
+
+    // Then QCore should release its UE context, moving it to CM-IDLE.
+    du.handle_ue_context_release(&ue.du_ue_context).await?;
+
+    // When the UE comes back with a Service Request for session 1
+    ue.new_du_ue_context(2, qc.ip_addr()).await?;
+    ue.perform_rrc_setup_with_service_request(&[1], &[1])
+        .await?;
+
+    // Then QCore should resume security and set up a DRB for the session...
+    ue.handle_rrc_security_mode().await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    let service_accept = ue.handle_rrc_reconfiguration_with_service_accept().await?;
+    let pdu_session_status = service_accept.pdu_session_status.map(|x| x.value);
+    ensure!(
+        pdu_session_status == Some(vec![0b10, 0]),
+        "Unexpected PDU session status {pdu_session_status:?}"
+    );
+
+    // ...and the session should carry traffic on its original IP address.
+    pass_through_uplink_ipv4(&ue, &dn).await?;
+    pass_through_downlink_ipv4(&dn, &ue).await?;
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn service_request_after_implicit_deregistration() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init().await?;
+
+    // Given a registered UE whose context has been released
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    du.send_ue_context_release_request(&ue.du_ue_context)
+        .await?;
+    du.handle_ue_context_release(&ue.du_ue_context).await?;
+
+    // When it sends a Service Request after the mobile reachable timer has expired
+    async_std::task::sleep(Duration::from_secs(4)).await;
+    ue.new_du_ue_context(2, qc.ip_addr()).await?;
+    ue.perform_rrc_setup_with_service_request(&[], &[]).await?;
+
+    // Then QCore should reject it with 5GMM cause #9 (UE identity cannot be derived by the network)
+    // and release its context.
+    let cause = ue.receive_nas_service_reject().await?;
+    ensure!(cause == 9, "Unexpected 5GMM cause {cause}");
+    du.handle_ue_context_release(&ue.du_ue_context).await
+}
+
+#[async_std::test]
+async fn paging_for_downlink_data() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) = init().await?;
+
+    // Given an idle UE with PDU session 1
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    ue.send_nas_pdu_session_establishment_request_with_id(1)
+        .await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+    du.send_ue_inactivity_notification(&ue.du_ue_context)
+        .await?;
+    du.handle_ue_context_release(&ue.du_ue_context).await?;
+
+    // When a downlink packet arrives for it
+    send_downlink_ipv4_to_idle_ue(&dn, &ue).await?;
+
+    // Then QCore should page it using its 5G-S-TMSI...
+    let tmsi = du.receive_paging().await?;
+    ensure!(tmsi == ue.tmsi, "Paged {tmsi:?} rather than {:?}", ue.tmsi);
+
+    // ...and page it again after the 1 second paging retransmission timer if it misses the first one.
+    async_std::task::sleep(Duration::from_millis(600)).await;
+    let tmsi = du.receive_paging().await?;
+    ensure!(tmsi == ue.tmsi, "Paged {tmsi:?} rather than {:?}", ue.tmsi);
+
+    // When the UE responds with a Service Request
+    ue.new_du_ue_context(2, qc.ip_addr()).await?;
+    ue.perform_rrc_setup_with_paging_response(&[1]).await?;
+    ue.handle_rrc_security_mode().await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_service_accept().await?;
+
+    // Then QCore should deliver the buffered packet, and the session should carry traffic as normal...
+    let _buffered_packet = ue.recv_f1u_data_packet().await?;
+    pass_through_uplink_ipv4(&ue, &dn).await?;
+    pass_through_downlink_ipv4(&dn, &ue).await?;
+
+    // ...and QCore should give the UE a new 5G-GUTI, since the paging exposed its 5G-S-TMSI.
+    let command = ue.handle_nas_configuration_update().await?;
+    ensure!(command.fg_guti.is_some(), "Expected a new 5G-GUTI");
+    ensure!(ue.tmsi != tmsi, "5G-TMSI was not reallocated");
+
+    Ok(())
+}
+
+// The UDP payload of a downlink IPv4 packet follows the 20 byte IPv4 header and 8 byte UDP header.
+fn udp_payload(ip_packet: &[u8]) -> &[u8] {
+    ip_packet.get(28..).unwrap_or_default()
+}
+
+#[async_std::test]
+async fn downlink_buffer_full() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) =
+        init_with_config(|config| config.downlink_buffer_max_packets = 3).await?;
+
+    // Given an idle UE with PDU session 1, and room to buffer 3 downlink packets
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    ue.send_nas_pdu_session_establishment_request_with_id(1)
+        .await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+    du.send_ue_inactivity_notification(&ue.du_ue_context)
+        .await?;
+    du.handle_ue_context_release(&ue.du_ue_context).await?;
+
+    // When 5 downlink packets arrive for it, and it then responds to paging
+    for n in 1..=5 {
+        send_downlink_ipv4_payload_to_idle_ue(&dn, &ue, &[n; 10]).await?;
+    }
+    let _tmsi = du.receive_paging().await?;
+    ue.new_du_ue_context(2, qc.ip_addr()).await?;
+    ue.perform_rrc_setup_with_paging_response(&[1]).await?;
+    ue.handle_rrc_security_mode().await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_service_accept().await?;
+
+    // Then QCore should deliver the first 3 packets in order, and have dropped the rest.
+    for n in 1..=3 {
+        let packet = ue.recv_f1u_data_packet().await?;
+        ensure!(
+            udp_payload(&packet) == [n; 10],
+            "Expected buffered packet {n}, got {packet:x?}"
+        );
+    }
+    ensure!(
+        ue.recv_f1u_data_packet().await.is_err(),
+        "Expected packets beyond the buffer limit to be dropped"
+    );
+    ue.handle_nas_configuration_update().await?;
+    Ok(())
+}
+
+#[async_std::test]
+async fn downlink_buffer_expiry() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) = init_with_config(|config| {
+        config.downlink_buffer_max_age = Duration::from_millis(500);
+    })
+    .await?;
+
+    // Given an idle UE with PDU session 1, and a downlink buffer that holds packets for half a second
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    ue.send_nas_pdu_session_establishment_request_with_id(1)
+        .await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+    du.send_ue_inactivity_notification(&ue.du_ue_context)
+        .await?;
+    du.handle_ue_context_release(&ue.du_ue_context).await?;
+
+    // When a downlink packet arrives for it, but it takes longer than that to respond to paging
+    send_downlink_ipv4_payload_to_idle_ue(&dn, &ue, &[1; 10]).await?;
+    let _tmsi = du.receive_paging().await?;
+    async_std::task::sleep(Duration::from_millis(600)).await;
+    ue.new_du_ue_context(2, qc.ip_addr()).await?;
+    ue.perform_rrc_setup_with_paging_response(&[1]).await?;
+    ue.handle_rrc_security_mode().await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_service_accept().await?;
+
+    // Then QCore should have dropped the packet, while new packets get through.
+    ensure!(
+        ue.recv_f1u_data_packet().await.is_err(),
+        "Expected the expired packet to be dropped"
+    );
+    ue.handle_nas_configuration_update().await?;
+    pass_through_downlink_ipv4(&dn, &ue).await
+}
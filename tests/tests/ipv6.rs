@@ -0,0 +1,170 @@
+use anyhow::ensure;
+use qcore_tests::{MockUe, PduSessionType, framework::*};
+
+// TS24.501, table 9.11.4.2.1.
+const PDU_SESSION_TYPE_IPV4_ONLY_ALLOWED: u8 = 0b00110010;
+
+#[async_std::test]
+async fn ipv4v6_pdu_session() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) = init().await?;
+
+    // Given a registered UE
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+
+    // When the UE asks for an IPv4v6 PDU session on a data network with an IPv6 prefix
+    ue.send_nas_pdu_session_establishment_request_with_type(PduSessionType::IPV4V6)
+        .await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    let accept = ue.handle_rrc_reconfiguration_with_session_accept().await?;
+
+    // Then QCore should give it both an IPv4 address and an IPv6 interface identifier.
+    ensure!(
+        accept.selected_pdu_session_type.value & 0b111 == PduSessionType::IPV4V6,
+        "Expected IPv4v6 PDU session, got type {}",
+        accept.selected_pdu_session_type.value
+    );
+
+    // And send it an unsolicited Router Advertisement for a /64 prefix from the data network's /56.
+    ue.handle_router_advertisement().await?;
+    check_ipv6_prefix(&ue)?;
+    pass_through_uplink_ipv4(&ue, &dn).await?;
+    pass_through_downlink_ipv4(&dn, &ue).await?;
+
+    // When the UE sends a Router Solicitation
+    // Then QCore should answer with another Router Advertisement for the same prefix.
+    ue.perform_ipv6_address_autoconfiguration().await?;
+    check_ipv6_prefix(&ue)
+}
+
+#[async_std::test]
+async fn ipv6_pdu_session() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init().await?;
+
+    // Given a registered UE
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+
+    // When the UE asks for an IPv6 PDU session on a data network with an IPv6 prefix
+    ue.send_nas_pdu_session_establishment_request_with_type(PduSessionType::IPV6)
+        .await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    let accept = ue.handle_rrc_reconfiguration_with_session_accept().await?;
+
+    // Then QCore should give it an IPv6 session and a Router Advertisement.
+    ensure!(
+        accept.selected_pdu_session_type.value & 0b111 == PduSessionType::IPV6,
+        "Expected IPv6 PDU session, got type {}",
+        accept.selected_pdu_session_type.value
+    );
+    ue.handle_router_advertisement().await?;
+    check_ipv6_prefix(&ue)
+}
+
+#[async_std::test]
+#[ignore = "needs a local IPv6 address for the data network's UDP server"]
+async fn ipv6_pass_through() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) = init().await?;
+
+    // Given a UE with an IPv6 PDU session and an address from its Router Advertisement
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    ue.send_nas_pdu_session_establishment_request_with_type(PduSessionType::IPV6)
+        .await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+    ue.handle_router_advertisement().await?;
+
+    // Then IPv6 packets should pass through in both directions.
+    pass_through_uplink_ipv6(&ue, &dn).await?;
+    pass_through_downlink_ipv6(&dn, &ue).await
+}
+
+#[async_std::test]
+async fn ipv4v6_pdu_session_falls_back_to_ipv4() -> anyhow::Result<()> {
+    // Given a data network without an IPv6 prefix
+    let (mut du, qc, dn, sims, logger) = init_with_config(|config| {
+        config.data_networks[0].ue_ipv6_prefix = None;
+    })
+    .await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+
+    // When a UE asks for an IPv4v6 PDU session
+    ue.send_nas_pdu_session_establishment_request_with_type(PduSessionType::IPV4V6)
+        .await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    let accept = ue.handle_rrc_reconfiguration_with_session_accept().await?;
+
+    // Then QCore should give it an IPv4 one, with 5GSM cause #50.
+    ensure!(
+        accept.selected_pdu_session_type.value & 0b111 == PduSessionType::IPV4,
+        "Expected IPv4 PDU session, got type {}",
+        accept.selected_pdu_session_type.value
+    );
+    let cause = accept.fgsm_cause.map(|x| x.value);
+    ensure!(
+        cause == Some(PDU_SESSION_TYPE_IPV4_ONLY_ALLOWED),
+        "Expected cause #50, got {cause:?}"
+    );
+    pass_through_uplink_ipv4(&ue, &dn).await?;
+    pass_through_downlink_ipv4(&dn, &ue).await
+}
+
+#[async_std::test]
+async fn ipv6_pdu_session_reject() -> anyhow::Result<()> {
+    // Given a data network without an IPv6 prefix
+    let (mut du, qc, _dn, sims, logger) = init_with_config(|config| {
+        config.data_networks[0].ue_ipv6_prefix = None;
+    })
+    .await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+
+    // When a UE asks for an IPv6 PDU session
+    ue.send_nas_pdu_session_establishment_request_with_type(PduSessionType::IPV6)
+        .await?;
+
+    // Then QCore should reject it with 5GSM cause #50.
+    let cause = ue.receive_nas_pdu_session_establishment_reject().await?;
+    ensure!(
+        cause == PDU_SESSION_TYPE_IPV4_ONLY_ALLOWED,
+        "Expected cause #50, got {cause}"
+    );
+    Ok(())
+}
+
+// The UE's address should be in a /64 prefix from the test data network's fd00:0:0:ff00::/56.
+fn check_ipv6_prefix(ue: &MockUe<'_>) -> anyhow::Result<()> {
+    ensure!(
+        ue.ipv6_addr.segments()[0..3] == [0xfd00, 0, 0] && ue.ipv6_addr.octets()[6] == 0xff,
+        "Unexpected IPv6 address {}",
+        ue.ipv6_addr
+    );
+    Ok(())
+}
@@ -0,0 +1,60 @@
+use anyhow::ensure;
+use qcore_tests::{MockUe, framework::*};
+
+#[async_std::test]
+async fn multiple_pdu_sessions() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) = init().await?;
+
+    // Given a registered UE with a PDU session
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    ue.send_nas_pdu_session_establishment_request_with_id(1)
+        .await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+    let (first_ip, first_drb) = (ue.ipv4_addr, ue.drb_id);
+    let first_ue_ambr_ul = ue.du_ue_context.gnb_du_ue_ambr_ul;
+
+    // When the UE establishes a second PDU session
+    ue.send_nas_pdu_session_establishment_request_with_id(2)
+        .await?;
+
+    // Then QCore should add a second DRB with a UE Context Modification
+    du.handle_f1_ue_context_modification_with_drb_setup(&mut ue.du_ue_context)
+        .await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+    ensure!(ue.ipv4_addr != first_ip, "Sessions share an IP address");
+    ensure!(ue.drb_id != first_drb, "Sessions share a DRB");
+
+    // And, since the UE has no subscribed UE AMBR, tell the DU that its UE AMBR is now the sum of the two
+    // session AMBRs.
+    ensure!(
+        ue.du_ue_context.gnb_du_ue_ambr_ul == first_ue_ambr_ul.map(|x| x * 2),
+        "Expected gNB-DU UE AMBR UL to double from {first_ue_ambr_ul:?}, got {:?}",
+        ue.du_ue_context.gnb_du_ue_ambr_ul
+    );
+
+    // And the second session should carry traffic.
+    pass_through_uplink_ipv4(&ue, &dn).await?;
+    pass_through_downlink_ipv4(&dn, &ue).await?;
+
+    // And it should survive the release of the first session.
+    ue.send_nas_pdu_session_release_request(1).await?;
+    du.handle_f1_ue_context_modification_with_drb_release(&mut ue.du_ue_context)
+        .await?;
+    ue.handle_rrc_reconfiguration_with_session_release().await?;
+    ensure!(
+        ue.du_ue_context.gnb_du_ue_ambr_ul == first_ue_ambr_ul,
+        "Expected gNB-DU UE AMBR UL to drop back to {first_ue_ambr_ul:?}, got {:?}",
+        ue.du_ue_context.gnb_du_ue_ambr_ul
+    );
+    pass_through_uplink_ipv4(&ue, &dn).await?;
+    pass_through_downlink_ipv4(&dn, &ue).await?;
+
+    Ok(())
+}
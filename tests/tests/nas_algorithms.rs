@@ -0,0 +1,157 @@
+use anyhow::ensure;
+use qcore::NasCipheringAlgorithm;
+use qcore_tests::{MockUe, framework::*};
+
+// TS24.501, 9.11.3.54 - bitmaps of 5G-EA0 to 5G-EA7 and 5G-IA0 to 5G-IA7.
+const EA0_ONLY: u8 = 0b10000000;
+const EA0_AND_EA2: u8 = 0b10100000;
+const IA1_ONLY: u8 = 0b01000000;
+const IA2_ONLY: u8 = 0b00100000;
+
+// TS33.501, 5.11.1.
+const NEA0: u8 = 0;
+const NEA2: u8 = 2;
+const NIA2: u8 = 2;
+
+// TS24.501, table 9.11.3.2.1.
+const UE_SECURITY_CAPABILITIES_MISMATCH: u8 = 23;
+const INVALID_MANDATORY_INFORMATION: u8 = 96;
+
+#[async_std::test]
+async fn nas_algorithm_preference_order() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init().await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given QCore's default preference of NEA2 over NEA0, and a UE that supports both
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.ue_security_capability = [EA0_AND_EA2, IA2_ONLY];
+
+    // When it registers
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+
+    // Then QCore should select NEA2.
+    let selected = ue.handle_nas_security_mode().await?;
+    ensure!(
+        selected == (NEA2, NIA2),
+        "Unexpected algorithms {selected:?}"
+    );
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+
+    // And a UE that doesn't support NEA2 should get QCore's next preference, NEA0.
+    let mut ue_2 = MockUe::new(nth_sim(1, sims), 2, &du, qc.ip_addr(), &logger).await?;
+    ue_2.ue_security_capability = [EA0_ONLY, IA2_ONLY];
+    ue_2.perform_rrc_setup().await?;
+    ue_2.handle_nas_authentication().await?;
+    let selected = ue_2.handle_nas_security_mode().await?;
+    ensure!(
+        selected == (NEA0, NIA2),
+        "Unexpected algorithms {selected:?}"
+    );
+    ue_2.handle_rrc_security_mode().await?;
+    ue_2.handle_nas_registration_accept().await?;
+    Ok(())
+}
+
+#[async_std::test]
+async fn configured_nas_algorithm_preference_order() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init_with_config(|config| {
+        config.nas_ciphering_algorithms =
+            vec![NasCipheringAlgorithm::Nea0, NasCipheringAlgorithm::Nea2]
+    })
+    .await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given QCore configured to prefer NEA0 over NEA2, and a UE that supports both
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.ue_security_capability = [EA0_AND_EA2, IA2_ONLY];
+
+    // When it registers
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+
+    // Then QCore should select NEA0.
+    let selected = ue.handle_nas_security_mode().await?;
+    ensure!(
+        selected == (NEA0, NIA2),
+        "Unexpected algorithms {selected:?}"
+    );
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    Ok(())
+}
+
+#[async_std::test]
+async fn no_common_nas_algorithm() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init().await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given a UE whose only integrity algorithm is NIA1, which QCore doesn't support
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.ue_security_capability = [EA0_AND_EA2, IA1_ONLY];
+
+    // When it registers
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+
+    // Then QCore rejects it with 5GMM cause #23 (UE security capabilities mismatch) and releases its context.
+    let cause = ue.receive_nas_registration_reject().await?;
+    ensure!(
+        cause == UE_SECURITY_CAPABILITIES_MISMATCH,
+        "Unexpected 5GMM cause {cause}"
+    );
+    du.handle_ue_context_release(&ue.du_ue_context).await
+}
+
+#[async_std::test]
+async fn nas_bidding_down_detected() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init().await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given a UE that supports NEA2
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.ue_security_capability = [EA0_AND_EA2, IA2_ONLY];
+
+    // When an attacker strips NEA2 from its initial Registration Request, so that QCore selects NEA0
+    ue.perform_rrc_setup_with_tampered_security_capability(&[EA0_ONLY, IA2_ONLY])
+        .await?;
+    ue.handle_nas_authentication().await?;
+    let selected = ue.handle_nas_security_mode().await?;
+    ensure!(
+        selected == (NEA0, NIA2),
+        "Unexpected algorithms {selected:?}"
+    );
+
+    // Then QCore should spot the difference from the integrity protected copy in the Security Mode Complete,
+    // and reject the UE with 5GMM cause #23 (UE security capabilities mismatch).
+    let cause = ue.receive_nas_registration_reject().await?;
+    ensure!(
+        cause == UE_SECURITY_CAPABILITIES_MISMATCH,
+        "Unexpected 5GMM cause {cause}"
+    );
+    du.handle_ue_context_release(&ue.du_ue_context).await
+}
+
+#[async_std::test]
+async fn security_mode_complete_without_registration_request() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init().await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given a registering UE
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+
+    // When it leaves the copy of its Registration Request out of the Security Mode Complete
+    ue.handle_nas_security_mode_without_nas_container().await?;
+
+    // Then QCore rejects it with 5GMM cause #96 (invalid mandatory information), since it can't check the UE's
+    // security capabilities, and releases its context.
+    let cause = ue.receive_nas_registration_reject().await?;
+    ensure!(
+        cause == INVALID_MANDATORY_INFORMATION,
+        "Unexpected 5GMM cause {cause}"
+    );
+    du.handle_ue_context_release(&ue.du_ue_context).await
+}
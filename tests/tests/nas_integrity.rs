@@ -0,0 +1,57 @@
+use qcore_tests::{MockUe, framework::*};
+
+#[async_std::test]
+async fn bad_nas_mac_discarded() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) = init().await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given a registered UE
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+
+    // When it sends a PDU Session Establishment Request that fails the NAS integrity check
+    ue.send_nas_pdu_session_establishment_request_with_bad_mac()
+        .await?;
+
+    // Then QCore should discard it without setting up a session.
+    du.expect_no_pdu().await?;
+
+    // And it should still accept the UE's next, correctly protected, request.
+    ue.send_nas_pdu_session_establishment_request().await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+    pass_through_uplink_ipv4(&ue, &dn).await
+}
+
+#[async_std::test]
+async fn replayed_nas_discarded() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) = init().await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given a UE with a PDU session
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    ue.send_nas_pdu_session_establishment_request().await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+
+    // When its PDU Session Establishment Request is replayed with the same uplink NAS COUNT
+    ue.replay_last_nas().await?;
+
+    // Then QCore should discard it, leaving the session as it was.
+    du.expect_no_pdu().await?;
+    pass_through_uplink_ipv4(&ue, &dn).await?;
+    pass_through_downlink_ipv4(&dn, &ue).await?;
+
+    // And the uplink NAS COUNT should not have moved on, so the UE's next message is still accepted.
+    ue.send_nas_deregistration_request().await?;
+    du.handle_ue_context_release(&ue.du_ue_context).await
+}
@@ -0,0 +1,137 @@
+use anyhow::ensure;
+use qcore::UeConfigurationUpdate;
+use qcore_tests::{MockUe, framework::*};
+use std::time::Duration;
+
+// Matches the NAS retransmission timer in the test framework config.
+const NAS_RETRANSMISSION_TIMER: Duration = Duration::from_secs(1);
+
+// Receive a NAS message and drop it, as if it had been lost over the air, then give QCore time to retransmit it.
+async fn lose_nas_message(ue: &MockUe<'_>) -> anyhow::Result<()> {
+    let _lost = ue.receive_nas().await?;
+    async_std::task::sleep(NAS_RETRANSMISSION_TIMER).await;
+    Ok(())
+}
+
+// Lose a security protected NAS message and each of its four retransmissions, checking that every transmission
+// has a new sequence number, as the UE's replay protection requires.
+async fn lose_protected_nas_message_and_retransmissions(ue: &MockUe<'_>) -> anyhow::Result<()> {
+    let mut sequence_numbers = vec![];
+    for _ in 0..5 {
+        // TS24.501, figure 9.1.1.2 - the sequence number follows the EPD, security header type and MAC.
+        let nas = ue.receive_nas().await?;
+        ensure!(nas.len() > 6, "Expected security protected NAS message");
+        sequence_numbers.push(nas[6]);
+        async_std::task::sleep(NAS_RETRANSMISSION_TIMER).await;
+    }
+    ensure!(
+        sequence_numbers.windows(2).all(|x| x[1] > x[0]),
+        "Retransmissions reused sequence numbers {sequence_numbers:?}"
+    );
+    Ok(())
+}
+
+#[async_std::test]
+async fn nas_retransmission() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init().await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+
+    // When the Authentication Request and Security Mode Command are both lost once
+    lose_nas_message(&ue).await?;
+    ue.handle_nas_authentication().await?;
+    lose_nas_message(&ue).await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+
+    // Then QCore should retransmit them and the UE should go on to register.
+    ue.handle_nas_registration_accept().await?;
+    Ok(())
+}
+
+#[async_std::test]
+async fn nas_timer_expiry() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init().await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+
+    // When the UE never answers the Authentication Request or its four retransmissions
+    for _ in 0..5 {
+        lose_nas_message(&ue).await?;
+    }
+
+    // Then QCore should give up and release the UE context.
+    du.handle_ue_context_release(&ue.du_ue_context).await
+}
+
+#[async_std::test]
+async fn t3522_expiry() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init().await?;
+
+    // Given a registered UE
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let sim = nth_sim(0, sims);
+    let imsi = sim.0.clone();
+    let mut ue = MockUe::new(sim.clone(), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+
+    // When the UE never answers a Deregistration Request or its retransmissions
+    qc.deregister_ue(&imsi, false).await?;
+    lose_protected_nas_message_and_retransmissions(&ue).await?;
+
+    // Then QCore should release the UE context...
+    du.handle_ue_context_release(&ue.du_ue_context).await?;
+
+    // ...and treat the UE as deregistered, so that it no longer recognizes its 5G-GUTI.
+    let mut ue_2 = MockUe::new(sim, 2, &du, qc.ip_addr(), &logger).await?;
+    ue_2.perform_rrc_setup_with_guti(&ue.tmsi).await?;
+    ue_2.handle_nas_identity_request().await?;
+    ue_2.handle_nas_authentication().await?;
+    ue_2.handle_nas_security_mode().await?;
+    ue_2.handle_rrc_security_mode().await?;
+    ue_2.handle_nas_registration_accept().await?;
+    Ok(())
+}
+
+#[async_std::test]
+async fn t3555_expiry() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init().await?;
+
+    // Given a registered UE
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let sim = nth_sim(0, sims);
+    let imsi = sim.0.clone();
+    let mut ue = MockUe::new(sim, 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+
+    // When the UE never answers a Configuration Update Command that reallocates its 5G-GUTI
+    qc.update_ue_configuration(
+        &imsi,
+        UeConfigurationUpdate {
+            reallocate_guti: true,
+            ..Default::default()
+        },
+    )
+    .await?;
+    lose_protected_nas_message_and_retransmissions(&ue).await?;
+
+    // Then QCore should release the UE context...
+    du.handle_ue_context_release(&ue.du_ue_context).await?;
+
+    // ...but keep the UE registered under its old 5G-GUTI, since the new one never took effect.
+    ue.new_du_ue_context(2, qc.ip_addr()).await?;
+    ue.perform_rrc_setup_with_own_guti().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    Ok(())
+}
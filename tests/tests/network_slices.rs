@@ -0,0 +1,123 @@
+use anyhow::{anyhow, ensure};
+use qcore::{SimCreds, SimTable};
+use qcore_tests::{MockUe, framework::*};
+use xxap::Snssai;
+
+// A SIM in test_sims.toml that is only subscribed to slice 1, out of QCore's slices 1 and 1-000001.
+const SLICE_1_IMSI: &str = "208932222222222";
+const SLICE_1: Snssai = Snssai(1, None);
+const SLICE_1_000001: Snssai = Snssai(1, Some([0, 0, 1]));
+
+// TS24.501, table 9.11.3.2.1.
+const NO_NETWORK_SLICES_AVAILABLE: u8 = 0b00111110;
+const PAYLOAD_WAS_NOT_FORWARDED: u8 = 0b01011010;
+
+fn slice_1_sim(sims: &'static SimTable) -> anyhow::Result<(String, &'static SimCreds)> {
+    let (imsi, sim_creds) = sims
+        .get_key_value(SLICE_1_IMSI)
+        .ok_or(anyhow!("imsi-{SLICE_1_IMSI} missing from test SIMs"))?;
+    Ok((imsi.clone(), sim_creds))
+}
+
+#[async_std::test]
+async fn network_slices() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) = init().await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given a UE that asks for a slice it isn't subscribed to, as well as one that it is
+    let mut ue = MockUe::new(slice_1_sim(sims)?, 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup_with_requested_nssai(&[SLICE_1_000001, SLICE_1])
+        .await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+
+    // When it registers, then it should be allowed the one slice and have the other rejected.
+    let registration_accept = ue.handle_nas_registration_accept().await?;
+    let allowed_nssai = registration_accept.allowed_nssai.map(|x| x.value);
+    ensure!(
+        allowed_nssai == Some(vec![1, 1]),
+        "Unexpected allowed NSSAI {allowed_nssai:?}"
+    );
+    let rejected_nssai = registration_accept.rejected_nssai.map(|x| x.value);
+    ensure!(
+        rejected_nssai == Some(vec![0x40, 1, 0, 0, 1]),
+        "Unexpected rejected NSSAI {rejected_nssai:?}"
+    );
+
+    // And its request for a PDU session on the rejected slice should be sent back with 5GMM cause #90 (payload was
+    // not forwarded).
+    ue.send_nas_pdu_session_establishment_request_with_snssai(SLICE_1_000001)
+        .await?;
+    let cause = ue.receive_nas_payload_not_forwarded().await?;
+    ensure!(
+        cause == PAYLOAD_WAS_NOT_FORWARDED,
+        "Unexpected 5GMM cause {cause}"
+    );
+
+    // But it should be able to establish one on the allowed slice.
+    ue.send_nas_pdu_session_establishment_request_with_snssai(SLICE_1)
+        .await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+    pass_through_uplink_ipv4(&ue, &dn).await?;
+    pass_through_downlink_ipv4(&dn, &ue).await?;
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn network_slice_with_sd() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) = init().await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given a UE, subscribed to all slices, that asks for the slice with an SD
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup_with_requested_nssai(&[SLICE_1_000001])
+        .await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+
+    // When it registers, then it should be allowed that slice, with its SD.
+    let registration_accept = ue.handle_nas_registration_accept().await?;
+    let allowed_nssai = registration_accept.allowed_nssai.map(|x| x.value);
+    ensure!(
+        allowed_nssai == Some(vec![4, 1, 0, 0, 1]),
+        "Unexpected allowed NSSAI {allowed_nssai:?}"
+    );
+
+    // And it should be able to establish a PDU session on it.
+    ue.send_nas_pdu_session_establishment_request_with_snssai(SLICE_1_000001)
+        .await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    let accept = ue.handle_rrc_reconfiguration_with_session_accept().await?;
+    let s_nssai = accept.s_nssai.map(|x| x.value);
+    ensure!(
+        s_nssai == Some(vec![1, 0, 0, 1]),
+        "Unexpected S-NSSAI {s_nssai:?}"
+    );
+    pass_through_uplink_ipv4(&ue, &dn).await?;
+    pass_through_downlink_ipv4(&dn, &ue).await
+}
+
+#[async_std::test]
+async fn no_network_slices_available() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init().await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given a UE that only asks for a slice it isn't subscribed to
+    let mut ue = MockUe::new(slice_1_sim(sims)?, 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup_with_requested_nssai(&[SLICE_1_000001])
+        .await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+
+    // Then QCore rejects it with 5GMM cause #62 (no network slices available) and releases its context.
+    let cause = ue.receive_nas_registration_reject().await?;
+    ensure!(
+        cause == NO_NETWORK_SLICES_AVAILABLE,
+        "Unexpected 5GMM cause {cause}"
+    );
+    du.handle_ue_context_release(&ue.du_ue_context).await
+}
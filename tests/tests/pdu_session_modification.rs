@@ -0,0 +1,101 @@
+use anyhow::{bail, ensure};
+use qcore::{PduSessionModification, SessionAmbr};
+use qcore_tests::{MockUe, framework::*};
+
+#[async_std::test]
+async fn ue_requested_pdu_session_modification() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) = init().await?;
+
+    // Given a registered UE with a PDU session
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    ue.send_nas_pdu_session_establishment_request().await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+
+    // When the UE asks for a new QoS rule, on a new QoS flow with 5QI 7
+    ue.send_nas_pdu_session_modification_request().await?;
+
+    // Then QCore should map a new QoS flow to the default DRB and authorize the QoS rule and the 5QI.  QFI 2 is
+    // already taken by the test data network's voice QoS flow, which has a DRB of its own.
+    let drbs = du
+        .handle_f1_ue_context_modification_with_drb_modification(&mut ue.du_ue_context)
+        .await?;
+    ensure!(
+        drbs == vec![(ue.drb_id, vec![1, 3])],
+        "Unexpected DRB modifications {drbs:?}"
+    );
+    let command = ue
+        .handle_rrc_reconfiguration_with_session_modification()
+        .await?;
+    ensure!(command.authorized_qos_rules.is_some());
+    let Some(flows) = command.authorized_qos_flow_descriptions else {
+        bail!("Missing authorized QoS flow descriptions");
+    };
+    ensure!(
+        flows.value == [3, 0b001_00000, 0b0_1_000001, 0x01, 0x01, 7],
+        "Unexpected QoS flow descriptions {:?}",
+        flows.value
+    );
+
+    // And the session should carry on working.
+    pass_through_uplink_ipv4(&ue, &dn).await?;
+    pass_through_downlink_ipv4(&dn, &ue).await
+}
+
+#[async_std::test]
+async fn network_requested_pdu_session_modification() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) = init().await?;
+
+    // Given a registered UE with a PDU session
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let sim = nth_sim(0, sims);
+    let imsi = sim.0.clone();
+    let mut ue = MockUe::new(sim, 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    ue.send_nas_pdu_session_establishment_request().await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+
+    // When the operator changes the session AMBR
+    let modification = PduSessionModification {
+        session_ambr: Some(SessionAmbr {
+            downlink_kbps: 100_500,
+            uplink_kbps: 2000,
+        }),
+        ..Default::default()
+    };
+    qc.modify_pdu_session(&imsi, 1, modification).await?;
+
+    // Then QCore should modify both DRBs, since the session AMBR is part of the QoS of each, and tell the UE the
+    // new session AMBR.
+    let drbs = du
+        .handle_f1_ue_context_modification_with_drb_modification(&mut ue.du_ue_context)
+        .await?;
+    let qfis: Vec<Vec<u8>> = drbs.into_iter().map(|(_, qfis)| qfis).collect();
+    ensure!(qfis == vec![vec![1], vec![2]], "Unexpected QFIs {qfis:?}");
+    let command = ue
+        .handle_rrc_reconfiguration_with_session_modification()
+        .await?;
+    let Some(session_ambr) = command.session_ambr else {
+        bail!("Missing session AMBR");
+    };
+    ensure!(
+        session_ambr.value == [2, 0x62, 0x25, 1, 0x07, 0xd0],
+        "Session AMBR {:?} should be 25125 x 4 Kbps downlink and 2000 x 1 Kbps uplink",
+        session_ambr.value
+    );
+    ensure!(command.authorized_qos_rules.is_none());
+
+    pass_through_uplink_ipv4(&ue, &dn).await?;
+    pass_through_downlink_ipv4(&dn, &ue).await
+}
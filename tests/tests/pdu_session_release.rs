@@ -0,0 +1,74 @@
+use qcore_tests::{MockUe, framework::*};
+
+#[async_std::test]
+async fn pdu_session_release() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) = init().await?;
+
+    // Given a registered UE with a PDU session
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    ue.send_nas_pdu_session_establishment_request().await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+
+    // When the UE releases its PDU session
+    ue.send_nas_pdu_session_release_request(1).await?;
+
+    // Then QCore should remove the DRB and send a release command.
+    du.handle_f1_ue_context_modification_with_drb_release(&mut ue.du_ue_context)
+        .await?;
+    ue.handle_rrc_reconfiguration_with_session_release().await?;
+
+    // And the UE stays registered, so it can establish a new PDU session, whose DRB is added to the UE's existing
+    // F1 UE context.
+    ue.send_nas_pdu_session_establishment_request().await?;
+    du.handle_f1_ue_context_modification_with_drb_setup(&mut ue.du_ue_context)
+        .await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+    pass_through_uplink_ipv4(&ue, &dn).await?;
+    pass_through_downlink_ipv4(&dn, &ue).await?;
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn pdu_session_establishment_during_release() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) = init().await?;
+
+    // Given a registered UE that is releasing its PDU session
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    ue.send_nas_pdu_session_establishment_request().await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+    ue.send_nas_pdu_session_release_request(1).await?;
+    du.handle_f1_ue_context_modification_with_drb_release(&mut ue.du_ue_context)
+        .await?;
+    let (pdu_session_id, pti) = ue
+        .receive_rrc_reconfiguration_with_session_release()
+        .await?;
+
+    // When it asks for a new PDU session before it sends the Release Complete
+    ue.send_nas_pdu_session_establishment_request().await?;
+    ue.send_nas_pdu_session_release_complete(pdu_session_id, pti)
+        .await?;
+
+    // Then QCore should finish the release and then set up the new PDU session.
+    du.handle_f1_ue_context_modification_with_drb_setup(&mut ue.du_ue_context)
+        .await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+    pass_through_uplink_ipv4(&ue, &dn).await?;
+    pass_through_downlink_ipv4(&dn, &ue).await?;
+
+    Ok(())
+}
@@ -0,0 +1,198 @@
+use anyhow::{Result, anyhow, ensure};
+use qcore::{PacketFilterTemplate, PduSessionModification, QosFlowTemplate};
+use qcore_tests::{MockUe, framework::*};
+use std::net::{IpAddr, SocketAddr};
+
+// The voice QoS flow is the first one after the default QoS flow.  Its QoS rule has the same ID as its QFI.
+const DEFAULT_QFI: u8 = 1;
+const VOICE_QFI: u8 = 2;
+
+// A QoS flow with 5QI 7 for UDP traffic to this port on the UE, added by a PDU session modification.
+const VIDEO_QFI: u8 = 3;
+const VIDEO_PORT: u16 = 5006;
+
+#[async_std::test]
+async fn qos_flow_template() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) = init().await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given a UE with a PDU session on a data network with a voice QoS flow template
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    ue.send_nas_pdu_session_establishment_request().await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    let accept = ue.handle_rrc_reconfiguration_with_session_accept().await?;
+
+    // Then the session should have a voice QoS flow on a DRB of its own.
+    ensure!(accept.authorized_qos_flow_descriptions.is_some());
+    let default_drb = drb_of(&ue, DEFAULT_QFI)?;
+    let voice_drb = drb_of(&ue, VOICE_QFI)?;
+    ensure!(
+        default_drb != voice_drb,
+        "Voice QoS flow is on the default DRB"
+    );
+
+    // And downlink packets to the voice port should go down the voice DRB.
+    dn.send_n6_udp_packet(SocketAddr::new(IpAddr::V4(ue.ipv4_addr), TEST_VOICE_PORT))
+        .await?;
+    let (drb_id, _) = ue.recv_f1u_data_packet_with_drb_id().await?;
+    ensure!(drb_id == voice_drb, "Voice packet arrived on DRB {drb_id}");
+
+    // And other downlink packets should go down the default DRB.
+    dn.send_n6_udp_packet(SocketAddr::new(
+        IpAddr::V4(ue.ipv4_addr),
+        TEST_VOICE_PORT + 1,
+    ))
+    .await?;
+    let (drb_id, _) = ue.recv_f1u_data_packet_with_drb_id().await?;
+    ensure!(
+        drb_id == default_drb,
+        "Other packet arrived on DRB {drb_id}"
+    );
+
+    pass_through_uplink_ipv4(&ue, &dn).await
+}
+
+#[async_std::test]
+async fn new_qos_flow_sdap_header() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) =
+        init_with_config(|config| config.sdap_header_dl = true).await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given a UE with a PDU session whose DRBs have a downlink SDAP header
+    let sim = nth_sim(0, sims);
+    let imsi = sim.0.clone();
+    let mut ue = MockUe::new(sim, 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    ue.send_nas_pdu_session_establishment_request().await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+    let default_drb = drb_of(&ue, DEFAULT_QFI)?;
+    let voice_drb = drb_of(&ue, VOICE_QFI)?;
+
+    // When the operator adds a video QoS flow to the session
+    let video = QosFlowTemplate {
+        five_qi: 7,
+        arp_priority_level: None,
+        packet_filters: vec![PacketFilterTemplate {
+            protocol: Some(17), // UDP
+            local_port: Some(VIDEO_PORT),
+            ..Default::default()
+        }],
+    };
+    let modification = PduSessionModification {
+        add_qos_rules: vec![video.qos_rule(VIDEO_QFI, 0x80, VIDEO_QFI)],
+        add_qos_flows: vec![video.qos_flow(VIDEO_QFI)],
+        ..Default::default()
+    };
+    qc.modify_pdu_session(&imsi, 1, modification).await?;
+
+    // Then QCore should map it to the default DRB.
+    let drbs = du
+        .handle_f1_ue_context_modification_with_drb_modification(&mut ue.du_ue_context)
+        .await?;
+    ensure!(
+        drbs == vec![(default_drb, vec![DEFAULT_QFI, VIDEO_QFI])],
+        "Unexpected DRB modifications {drbs:?}"
+    );
+    ue.handle_rrc_reconfiguration_with_session_modification()
+        .await?;
+    ensure!(drb_of(&ue, VIDEO_QFI)? == default_drb);
+
+    // And each downlink packet should carry the QFI of its own QoS flow in its SDAP header.
+    for (port, expected_drb, expected_qfi) in [
+        (VIDEO_PORT, default_drb, VIDEO_QFI),
+        (TEST_VOICE_PORT, voice_drb, VOICE_QFI),
+        (VIDEO_PORT + 1, default_drb, DEFAULT_QFI),
+    ] {
+        dn.send_n6_udp_packet(SocketAddr::new(IpAddr::V4(ue.ipv4_addr), port))
+            .await?;
+        let (drb_id, qfi, _) = ue.recv_f1u_data_packet_with_sdap_qfi().await?;
+        ensure!(
+            (drb_id, qfi) == (expected_drb, expected_qfi),
+            "Packet to port {port} arrived on DRB {drb_id} with QFI {qfi}"
+        );
+    }
+
+    pass_through_uplink_ipv4(&ue, &dn).await
+}
+
+#[async_std::test]
+async fn delete_qos_flow_of_own_drb() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) = init().await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given a UE with a PDU session with a voice QoS flow on a DRB of its own
+    let sim = nth_sim(0, sims);
+    let imsi = sim.0.clone();
+    let mut ue = MockUe::new(sim, 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    ue.send_nas_pdu_session_establishment_request().await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+    let default_drb = drb_of(&ue, DEFAULT_QFI)?;
+    let voice_drb = drb_of(&ue, VOICE_QFI)?;
+
+    // When the operator deletes the voice QoS rule
+    let modification = PduSessionModification {
+        delete_qos_rules: vec![VOICE_QFI],
+        ..Default::default()
+    };
+    qc.modify_pdu_session(&imsi, 1, modification).await?;
+
+    // Then QCore should release the voice DRB, rather than touch the QoS flows of the default DRB.
+    let drbs = du
+        .handle_f1_ue_context_modification_with_drb_modification(&mut ue.du_ue_context)
+        .await?;
+    ensure!(
+        drbs == vec![(default_drb, vec![DEFAULT_QFI])],
+        "Unexpected DRB modifications {drbs:?}"
+    );
+    ensure!(
+        !ue.du_ue_context
+            .drbs
+            .iter()
+            .any(|x| x.drb_id.0 == voice_drb),
+        "DU still has the voice DRB"
+    );
+    ue.handle_rrc_reconfiguration_with_session_modification()
+        .await?;
+    ensure!(
+        ue.qos_flow_drbs == vec![(DEFAULT_QFI, default_drb)],
+        "Unexpected QoS flow mapping {:?}",
+        ue.qos_flow_drbs
+    );
+
+    // And downlink packets to the voice port should go down the default DRB.
+    dn.send_n6_udp_packet(SocketAddr::new(IpAddr::V4(ue.ipv4_addr), TEST_VOICE_PORT))
+        .await?;
+    let (drb_id, _) = ue.recv_f1u_data_packet_with_drb_id().await?;
+    ensure!(
+        drb_id == default_drb,
+        "Voice packet arrived on DRB {drb_id}"
+    );
+
+    pass_through_uplink_ipv4(&ue, &dn).await?;
+    pass_through_downlink_ipv4(&dn, &ue).await
+}
+
+// The DRB that a QoS flow of the UE's PDU session is mapped to.
+fn drb_of(ue: &MockUe, qfi: u8) -> Result<u8> {
+    ue.qos_flow_drbs
+        .iter()
+        .find(|(x, _)| *x == qfi)
+        .map(|(_, drb_id)| *drb_id)
+        .ok_or(anyhow!("QFI {qfi} not mapped to a DRB"))
+}
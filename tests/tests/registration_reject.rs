@@ -0,0 +1,150 @@
+use qcore::SimCreds;
+use qcore_tests::{MockDu, MockUe, framework::*};
+use std::collections::HashMap;
+use std::sync::LazyLock;
+
+#[async_std::test]
+async fn unknown_imsi_registration_reject() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init().await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given a UE whose IMSI isn't in QCore's SIM table
+    let (_imsi, sim_creds) = nth_sim(0, sims);
+    let unknown_sim = ("208939999999999".to_string(), sim_creds);
+    let mut ue = MockUe::new(unknown_sim, 1, &du, qc.ip_addr(), &logger).await?;
+
+    // When it tries to register
+    ue.perform_rrc_setup().await?;
+
+    // Then QCore rejects it with 5GMM cause #7 (5GS services not allowed) and releases its context.
+    let cause = ue.receive_nas_registration_reject().await?;
+    anyhow::ensure!(cause == 7, "Unexpected 5GMM cause {cause}");
+    du.handle_ue_context_release(&ue.du_ue_context).await
+}
+
+// A SIM with different credentials to those that QCore holds for its IMSI.
+static WRONG_SIM_CREDS: LazyLock<SimCreds> = LazyLock::new(|| SimCreds {
+    ki: [0x11; 16],
+    opc: [0x22; 16],
+    snssais: Vec::new(),
+    static_ipv4_addrs: HashMap::new(),
+    session_ambr: None,
+    ue_ambr: None,
+});
+
+#[async_std::test]
+async fn authentication_mac_failure_reject() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init().await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given a UE that can't verify QCore's authentication challenge
+    let (imsi, _sim_creds) = nth_sim(0, sims);
+    let mut ue = MockUe::new((imsi, &*WRONG_SIM_CREDS), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+
+    // When it reports a MAC failure
+    ue.handle_nas_authentication_with_mac_failure().await?;
+
+    // Then QCore sends Authentication Reject and releases its context.
+    ue.receive_nas_authentication_reject().await?;
+    du.handle_ue_context_release(&ue.du_ue_context).await
+}
+
+#[async_std::test]
+async fn wrong_res_star_authentication_reject() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init().await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given a UE that is registering
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+
+    // When it responds to the authentication challenge with the wrong RES*
+    ue.handle_nas_authentication_with_wrong_res().await?;
+
+    // Then QCore sends Authentication Reject and releases its context.
+    ue.receive_nas_authentication_reject().await?;
+    du.handle_ue_context_release(&ue.du_ue_context).await
+}
+
+// The TAC of the mock UE's default IMEISV, and one belonging to some other device.
+const MOCK_UE_TAC: &str = "35693803";
+const OTHER_TAC: &str = "35000000";
+
+// Register a UE, and check that QCore rejects it with 5GMM cause #6 (illegal ME) once it has given its IMEISV in the
+// Security Mode Complete, and releases its context.
+async fn expect_illegal_me(du: &MockDu, ue: &mut MockUe<'_>) -> anyhow::Result<()> {
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    let cause = ue.receive_nas_registration_reject().await?;
+    anyhow::ensure!(cause == 6, "Unexpected 5GMM cause {cause}");
+    du.handle_ue_context_release(&ue.du_ue_context).await
+}
+
+#[async_std::test]
+async fn blocked_device_registration_reject() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init_with_config(|config| {
+        config.equipment_identities.deny = vec![OTHER_TAC.to_string()];
+    })
+    .await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given a UE with a valid SIM in a device whose TAC is on QCore's deny list
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.imeisv = Some(format!("{OTHER_TAC}12345601"));
+
+    // When it registers, then QCore rejects it.
+    expect_illegal_me(&du, &mut ue).await
+}
+
+#[async_std::test]
+async fn allowed_device_registration() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init_with_config(|config| {
+        config.equipment_identities.allow = Some(vec![MOCK_UE_TAC.to_string()]);
+    })
+    .await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given a UE in a device whose TAC is on QCore's allow list
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+
+    // When it registers, then QCore accepts it.
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    Ok(())
+}
+
+#[async_std::test]
+async fn unlisted_device_registration_reject() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init_with_config(|config| {
+        config.equipment_identities.allow = Some(vec![OTHER_TAC.to_string()]);
+    })
+    .await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given a UE in a device whose TAC is not on QCore's allow list
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+
+    // When it registers, then QCore rejects it.
+    expect_illegal_me(&du, &mut ue).await
+}
+
+#[async_std::test]
+async fn unidentified_device_registration_reject() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init_with_config(|config| {
+        config.equipment_identities.allow = Some(vec![MOCK_UE_TAC.to_string()]);
+    })
+    .await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given a UE that doesn't give its IMEISV, when QCore has an allow list
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.imeisv = None;
+
+    // When it registers, then QCore rejects it, since it can't tell whether the device is allowed.
+    expect_illegal_me(&du, &mut ue).await
+}
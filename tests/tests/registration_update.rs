@@ -0,0 +1,208 @@
+use anyhow::ensure;
+use qcore_tests::{MockUe, framework::*};
+use std::time::Duration;
+
+#[async_std::test]
+async fn mobility_registration_update() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) = init().await?;
+
+    // Given a registered UE with PDU session 1
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    ue.send_nas_pdu_session_establishment_request_with_id(1)
+        .await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+
+    // When it sends a mobility registration update saying that it has uplink data for sessions 1 and 2
+    ue.send_nas_mobility_registration_request(&[1], &[1, 2])
+        .await?;
+
+    // Then QCore should accept it on the existing security context, with a fresh T3512 of 2 seconds...
+    let registration_accept = ue.handle_nas_registration_accept().await?;
+    let t3512_value = registration_accept.t3512_value.map(|x| x.value);
+    ensure!(
+        t3512_value == Some(vec![0b011_00001]),
+        "Unexpected T3512 value {t3512_value:?}"
+    );
+
+    // ...saying that session 1 is active, but that session 2 can't be reactivated.
+    let pdu_session_status = registration_accept.pdu_session_status.map(|x| x.value);
+    ensure!(
+        pdu_session_status == Some(vec![0b10, 0]),
+        "Unexpected PDU session status {pdu_session_status:?}"
+    );
+    let reactivation_result = registration_accept
+        .pdu_session_reactivation_result
+        .map(|x| x.value);
+    ensure!(
+        reactivation_result == Some(vec![0b100, 0]),
+        "Unexpected PDU session reactivation result {reactivation_result:?}"
+    );
+
+    // And session 1 should still carry traffic.
+    pass_through_uplink_ipv4(&ue, &dn).await?;
+    pass_through_downlink_ipv4(&dn, &ue).await?;
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn implicit_deregistration() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init().await?;
+
+    // Given a registered UE whose context has been released
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let sim = nth_sim(0, sims);
+    let mut ue = MockUe::new(sim.clone(), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    du.send_ue_context_release_request(&ue.du_ue_context)
+        .await?;
+    du.handle_ue_context_release(&ue.du_ue_context).await?;
+
+    // When it stays out of touch for longer than the mobile reachable timer
+    async_std::task::sleep(Duration::from_secs(4)).await;
+
+    // Then QCore should have implicitly deregistered it, and so not recognize its 5G-GUTI.
+    let mut ue_2 = MockUe::new(sim, 2, &du, qc.ip_addr(), &logger).await?;
+    ue_2.perform_rrc_setup_with_guti(&ue.tmsi).await?;
+    ue_2.handle_nas_identity_request().await?;
+    ue_2.handle_nas_authentication().await?;
+    ue_2.handle_nas_security_mode().await?;
+    ue_2.handle_rrc_security_mode().await?;
+    ue_2.handle_nas_registration_accept().await?;
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn idle_mode_periodic_registration_update() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init().await?;
+
+    // Given a registered UE whose context has been released
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    du.send_ue_context_release_request(&ue.du_ue_context)
+        .await?;
+    du.handle_ue_context_release(&ue.du_ue_context).await?;
+
+    // When T3512 expires and it comes back with a periodic registration update
+    async_std::task::sleep(Duration::from_secs(2)).await;
+    ue.new_du_ue_context(2, qc.ip_addr()).await?;
+    ue.perform_rrc_setup_with_periodic_registration().await?;
+
+    // Then QCore should accept it on its existing security context, with a fresh T3512 of 2 seconds.
+    ue.handle_rrc_security_mode().await?;
+    let registration_accept = ue.handle_nas_registration_accept().await?;
+    let t3512_value = registration_accept.t3512_value.map(|x| x.value);
+    ensure!(
+        t3512_value == Some(vec![0b011_00001]),
+        "Unexpected T3512 value {t3512_value:?}"
+    );
+
+    // And when the UE goes idle again for longer than the mobile reachable timer would have run since the
+    // first release...
+    du.send_ue_context_release_request(&ue.du_ue_context)
+        .await?;
+    du.handle_ue_context_release(&ue.du_ue_context).await?;
+    async_std::task::sleep(Duration::from_secs(2)).await;
+
+    // ...QCore should still recognize its 5G-GUTI, because the update restarted the timer.
+    ue.new_du_ue_context(3, qc.ip_addr()).await?;
+    ue.perform_rrc_setup_with_own_guti().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+
+    Ok(())
+}
+
+#[async_std::test]
+async fn unsupported_registration_type() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init().await?;
+
+    // Given a registered UE
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+
+    // When it sends an emergency registration, which QCore doesn't support
+    ue.send_nas_emergency_registration_request().await?;
+
+    // Then QCore rejects it with 5GMM cause #111 (protocol error, unspecified) and releases its context.
+    let cause = ue.receive_nas_registration_reject().await?;
+    ensure!(cause == 111, "Unexpected 5GMM cause {cause}");
+    du.handle_ue_context_release(&ue.du_ue_context).await
+}
+
+#[async_std::test]
+async fn idle_mode_registration_update_keeps_pdu_sessions() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) = init().await?;
+
+    // Given a registered UE with PDU sessions 1 and 2 whose context has been released
+    du.perform_f1_setup(qc.ip_addr()).await?;
+    let mut ue = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+    ue.send_nas_pdu_session_establishment_request_with_id(2)
+        .await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+    ue.send_nas_pdu_session_establishment_request_with_id(1)
+        .await?;
+    du.handle_f1_ue_context_modification_with_drb_setup(&mut ue.du_ue_context)
+        .await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+    du.send_ue_context_release_request(&ue.du_ue_context)
+        .await?;
+    du.handle_ue_context_release(&ue.du_ue_context).await?;
+
+    // When it comes back with a mobility registration update saying that only session 1 is still active
+    ue.new_du_ue_context(2, qc.ip_addr()).await?;
+    ue.perform_rrc_setup_with_mobility_registration(&[1], &[])
+        .await?;
+
+    // Then QCore should accept it on its existing security context, saying that session 1 is still active.
+    ue.handle_rrc_security_mode().await?;
+    let registration_accept = ue.handle_nas_registration_accept().await?;
+    let pdu_session_status = registration_accept.pdu_session_status.map(|x| x.value);
+    ensure!(
+        pdu_session_status == Some(vec![0b10, 0]),
+        "Unexpected PDU session status {pdu_session_status:?}"
+    );
+
+    // And when the UE later uses a Service Request to get the user plane of session 1 back
+    du.send_ue_context_release_request(&ue.du_ue_context)
+        .await?;
+    du.handle_ue_context_release(&ue.du_ue_context).await?;
+    ue.new_du_ue_context(3, qc.ip_addr()).await?;
+    ue.perform_rrc_setup_with_service_request(&[1], &[1])
+        .await?;
+    ue.handle_rrc_security_mode().await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_service_accept().await?;
+
+    // Then the session should still carry traffic to and from the IP address that it had before.
+    pass_through_uplink_ipv4(&ue, &dn).await?;
+    pass_through_downlink_ipv4(&dn, &ue).await
+}
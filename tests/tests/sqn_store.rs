@@ -0,0 +1,59 @@
+use anyhow::ensure;
+use qcore::SqnStore;
+use slog::{Discard, Logger, o};
+use std::path::PathBuf;
+
+const IMSI: &str = "208930000000001";
+
+#[async_std::test]
+async fn sqns_survive_restart() -> anyhow::Result<()> {
+    let logger = Logger::root(Discard, o!());
+    let filename = sqn_file("restart");
+
+    // Given a fresh SQN store that has handed out two SQNs
+    let sqns = SqnStore::load(&filename, &logger)?;
+    ensure!(sqns.next_sqn(IMSI).await? == sqn(1, 1));
+    ensure!(sqns.next_sqn(IMSI).await? == sqn(2, 2));
+
+    // When QCore restarts and loads the store from file
+    let sqns = SqnStore::load(&filename, &logger)?;
+
+    // Then the next SQN should follow on from the last one used.
+    let next = sqns.next_sqn(IMSI).await;
+    std::fs::remove_file(&filename)?;
+    ensure!(next? == sqn(3, 3));
+    Ok(())
+}
+
+#[async_std::test]
+async fn resynchronized_sqn_survives_restart() -> anyhow::Result<()> {
+    let logger = Logger::root(Discard, o!());
+    let filename = sqn_file("resync");
+
+    // Given an SQN store that has resynchronized with a UE that is ahead of it
+    let sqns = SqnStore::load(&filename, &logger)?;
+    sqns.next_sqn(IMSI).await?;
+    sqns.resync(IMSI, &sqn(100, 7), &logger).await?;
+
+    // When QCore restarts and loads the store from file
+    let sqns = SqnStore::load(&filename, &logger)?;
+
+    // Then the next SQN should be fresh to the UE - TS33.102, C.3.4.
+    let next = sqns.next_sqn(IMSI).await;
+    std::fs::remove_file(&filename)?;
+    ensure!(next? == sqn(101, 8));
+    Ok(())
+}
+
+// A temporary SQN file path, which doesn't exist yet.
+fn sqn_file(name: &str) -> String {
+    let path: PathBuf =
+        std::env::temp_dir().join(format!("qcore-sqns-{name}-{}.toml", std::process::id()));
+    let _ = std::fs::remove_file(&path);
+    path.to_string_lossy().into_owned()
+}
+
+// SQN = SEQ || IND, where IND is 5 bits - TS33.102, C.1.1.
+fn sqn(seq: u64, ind: u64) -> [u8; 6] {
+    ((seq << 5) | ind).to_be_bytes()[2..8].try_into().unwrap()
+}
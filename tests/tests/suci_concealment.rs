@@ -0,0 +1,39 @@
+use hex_literal::hex;
+use qcore::{EciesProfile, HomeNetworkKey};
+use qcore_tests::{MockUe, framework::*};
+use std::collections::HashMap;
+
+// The Profile A home network key pair of TS33.501, C.4.3.
+const HOME_NETWORK_PUBLIC_KEY_ID: u8 = 1;
+const HOME_NETWORK_PRIVATE_KEY: [u8; 32] =
+    hex!("c53c22208b61860b06c62e5406a7b330c2b577aa5558981510d128247d38bd1d");
+const HOME_NETWORK_PUBLIC_KEY: [u8; 32] =
+    hex!("5a8d38864820197c3394b92613b20b91633cbd897119273bf8e4a6f4eec0a650");
+
+#[async_std::test]
+async fn concealed_suci_registration() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) = init_with_config(|config| {
+        config.home_network_keys = HashMap::from([(
+            HOME_NETWORK_PUBLIC_KEY_ID,
+            HomeNetworkKey {
+                profile: EciesProfile::A,
+                private_key: HOME_NETWORK_PRIVATE_KEY,
+            },
+        )])
+    })
+    .await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given a UE whose SIM conceals its SUCI with QCore's Profile A home network public key
+    let mut ue = MockUe::new(nth_imsi(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+
+    // When it registers
+    ue.perform_rrc_setup_with_concealed_suci(HOME_NETWORK_PUBLIC_KEY_ID, &HOME_NETWORK_PUBLIC_KEY)
+        .await?;
+
+    // Then QCore de-conceals its IMSI and authenticates and registers it.
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await
+}
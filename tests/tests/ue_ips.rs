@@ -0,0 +1,105 @@
+use anyhow::{anyhow, ensure};
+use qcore::{SimCreds, SimTable};
+use qcore_tests::{MockUe, framework::*};
+use std::net::Ipv4Addr;
+
+// A SIM in test_sims.toml with a static IPv4 address on the internet data network.
+const STATIC_IP_IMSI: &str = "208933333333333";
+const STATIC_IP: Ipv4Addr = Ipv4Addr::new(10, 255, 0, 200);
+
+fn static_ip_sim(sims: &'static SimTable) -> anyhow::Result<(String, &'static SimCreds)> {
+    let (imsi, sim_creds) = sims
+        .get_key_value(STATIC_IP_IMSI)
+        .ok_or(anyhow!("imsi-{STATIC_IP_IMSI} missing from test SIMs"))?;
+    Ok((imsi.clone(), sim_creds))
+}
+
+#[async_std::test]
+async fn static_ue_ip() -> anyhow::Result<()> {
+    let (mut du, qc, dn, sims, logger) = init().await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given a registered UE whose SIM has a static IP address
+    let mut ue = MockUe::new(static_ip_sim(sims)?, 1, &du, qc.ip_addr(), &logger).await?;
+    ue.perform_rrc_setup().await?;
+    ue.handle_nas_authentication().await?;
+    ue.handle_nas_security_mode().await?;
+    ue.handle_rrc_security_mode().await?;
+    ue.handle_nas_registration_accept().await?;
+
+    // When it establishes a PDU session
+    ue.send_nas_pdu_session_establishment_request().await?;
+    du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+    ue.handle_rrc_reconfiguration_with_session_accept().await?;
+
+    // Then it should get its static IP address.
+    ensure!(
+        ue.ipv4_addr == STATIC_IP,
+        "Expected static IP {STATIC_IP}, got {}",
+        ue.ipv4_addr
+    );
+    pass_through_uplink_ipv4(&ue, &dn).await?;
+    pass_through_downlink_ipv4(&dn, &ue).await
+}
+
+#[async_std::test]
+async fn static_ue_ip_outside_subnet() -> anyhow::Result<()> {
+    // Given a SIM with a static IP address on the internet data network, when that data network's UE subnet
+    // doesn't contain it
+    let result = init_with_config(|config| {
+        config.data_networks[0].ue_subnet = Ipv4Addr::new(10, 254, 0, 0);
+    })
+    .await;
+
+    // Then QCore should refuse to start.
+    ensure!(result.is_err(), "QCore started with a bad static IP");
+    Ok(())
+}
+
+#[async_std::test]
+async fn sticky_ue_ips() -> anyhow::Result<()> {
+    let (mut du, qc, _dn, sims, logger) =
+        init_with_config(|config| config.sticky_ue_ips = true).await?;
+    du.perform_f1_setup(qc.ip_addr()).await?;
+
+    // Given two UEs with PDU sessions
+    let mut ue_1 = MockUe::new(nth_sim(0, sims), 1, &du, qc.ip_addr(), &logger).await?;
+    let mut ue_2 = MockUe::new(nth_sim(1, sims), 2, &du, qc.ip_addr(), &logger).await?;
+    for ue in [&mut ue_1, &mut ue_2] {
+        ue.perform_rrc_setup().await?;
+        ue.handle_nas_authentication().await?;
+        ue.handle_nas_security_mode().await?;
+        ue.handle_rrc_security_mode().await?;
+        ue.handle_nas_registration_accept().await?;
+        ue.send_nas_pdu_session_establishment_request().await?;
+        du.handle_f1_ue_context_setup(&mut ue.du_ue_context).await?;
+        ue.handle_rrc_reconfiguration_with_session_accept().await?;
+    }
+    let ue_2_ip = ue_2.ipv4_addr;
+
+    // When both deregister, and the second UE then registers again first
+    for ue in [&mut ue_1, &mut ue_2] {
+        ue.send_nas_deregistration_request().await?;
+        ue.receive_nas_deregistration_accept().await?;
+        du.handle_ue_context_release(&ue.du_ue_context).await?;
+    }
+    let mut ue_2 = MockUe::new(nth_sim(1, sims), 3, &du, qc.ip_addr(), &logger).await?;
+    ue_2.perform_rrc_setup().await?;
+    ue_2.handle_nas_authentication().await?;
+    ue_2.handle_nas_security_mode().await?;
+    ue_2.handle_rrc_security_mode().await?;
+    ue_2.handle_nas_registration_accept().await?;
+    ue_2.send_nas_pdu_session_establishment_request().await?;
+    du.handle_f1_ue_context_setup(&mut ue_2.du_ue_context)
+        .await?;
+    ue_2.handle_rrc_reconfiguration_with_session_accept()
+        .await?;
+
+    // Then it should get the same IP address as before, rather than the first UE's.
+    ensure!(
+        ue_2.ipv4_addr == ue_2_ip,
+        "Expected sticky IP {ue_2_ip}, got {}",
+        ue_2.ipv4_addr
+    );
+    Ok(())
+}